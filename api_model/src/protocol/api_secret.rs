@@ -0,0 +1,38 @@
+//! File format of the local API secret, written by the server and read by its clients.
+//!
+//! The file holds a header followed by the secret in hex: `lumo-api-secret <hex>`.
+
+pub const API_SECRET_LEN: usize = 32;
+
+pub const API_SECRET_HEADER: &str = "lumo-api-secret";
+
+/// Content of the file holding `secret`
+pub fn format_api_secret(secret: &[u8; API_SECRET_LEN]) -> String {
+    format!("{} {}\n", API_SECRET_HEADER, hex::encode(secret))
+}
+
+/// Secret held by the file content, `None` when it is not an API secret
+pub fn parse_api_secret(content: &str) -> Option<[u8; API_SECRET_LEN]> {
+    content
+        .trim()
+        .strip_prefix(API_SECRET_HEADER)
+        .and_then(|hex_secret| hex::decode(hex_secret.trim()).ok())
+        .and_then(|secret| <[u8; API_SECRET_LEN]>::try_from(secret).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_round_trip_and_garbage_is_refused() {
+        let secret = [7u8; API_SECRET_LEN];
+        assert_eq!(parse_api_secret(&format_api_secret(&secret)), Some(secret));
+
+        assert_eq!(parse_api_secret("not a secret"), None);
+        assert_eq!(parse_api_secret(API_SECRET_HEADER), None);
+        // Only secrets of the expected length
+        let short = format!("{} {}", API_SECRET_HEADER, hex::encode([7u8; 16]));
+        assert_eq!(parse_api_secret(&short), None);
+    }
+}
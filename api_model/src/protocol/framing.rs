@@ -0,0 +1,121 @@
+//! Length framing of messages sent over a stream, such as the local API socket.
+//!
+//! A message is sent as one or more parts, each one prefixed with its length as a big-endian
+//! `u32`, and ends with an empty part. Parts are at most `MAX_PART_LEN` long, so a reader can
+//! stream a message of any size without a datagram limit, and bound what it accepts.
+
+use crate::err::Result;
+use std::io::{self, Read};
+
+/// Longest part a message is split into
+pub const MAX_PART_LEN: usize = 64 * 1024;
+
+/// Split `payload` into length-prefixed parts, followed by the empty part ending the message
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    let parts = payload.len().div_ceil(MAX_PART_LEN);
+    let mut out = Vec::with_capacity(payload.len() + 4 * (parts + 1));
+    for part in payload.chunks(MAX_PART_LEN) {
+        out.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        out.extend_from_slice(part);
+    }
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out
+}
+
+/// Accumulates the parts of a message, refusing messages longer than `max_len`
+pub struct MessageAssembler {
+    max_len: usize,
+    message: Vec<u8>,
+}
+
+impl MessageAssembler {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            message: Vec::new(),
+        }
+    }
+
+    /// Check the length prefix of the next part, returns false on the empty part ending the message
+    pub fn expect_part(&self, len: u32) -> Result<bool> {
+        let len = len as usize;
+        if len > MAX_PART_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("part of {} bytes exceeds {} bytes", len, MAX_PART_LEN),
+            )
+            .into());
+        }
+        if self.message.len() + len > self.max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("message exceeds {} bytes", self.max_len),
+            )
+            .into());
+        }
+        Ok(len > 0)
+    }
+
+    pub fn push_part(&mut self, part: &[u8]) {
+        self.message.extend_from_slice(part);
+    }
+
+    pub fn into_message(self) -> Vec<u8> {
+        self.message
+    }
+}
+
+/// Read one message from `reader`, returns `None` when the stream ends before it starts
+pub fn read_message<R: Read>(reader: &mut R, max_len: usize) -> Result<Option<Vec<u8>>> {
+    let mut assembler = MessageAssembler::new(max_len);
+    let mut first = true;
+    loop {
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Err(e) if first && e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            other => other?,
+        }
+        first = false;
+        let len = u32::from_be_bytes(len);
+        if !assembler.expect_part(len)? {
+            return Ok(Some(assembler.into_message()));
+        }
+        let mut part = vec![0u8; len as usize];
+        reader.read_exact(&mut part)?;
+        assembler.push_part(&part);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_messages_round_trip_in_several_parts() {
+        let payload = (0..MAX_PART_LEN * 2 + 10)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let encoded = encode(&payload);
+        // 3 parts and the end of message
+        assert_eq!(encoded.len(), payload.len() + 4 * 4);
+
+        let mut reader = &encoded[..];
+        let back = read_message(&mut reader, usize::MAX).unwrap();
+        assert_eq!(back, Some(payload));
+        assert_eq!(read_message(&mut reader, usize::MAX).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_message_is_a_single_end_part() {
+        let encoded = encode(&[]);
+        assert_eq!(encoded, vec![0, 0, 0, 0]);
+        assert_eq!(read_message(&mut &encoded[..], 0).unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn messages_over_the_limit_and_truncated_streams_are_refused() {
+        let encoded = encode(&[7u8; 100]);
+        assert!(read_message(&mut &encoded[..], 99).is_err());
+        assert!(read_message(&mut &encoded[..50], 100).is_err());
+    }
+}
@@ -1,12 +1,28 @@
 use crate::err::Result;
+use crate::protocol::message::api_response_message::{ApiResponseKind, ApiResponseMessage};
 use crate::protocol::models::file::list_local_files::ListLocalFilesRequest;
 use crate::protocol::models::file::pull_file::PullFileRequest;
+use crate::protocol::models::file::push_file::PushFileRequest;
+use crate::protocol::models::group::rotate_token::RotateTokenRequest;
 use crate::protocol::models::local_file::local_pull_file::LocalPullFileRequest;
+use crate::protocol::models::peer::add_peer::AddPeerRequest;
 use crate::protocol::models::peer::list_peers::ListPeersRequest;
+use crate::protocol::models::peer::peer_keys::{
+    ForgetPeerRequest, ListPeerKeysRequest, TrustPeerRequest,
+};
 use crate::protocol::models::task::list_tasks::ListTasksRequest;
 use crate::protocol::protocol::Protocol;
 use crate::protocol::token::Token;
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Separates the tags of API requests from any other use of the API secret
+const AUTH_TAG_CONTEXT: &[u8] = b"lumo-api-request\0";
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ApiRequestKind {
@@ -16,35 +32,106 @@ pub enum ApiRequestKind {
     PullFile(PullFileRequest),
     ListTasks(ListTasksRequest),
     ListLocalFiles(ListLocalFilesRequest),
+    PushFile(PushFileRequest),
+    ListPeerKeys(ListPeerKeysRequest),
+    TrustPeer(TrustPeerRequest),
+    ForgetPeer(ForgetPeerRequest),
+    RotateToken(RotateTokenRequest),
+    AddPeer(AddPeerRequest),
 }
 
 #[derive(Debug, Clone)]
 pub struct ApiRequestMessage {
+    /// Chosen by the client and echoed in the response, telling it apart from the responses to
+    /// other requests
+    pub request_id: u64,
     pub from_ip: String,
     pub from_port: u16,
     pub request: ApiRequestKind,
+    /// When the request was made, in ms since the epoch. Covered by the tag, the server refuses
+    /// requests older than its replay window
+    pub timestamp_ms: u64,
+    /// HMAC-SHA256 of the request under the API secret of the server, proving the sender can
+    /// read the secret, i.e. runs as the user owning the server
+    pub auth_tag: Option<Bytes>,
 }
 
 impl ApiRequestMessage {
-    pub fn new(from_ip: String, from_port: u16, request: ApiRequestKind) -> Self {
+    pub fn new(request_id: u64, from_ip: String, from_port: u16, request: ApiRequestKind) -> Self {
         Self {
+            request_id,
             from_ip,
             from_port,
             request,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            auth_tag: None,
         }
     }
-}
 
-impl Protocol for ApiRequestMessage {
-    fn serialize(&self) -> Vec<u8> {
-        // Format: +API_REQUEST, +from_ip, :from_port, $<request-bytes>
+    /// When the request was made, on the clock of the client
+    pub fn sent_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp_ms)
+    }
+
+    /// Tokens covered by the authentication tag, i.e. all of them but the tag
+    fn request_tokens(&self) -> Vec<Token> {
         let request_bytes = bincode::serialize(&self.request).unwrap_or_else(|_e| Vec::new());
-        let tokens = vec![
+        vec![
             Token::Simple(String::from("API_REQUEST")),
+            Token::Integer(self.request_id),
             Token::Simple(self.from_ip.clone()),
             Token::Integer(self.from_port as u64),
             Token::Data(bytes::Bytes::from(request_bytes)),
-        ];
+            Token::Integer(self.timestamp_ms),
+        ]
+    }
+
+    fn mac(&self, secret: &[u8]) -> Result<HmacSha256> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(secret)?;
+        mac.update(AUTH_TAG_CONTEXT);
+        for t in self.request_tokens() {
+            mac.update(&t.to_bytes());
+        }
+        Ok(mac)
+    }
+
+    /// Tag the request with `secret`
+    pub fn authenticated(mut self, secret: &[u8]) -> Result<Self> {
+        self.auth_tag = Some(Bytes::copy_from_slice(
+            &self.mac(secret)?.finalize().into_bytes(),
+        ));
+        Ok(self)
+    }
+
+    /// Check the request was tagged with `secret`
+    pub fn verify(&self, secret: &[u8]) -> Result<()> {
+        let tag = self
+            .auth_tag
+            .as_ref()
+            .ok_or("request is not authenticated")?;
+        self.mac(secret)?
+            .verify_slice(tag)
+            .map_err(|_| "authentication tag mismatch".into())
+    }
+
+    /// Answer the request, under its id
+    pub fn response(&self, response: ApiResponseKind) -> ApiResponseMessage {
+        ApiResponseMessage {
+            request_id: self.request_id,
+            response,
+        }
+    }
+}
+
+impl Protocol for ApiRequestMessage {
+    fn serialize(&self) -> Vec<u8> {
+        // Format: +API_REQUEST, :request_id, +from_ip, :from_port, $<request-bytes>, :timestamp_ms[, $<auth-tag>]
+        let mut tokens = self.request_tokens();
+        if let Some(tag) = &self.auth_tag {
+            tokens.push(Token::Data(tag.clone()));
+        }
         let mut out = Vec::new();
         for t in tokens {
             out.extend_from_slice(&t.to_bytes());
@@ -56,19 +143,8 @@ impl Protocol for ApiRequestMessage {
     where
         Self: Sized,
     {
-        use std::io;
         let tokens = Token::parse_all(bytes)?;
-        // We expect exactly 4 tokens
-        if tokens.len() != 4 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!(
-                    "expected 4 tokens for ApiRequestMessage, got {}",
-                    tokens.len()
-                ),
-            )
-            .into());
-        }
+        // The number of tokens is checked along with their types
         Self::from_tokens(&tokens)
     }
 
@@ -77,11 +153,13 @@ impl Protocol for ApiRequestMessage {
         Self: Sized,
     {
         use std::io;
-        if tokens.len() != 4 {
+        // A seventh token is only valid as the authentication tag
+        let tagged = tokens.len() == 7 && matches!(tokens[6], Token::Data(_));
+        if tokens.len() != 6 && !tagged {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!(
-                    "expected 4 tokens for ApiRequestMessage, got {}",
+                    "expected 6 tokens for ApiRequestMessage, or 7 with an authentication tag, got {}",
                     tokens.len()
                 ),
             )
@@ -97,7 +175,17 @@ impl Protocol for ApiRequestMessage {
                 .into());
             }
         }
-        let from_ip = match &tokens[1] {
+        let request_id = match &tokens[1] {
+            Token::Integer(v) => *v,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Integer for request_id, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        let from_ip = match &tokens[2] {
             Token::Simple(s) => s.clone(),
             other => {
                 return Err(io::Error::new(
@@ -107,7 +195,7 @@ impl Protocol for ApiRequestMessage {
                 .into());
             }
         };
-        let from_port = match &tokens[2] {
+        let from_port = match &tokens[3] {
             Token::Integer(v) => {
                 if *v > u16::MAX as u64 {
                     return Err(io::Error::new(
@@ -126,7 +214,7 @@ impl Protocol for ApiRequestMessage {
                 .into());
             }
         };
-        let request = match &tokens[3] {
+        let request = match &tokens[4] {
             Token::Data(b) => match bincode::deserialize::<ApiRequestKind>(&b[..]) {
                 Ok(v) => v,
                 Err(e) => {
@@ -145,10 +233,27 @@ impl Protocol for ApiRequestMessage {
                 .into());
             }
         };
+        let timestamp_ms = match &tokens[5] {
+            Token::Integer(v) => *v,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Integer for timestamp_ms, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        let auth_tag = match tokens.get(6) {
+            Some(Token::Data(tag)) => Some(tag.clone()),
+            _ => None,
+        };
         Ok(ApiRequestMessage {
+            request_id,
             from_ip,
             from_port,
             request,
+            timestamp_ms,
+            auth_tag,
         })
     }
 }
@@ -169,33 +274,43 @@ mod tests {
     #[test]
     fn serialize_format_info() {
         let msg = ApiRequestMessage {
+            request_id: 3,
             from_ip: "127.0.0.1".to_string(),
             from_port: 8080,
             request: ApiRequestKind::Info,
+            timestamp_ms: 42,
+            auth_tag: None,
         };
         let bytes = msg.serialize();
         let tokens = Token::parse_all(&bytes).expect("parse tokens");
-        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens.len(), 6);
         assert!(matches!(tokens[0], Token::Simple(ref s) if s == "API_REQUEST"));
-        assert!(matches!(tokens[1], Token::Simple(ref s) if s == "127.0.0.1"));
-        assert!(matches!(tokens[2], Token::Integer(8080)));
+        assert!(matches!(tokens[1], Token::Integer(3)));
+        assert!(matches!(tokens[2], Token::Simple(ref s) if s == "127.0.0.1"));
+        assert!(matches!(tokens[3], Token::Integer(8080)));
         // Compare Data payload equals bincode of INFO
         let expected = bincode::serialize(&ApiRequestKind::Info).unwrap();
-        match &tokens[3] {
+        match &tokens[4] {
             Token::Data(b) => assert_eq!(&b[..], &expected[..]),
             _ => panic!("expected Data token for request"),
         }
+        assert!(matches!(tokens[5], Token::Integer(42)));
     }
 
     #[test]
     fn roundtrip_list_peers() {
         let msg = ApiRequestMessage {
+            request_id: u64::MAX,
             from_ip: "10.0.0.2".to_string(),
             from_port: 6553,
             request: ApiRequestKind::ListPeers(ListPeersRequest),
+            timestamp_ms: 42,
+            auth_tag: None,
         };
         let bytes = msg.serialize();
         let parsed = ApiRequestMessage::deserialize(&bytes).expect("deserialize");
+        assert_eq!(parsed.request_id, u64::MAX);
+        assert_eq!(parsed.timestamp_ms, 42);
         assert_eq!(parsed.from_ip, "10.0.0.2");
         assert_eq!(parsed.from_port, 6553);
         match parsed.request {
@@ -209,9 +324,11 @@ mod tests {
         let payload = bincode::serialize(&ApiRequestKind::Info).unwrap();
         let bytes = concat_tokens(vec![
             Token::Simple("WRONG".into()),
+            Token::Integer(1),
             Token::Simple("1.2.3.4".into()),
             Token::Integer(1234),
             Token::Data(Bytes::from(payload)),
+            Token::Integer(0),
         ]);
         let res = ApiRequestMessage::deserialize(&bytes);
         assert!(res.is_err());
@@ -227,9 +344,11 @@ mod tests {
         let payload = bincode::serialize(&ApiRequestKind::Info).unwrap();
         let bytes = concat_tokens(vec![
             Token::Simple("API_REQUEST".into()),
+            Token::Integer(1),
             Token::Integer(1), // wrong type
             Token::Integer(1234),
             Token::Data(Bytes::from(payload)),
+            Token::Integer(0),
         ]);
         let res = ApiRequestMessage::deserialize(&bytes);
         assert!(res.is_err());
@@ -242,9 +361,11 @@ mod tests {
         let payload = bincode::serialize(&ApiRequestKind::Info).unwrap();
         let bytes = concat_tokens(vec![
             Token::Simple("API_REQUEST".into()),
+            Token::Integer(1),
             Token::Simple("host".into()),
             Token::Integer(u16::MAX as u64 + 1),
             Token::Data(Bytes::from(payload)),
+            Token::Integer(0),
         ]);
         let res = ApiRequestMessage::deserialize(&bytes);
         assert!(res.is_err());
@@ -256,9 +377,11 @@ mod tests {
     fn deserialize_invalid_request_payload() {
         let bytes = concat_tokens(vec![
             Token::Simple("API_REQUEST".into()),
+            Token::Integer(1),
             Token::Simple("host".into()),
             Token::Integer(1),
             Token::Data(Bytes::from_static(b"not-bincode")),
+            Token::Integer(0),
         ]);
         let res = ApiRequestMessage::deserialize(&bytes);
         assert!(res.is_err());
@@ -271,15 +394,42 @@ mod tests {
         let payload = bincode::serialize(&ApiRequestKind::Info).unwrap();
         let mut bytes = concat_tokens(vec![
             Token::Simple("API_REQUEST".into()),
+            Token::Integer(1),
             Token::Simple("host".into()),
             Token::Integer(1),
             Token::Data(Bytes::from(payload)),
+            Token::Integer(0),
         ]);
         // Append an extra token
         bytes.extend_from_slice(&Token::Null.to_bytes());
         let res = ApiRequestMessage::deserialize(&bytes);
         assert!(res.is_err());
         let s = res.err().unwrap().to_string();
-        assert!(s.contains("expected 4 tokens for ApiRequestMessage"), "{s}");
+        assert!(s.contains("expected 6 tokens for ApiRequestMessage"), "{s}");
+    }
+
+    #[test]
+    fn authenticated_requests_round_trip_and_verify_with_the_secret_only() {
+        let secret = [7u8; 32];
+        let msg = ApiRequestMessage::new(1, "127.0.0.1".into(), 1, ApiRequestKind::Info)
+            .authenticated(&secret)
+            .unwrap();
+        let parsed = ApiRequestMessage::deserialize(&msg.serialize()).expect("deserialize");
+        assert!(parsed.verify(&secret).is_ok());
+        assert!(parsed.verify(&[8u8; 32]).is_err());
+
+        // The tag covers the request and its id
+        let mut tampered = parsed.clone();
+        tampered.request = ApiRequestKind::ListPeers(ListPeersRequest);
+        assert!(tampered.verify(&secret).is_err());
+        let mut replayed = parsed.clone();
+        replayed.request_id += 1;
+        assert!(replayed.verify(&secret).is_err());
+        let mut backdated = parsed.clone();
+        backdated.timestamp_ms -= 1;
+        assert!(backdated.verify(&secret).is_err());
+
+        let untagged = ApiRequestMessage::new(1, "127.0.0.1".into(), 1, ApiRequestKind::Info);
+        assert!(untagged.verify(&secret).is_err());
     }
 }
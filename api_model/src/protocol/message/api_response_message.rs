@@ -1,8 +1,14 @@
 use crate::err::Result;
 use crate::protocol::models::file::list_local_files::ListLocalFilesResponse;
 use crate::protocol::models::file::pull_file::PullFileResponse;
+use crate::protocol::models::file::push_file::PushFileResponse;
+use crate::protocol::models::group::rotate_token::RotateTokenResponse;
 use crate::protocol::models::local_file::local_pull_file::LocalPullFileResponse;
+use crate::protocol::models::peer::add_peer::AddPeerResponse;
 use crate::protocol::models::peer::list_peers::ListPeersResponse;
+use crate::protocol::models::peer::peer_keys::{
+    ForgetPeerResponse, ListPeerKeysResponse, TrustPeerResponse,
+};
 use crate::protocol::models::task::list_tasks::ListTasksResponse;
 use crate::protocol::protocol::Protocol;
 use crate::protocol::token::Token;
@@ -16,19 +22,33 @@ pub enum ApiResponseKind {
     PullFile(PullFileResponse),
     ListTasks(ListTasksResponse),
     ListLocalFiles(ListLocalFilesResponse),
+    PushFile(PushFileResponse),
+    ListPeerKeys(ListPeerKeysResponse),
+    TrustPeer(TrustPeerResponse),
+    ForgetPeer(ForgetPeerResponse),
+    RotateToken(RotateTokenResponse),
+    AddPeer(AddPeerResponse),
+    /// The request did not carry a valid tag of the API secret
+    Unauthenticated(String),
 }
 
+/// Id of the response to a request too malformed for its own id to be known
+pub const UNKNOWN_REQUEST_ID: u64 = 0;
+
 #[derive(Debug, Clone)]
 pub struct ApiResponseMessage {
+    /// Id of the request answered
+    pub request_id: u64,
     pub response: ApiResponseKind,
 }
 
 impl Protocol for ApiResponseMessage {
     fn serialize(&self) -> Vec<u8> {
-        // Format: +API_RESPONSE, $<response-bytes>
+        // Format: +API_RESPONSE, :request_id, $<response-bytes>
         let resp_bytes = bincode::serialize(&self.response).unwrap_or_else(|_e| Vec::new());
         let tokens = vec![
             Token::Simple(String::from("API_RESPONSE")),
+            Token::Integer(self.request_id),
             Token::Data(bytes::Bytes::from(resp_bytes)),
         ];
         let mut out = Vec::new();
@@ -44,11 +64,11 @@ impl Protocol for ApiResponseMessage {
     {
         use std::io;
         let tokens = Token::parse_all(bytes)?;
-        if tokens.len() != 2 {
+        if tokens.len() != 3 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!(
-                    "expected 2 tokens for ApiResponseMessage, got {}",
+                    "expected 3 tokens for ApiResponseMessage, got {}",
                     tokens.len()
                 ),
             )
@@ -62,11 +82,11 @@ impl Protocol for ApiResponseMessage {
         Self: Sized,
     {
         use std::io;
-        if tokens.len() != 2 {
+        if tokens.len() != 3 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!(
-                    "expected 2 tokens for ApiResponseMessage, got {}",
+                    "expected 3 tokens for ApiResponseMessage, got {}",
                     tokens.len()
                 ),
             )
@@ -82,7 +102,17 @@ impl Protocol for ApiResponseMessage {
                 .into());
             }
         }
-        let response = match &tokens[1] {
+        let request_id = match &tokens[1] {
+            Token::Integer(v) => *v,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Integer for request_id, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        let response = match &tokens[2] {
             Token::Data(b) => match bincode::deserialize::<ApiResponseKind>(&b[..]) {
                 Ok(v) => v,
                 Err(e) => {
@@ -101,7 +131,10 @@ impl Protocol for ApiResponseMessage {
                 .into());
             }
         };
-        Ok(ApiResponseMessage { response })
+        Ok(ApiResponseMessage {
+            request_id,
+            response,
+        })
     }
 }
 
@@ -121,15 +154,17 @@ mod tests {
     #[test]
     fn serialize_format_error() {
         let msg = ApiResponseMessage {
+            request_id: 3,
             response: ApiResponseKind::Error("oops".to_string()),
         };
         let bytes = msg.serialize();
         let tokens = Token::parse_all(&bytes).expect("parse tokens");
-        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens.len(), 3);
         assert!(matches!(tokens[0], Token::Simple(ref s) if s == "API_RESPONSE"));
+        assert!(matches!(tokens[1], Token::Integer(3)));
         // Compare Data payload equals bincode of Error("oops")
         let expected = bincode::serialize(&ApiResponseKind::Error("oops".to_string())).unwrap();
-        match &tokens[1] {
+        match &tokens[2] {
             Token::Data(b) => assert_eq!(&b[..], &expected[..]),
             _ => panic!("expected Data token for response"),
         }
@@ -138,10 +173,12 @@ mod tests {
     #[test]
     fn roundtrip_list_peers() {
         let resp = ApiResponseMessage {
+            request_id: u64::MAX,
             response: ApiResponseKind::ListPeers(ListPeersResponse { peers: vec![] }),
         };
         let bytes = resp.serialize();
         let parsed = ApiResponseMessage::deserialize(&bytes).expect("deserialize");
+        assert_eq!(parsed.request_id, u64::MAX);
         match parsed.response {
             ApiResponseKind::ListPeers(v) => assert!(v.peers.is_empty()),
             _ => panic!("expected LIST_PEERS variant"),
@@ -153,6 +190,7 @@ mod tests {
         let payload = bincode::serialize(&ApiResponseKind::Error("x".into())).unwrap();
         let bytes = concat_tokens(vec![
             Token::Simple("WRONG".into()),
+            Token::Integer(1),
             Token::Data(Bytes::from(payload)),
         ]);
         let res = ApiResponseMessage::deserialize(&bytes);
@@ -168,6 +206,7 @@ mod tests {
     fn deserialize_invalid_payload() {
         let bytes = concat_tokens(vec![
             Token::Simple("API_RESPONSE".into()),
+            Token::Integer(1),
             Token::Data(Bytes::from_static(b"not-bincode")),
         ]);
         let res = ApiResponseMessage::deserialize(&bytes);
@@ -181,6 +220,7 @@ mod tests {
         let payload = bincode::serialize(&ApiResponseKind::Error("x".into())).unwrap();
         let mut bytes = concat_tokens(vec![
             Token::Simple("API_RESPONSE".into()),
+            Token::Integer(1),
             Token::Data(Bytes::from(payload)),
         ]);
         // Append an extra token
@@ -189,7 +229,7 @@ mod tests {
         assert!(res.is_err());
         let s = res.err().unwrap().to_string();
         assert!(
-            s.contains("expected 2 tokens for ApiResponseMessage"),
+            s.contains("expected 3 tokens for ApiResponseMessage"),
             "{s}"
         );
     }
@@ -1,2 +1,3 @@
 pub mod api_request_message;
 pub mod api_response_message;
+pub mod reliable_message;
@@ -0,0 +1,177 @@
+use crate::err::Result;
+use crate::protocol::protocol::Protocol;
+use crate::protocol::token::Token;
+use bytes::Bytes;
+use std::io;
+
+/// Envelope of a message the receiver has to acknowledge.
+/// The sender retransmits it under the same id until an `AckMessage` with that id reaches
+/// `reply_port`, so the receiver may see it more than once and has to drop repeats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReliableMessage {
+    pub msg_id: u64,
+    /// Port the acknowledgement is sent to, on the address the envelope came from
+    pub reply_port: u16,
+    /// The wrapped message, serialized
+    pub payload: Bytes,
+}
+
+/// Acknowledgement of the `ReliableMessage` with the same id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AckMessage {
+    pub msg_id: u64,
+}
+
+impl ReliableMessage {
+    pub fn new(msg_id: u64, reply_port: u16, payload: Bytes) -> Self {
+        Self {
+            msg_id,
+            reply_port,
+            payload,
+        }
+    }
+
+    pub fn ack(&self) -> AckMessage {
+        AckMessage {
+            msg_id: self.msg_id,
+        }
+    }
+}
+
+fn invalid(msg: String) -> crate::err::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg).into()
+}
+
+fn expect_head(tokens: &[Token], head: &str, len: usize) -> Result<()> {
+    if tokens.len() != len {
+        return Err(invalid(format!(
+            "expected {} tokens for {}, got {}",
+            len,
+            head,
+            tokens.len()
+        )));
+    }
+    match &tokens[0] {
+        Token::Simple(s) if s == head => Ok(()),
+        other => Err(invalid(format!(
+            "expected leading Simple(\"{}\"), got {:?}",
+            head, other
+        ))),
+    }
+}
+
+fn integer(token: &Token, what: &str) -> Result<u64> {
+    match token {
+        Token::Integer(v) => Ok(*v),
+        other => Err(invalid(format!(
+            "expected Integer for {}, got {:?}",
+            what, other
+        ))),
+    }
+}
+
+impl Protocol for ReliableMessage {
+    fn serialize(&self) -> Vec<u8> {
+        // Format: +RELIABLE, :msg_id, :reply_port, $<payload>
+        let tokens = vec![
+            Token::Simple(String::from("RELIABLE")),
+            Token::Integer(self.msg_id),
+            Token::Integer(self.reply_port as u64),
+            Token::Data(self.payload.clone()),
+        ];
+        let mut out = Vec::new();
+        for t in tokens {
+            out.extend_from_slice(&t.to_bytes());
+        }
+        out
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::from_tokens(&Token::parse_all(bytes)?)
+    }
+
+    fn from_tokens(tokens: &[Token]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        expect_head(tokens, "RELIABLE", 4)?;
+        let msg_id = integer(&tokens[1], "msg_id")?;
+        let reply_port = integer(&tokens[2], "reply_port")?;
+        if reply_port > u16::MAX as u64 {
+            return Err(invalid(format!("port out of range: {}", reply_port)));
+        }
+        let payload = match &tokens[3] {
+            Token::Data(b) => b.clone(),
+            other => {
+                return Err(invalid(format!(
+                    "expected Data for payload, got {:?}",
+                    other
+                )));
+            }
+        };
+        Ok(Self {
+            msg_id,
+            reply_port: reply_port as u16,
+            payload,
+        })
+    }
+}
+
+impl Protocol for AckMessage {
+    fn serialize(&self) -> Vec<u8> {
+        // Format: +ACK, :msg_id
+        let mut out = Token::Simple(String::from("ACK")).to_bytes();
+        out.extend_from_slice(&Token::Integer(self.msg_id).to_bytes());
+        out
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::from_tokens(&Token::parse_all(bytes)?)
+    }
+
+    fn from_tokens(tokens: &[Token]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        expect_head(tokens, "ACK", 2)?;
+        Ok(Self {
+            msg_id: integer(&tokens[1], "msg_id")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reliable_message_round_trip_keeps_any_payload() {
+        // The payload is a message of its own, CRLFs included
+        let msg = ReliableMessage::new(42, 14514, Bytes::from_static(b"+PULL\r\n$3\r\na\r\n\r\n"));
+        let back = ReliableMessage::deserialize(&msg.serialize()).expect("deserialize");
+        assert_eq!(back, msg);
+        assert_eq!(back.ack(), AckMessage { msg_id: 42 });
+    }
+
+    #[test]
+    fn ack_round_trip() {
+        let ack = AckMessage { msg_id: u64::MAX };
+        assert_eq!(AckMessage::deserialize(&ack.serialize()).unwrap(), ack);
+    }
+
+    #[test]
+    fn reliable_message_rejects_out_of_range_port() {
+        let mut bytes = Token::Simple("RELIABLE".into()).to_bytes();
+        bytes.extend(Token::Integer(1).to_bytes());
+        bytes.extend(Token::Integer(70000).to_bytes());
+        bytes.extend(Token::Data(Bytes::from_static(b"x")).to_bytes());
+        assert!(ReliableMessage::deserialize(&bytes).is_err());
+        assert!(AckMessage::deserialize(&bytes).is_err());
+    }
+}
@@ -1,3 +1,5 @@
+pub mod api_secret;
+pub mod framing;
 pub mod message;
 pub mod models;
 pub mod protocol;
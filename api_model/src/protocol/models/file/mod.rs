@@ -1,3 +1,4 @@
 pub mod list_local_files;
 pub mod local_file;
 pub mod pull_file;
+pub mod push_file;
@@ -4,7 +4,14 @@ pub struct PullFileRequest {
     pub peer_identifier: String,
     pub path: String,
     pub expected_checksum: Option<Checksum>,
+    /// Pull every file under `path` when it is a directory
+    pub recursive: bool,
+    /// Transfer the files of a recursive pull as a single archive stream
+    pub bulk: bool,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct PullFileResponse;
+pub struct PullFileResponse {
+    /// Job tracking a recursive pull, to follow its progress
+    pub job_id: Option<u64>,
+}
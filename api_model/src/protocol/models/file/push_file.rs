@@ -0,0 +1,8 @@
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PushFileRequest {
+    pub peer_identifier: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PushFileResponse;
@@ -0,0 +1 @@
+pub mod rotate_token;
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// Switch the group to a new connection token, still accepting the previous one for a while
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RotateTokenRequest {
+    pub new_token: String,
+    /// How long messages protected with the previous token are still accepted
+    pub overlap_secs: u64,
+}
+
+// The token is a group secret, keep it out of logs
+impl std::fmt::Debug for RotateTokenRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RotateTokenRequest")
+            .field("new_token", &"<redacted>")
+            .field("overlap_secs", &self.overlap_secs)
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RotateTokenResponse {
+    /// Time the previous token stops being accepted
+    pub previous_accepted_until: SystemTime,
+}
@@ -1,4 +1,5 @@
 pub mod file;
+pub mod group;
 pub mod local_file;
 pub mod peer;
 pub mod task;
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// Send hellos directly to a peer broadcast does not reach, and keep it in the static peers of the
+/// config file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AddPeerRequest {
+    /// `host:port`, or just `host` for a peer listening on the same port as the server
+    pub addr: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AddPeerResponse {
+    /// The address as it is listed, with its port
+    pub addr: String,
+    /// Addresses it currently resolves to
+    pub resolved: Vec<String>,
+    /// False if the address was already listed
+    pub added: bool,
+    /// Why the added peer could not be saved to the config file, it is then only kept until the
+    /// server stops
+    pub save_error: Option<String>,
+}
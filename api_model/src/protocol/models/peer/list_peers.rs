@@ -10,6 +10,23 @@ pub struct Peer {
     pub is_main: bool,
 
     pub last_seen: SystemTime,
+
+    /// Subtrees the peer keeps in sync, the whole share when empty
+    pub subscribed: Vec<String>,
+    /// Subtrees left out of the subscribed ones
+    pub unsubscribed: Vec<String>,
+
+    /// Protocol version advertised by the peer, 0 for nodes predating the negotiation
+    pub protocol_version: u32,
+    /// Optional features advertised by the peer
+    pub capabilities: Vec<String>,
+
+    /// Offset of the peer clock from the node clock in ms, positive when the peer is ahead.
+    /// Unknown for peers whose hellos carry no timestamp.
+    pub clock_offset_ms: Option<i64>,
+
+    /// Gossip relays the peer was learned through, 0 when the node hears from it directly
+    pub hops: u8,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
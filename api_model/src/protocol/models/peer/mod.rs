@@ -1 +1,3 @@
+pub mod add_peer;
 pub mod list_peers;
+pub mod peer_keys;
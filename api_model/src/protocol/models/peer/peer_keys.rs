@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// Identity key pinned for a peer, along with the name it was first seen with
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerKey {
+    pub peer_name: String,
+    pub fingerprint: String,
+    pub pinned_at: SystemTime,
+
+    /// Fingerprint of the last key refused for this name, if any
+    pub refused_fingerprint: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListPeerKeysRequest;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListPeerKeysResponse {
+    pub keys: Vec<PeerKey>,
+}
+
+/// Pin a refused key in place of the key pinned for its name, e.g. after the peer was
+/// reinstalled
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrustPeerRequest {
+    /// Fingerprint of the refused key
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrustPeerResponse {
+    pub key: PeerKey,
+}
+
+/// Drop a pinned key, the key announced next with its name is pinned
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ForgetPeerRequest {
+    /// Fingerprint of the pinned key
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ForgetPeerResponse {
+    pub key: PeerKey,
+}
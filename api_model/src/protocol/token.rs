@@ -8,7 +8,7 @@ pub enum Token {
     Simple(String),
     // -XXXX\r\n
     Error(String),
-    // $XXXX\r\n
+    // $<len>\r\nXXXX\r\n
     Data(Bytes),
     // :XXXX\r\n
     Integer(u64),
@@ -18,6 +18,16 @@ pub enum Token {
     Null,
 }
 
+/// How the payload of a `$` Data token is delimited on the wire.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataFraming {
+    /// `$<len>\r\n<bytes>\r\n`, the payload may contain any byte including CRLF.
+    #[default]
+    LengthPrefixed,
+    /// `$<bytes>\r\n`, as sent by older nodes. The payload ends at the first CRLF.
+    Legacy,
+}
+
 impl Token {
     #[inline]
     fn to_utf8(bytes: &[u8]) -> Result<&str> {
@@ -49,11 +59,16 @@ impl Token {
     /// Formats mirror the parser:
     /// - +<utf8>\r\n for Simple
     /// - -<utf8>\r\n for Error
-    /// - $<bytes>\r\n for Data
+    /// - $<len>\r\n<bytes>\r\n for Data
     /// - :<u64>\r\n for Integer
     /// - ,<f64>\r\n for Float
     /// - ^\r\n       for Null
     pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(DataFraming::default())
+    }
+
+    /// Convert this token to its wire-format bytes, framing Data tokens as requested.
+    pub fn to_bytes_with(&self, framing: DataFraming) -> Vec<u8> {
         let mut out = Vec::new();
         match self {
             Token::Simple(s) => {
@@ -66,6 +81,12 @@ impl Token {
             }
             Token::Data(b) => {
                 out.push(b'$');
+                if framing == DataFraming::LengthPrefixed {
+                    let mut buf = [0u8; lexical_core::BUFFER_SIZE];
+                    let slc = lexical_core::write(b.len() as u64, &mut buf);
+                    out.extend_from_slice(slc);
+                    out.extend_from_slice(b"\r\n");
+                }
                 out.extend_from_slice(b);
             }
             Token::Integer(v) => {
@@ -94,11 +115,16 @@ impl Token {
     /// Formats:
     /// - +<utf8>\r\n => Simple
     /// - -<utf8>\r\n => Error
-    /// - $<bytes>\r\n => Data (raw bytes between marker and CRLF)
+    /// - $<len>\r\n<bytes>\r\n => Data (exactly `len` raw bytes)
     /// - :<u64>\r\n => Integer
     /// - ,<f64>\r\n => Float
     /// - ^\r\n       => Null
     pub fn parse_one(input: &[u8]) -> Result<(Token, usize)> {
+        Self::parse_one_with(input, DataFraming::default())
+    }
+
+    /// Parse a single token, expecting Data tokens in the given framing.
+    pub fn parse_one_with(input: &[u8], framing: DataFraming) -> Result<(Token, usize)> {
         if input.is_empty() {
             return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "empty input").into());
         }
@@ -130,6 +156,10 @@ impl Token {
         }
         let (prefix, body) = (input[0], &input[1..end]);
         let consumed = end + 2;
+        if prefix == b'$' && framing == DataFraming::LengthPrefixed {
+            return Self::parse_data_payload(body, &input[consumed..])
+                .map(|(token, used)| (token, consumed + used));
+        }
         let token = match prefix {
             b'+' => Token::Simple(Self::to_string(body)?),
             b'-' => Token::Error(Self::to_string(body)?),
@@ -165,18 +195,91 @@ impl Token {
         Ok((token, consumed))
     }
 
+    /// Read the payload of a length-prefixed Data token.
+    /// `header` holds the length digits, `rest` starts right after the header's CRLF.
+    /// Returns the token and the number of bytes consumed from `rest`.
+    fn parse_data_payload(header: &[u8], rest: &[u8]) -> Result<(Token, usize)> {
+        if header.is_empty() || !header.iter().all(u8::is_ascii_digit) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "data token length must be a decimal number",
+            )
+            .into());
+        }
+        let len = lexical_core::parse::<u64>(header)
+            .ok()
+            .and_then(|len| usize::try_from(len).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "data token too long"))?;
+        let needed = len
+            .checked_add(2)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "data token too long"))?;
+        if rest.len() < needed {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "data token announces {} bytes but only {} remain",
+                    len,
+                    rest.len().saturating_sub(2)
+                ),
+            )
+            .into());
+        }
+        if &rest[len..needed] != b"\r\n" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "data token payload must be followed by CRLF",
+            )
+            .into());
+        }
+        Ok((Token::Data(Bytes::copy_from_slice(&rest[..len])), needed))
+    }
+
     /// Parse all tokens from the input until exhaustion.
     /// Uses an index cursor without modifying the input slice.
     pub fn parse_all(input: &[u8]) -> Result<Vec<Token>> {
+        Self::parse_all_with(input, DataFraming::default())
+    }
+
+    /// Parse all tokens from the input, expecting Data tokens in the given framing.
+    pub fn parse_all_with(input: &[u8], framing: DataFraming) -> Result<Vec<Token>> {
         let mut tokens = Vec::new();
         let mut idx: usize = 0;
         while idx < input.len() {
-            let (tok, used) = Self::parse_one(&input[idx..])?;
+            let (tok, used) = Self::parse_one_with(&input[idx..], framing)?;
             tokens.push(tok);
             idx += used;
         }
         Ok(tokens)
     }
+
+    /// Parse all tokens from a message that may come from an older node.
+    /// Tries the length-prefixed framing first and falls back to the legacy one,
+    /// so both forms are accepted while a network is being upgraded.
+    pub fn parse_all_compat(input: &[u8]) -> Result<Vec<Token>> {
+        Self::parse_all_with(input, DataFraming::LengthPrefixed)
+            .or_else(|_| Self::parse_all_with(input, DataFraming::Legacy))
+    }
+
+    /// Encode a message again with Data tokens in the given framing, for a node that only
+    /// understands that one. Fails when a payload contains CRLF and cannot be framed the
+    /// legacy way.
+    pub fn reframe(input: &[u8], framing: DataFraming) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len());
+        for token in Self::parse_all_compat(input)? {
+            if framing == DataFraming::Legacy
+                && let Token::Data(b) = &token
+                && b.windows(2).any(|w| w == b"\r\n")
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "data token payload contains CRLF, it cannot be framed the legacy way",
+                )
+                .into());
+            }
+            out.extend_from_slice(&token.to_bytes_with(framing));
+        }
+        Ok(out)
+    }
 }
 
 #[cfg(test)]
@@ -198,11 +301,62 @@ mod tests {
 
     #[test]
     fn parse_data() {
-        let (t, _used) = Token::parse_one(b"$abc\r\n").unwrap();
+        let (t, used) = Token::parse_one(b"$3\r\nabc\r\n").unwrap();
         match t {
             Token::Data(b) => assert_eq!(&b[..], b"abc"),
             _ => panic!("wrong token"),
         }
+        assert_eq!(used, 9);
+    }
+
+    #[test]
+    fn parse_data_containing_crlf() {
+        let (t, used) = Token::parse_one(b"$4\r\na\r\nb\r\n+OK\r\n").unwrap();
+        match t {
+            Token::Data(b) => assert_eq!(&b[..], b"a\r\nb"),
+            _ => panic!("wrong token"),
+        }
+        assert_eq!(used, 10);
+    }
+
+    #[test]
+    fn parse_data_rejects_bad_framing() {
+        // Truncated payload, missing trailing CRLF and non numeric length
+        assert!(Token::parse_one(b"$5\r\nabc\r\n").is_err());
+        assert!(Token::parse_one(b"$3\r\nabcd\r\n").is_err());
+        assert!(Token::parse_one(b"$abc\r\n").is_err());
+        assert!(Token::parse_one(b"$\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn parse_data_legacy() {
+        let (t, used) = Token::parse_one_with(b"$abc\r\n", DataFraming::Legacy).unwrap();
+        match t {
+            Token::Data(b) => assert_eq!(&b[..], b"abc"),
+            _ => panic!("wrong token"),
+        }
+        assert_eq!(used, 6);
+    }
+
+    #[test]
+    fn parse_all_compat_accepts_both_framings() {
+        let new = Token::parse_all_compat(b"+PUSH\r\n$2\r\nxy\r\n^\r\n").unwrap();
+        let old = Token::parse_all_compat(b"+PUSH\r\n$xy\r\n^\r\n").unwrap();
+        for tokens in [new, old] {
+            assert_eq!(tokens.len(), 3);
+            assert!(matches!(&tokens[1], Token::Data(b) if &b[..] == b"xy"));
+        }
+    }
+
+    #[test]
+    fn reframe_converts_between_framings() {
+        let legacy = Token::reframe(b"+PUSH\r\n$2\r\nxy\r\n^\r\n", DataFraming::Legacy).unwrap();
+        assert_eq!(&legacy[..], b"+PUSH\r\n$xy\r\n^\r\n");
+        let prefixed = Token::reframe(&legacy, DataFraming::LengthPrefixed).unwrap();
+        assert_eq!(&prefixed[..], b"+PUSH\r\n$2\r\nxy\r\n^\r\n");
+
+        // A payload with CRLF would be cut short by an older node
+        assert!(Token::reframe(b"$4\r\na\r\nb\r\n", DataFraming::Legacy).is_err());
     }
 
     #[test]
@@ -248,7 +402,17 @@ mod tests {
     #[test]
     fn to_bytes_data() {
         let t = Token::Data(Bytes::from_static(b"abc"));
-        assert_eq!(&t.to_bytes()[..], b"$abc\r\n");
+        assert_eq!(&t.to_bytes()[..], b"$3\r\nabc\r\n");
+        assert_eq!(&t.to_bytes_with(DataFraming::Legacy)[..], b"$abc\r\n");
+    }
+
+    #[test]
+    fn to_bytes_data_empty() {
+        let t = Token::Data(Bytes::new());
+        assert_eq!(&t.to_bytes()[..], b"$0\r\n\r\n");
+        assert!(
+            matches!(Token::parse_one(b"$0\r\n\r\n").unwrap(), (Token::Data(b), 6) if b.is_empty())
+        );
     }
 
     #[test]
@@ -288,4 +452,56 @@ mod tests {
         let parsed = Token::parse_all(&bytes).unwrap();
         assert_eq!(parsed.len(), seq.len());
     }
+
+    fn data_payloads(tokens: &[Token]) -> Vec<Vec<u8>> {
+        tokens
+            .iter()
+            .filter_map(|t| match t {
+                Token::Data(b) => Some(b.to_vec()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    quickcheck::quickcheck! {
+        fn prop_data_round_trips(payload: Vec<u8>) -> bool {
+            let bytes = Token::Data(Bytes::from(payload.clone())).to_bytes();
+            match Token::parse_all(&bytes) {
+                Ok(tokens) => tokens.len() == 1 && data_payloads(&tokens) == vec![payload],
+                Err(_) => false,
+            }
+        }
+
+        fn prop_mixed_sequence_round_trips(payloads: Vec<Vec<u8>>, n: u64) -> bool {
+            let mut bytes = Vec::new();
+            for payload in &payloads {
+                bytes.extend_from_slice(&Token::Simple("DATA".into()).to_bytes());
+                bytes.extend_from_slice(&Token::Data(Bytes::from(payload.clone())).to_bytes());
+                bytes.extend_from_slice(&Token::Integer(n).to_bytes());
+                bytes.extend_from_slice(&Token::Null.to_bytes());
+            }
+            match Token::parse_all(&bytes) {
+                Ok(tokens) => {
+                    tokens.len() == payloads.len() * 4
+                        && data_payloads(&tokens) == payloads
+                        && tokens.chunks(4).all(|c| matches!(c[2], Token::Integer(v) if v == n))
+                }
+                Err(_) => false,
+            }
+        }
+
+        fn prop_compat_reads_new_framing(payloads: Vec<Vec<u8>>) -> bool {
+            let mut bytes = Vec::new();
+            for payload in &payloads {
+                bytes.extend_from_slice(&Token::Data(Bytes::from(payload.clone())).to_bytes());
+            }
+            matches!(Token::parse_all_compat(&bytes), Ok(tokens) if data_payloads(&tokens) == payloads)
+        }
+
+        fn prop_truncated_data_is_rejected(payload: Vec<u8>, cut: usize) -> bool {
+            let bytes = Token::Data(Bytes::from(payload)).to_bytes();
+            let cut = cut % bytes.len();
+            Token::parse_all(&bytes[..cut]).is_err() || cut == 0
+        }
+    }
 }
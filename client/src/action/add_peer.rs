@@ -0,0 +1,35 @@
+use crate::action::conn::Connection;
+use crate::error::ClientError;
+use crate::extract_response;
+use api_model::protocol::message::api_request_message::ApiRequestKind;
+use api_model::protocol::message::api_response_message::ApiResponseKind;
+use api_model::protocol::models::peer::add_peer::AddPeerRequest;
+use cli_handler::cli_impl;
+
+#[cli_impl]
+pub fn add_peer(addr: String) -> Result<(), ClientError> {
+    let conn = Connection::new(None)?;
+
+    let res = extract_response!(
+        conn.request(ApiRequestKind::AddPeer(AddPeerRequest { addr }))?,
+        ApiResponseKind::AddPeer
+    )?;
+    if !res.added {
+        println!("{} is already listed, sent it a hello", res.addr);
+    } else if let Some(e) = res.save_error {
+        println!(
+            "Added {} ({}) until the server stops, saving it to static_peers failed: {}",
+            res.addr,
+            res.resolved.join(", "),
+            e
+        );
+    } else {
+        println!(
+            "Added {} ({}) to static_peers",
+            res.addr,
+            res.resolved.join(", ")
+        );
+    }
+
+    Ok(())
+}
@@ -1,18 +1,70 @@
 use crate::error::ClientError;
+use api_model::protocol::api_secret::{API_SECRET_LEN, parse_api_secret};
+use api_model::protocol::framing;
 use api_model::protocol::message::api_request_message::{ApiRequestKind, ApiRequestMessage};
-use api_model::protocol::message::api_response_message::{ApiResponseKind, ApiResponseMessage};
+use api_model::protocol::message::api_response_message::{
+    ApiResponseKind, ApiResponseMessage, UNKNOWN_REQUEST_ID,
+};
+use api_model::protocol::message::reliable_message::{AckMessage, ReliableMessage};
 use api_model::protocol::protocol::Protocol;
-use std::net::UdpSocket;
+use api_model::protocol::token::Token;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Time given to the server to answer a request
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait for the server to acknowledge a request before sending it again, doubled every time
+const INITIAL_RETRANSMIT_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Longest response accepted over the API socket
+const MAX_STREAMED_RESPONSE_LEN: usize = 256 * 1024 * 1024;
+
+/// Address of the server API, set once from the command line
+static SERVER_ADDR: OnceLock<SocketAddr> = OnceLock::new();
+/// Socket of the server API, set once from the command line
+static SOCKET_PATH: OnceLock<PathBuf> = OnceLock::new();
+/// Working directory of the server, set once from the command line
+static WORKING_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub const DEFAULT_SERVER_ADDR: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 14514);
+
+/// Socket of the server API, under the working directory of the server
+pub const API_SOCKET_FILE: &str = ".disc/api.sock";
+
+/// Secret of the server, under its working directory, requests are tagged with it
+const API_SECRET_FILE: &str = ".disc/api_secret";
+
+pub fn set_server_addr(addr: SocketAddr) {
+    let _ = SERVER_ADDR.set(addr);
+}
+
+pub fn set_socket_path(path: PathBuf) {
+    let _ = SOCKET_PATH.set(path);
+}
+
+pub fn set_working_dir(path: PathBuf) {
+    let _ = WORKING_DIR.set(path);
+}
 
 pub struct ConnectionConfig {
-    api_port: u16,
+    server_addr: SocketAddr,
+    /// Tried first, the API is reached at `server_addr` over UDP without it or when nothing
+    /// listens on it
+    socket_path: Option<PathBuf>,
+    /// Where the API secret of the server is found
+    working_dir: Option<PathBuf>,
     size_in_kb: u32,
 }
 
 impl Default for ConnectionConfig {
     fn default() -> Self {
         Self {
-            api_port: 14514,
+            server_addr: SERVER_ADDR.get().copied().unwrap_or(DEFAULT_SERVER_ADDR),
+            socket_path: SOCKET_PATH.get().cloned(),
+            working_dir: WORKING_DIR.get().cloned(),
             size_in_kb: 1024,
         }
     }
@@ -36,10 +88,6 @@ impl Connection {
             .set_nonblocking(false)
             .map_err(|e| ClientError::ConnectionBindError(String::from(""), e.to_string()))?;
 
-        socket
-            .set_read_timeout(Some(std::time::Duration::from_secs(5)))
-            .map_err(|e| ClientError::ConnectionBindError(String::from(""), e.to_string()))?;
-
         match config {
             Some(c) => Ok(Self {
                 udp_socket: socket,
@@ -52,7 +100,11 @@ impl Connection {
         }
     }
 
-    fn serialize_payload(&self, api_request: ApiRequestKind) -> Result<Vec<u8>, ClientError> {
+    fn serialize_payload(
+        &self,
+        request_id: u64,
+        api_request: ApiRequestKind,
+    ) -> Result<Vec<u8>, ClientError> {
         let local_addr = self.udp_socket.local_addr().map_err(|e| {
             ClientError::ConnectionBindError(
                 String::from("failed to get local addr"),
@@ -60,59 +112,281 @@ impl Connection {
             )
         })?;
 
-        let payload =
-            ApiRequestMessage::new(local_addr.ip().to_string(), local_addr.port(), api_request)
-                .serialize();
+        let payload = ApiRequestMessage::new(
+            request_id,
+            local_addr.ip().to_string(),
+            local_addr.port(),
+            api_request,
+        )
+        .authenticated(&self.load_api_secret()?)
+        .map_err(|e| {
+            ClientError::InternalError(
+                String::from("failed to authenticate request"),
+                e.to_string(),
+            )
+        })?
+        .serialize();
 
         Ok(payload)
     }
 
-    fn receive_response(&self) -> Result<ApiResponseMessage, ClientError> {
-        let sz: usize = (self.config.size_in_kb * 1024 + 5) as usize;
-        let mut buf: Vec<u8> = vec![0; sz];
+    /// Read the API secret of the server, only its owner is allowed to
+    fn load_api_secret(&self) -> Result<[u8; API_SECRET_LEN], ClientError> {
+        let working_dir = self.config.working_dir.as_ref().ok_or_else(|| {
+            ClientError::AuthenticationError(String::from(
+                "the API secret is kept in the working directory of the server, pass --working-dir or --config",
+            ))
+        })?;
+        let path = working_dir.join(API_SECRET_FILE);
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            ClientError::AuthenticationError(format!(
+                "cannot read the API secret at {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        parse_api_secret(&content).ok_or_else(|| {
+            ClientError::AuthenticationError(format!("{} is not an API secret", path.display()))
+        })
+    }
 
-        let response = self.udp_socket.recv_from(&mut buf).map_err(|e| {
-            ClientError::ConnectionReceiverError(
+    /// Receive one datagram, waiting until `deadline` at most.
+    /// Returns `None` when nothing arrived in time.
+    fn receive_datagram(
+        &self,
+        buf: &mut [u8],
+        deadline: Instant,
+    ) -> Result<Option<(usize, SocketAddr)>, ClientError> {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        if wait.is_zero() {
+            return Ok(None);
+        }
+        self.udp_socket
+            .set_read_timeout(Some(wait))
+            .map_err(|e| ClientError::ConnectionBindError(String::from(""), e.to_string()))?;
+        match self.udp_socket.recv_from(buf) {
+            Ok(received) => Ok(Some(received)),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(ClientError::ConnectionReceiverError(
                 String::from("failed to receive response"),
                 e.to_string(),
+            )),
+        }
+    }
+
+    fn send_datagram(&self, payload: &[u8], addr: SocketAddr) -> Result<(), ClientError> {
+        self.udp_socket.send_to(payload, addr).map_err(|e| {
+            ClientError::ConnectionReceiverError(
+                String::from("failed to send request"),
+                e.to_string(),
             )
         })?;
+        Ok(())
+    }
 
-        if response.0 > (self.config.size_in_kb * 1024) as usize {
+    fn parse_response(&self, bytes: &[u8]) -> Result<ApiResponseMessage, ClientError> {
+        if bytes.len() > (self.config.size_in_kb * 1024) as usize {
             return Err(ClientError::ResponseParseError(
                 String::from("response size exceeds limit"),
                 String::from(""),
             ));
         }
 
-        Ok(
-            ApiResponseMessage::deserialize(&buf[..response.0]).map_err(|e| {
-                ClientError::ResponseParseError(
-                    String::from("failed to deserialize response"),
+        ApiResponseMessage::deserialize(bytes).map_err(|e| {
+            ClientError::ResponseParseError(
+                String::from("failed to deserialize response"),
+                e.to_string(),
+            )
+        })
+    }
+
+    /// Send the request under `msg_id` until the server acknowledges it, and wait for the
+    /// response to `request_id`, acknowledging it in turn. Responses to earlier requests, which
+    /// arrived too late, are acknowledged and dropped, along with datagrams that are no response.
+    fn exchange(
+        &self,
+        msg_id: u64,
+        request_id: u64,
+        payload: &[u8],
+    ) -> Result<ApiResponseMessage, ClientError> {
+        let sz: usize = (self.config.size_in_kb * 1024 + 5) as usize;
+        let mut buf: Vec<u8> = vec![0; sz];
+
+        let give_up_at = Instant::now() + RESPONSE_TIMEOUT;
+        let mut backoff = INITIAL_RETRANSMIT_BACKOFF;
+        let mut acked = false;
+        self.send_datagram(payload, self.config.server_addr)?;
+        let mut retransmit_at = Instant::now() + backoff;
+
+        loop {
+            let deadline = if acked {
+                give_up_at
+            } else {
+                retransmit_at.min(give_up_at)
+            };
+            let Some((n, from)) = self.receive_datagram(&mut buf, deadline)? else {
+                if Instant::now() >= give_up_at {
+                    return Err(ClientError::ConnectionTimeoutError(
+                        String::from("no response from server"),
+                        format!("waited {:?}", RESPONSE_TIMEOUT),
+                    ));
+                }
+                // Not even acknowledged, the request is likely lost
+                backoff *= 2;
+                retransmit_at = Instant::now() + backoff;
+                self.send_datagram(payload, self.config.server_addr)?;
+                continue;
+            };
+            let datagram = &buf[..n];
+
+            let response = match Token::parse_one(datagram) {
+                Ok((Token::Simple(head), _)) if head == "ACK" => {
+                    if AckMessage::deserialize(datagram).is_ok_and(|ack| ack.msg_id == msg_id) {
+                        acked = true;
+                    }
+                    continue;
+                }
+                Ok((Token::Simple(head), _)) if head == "RELIABLE" => {
+                    let Ok(envelope) = ReliableMessage::deserialize(datagram) else {
+                        continue;
+                    };
+                    let mut ack_addr = from;
+                    ack_addr.set_port(envelope.reply_port);
+                    self.send_datagram(&envelope.ack().serialize(), ack_addr)?;
+                    self.parse_response(&envelope.payload)
+                }
+                // Servers not acknowledging requests answer with the bare response
+                _ => self.parse_response(datagram),
+            };
+            // Anyone may send to our port, what is not a response does not end the wait
+            let Ok(response) = response else {
+                continue;
+            };
+            if response.request_id == request_id {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Exchange the request over the API socket.
+    /// Returns `None` when no server listens on it, so that UDP is used instead.
+    #[cfg(unix)]
+    fn request_over_socket(
+        &self,
+        socket_path: &std::path::Path,
+        request_id: u64,
+        payload: &[u8],
+    ) -> Result<Option<ApiResponseMessage>, ClientError> {
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+
+        let mut stream = match UnixStream::connect(socket_path) {
+            Ok(stream) => stream,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(ClientError::ConnectionBindError(
+                    format!("failed to connect to {}", socket_path.display()),
                     e.to_string(),
-                )
-            })?,
-        )
+                ));
+            }
+        };
+        stream
+            .set_read_timeout(Some(RESPONSE_TIMEOUT))
+            .map_err(|e| ClientError::ConnectionBindError(String::from(""), e.to_string()))?;
+
+        stream.write_all(&framing::encode(payload)).map_err(|e| {
+            ClientError::ConnectionReceiverError(
+                String::from("failed to send request"),
+                e.to_string(),
+            )
+        })?;
+        loop {
+            let response = framing::read_message(&mut stream, MAX_STREAMED_RESPONSE_LEN)
+                .map_err(|e| {
+                    ClientError::ConnectionReceiverError(
+                        String::from("failed to receive response"),
+                        e.to_string(),
+                    )
+                })?
+                .ok_or_else(|| {
+                    ClientError::ConnectionReceiverError(
+                        String::from("server closed the connection"),
+                        String::new(),
+                    )
+                })?;
+            // Messages are framed, one that is no response leaves the next ones readable
+            let Ok(response) = ApiResponseMessage::deserialize(&response) else {
+                continue;
+            };
+            // A request the server could not read is only ever ours on this connection
+            if response.request_id == request_id || response.request_id == UNKNOWN_REQUEST_ID {
+                return Ok(Some(response));
+            }
+        }
     }
 
     pub fn request(&self, api_request: ApiRequestKind) -> Result<ApiResponseKind, ClientError> {
-        let payload = self.serialize_payload(api_request)?;
-        let addr = format!("{}:{}", "127.0.0.1", self.config.api_port);
+        let request_id = random_id();
+        let request = self.serialize_payload(request_id, api_request)?;
+        match &self.config.socket_path {
+            #[cfg(unix)]
+            Some(socket_path) => {
+                if let Some(response) =
+                    self.request_over_socket(socket_path, request_id, &request)?
+                {
+                    return Ok(response.response);
+                }
+                eprintln!(
+                    "Warning: nothing listens on {}, reaching the server over UDP at {}",
+                    socket_path.display(),
+                    self.config.server_addr
+                );
+            }
+            _ => eprintln!(
+                "Warning: no API socket, pass --working-dir or --config; reaching the server over UDP at {}",
+                self.config.server_addr
+            ),
+        }
+
+        let msg_id = random_id();
+        let payload = ReliableMessage::new(msg_id, self.local_port()?, request.into()).serialize();
+
+        let response = self.exchange(msg_id, request_id, &payload)?;
+        Ok(response.response)
+    }
 
+    fn local_port(&self) -> Result<u16, ClientError> {
         self.udp_socket
-            .send_to(payload.as_ref(), addr)
+            .local_addr()
+            .map(|addr| addr.port())
             .map_err(|e| {
-                ClientError::ConnectionReceiverError(
-                    String::from("failed to send request"),
+                ClientError::ConnectionBindError(
+                    String::from("failed to get local addr"),
                     e.to_string(),
                 )
-            })?;
-
-        let response = self.receive_response()?;
-        Ok(response.response)
+            })
     }
 }
 
+/// Random id for a request or its envelope, never `UNKNOWN_REQUEST_ID`
+fn random_id() -> u64 {
+    rand::random::<u64>().max(UNKNOWN_REQUEST_ID + 1)
+}
+
 #[macro_export]
 macro_rules! extract_response {
     ($response:expr, $variant:path) => {
@@ -121,6 +395,9 @@ macro_rules! extract_response {
             api_model::protocol::message::api_response_message::ApiResponseKind::Error(err) => {
                 ::core::result::Result::Err($crate::error::ClientError::ResponseError(err))
             }
+            api_model::protocol::message::api_response_message::ApiResponseKind::Unauthenticated(
+                err,
+            ) => ::core::result::Result::Err($crate::error::ClientError::AuthenticationError(err)),
             _ => ::core::result::Result::Err($crate::error::ClientError::ResponseParseError(
                 format!("Expected {}", stringify!($variant)),
                 String::new(),
@@ -128,3 +405,127 @@ macro_rules! extract_response {
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api_model::protocol::api_secret::format_api_secret;
+    use std::path::Path;
+
+    /// Working directory holding an API secret, as the server leaves it
+    fn working_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("client-conn-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(dir.join(".disc")).unwrap();
+        std::fs::write(
+            dir.join(API_SECRET_FILE),
+            format_api_secret(&[7u8; API_SECRET_LEN]),
+        )
+        .unwrap();
+        dir
+    }
+
+    fn connection(
+        server_addr: SocketAddr,
+        socket_path: Option<PathBuf>,
+        working_dir: &Path,
+    ) -> Connection {
+        Connection::new(Some(ConnectionConfig {
+            server_addr,
+            socket_path,
+            working_dir: Some(working_dir.to_path_buf()),
+            size_in_kb: 1024,
+        }))
+        .unwrap()
+    }
+
+    fn response(request_id: u64, text: &str) -> Vec<u8> {
+        ApiResponseMessage {
+            request_id,
+            response: ApiResponseKind::Error(text.into()),
+        }
+        .serialize()
+    }
+
+    fn error_text(response: ApiResponseKind) -> String {
+        match response {
+            ApiResponseKind::Error(text) => text,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mismatched_or_garbled_responses_are_dropped_over_the_socket() {
+        use std::io::Write;
+        use std::os::unix::net::UnixListener;
+
+        let dir = working_dir("socket");
+        let socket_path = dir.join(API_SOCKET_FILE);
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = framing::read_message(&mut stream, usize::MAX)
+                .unwrap()
+                .unwrap();
+            let request = ApiRequestMessage::deserialize(&request).unwrap();
+            for message in [
+                b"garbage".to_vec(),
+                response(request.request_id.wrapping_add(1), "stale"),
+                response(request.request_id, "answer"),
+            ] {
+                stream.write_all(&framing::encode(&message)).unwrap();
+            }
+        });
+
+        let conn = connection(DEFAULT_SERVER_ADDR, Some(socket_path), &dir);
+        let response = conn.request(ApiRequestKind::Info).unwrap();
+        assert_eq!(error_text(response), "answer");
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mismatched_or_garbled_responses_are_dropped_over_udp() {
+        let dir = working_dir("udp");
+        let server_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let server_addr = server_socket.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut buf = vec![0u8; 64 * 1024];
+            let (n, client) = server_socket.recv_from(&mut buf).unwrap();
+            let envelope = ReliableMessage::deserialize(&buf[..n]).unwrap();
+            let request = ApiRequestMessage::deserialize(&envelope.payload).unwrap();
+            server_socket
+                .send_to(&envelope.ack().serialize(), client)
+                .unwrap();
+
+            let reliable = |msg_id: u64, payload: Vec<u8>| {
+                ReliableMessage::new(msg_id, server_addr.port(), payload.into()).serialize()
+            };
+            for datagram in [
+                b"garbage".to_vec(),
+                reliable(1, b"garbage".to_vec()),
+                Token::Simple(String::from("RELIABLE")).to_bytes(),
+                response(request.request_id.wrapping_add(1), "stale"),
+                reliable(2, response(request.request_id.wrapping_add(2), "stale")),
+                reliable(3, response(request.request_id, "answer")),
+            ] {
+                server_socket.send_to(&datagram, client).unwrap();
+            }
+            // Every envelope is acknowledged, the one with the answer included
+            let mut acked = Vec::new();
+            while acked.len() < 3 {
+                let (n, _) = server_socket.recv_from(&mut buf).unwrap();
+                acked.push(AckMessage::deserialize(&buf[..n]).unwrap().msg_id);
+            }
+            acked.sort();
+            assert_eq!(acked, [1, 2, 3]);
+        });
+
+        let conn = connection(server_addr, None, &dir);
+        let response = conn.request(ApiRequestKind::Info).unwrap();
+        assert_eq!(error_text(response), "answer");
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -2,13 +2,13 @@ use crate::action::conn::Connection;
 use crate::error::ClientError;
 use crate::extract_response;
 use crate::format::table::{Schema, TableColumn, TableEntry, TableFormatter, format_table};
-use crate::format::util;
+use crate::format::{util, xterm_color};
 use api_model::protocol::message::api_request_message::ApiRequestKind;
 use api_model::protocol::message::api_response_message::ApiResponseKind;
 use api_model::protocol::models::peer::list_peers::{ListPeersRequest, Peer};
 use cli_handler::cli_impl;
 
-static FULL_PEER_TABLE_SCHEMA: [&'static TableColumn; 5] = [
+static FULL_PEER_TABLE_SCHEMA: [&'static TableColumn; 9] = [
     &TableColumn { idx: 0, name: "Id" },
     &TableColumn {
         idx: 1,
@@ -26,16 +26,32 @@ static FULL_PEER_TABLE_SCHEMA: [&'static TableColumn; 5] = [
         idx: 0,
         name: "Last seen",
     },
+    &TableColumn {
+        idx: 0,
+        name: "Subscription",
+    },
+    &TableColumn {
+        idx: 0,
+        name: "Protocol",
+    },
+    &TableColumn {
+        idx: 0,
+        name: "Clock",
+    },
+    &TableColumn {
+        idx: 0,
+        name: "Via",
+    },
 ];
 
 pub struct FullPeerTable;
-impl Schema<5> for FullPeerTable {
-    fn names() -> [&'static TableColumn; 5] {
+impl Schema<9> for FullPeerTable {
+    fn names() -> [&'static TableColumn; 9] {
         FULL_PEER_TABLE_SCHEMA
     }
 }
 
-impl TableEntry<5, FullPeerTable> for Peer {
+impl TableEntry<9, FullPeerTable> for Peer {
     fn fmt(&self) -> std::collections::HashMap<usize, String> {
         let mut row = std::collections::HashMap::new();
 
@@ -44,10 +60,59 @@ impl TableEntry<5, FullPeerTable> for Peer {
         row.insert(2, self.peer_addr.clone());
         row.insert(3, self.is_main.to_string());
         row.insert(4, util::system_time_to_human_readable(self.last_seen));
+        row.insert(5, format_subscription(self));
+        row.insert(6, format_protocol(self));
+        row.insert(7, format_clock_offset(self));
+        row.insert(8, format_hops(self));
         row
     }
 }
 
+/// e.g. "artifacts, -artifacts/tmp", or "all" for the whole share
+fn format_subscription(peer: &Peer) -> String {
+    let mut subtrees: Vec<String> = if peer.subscribed.is_empty() {
+        vec![String::from("all")]
+    } else {
+        peer.subscribed.clone()
+    };
+    subtrees.extend(peer.unsubscribed.iter().map(|s| format!("-{}", s)));
+    subtrees.join(", ")
+}
+
+/// e.g. "v1 (bulk-archive, push-offer)"
+fn format_protocol(peer: &Peer) -> String {
+    if peer.capabilities.is_empty() {
+        format!("v{}", peer.protocol_version)
+    } else {
+        format!(
+            "v{} ({})",
+            peer.protocol_version,
+            peer.capabilities.join(", ")
+        )
+    }
+}
+
+/// Offsets past this are shown, below it the clocks are considered in sync
+const LARGE_CLOCK_SKEW_MS: i64 = 2_000;
+
+/// e.g. "in sync", or "+15.2s" highlighted when the peer clock is 15.2s ahead
+fn format_clock_offset(peer: &Peer) -> String {
+    match peer.clock_offset_ms {
+        None => String::from("unknown"),
+        Some(ms) if ms.abs() < LARGE_CLOCK_SKEW_MS => String::from("in sync"),
+        Some(ms) => xterm_color::bold_yellow(&format!("{:+.1}s", ms as f64 / 1000.0)),
+    }
+}
+
+/// "direct", or the number of gossip relays the peer was learned through, e.g. "gossip (2 hops)"
+fn format_hops(peer: &Peer) -> String {
+    match peer.hops {
+        0 => String::from("direct"),
+        1 => String::from("gossip (1 hop)"),
+        n => format!("gossip ({} hops)", n),
+    }
+}
+
 #[cli_impl]
 pub fn list_peers() -> Result<(), ClientError> {
     let conn = Connection::new(None)?;
@@ -56,7 +121,7 @@ pub fn list_peers() -> Result<(), ClientError> {
         conn.request(ApiRequestKind::ListPeers(ListPeersRequest))?,
         ApiResponseKind::ListPeers
     )?;
-    let table_fmt = TableFormatter::<5, FullPeerTable>::new();
+    let table_fmt = TableFormatter::<9, FullPeerTable>::new();
     let formatted_table = format_table(&table_fmt, &res.peers);
     println!("{}", formatted_table);
 
@@ -1,6 +1,11 @@
-mod conn;
+pub(crate) mod add_peer;
+pub(crate) mod conn;
 pub(crate) mod list_local_files;
 pub(crate) mod list_peers;
 pub(crate) mod list_tasks;
 pub(crate) mod local_pull_file;
+pub(crate) mod peer_keys;
 pub(crate) mod pull_file;
+pub(crate) mod push_file;
+pub(crate) mod rotate_token;
+pub(crate) mod server_config;
@@ -0,0 +1,104 @@
+use crate::action::conn::Connection;
+use crate::error::ClientError;
+use crate::extract_response;
+use crate::format::table::{Schema, TableColumn, TableEntry, TableFormatter, format_table};
+use crate::format::util;
+use api_model::protocol::message::api_request_message::ApiRequestKind;
+use api_model::protocol::message::api_response_message::ApiResponseKind;
+use api_model::protocol::models::peer::peer_keys::{
+    ForgetPeerRequest, ListPeerKeysRequest, PeerKey, TrustPeerRequest,
+};
+use cli_handler::cli_impl;
+
+static PEER_KEY_TABLE_SCHEMA: [&TableColumn; 4] = [
+    &TableColumn {
+        idx: 0,
+        name: "Name",
+    },
+    &TableColumn {
+        idx: 0,
+        name: "Fingerprint",
+    },
+    &TableColumn {
+        idx: 0,
+        name: "Pinned",
+    },
+    &TableColumn {
+        idx: 0,
+        name: "Status",
+    },
+];
+
+pub struct PeerKeyTable;
+impl Schema<4> for PeerKeyTable {
+    fn names() -> [&'static TableColumn; 4] {
+        PEER_KEY_TABLE_SCHEMA
+    }
+}
+
+impl TableEntry<4, PeerKeyTable> for PeerKey {
+    fn fmt(&self) -> std::collections::HashMap<usize, String> {
+        let mut row = std::collections::HashMap::new();
+
+        row.insert(0, self.peer_name.clone());
+        row.insert(1, self.fingerprint.clone());
+        row.insert(2, util::system_time_to_human_readable(self.pinned_at));
+        row.insert(
+            3,
+            match &self.refused_fingerprint {
+                Some(fingerprint) => format!("refused {}", fingerprint),
+                None => String::from("ok"),
+            },
+        );
+        row
+    }
+}
+
+#[cli_impl]
+pub fn list_peer_keys() -> Result<(), ClientError> {
+    let conn = Connection::new(None)?;
+
+    let res = extract_response!(
+        conn.request(ApiRequestKind::ListPeerKeys(ListPeerKeysRequest))?,
+        ApiResponseKind::ListPeerKeys
+    )?;
+    let table_fmt = TableFormatter::<4, PeerKeyTable>::new();
+    let formatted_table = format_table(&table_fmt, &res.keys);
+    println!("{}", formatted_table);
+
+    Ok(())
+}
+
+#[cli_impl]
+pub fn trust_peer(fingerprint: String) -> Result<(), ClientError> {
+    let conn = Connection::new(None)?;
+
+    let res = extract_response!(
+        conn.request(ApiRequestKind::TrustPeer(TrustPeerRequest { fingerprint }))?,
+        ApiResponseKind::TrustPeer
+    )?;
+    println!(
+        "Pinned key {} for peer {}",
+        res.key.fingerprint, res.key.peer_name
+    );
+
+    Ok(())
+}
+
+#[cli_impl]
+pub fn forget_peer(fingerprint: String) -> Result<(), ClientError> {
+    let conn = Connection::new(None)?;
+
+    let res = extract_response!(
+        conn.request(ApiRequestKind::ForgetPeer(ForgetPeerRequest {
+            fingerprint
+        }))?,
+        ApiResponseKind::ForgetPeer
+    )?;
+    println!(
+        "Forgot key {} of peer {}",
+        res.key.fingerprint, res.key.peer_name
+    );
+
+    Ok(())
+}
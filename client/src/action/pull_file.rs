@@ -4,6 +4,8 @@ use crate::extract_response;
 use api_model::protocol::message::api_request_message::ApiRequestKind;
 use api_model::protocol::message::api_response_message::ApiResponseKind;
 use api_model::protocol::models::file::pull_file::PullFileRequest;
+use api_model::protocol::models::task::list_tasks::ListTasksRequest;
+use api_model::protocol::models::task::task::JobStatus;
 use cli_handler::cli_impl;
 
 type Checksum = u64;
@@ -13,17 +15,61 @@ pub fn pull_file(
     peer_identifier: String,
     file_path: String,
     expected_checksum: Option<Checksum>,
+    recursive: bool,
+    bulk: bool,
 ) -> Result<(), ClientError> {
     let conn = Connection::new(None)?;
 
-    extract_response!(
+    let res = extract_response!(
         conn.request(ApiRequestKind::PullFile(PullFileRequest {
             peer_identifier,
             path: file_path.to_string(),
             expected_checksum,
+            recursive,
+            bulk,
         }))?,
         ApiResponseKind::PullFile
     )?;
 
+    if let Some(job_id) = res.job_id {
+        follow_directory_pull(&conn, job_id)?;
+    }
+
     Ok(())
 }
+
+/// Poll the job of a recursive pull, and print its progress until it terminates.
+fn follow_directory_pull(conn: &Connection, job_id: u64) -> Result<(), ClientError> {
+    let mut last_message: Option<String> = None;
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+
+        let res = extract_response!(
+            conn.request(ApiRequestKind::ListTasks(ListTasksRequest))?,
+            ApiResponseKind::ListTasks
+        )?;
+        let task = res
+            .tasks
+            .into_iter()
+            .find(|t| t.job_id == job_id)
+            .ok_or_else(|| ClientError::ResponseError(format!("Task {:016x} not found", job_id)))?;
+
+        if task.status_message != last_message {
+            if let Some(msg) = &task.status_message {
+                println!("{}", msg);
+            }
+            last_message = task.status_message;
+        }
+
+        match task.status {
+            JobStatus::Pending | JobStatus::Running => continue,
+            JobStatus::Completed => return Ok(()),
+            status => {
+                return Err(ClientError::ResponseError(format!(
+                    "Directory pull ended with status {:?}",
+                    status
+                )));
+            }
+        }
+    }
+}
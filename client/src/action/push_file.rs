@@ -0,0 +1,22 @@
+use crate::action::conn::Connection;
+use crate::error::ClientError;
+use crate::extract_response;
+use api_model::protocol::message::api_request_message::ApiRequestKind;
+use api_model::protocol::message::api_response_message::ApiResponseKind;
+use api_model::protocol::models::file::push_file::PushFileRequest;
+use cli_handler::cli_impl;
+
+#[cli_impl]
+pub fn push_file(peer_identifier: String, file_path: String) -> Result<(), ClientError> {
+    let conn = Connection::new(None)?;
+
+    extract_response!(
+        conn.request(ApiRequestKind::PushFile(PushFileRequest {
+            peer_identifier,
+            path: file_path.to_string(),
+        }))?,
+        ApiResponseKind::PushFile
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use crate::action::conn::Connection;
+use crate::error::ClientError;
+use crate::extract_response;
+use crate::format::util;
+use api_model::protocol::message::api_request_message::ApiRequestKind;
+use api_model::protocol::message::api_response_message::ApiResponseKind;
+use api_model::protocol::models::group::rotate_token::RotateTokenRequest;
+use cli_handler::cli_impl;
+
+#[cli_impl]
+pub fn rotate_token(new_token: String, overlap_secs: u64) -> Result<(), ClientError> {
+    let conn = Connection::new(None)?;
+
+    let res = extract_response!(
+        conn.request(ApiRequestKind::RotateToken(RotateTokenRequest {
+            new_token,
+            overlap_secs,
+        }))?,
+        ApiResponseKind::RotateToken
+    )?;
+    println!(
+        "Connection token rotated, the previous one is accepted until {}",
+        util::system_time_to_human_readable(res.previous_accepted_until)
+    );
+    println!("Rotate the other nodes before then, the config file already holds the new token");
+
+    Ok(())
+}
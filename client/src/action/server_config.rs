@@ -0,0 +1,44 @@
+use crate::error::ClientError;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// The part of the server configuration telling where the server keeps its state
+#[derive(Deserialize)]
+struct ServerConfig {
+    app_config: AppConfig,
+}
+
+#[derive(Deserialize)]
+struct AppConfig {
+    working_dir: String,
+}
+
+/// Working directory of the server configured by the file at `path`.
+/// A relative working directory is taken from the directory of the configuration file.
+pub fn load_working_dir(path: &Path) -> Result<PathBuf, ClientError> {
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        ClientError::ConfigError(
+            format!("cannot read the server config at {}", path.display()),
+            e.to_string(),
+        )
+    })?;
+    let config: ServerConfig = toml::from_str(&content).map_err(|e| {
+        ClientError::ConfigError(
+            format!("{} is not a server config", path.display()),
+            e.to_string(),
+        )
+    })?;
+    let working_dir = expand_tilde(&config.app_config.working_dir);
+    let working_dir = path.parent().unwrap_or(Path::new("")).join(working_dir);
+    Ok(std::fs::canonicalize(&working_dir).unwrap_or(working_dir))
+}
+
+/// Expand a leading `~` to the home directory, as the server does
+fn expand_tilde(path: &str) -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match (path.strip_prefix('~'), home) {
+        (Some(""), Some(home)) => home,
+        (Some(rest), Some(home)) if rest.starts_with('/') => home.join(&rest[1..]),
+        _ => PathBuf::from(path),
+    }
+}
@@ -12,6 +12,21 @@ pub enum FileCommands {
 
         #[arg(short = 'c', long = "checksum")]
         expected_checksum: Option<u64>,
+
+        /// Pull every file under the directory
+        #[arg(short = 'r', long = "recursive")]
+        recursive: bool,
+
+        /// Transfer the directory as a single archive, suited to many small files
+        #[arg(short = 'b', long = "bulk", requires = "recursive")]
+        bulk: bool,
+    },
+    Push {
+        #[arg(short = 'p', long = "peer")]
+        peer_identifier: String,
+
+        #[arg(short = 'f', long = "file")]
+        file_path: String,
     },
     ListLocal,
 }
@@ -22,11 +37,19 @@ pub fn handle_file_commands(cmd: &FileCommands) {
             peer_identifier,
             file_path,
             expected_checksum,
+            recursive,
+            bulk,
         } => action::pull_file::pull_file(
             peer_identifier.clone(),
             file_path.clone(),
             expected_checksum.clone(),
+            *recursive,
+            *bulk,
         ),
+        FileCommands::Push {
+            peer_identifier,
+            file_path,
+        } => action::push_file::push_file(peer_identifier.clone(), file_path.clone()),
         FileCommands::ListLocal => action::list_local_files::list_local_files(),
     }
 }
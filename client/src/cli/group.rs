@@ -0,0 +1,23 @@
+use crate::action;
+use clap::Subcommand;
+
+#[derive(Debug, Subcommand)]
+pub enum GroupCommands {
+    /// Switch to a new connection token, accepting the previous one for a while
+    RotateToken {
+        /// New connection token, 1-64 alphanumeric characters
+        new_token: String,
+        /// Seconds during which the previous token is still accepted, a week at most
+        #[arg(long, default_value_t = 600)]
+        overlap_secs: u64,
+    },
+}
+
+pub fn handle_group_commands(cmd: &GroupCommands) {
+    match cmd {
+        GroupCommands::RotateToken {
+            new_token,
+            overlap_secs,
+        } => action::rotate_token::rotate_token(new_token.clone(), *overlap_secs),
+    }
+}
@@ -5,6 +5,23 @@ use clap::Subcommand;
 pub enum PeerCommands {
     /// List known peers
     List,
+    /// List the pinned identity keys of peers
+    Keys,
+    /// Accept the new identity key of a peer whose key changed
+    Trust {
+        /// Fingerprint of the refused key, as listed by `peer keys`
+        fingerprint: String,
+    },
+    /// Drop a pinned identity key, the next key announced with its name is pinned
+    Forget {
+        /// Fingerprint of the pinned key, as listed by `peer keys`
+        fingerprint: String,
+    },
+    /// Send hellos directly to a peer that broadcast does not reach
+    Add {
+        /// host:port of the peer, or host when it listens on the same port as the server
+        addr: String,
+    },
 }
 
 pub fn handle_peer_commands(peer_cmd: &PeerCommands) {
@@ -12,5 +29,9 @@ pub fn handle_peer_commands(peer_cmd: &PeerCommands) {
         PeerCommands::List => {
             action::list_peers::list_peers();
         }
+        PeerCommands::Keys => action::peer_keys::list_peer_keys(),
+        PeerCommands::Trust { fingerprint } => action::peer_keys::trust_peer(fingerprint.clone()),
+        PeerCommands::Forget { fingerprint } => action::peer_keys::forget_peer(fingerprint.clone()),
+        PeerCommands::Add { addr } => action::add_peer::add_peer(addr.clone()),
     }
 }
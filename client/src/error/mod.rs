@@ -2,6 +2,8 @@ use std::fmt::{Debug, Display};
 
 pub enum ClientError {
     ResponseError(String),
+    /// The server did not take the request as coming from its owner
+    AuthenticationError(String),
 
     ConnectionBindError(String, String),
     ConnectionTimeoutError(String, String),
@@ -10,6 +12,9 @@ pub enum ClientError {
     ResponseParseError(String, String),
 
     InternalError(String, String),
+
+    /// The server configuration, which tells where to reach the server, could not be read
+    ConfigError(String, String),
 }
 
 impl Display for ClientError {
@@ -24,7 +29,9 @@ impl Display for ClientError {
             }
             ClientError::ResponseParseError(msg, _) => write!(f, "Response parse error: {}", msg),
             ClientError::InternalError(msg, _) => write!(f, "Internal error: {}", msg),
+            ClientError::ConfigError(msg, _) => write!(f, "Config error: {}", msg),
             ClientError::ResponseError(msg) => write!(f, "client error: {}", msg),
+            ClientError::AuthenticationError(msg) => write!(f, "Authentication error: {}", msg),
             _ => write!(f, "Unknown error"),
         }
     }
@@ -48,7 +55,11 @@ impl Debug for ClientError {
             ClientError::InternalError(msg, trace) => {
                 write!(f, "Internal error: {}\nTrace: {}", msg, trace)
             }
+            ClientError::ConfigError(msg, trace) => {
+                write!(f, "Config error: {}\nTrace: {}", msg, trace)
+            }
             ClientError::ResponseError(msg) => write!(f, "Client error: {}", msg),
+            ClientError::AuthenticationError(msg) => write!(f, "Authentication error: {}", msg),
             _ => write!(f, "Unknown error"),
         }
     }
@@ -4,10 +4,14 @@ mod error;
 mod format;
 
 use crate::cli::file::FileCommands;
+use crate::cli::group::GroupCommands;
 use crate::cli::local_file::LocalFileCommands;
 use crate::cli::peer::PeerCommands;
 use crate::cli::task::TaskCommands;
+use crate::error::ClientError;
 use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(
@@ -17,6 +21,24 @@ use clap::{Parser, Subcommand};
     propagate_version = true
 )]
 pub struct Cli {
+    /// Address of the server to talk to, for servers configured with another port or bind address
+    #[arg(long, global = true, default_value_t = action::conn::DEFAULT_SERVER_ADDR)]
+    server: SocketAddr,
+
+    /// Config file of the server, its API socket and secret are found under its working directory
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Working directory of the server, instead of the one in `--config`
+    #[arg(long, global = true)]
+    working_dir: Option<PathBuf>,
+
+    /// Unix socket of the server API, `.disc/api.sock` under the working directory of the server
+    /// by default. The server is reached over UDP at `--server` when nothing listens on it,
+    /// requests are authenticated with `.disc/api_secret` under the working directory either way
+    #[arg(long, global = true)]
+    socket: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -46,14 +68,50 @@ pub enum Commands {
         #[command(subcommand)]
         command: FileCommands,
     },
+    #[command(name = "group", about = "Group related commands")]
+    Group {
+        #[command(subcommand)]
+        command: GroupCommands,
+    },
+}
+
+/// Working directory of the server given on the command line, or the one in `--config`
+fn working_dir(cli: &Cli) -> Result<Option<PathBuf>, ClientError> {
+    if let Some(dir) = &cli.working_dir {
+        return Ok(Some(dir.clone()));
+    }
+    cli.config
+        .as_deref()
+        .map(action::server_config::load_working_dir)
+        .transpose()
 }
 
 fn main() {
     let cli = Cli::parse();
+    action::conn::set_server_addr(cli.server);
+    let working_dir = match working_dir(&cli) {
+        Ok(working_dir) => working_dir,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let socket_path = cli.socket.clone().or_else(|| {
+        working_dir
+            .as_ref()
+            .map(|dir| dir.join(action::conn::API_SOCKET_FILE))
+    });
+    if let Some(path) = socket_path {
+        action::conn::set_socket_path(path);
+    }
+    if let Some(dir) = working_dir {
+        action::conn::set_working_dir(dir);
+    }
     match &cli.command {
         Commands::Peer { command } => cli::peer::handle_peer_commands(command),
         Commands::Task { command } => cli::task::handle_task_commands(command),
         Commands::LocalFile { command } => cli::local_file::handle_local_file_commands(command),
         Commands::File { command } => cli::file::handle_file_commands(command),
+        Commands::Group { command } => cli::group::handle_group_commands(command),
     }
 }
@@ -4,7 +4,7 @@ use server::global_var::{ENV_VAR, LOGGER_CELL};
 use server::utilities::temp_dir::TmpDirGuard;
 use std::path::PathBuf;
 
-// We benchmark the in-memory AES encrypt/decrypt functions.
+// We benchmark the in-memory AES-GCM encrypt/decrypt functions.
 // They depend on ENV_VAR for key derivation; initialize it once.
 fn ensure_env() -> TmpDirGuard {
     use server::config::{Config, EnvVar};
@@ -29,10 +29,12 @@ fn ensure_env() -> TmpDirGuard {
 
 fn bench_encrypt_decrypt(c: &mut Criterion) {
     let _guard = ensure_env();
-    use server::utilities::crypto::{decrypt, encrypt};
+    use server::utilities::crypto::{KeyPurpose, NONCE_LEN, associated_data, decrypt, encrypt};
 
     let sizes = [1024usize, 1024 * 1024]; // 1 KiB, 1 MiB
-    let iv = [0xABu8; 16];
+    // A fixed nonce is fine here since nothing leaves the benchmark
+    let nonce = [0xABu8; NONCE_LEN];
+    let aad = associated_data("BENCH", "127.0.0.1");
 
     for &sz in &sizes {
         let label_enc = format!(
@@ -50,16 +52,29 @@ fn bench_encrypt_decrypt(c: &mut Criterion) {
 
         c.bench_function(&label_enc, |b| {
             b.iter(|| {
-                let ct = encrypt(black_box(data_bytes.clone()), &iv).expect("encrypt ok");
+                let ct = encrypt(
+                    KeyPurpose::Control,
+                    black_box(data_bytes.clone()),
+                    &nonce,
+                    &aad,
+                )
+                .expect("encrypt ok");
                 black_box(ct)
             })
         });
 
         // Pre-compute ciphertext for decrypt benchmark
-        let ciphertext = encrypt(data_bytes.clone(), &iv).expect("encrypt ok");
+        let ciphertext =
+            encrypt(KeyPurpose::Control, data_bytes.clone(), &nonce, &aad).expect("encrypt ok");
         c.bench_function(&label_dec, |b| {
             b.iter(|| {
-                let pt = decrypt(black_box(ciphertext.clone()), &iv).expect("decrypt ok");
+                let pt = decrypt(
+                    KeyPurpose::Control,
+                    black_box(ciphertext.clone()),
+                    &nonce,
+                    &aad,
+                )
+                .expect("decrypt ok");
                 black_box(pt)
             })
         });
@@ -80,7 +95,8 @@ fn format_sz(sz: usize) -> String {
 
 fn bench_encrypt_decrypt_file(c: &mut Criterion) {
     let _guard = ensure_env();
-    use server::utilities::crypto::{f_from_encryption, f_to_encryption};
+    use server::utilities::crypto::f_to_encryption;
+    let recipient = age::x25519::Identity::generate().to_public();
 
     let sizes = [
         1024usize,
@@ -109,7 +125,7 @@ fn bench_encrypt_decrypt_file(c: &mut Criterion) {
                     let _ = std::fs::remove_file(&tmp_t);
                 },
                 |_data| {
-                    let r = tokio_test::block_on(f_to_encryption(&tmp_f, &tmp_t, "benchmark"));
+                    let r = tokio_test::block_on(f_to_encryption(&tmp_f, &tmp_t, &recipient));
                     black_box(r).expect("Encryption failed");
                 },
                 BatchSize::PerIteration,
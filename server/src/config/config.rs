@@ -1,3 +1,4 @@
+use crate::constants::{TCP_FILE_PORT, UPD_MESSAGE_PORT};
 use crate::err::Result;
 use crate::fs::util::expand_tilde;
 use regex::Regex;
@@ -5,6 +6,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap as Map;
 use std::fs;
 use std::io::{IsTerminal, Write};
+use std::net::IpAddr;
 use std::path::Path;
 use structopt::lazy_static::lazy_static;
 
@@ -19,11 +21,96 @@ pub struct Identity {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Connection {
     pub conn_token: String,
+
+    /// UDP port the control messages and the API are served on
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// TCP port the file transfers are served on
+    #[serde(default = "default_file_port")]
+    pub file_port: u16,
+    /// Address to listen on and to announce to peers, all addresses when unset.
+    /// Several nodes can share a host by binding distinct addresses, e.g. 127.0.0.2 and 127.0.0.3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bind_addr: Option<IpAddr>,
+    /// Network interface the announced address is picked from, any private one when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// Peers hellos are sent to directly, for networks where broadcast does not reach them.
+    /// Entries are `host:port`, or just `host` for peers listening on `port`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub static_peers: Vec<String>,
+}
+
+fn default_port() -> u16 {
+    UPD_MESSAGE_PORT
+}
+
+fn default_file_port() -> u16 {
+    TCP_FILE_PORT
+}
+
+/// Decides whether files offered by a peer through a push are accepted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PushPolicy {
+    /// Accept pushes only from peers currently present in the peer table, whose key signed the
+    /// offer. Configs written for the former `accept_all` get it, as unsigned offers are refused
+    /// whatever the policy.
+    #[default]
+    #[serde(alias = "accept_all")]
+    KnownPeers,
+    /// Never accept pushed files.
+    RejectAll,
+}
+
+/// Subtrees of the share a node keeps in sync, relative to the working directory.
+/// Automatic transfers skip the paths a node is not subscribed to, as do the files listed for a
+/// directory pull; explicit pulls of a file are always allowed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct SelectiveSync {
+    /// Subscribed subtrees. The whole share is subscribed when empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Subtrees left out of the subscribed ones.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl SelectiveSync {
+    /// True when the node is subscribed to the whole share.
+    pub fn is_everything(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// The most specific subtree containing `path` decides whether it is subscribed.
+    pub fn is_subscribed<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = path.as_ref();
+        let deepest_match = |subtrees: &[String]| {
+            subtrees
+                .iter()
+                .map(Path::new)
+                .filter(|subtree| path.starts_with(subtree))
+                .map(|subtree| subtree.components().count())
+                .max()
+        };
+        match (deepest_match(&self.include), deepest_match(&self.exclude)) {
+            (Some(included), Some(excluded)) => included > excluded,
+            (None, Some(_)) => false,
+            (Some(_), None) => true,
+            (None, None) => self.include.is_empty(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AppConfig {
     pub working_dir: String,
+
+    #[serde(default)]
+    pub push_policy: PushPolicy,
+
+    #[serde(default)]
+    pub selective_sync: SelectiveSync,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -43,9 +130,16 @@ impl Config {
             },
             connection: Connection {
                 conn_token: String::from(""),
+                port: default_port(),
+                file_port: default_file_port(),
+                bind_addr: None,
+                interface: None,
+                static_peers: Vec::new(),
             },
             app_config: AppConfig {
                 working_dir: String::from(""),
+                push_policy: PushPolicy::default(),
+                selective_sync: SelectiveSync::default(),
             },
         }
     }
@@ -65,6 +159,8 @@ impl Config {
         }
     }
 
+    /// Write the config to `config_path`, readable by its owner only as it holds the connection
+    /// token. The file is written aside then renamed, a crash never leaves a truncated file behind
     pub fn dump(&self, config_path: &str) -> Result<()> {
         let path = Path::new(config_path);
         if let Some(parent) = path.parent() {
@@ -72,9 +168,22 @@ impl Config {
                 fs::create_dir_all(parent)?;
             }
         }
-        let p = fs::File::create(path)?;
-        let mut f_writer = std::io::BufWriter::new(p);
+        let tmp_path = path.with_extension("tmp");
+        let _ = fs::remove_file(&tmp_path);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut f_writer = std::io::BufWriter::new(options.open(&tmp_path)?);
         f_writer.write_all(toml::to_string(&self)?.as_bytes())?;
+        f_writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, path)?;
         Ok(())
     }
 }
@@ -205,7 +314,8 @@ fn read_input(required_input: &ConfigInput) -> Result<(String, String)> {
     }
 }
 
-pub fn interactive_config_setup(default_config_path: &str) -> Result<Config> {
+/// Returns the config along with the path it was saved to
+pub fn interactive_config_setup(default_config_path: &str) -> Result<(Config, String)> {
     let mut config = Config::new();
 
     let mut input_map = Map::<String, String>::new();
@@ -239,12 +349,13 @@ pub fn interactive_config_setup(default_config_path: &str) -> Result<Config> {
     // Persist the configuration as TOML. Parent directories are created in dump().
     config.dump(&save_path)?;
 
-    Ok(config)
+    Ok((config, save_path))
 }
 
-pub fn get_or_create_config(config_path: Option<&str>) -> Result<Config> {
+/// Returns the config along with the path of its file
+pub fn get_or_create_config(config_path: Option<&str>) -> Result<(Config, String)> {
     match Config::from_config(config_path) {
-        Ok(config) => Ok(config),
+        Ok(config) => Ok((config, expand_tilde(config_path.unwrap()))),
         Err(_e) => {
             if !std::io::stdin().is_terminal() {
                 return Err("No configuration file found and stdin is not a TTY; run in a terminal to create a config or provide --config pointing to a valid file.".into());
@@ -273,6 +384,46 @@ mod tests {
         p
     }
 
+    #[test]
+    fn selective_sync_most_specific_subtree_wins() {
+        let everything = SelectiveSync::default();
+        assert!(everything.is_subscribed("any/file.txt"));
+
+        let sync = SelectiveSync {
+            include: vec!["artifacts/".into(), "docs/public".into()],
+            exclude: vec!["artifacts/tmp".into(), "docs".into()],
+        };
+        assert!(sync.is_subscribed("artifacts/build.zip"));
+        assert!(!sync.is_subscribed("artifacts/tmp/build.zip"));
+        assert!(sync.is_subscribed("docs/public/readme.md"));
+        assert!(!sync.is_subscribed("docs/private/notes.md"));
+        assert!(!sync.is_subscribed("src/main.rs"));
+        // Subtrees match whole path components only
+        assert!(!sync.is_subscribed("artifacts-old/build.zip"));
+
+        let excluded_only = SelectiveSync {
+            include: vec![],
+            exclude: vec!["videos".into()],
+        };
+        assert!(excluded_only.is_subscribed("photos/a.jpg"));
+        assert!(!excluded_only.is_subscribed("videos/a.mp4"));
+    }
+
+    #[test]
+    fn push_policy_accept_all_is_read_as_known_peers() {
+        let policy = |value: &str| {
+            toml::from_str::<AppConfig>(&format!(
+                "working_dir = \"/tmp\"\npush_policy = \"{}\"",
+                value
+            ))
+            .map(|cfg| cfg.push_policy)
+        };
+        assert_eq!(policy("known_peers").unwrap(), PushPolicy::KnownPeers);
+        assert_eq!(policy("accept_all").unwrap(), PushPolicy::KnownPeers);
+        assert_eq!(policy("reject_all").unwrap(), PushPolicy::RejectAll);
+        assert!(policy("anyone").is_err());
+    }
+
     #[test]
     fn rust_version_regex() {
         let ci = ConfigInput {
@@ -354,6 +505,13 @@ mod tests {
         cfg.dump(path.to_str().unwrap())
             .expect("dump should succeed");
         assert!(path.exists());
+        assert!(!path.with_extension("tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
 
         // Read back and validate some fields
         let mut s = String::new();
@@ -365,6 +523,47 @@ mod tests {
         assert_eq!(loaded.identity.machine_name, "m1");
     }
 
+    #[test]
+    fn connection_settings_default_to_the_reserved_ports() {
+        let legacy = r#"
+            [identity]
+            machine_name = "m1"
+            private_key_loc = "/k/priv"
+            public_key_loc = "/k/pub"
+
+            [connection]
+            conn_token = "TOKEN"
+
+            [app_config]
+            working_dir = "/tmp"
+        "#;
+        let cfg: Config = toml::from_str(legacy).unwrap();
+        assert_eq!(cfg.connection.port, UPD_MESSAGE_PORT);
+        assert_eq!(cfg.connection.file_port, TCP_FILE_PORT);
+        assert!(cfg.connection.bind_addr.is_none());
+        assert!(cfg.connection.interface.is_none());
+        assert!(cfg.connection.static_peers.is_empty());
+
+        let custom = legacy.replace(
+            r#"conn_token = "TOKEN""#,
+            r#"conn_token = "TOKEN"
+            port = 24514
+            file_port = 21451
+            bind_addr = "127.0.0.2"
+            interface = "lo"
+            static_peers = ["10.1.2.3:14514", "nas.lan"]"#,
+        );
+        let cfg: Config = toml::from_str(&custom).unwrap();
+        assert_eq!(cfg.connection.port, 24514);
+        assert_eq!(cfg.connection.file_port, 21451);
+        assert_eq!(
+            cfg.connection.bind_addr,
+            Some(IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 2)))
+        );
+        assert_eq!(cfg.connection.interface.as_deref(), Some("lo"));
+        assert_eq!(cfg.connection.static_peers, ["10.1.2.3:14514", "nas.lan"]);
+    }
+
     #[test]
     #[serial_test::serial]
     fn from_config_expands_tilde_with_home() {
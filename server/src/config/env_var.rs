@@ -1,13 +1,16 @@
-use crate::config::config::Config;
-use crate::constants::{TCP_FILE_PORT, UPD_MESSAGE_PORT};
+use crate::config::config::{Config, PushPolicy, SelectiveSync};
 use crate::err::Result;
 use crate::fs::util::expand_tilde;
-use crate::network::get_private_ipv4_with_mac;
+use crate::network::{LocalAddr, get_interface_addr, get_local_addr, get_private_ip_with_mac};
 use std::net::IpAddr;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
 
+/// Held while the config file is read, updated and written back, so that concurrent updates do
+/// not overwrite each other
+static CONFIG_FILE_LOCK: Mutex<()> = Mutex::new(());
+
 #[derive(Debug)]
 struct KeySpec {
     private_key_location: String,
@@ -17,17 +20,24 @@ struct KeySpec {
 #[derive(Debug)]
 struct Identity {
     machine_name: String,
-    mac_addr: String,
 
     key_spec: KeySpec,
 }
 
 #[derive(Debug)]
 struct ConnectionConfig {
-    conn_token: String,
+    /// Replaced when the group rotates its token
+    conn_token: std::sync::RwLock<String>,
     port: u16,
     file_sync_port: u16,
-    ip_addr: IpAddr,
+    /// Address the listeners are bound to, all addresses when unset
+    bind_addr: Option<IpAddr>,
+    /// Interface the announced address is picked from, any when unset
+    interface: Option<String>,
+    /// Announced address along with its interface, replaced as a whole when the host moves
+    /// networks so that readers never see the address of one interface with the index of another
+    local: std::sync::RwLock<LocalAddr>,
+    static_peers: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -42,6 +52,8 @@ pub struct StaticAppConfig {
     working_dir: String,
 
     pull_task_validity_in_sec: u64,
+    push_policy: PushPolicy,
+    selective_sync: SelectiveSync,
 }
 
 impl AppConfig {
@@ -60,6 +72,8 @@ impl AppConfig {
 
 #[derive(Debug)]
 pub struct EnvVar {
+    /// File the config was read from, runtime changes are saved back to it
+    config_path: Option<String>,
     identity: Identity,
     connection: ConnectionConfig,
     pub(crate) app_config: Arc<RwLock<AppConfig>>,
@@ -77,23 +91,68 @@ impl EnvVar {
             Err(_) => path_buf.to_string_lossy().to_string(),
         }
     }
+    /// Address announced to peers: the bind address when it is a specific one, otherwise the
+    /// best address of the configured interface, otherwise any private address
+    fn pick_local_addr(bind_addr: Option<IpAddr>, interface: Option<&str>) -> Result<LocalAddr> {
+        if let Some(bind_addr) = bind_addr
+            && !bind_addr.is_unspecified()
+        {
+            return get_local_addr(bind_addr).ok_or_else(|| {
+                format!("bind_addr {} is not an address of this host", bind_addr).into()
+            });
+        }
+        if let Some(name) = interface {
+            return get_interface_addr(name)
+                .ok_or_else(|| format!("No address found on interface '{}'", name).into());
+        }
+        get_private_ip_with_mac().ok_or_else(|| {
+            "No private IPv4, IPv6 unique local or link-local address found on any interface".into()
+        })
+    }
+
+    /// Save the runtime changes of the config to `path`
+    pub fn with_config_path(mut self, path: String) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Apply `update` to the config file, the other settings of the file are left as they are
+    fn update_config_file(&self, update: impl FnOnce(&mut Config)) -> Result<()> {
+        let path = self
+            .config_path
+            .as_deref()
+            .ok_or("The server was started without a config file")?;
+        let _guard = CONFIG_FILE_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut config = Config::from_config(Some(path))?;
+        update(&mut config);
+        config.dump(path)
+    }
+
     pub fn from_config(config: &Config) -> Result<Self> {
-        let (ipv4, mac_addr) = get_private_ipv4_with_mac().unwrap();
+        let local = Self::pick_local_addr(
+            config.connection.bind_addr,
+            config.connection.interface.as_deref(),
+        )?;
 
         Ok(Self {
+            config_path: None,
             identity: Identity {
                 machine_name: config.identity.machine_name.clone(),
-                mac_addr: mac_addr.map(|u| format!("{:02x}", u)).join(":"),
                 key_spec: KeySpec {
                     private_key_location: expand_tilde(&config.identity.private_key_loc),
                     public_key_location: expand_tilde(&config.identity.public_key_loc),
                 },
             },
             connection: ConnectionConfig {
-                conn_token: config.connection.conn_token.clone(),
-                port: UPD_MESSAGE_PORT, // reserved port for server to listen on protocol messages
-                file_sync_port: TCP_FILE_PORT, // reserved port for file sync
-                ip_addr: IpAddr::V4(ipv4),
+                conn_token: std::sync::RwLock::new(config.connection.conn_token.clone()),
+                port: config.connection.port,
+                file_sync_port: config.connection.file_port,
+                bind_addr: config.connection.bind_addr,
+                interface: config.connection.interface.clone(),
+                local: std::sync::RwLock::new(local),
+                static_peers: config.connection.static_peers.clone(),
             },
             app_config: Arc::new(RwLock::new(AppConfig {
                 working_dir: Self::normalize_working_dir(&config.app_config.working_dir),
@@ -102,6 +161,8 @@ impl EnvVar {
             static_app_config: StaticAppConfig {
                 working_dir: Self::normalize_working_dir(&config.app_config.working_dir),
                 pull_task_validity_in_sec: 10,
+                push_policy: config.app_config.push_policy,
+                selective_sync: config.app_config.selective_sync.clone(),
             },
         })
     }
@@ -116,20 +177,118 @@ impl EnvVar {
         downloads_dir.to_string_lossy().to_string()
     }
 
-    pub fn get_conn_token(&self) -> &str {
-        &self.connection.conn_token
+    pub fn get_known_peers_path(&self) -> String {
+        let working_dir = PathBuf::from(self.get_working_dir());
+        let known_peers = working_dir.join(".disc").join("known_peers");
+        known_peers.to_string_lossy().to_string()
+    }
+
+    /// Unix socket the local API is served on
+    pub fn get_api_socket_path(&self) -> String {
+        let working_dir = PathBuf::from(self.get_working_dir());
+        let socket = working_dir.join(".disc").join("api.sock");
+        socket.to_string_lossy().to_string()
+    }
+
+    /// Secret the local API requests are tagged with, next to the API socket
+    pub fn get_api_secret_path(&self) -> String {
+        let working_dir = PathBuf::from(self.get_working_dir());
+        let secret = working_dir.join(".disc").join("api_secret");
+        secret.to_string_lossy().to_string()
+    }
+
+    pub fn get_conn_token(&self) -> String {
+        self.connection
+            .conn_token
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Switch to `token` and save it to the config file, so that a restart keeps it
+    pub fn update_conn_token(&self, token: &str) -> Result<()> {
+        self.update_config_file(|config| config.connection.conn_token = token.to_string())?;
+        *self
+            .connection
+            .conn_token
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = token.to_string();
+        Ok(())
+    }
+
+    /// Save a peer added at runtime to the config file, so that it is kept after a restart
+    pub fn save_static_peer(&self, entry: &str) -> Result<()> {
+        self.update_config_file(|config| {
+            if !config.connection.static_peers.iter().any(|e| e == entry) {
+                config.connection.static_peers.push(entry.to_string());
+            }
+        })
     }
 
     pub fn get_port(&self) -> u16 {
         self.connection.port
     }
 
+    pub fn get_file_sync_port(&self) -> u16 {
+        self.connection.file_sync_port
+    }
+
+    pub fn get_bind_addr(&self) -> Option<IpAddr> {
+        self.connection.bind_addr
+    }
+
+    /// Address announced to peers along with its interface, read at once
+    pub fn get_local_addr(&self) -> LocalAddr {
+        *self
+            .connection
+            .local
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
     pub fn get_ip_addr(&self) -> IpAddr {
-        self.connection.ip_addr
+        self.get_local_addr().ip
+    }
+
+    pub fn get_if_index(&self) -> u32 {
+        self.get_local_addr().if_index
+    }
+
+    /// Check the announced address is still assigned to this host, and pick a new one when it
+    /// is not, e.g. after moving networks or a DHCP renewal.
+    /// Returns the previous and the new address when the address changed.
+    pub fn refresh_local_addr(&self) -> Result<Option<(LocalAddr, LocalAddr)>> {
+        let current = self.get_local_addr();
+        let still_assigned = get_local_addr(current.ip).filter(|addr| {
+            self.connection.interface.is_none() || addr.if_index == current.if_index
+        });
+        let new = match still_assigned {
+            // Interfaces may be renumbered when they come back up
+            Some(addr) if addr == current => return Ok(None),
+            Some(addr) => addr,
+            None => Self::pick_local_addr(
+                self.connection.bind_addr,
+                self.connection.interface.as_deref(),
+            )?,
+        };
+        let mut local = self
+            .connection
+            .local
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let old = std::mem::replace(&mut *local, new);
+        Ok(Some((old, new)))
+    }
+
+    pub fn get_static_peers(&self) -> &[String] {
+        &self.connection.static_peers
     }
 
     pub fn get_mac_addr(&self) -> String {
-        self.identity.mac_addr.clone()
+        self.get_local_addr()
+            .mac
+            .map(|u| format!("{:02x}", u))
+            .join(":")
     }
 
     pub fn get_machine_name(&self) -> String {
@@ -147,12 +306,20 @@ impl EnvVar {
     pub fn get_pull_task_validity_in_sec(&self) -> u64 {
         self.static_app_config.pull_task_validity_in_sec
     }
+
+    pub fn get_push_policy(&self) -> PushPolicy {
+        self.static_app_config.push_policy
+    }
+
+    pub fn get_selective_sync(&self) -> &SelectiveSync {
+        &self.static_app_config.selective_sync
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::config::Config;
+    use crate::config::config::{Config, PushPolicy};
     use serial_test::serial;
     use std::env;
 
@@ -192,4 +359,90 @@ mod tests {
                 .starts_with(&expected_home)
         );
     }
+
+    #[tokio::test]
+    async fn envvar_announces_the_configured_bind_addr_and_ports() {
+        let mut cfg = Config::new();
+        cfg.identity.machine_name = "machine".into();
+        cfg.connection.conn_token = "TOKEN123".into();
+        cfg.connection.port = 24514;
+        cfg.connection.file_port = 21451;
+        cfg.connection.bind_addr = Some(IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+
+        let ev = EnvVar::from_config(&cfg).expect("loopback is an address of this host");
+        assert_eq!(ev.get_ip_addr(), IpAddr::V4(std::net::Ipv4Addr::LOCALHOST));
+        assert_eq!(ev.get_bind_addr(), cfg.connection.bind_addr);
+        assert_eq!(ev.get_port(), 24514);
+        assert_eq!(ev.get_file_sync_port(), 21451);
+
+        // 192.0.2.0/24 is reserved for documentation and never assigned
+        cfg.connection.bind_addr = Some("192.0.2.1".parse().unwrap());
+        assert!(EnvVar::from_config(&cfg).is_err());
+    }
+
+    #[tokio::test]
+    async fn envvar_saves_a_new_conn_token_to_the_config_file() {
+        let dir = crate::utilities::temp_dir::tmp_dir("env_config");
+        let path = dir.join("config.toml").to_string_lossy().to_string();
+        let mut cfg = Config::new();
+        cfg.identity.machine_name = "machine".into();
+        cfg.connection.conn_token = "TOKEN123".into();
+        cfg.connection.port = 24514;
+        cfg.dump(&path).unwrap();
+
+        // Without a config file the token cannot be kept, so it is not changed
+        let ev = EnvVar::from_config(&cfg).unwrap();
+        assert!(ev.update_conn_token("TOKEN456").is_err());
+        assert_eq!(ev.get_conn_token(), "TOKEN123");
+
+        let ev = ev.with_config_path(path.clone());
+        ev.update_conn_token("TOKEN456").unwrap();
+        assert_eq!(ev.get_conn_token(), "TOKEN456");
+        let saved = Config::from_config(Some(&path)).unwrap();
+        assert_eq!(saved.connection.conn_token, "TOKEN456");
+        assert_eq!(saved.connection.port, 24514);
+    }
+
+    #[test]
+    fn envvar_saves_static_peers_to_the_config_file() {
+        let dir = crate::utilities::temp_dir::tmp_dir("env_static_peers");
+        let path = dir.join("config.toml").to_string_lossy().to_string();
+        let mut cfg = Config::new();
+        cfg.identity.machine_name = "machine".into();
+        cfg.connection.conn_token = "TOKEN123".into();
+        cfg.connection.static_peers = vec!["nas.lan:14514".into()];
+        cfg.dump(&path).unwrap();
+
+        let ev = EnvVar::from_config(&cfg)
+            .unwrap()
+            .with_config_path(path.clone());
+        ev.save_static_peer("10.1.2.3:14514").unwrap();
+        ev.save_static_peer("10.1.2.3:14514").unwrap();
+        let saved = Config::from_config(Some(&path)).unwrap();
+        assert_eq!(
+            saved.connection.static_peers,
+            ["nas.lan:14514", "10.1.2.3:14514"]
+        );
+    }
+
+    #[tokio::test]
+    async fn envvar_replaces_an_address_no_longer_assigned() {
+        let mut cfg = Config::new();
+        cfg.identity.machine_name = "machine".into();
+        cfg.connection.conn_token = "TOKEN123".into();
+        cfg.connection.interface = Some("lo".into());
+
+        let ev = EnvVar::from_config(&cfg).expect("every host has a loopback interface");
+        let picked = ev.get_local_addr();
+        assert_eq!(ev.refresh_local_addr().unwrap(), None);
+
+        // Pretend the host used to be on a network it has since left
+        let gone = LocalAddr {
+            ip: "192.0.2.1".parse().unwrap(),
+            ..picked
+        };
+        *ev.connection.local.write().unwrap() = gone;
+        assert_eq!(ev.refresh_local_addr().unwrap(), Some((gone, picked)));
+        assert_eq!(ev.get_ip_addr(), picked.ip);
+    }
 }
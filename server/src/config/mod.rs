@@ -1,5 +1,7 @@
 mod config;
 pub use config::Config;
+pub use config::PushPolicy;
+pub use config::SelectiveSync;
 mod env_var;
 pub use config::get_or_create_config;
 pub use env_var::EnvVar;
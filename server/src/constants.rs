@@ -1,3 +1,9 @@
 pub static UPD_MESSAGE_PORT: u16 = 14514;
 pub static TCP_FILE_PORT: u16 = 11451;
 pub static LOCAL_ADDR: &str = "127.0.0.1";
+/// Organization-local multicast group hellos are sent to on IPv4 ("lu")
+pub static DISCOVERY_MULTICAST_V4: std::net::Ipv4Addr =
+    std::net::Ipv4Addr::new(239, 255, 0x6c, 0x75);
+/// Link-local multicast group hellos are sent to on IPv6, which has no broadcast ("lumo")
+pub static DISCOVERY_MULTICAST_V6: std::net::Ipv6Addr =
+    std::net::Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x6c75, 0x6d6f);
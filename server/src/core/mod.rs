@@ -2,5 +2,10 @@ mod protocol;
 pub mod tasks;
 mod topology;
 
+pub use topology::KnownPeer;
 pub use topology::PEER_TABLE;
+pub use topology::Peer;
+pub use topology::STATIC_PEERS;
+pub use topology::get_known_peers;
 pub use topology::init_topology;
+pub use topology::static_peers;
@@ -1,10 +1,11 @@
 use crate::core::protocol::file_sync::{FileSync, FileSyncAck, FileSyncError};
 use crate::err::Result;
+use crate::fs::LumoFile;
 use crate::global_var::{ENV_VAR, LOGGER};
 use crate::lumo_error;
 use crate::network::TcpConn;
 use crate::types::Expected;
-use crate::utilities::crypto::f_from_encryption;
+use crate::utilities::crypto::{f_from_encryption, max_encrypted_size};
 use crate::utilities::format::size_to_human_readable;
 use bytes::Bytes;
 use rand::random;
@@ -46,6 +47,8 @@ impl FileRecvSummary {
 pub struct FileRecvTracker {
     nonce: Nonce,
     expected_checksum: Expected<Checksum>,
+    // Ephemeral key of this transfer, the sender encrypts the file for its public half
+    identity: age::x25519::Identity,
 
     enc_tmp_path: PathBuf,
     target_path: PathBuf,
@@ -63,13 +66,14 @@ impl FileRecvTracker {
         Self {
             nonce,
             expected_checksum: maybe_checksum.into(),
+            identity: age::x25519::Identity::generate(),
             enc_tmp_path,
             target_path,
         }
     }
 
     async fn sync(&self, conn: &mut TcpConn) -> Result<FileSyncAck> {
-        let sync = FileSync::new(self.nonce).to_encryption()?;
+        let sync = FileSync::new(self.nonce, &self.identity.to_public()).to_encryption()?;
         LOGGER.debug(format!("FileSync: {:?}", &sync));
         conn.send_bytes(Bytes::from(sync)).await?;
         let ack =
@@ -83,15 +87,18 @@ impl FileRecvTracker {
         &self,
         conn: TcpConn,
         file: &mut File,
-        total_size: u64,
+        plain_size: u64,
     ) -> Result<u64> {
         LOGGER.info(format!(
             "Starting file receive: nonce={}, size={} bytes -> {}",
             self.nonce,
-            size_to_human_readable(total_size),
+            size_to_human_readable(plain_size),
             self.target_path.display()
         ));
 
+        // The sender encrypts while it sends and closes the connection once done,
+        // the ciphertext is read up to the end of the stream
+        let total_size = max_encrypted_size(plain_size);
         // expected transfer lower bound: 5 MB / s
         // total_size / 1024 / 1024 / 5
         let read_timeout =
@@ -122,6 +129,38 @@ impl FileRecvTracker {
 
         Ok(sz)
     }
+    /// Compare the decrypted file against the checksum announced by the sender, if any
+    async fn verify_checksum(&self) -> std::result::Result<(), FileSyncError> {
+        if self.expected_checksum.is_any() {
+            return Ok(());
+        }
+        let checksum = async {
+            LumoFile::new(self.target_path.clone())
+                .await?
+                .get_checksum()
+                .await
+        }
+        .await
+        .map_err(|e| {
+            LOGGER.error(format!(
+                "Failed to get checksum of received file {}: {:?}",
+                self.target_path.display(),
+                e
+            ));
+            FileSyncError::SystemError
+        })?;
+        if self.expected_checksum.not_match_expected(&checksum) {
+            LOGGER.warn(format!(
+                "Received file {} has checksum {}, expected {:?}",
+                self.target_path.display(),
+                checksum,
+                self.expected_checksum
+            ));
+            return Err(FileSyncError::ChecksumMismatch);
+        }
+        Ok(())
+    }
+
     pub async fn recv(
         &self,
         mut conn: TcpConn,
@@ -161,8 +200,7 @@ impl FileRecvTracker {
         }
 
         let start_decrypt_time = std::time::Instant::now();
-        let passphrase = format!("{}", ack.nonce());
-        f_from_encryption(&self.enc_tmp_path, &self.target_path, &passphrase)
+        f_from_encryption(&self.enc_tmp_path, &self.target_path, &self.identity)
             .await
             .map_err(|e| {
                 LOGGER.error(format!(
@@ -171,6 +209,7 @@ impl FileRecvTracker {
                 ));
                 FileSyncError::FileMalformed
             })?;
+        self.verify_checksum().await?;
 
         let summary = FileRecvSummary::new(
             ack.nonce(),
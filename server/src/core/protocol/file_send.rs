@@ -1,119 +1,163 @@
 use crate::core::protocol::file_sync::{FileSyncAck, FileSyncError};
-use crate::err::Result;
-use crate::fs::{LumoFile, fs_lock};
-use crate::global_var::{ENV_VAR, LOGGER};
+use crate::fs::fs_lock;
+use crate::global_var::LOGGER;
 use crate::network::TcpConn;
-use crate::utilities::crypto::f_to_encryption;
+use crate::utilities::crypto::encrypt_to_writer;
 use bytes::Bytes;
+use std::io::{BufReader, BufWriter, Write};
 use std::path::PathBuf;
-use tokio::io::AsyncReadExt;
-use tokio::time::error::Elapsed;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 
 type Nonce = u64;
-type Checksum = u64;
+
+/// Size of the ciphertext chunks handed over to the socket
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks encrypted ahead of the socket
+const CHUNKS_AHEAD: usize = 16;
 
 pub struct FileSendSummary {
     pub nonce: Nonce,
     pub file_size: u64,
-    pub checksum: Checksum,
     pub elapsed: std::time::Duration,
 }
 
 impl FileSendSummary {
-    fn new(nonce: Nonce, file_size: u64, checksum: Checksum, elapsed: std::time::Duration) -> Self {
+    fn new(nonce: Nonce, file_size: u64, elapsed: std::time::Duration) -> Self {
         Self {
             nonce,
             file_size,
-            checksum,
             elapsed,
         }
     }
 }
 
+/// Hands the ciphertext produced by the blocking encryption over to the socket
+struct ChunkSender(mpsc::Sender<Vec<u8>>);
+
+impl Write for ChunkSender {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The receiving end is gone once the transfer failed
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 struct FileSendTracker {
     nonce: Nonce,
+    recipient: age::x25519::Recipient,
 
     source_path: PathBuf,
 }
 
 impl FileSendTracker {
-    pub fn new(nonce: Nonce, source_path: PathBuf) -> Self {
-        // Build base path: <working_dir>/.disc/tmp_downloads
-        let base = PathBuf::from(ENV_VAR.get().unwrap().get_temp_downloads_dir());
-
-        FileSendTracker { nonce, source_path }
+    pub fn new(nonce: Nonce, source_path: PathBuf, recipient: age::x25519::Recipient) -> Self {
+        FileSendTracker {
+            nonce,
+            recipient,
+            source_path,
+        }
     }
 
+    /// Encrypt the source for the key the downloader sent for this transfer while it is sent,
+    /// and close the connection once done so the downloader knows the ciphertext is complete.
     async fn send_file(
         &self,
         conn: &mut TcpConn,
-        total_size: u64,
+        source: std::fs::File,
+        plain_size: u64,
     ) -> std::result::Result<u64, FileSyncError> {
-        // Acquire a read lock on the encrypted temp file to ensure consistency across processes
-        let guard = fs_lock::RwLock::new(&self.source_path)
-            .read()
-            .await
-            .map_err(|e| {
-                LOGGER.warn(format!("Failed to lock file: {:?}", e));
-                FileSyncError::SystemError
-            })?;
-
         LOGGER.trace(format!(
             "Sending file {} to peer",
             self.source_path.display()
         ));
 
-        // Clone the underlying std::fs::File so we can create an async file while keeping the lock guard alive
-        let std_file = guard.try_clone().map_err(|e| {
-            LOGGER.warn(format!("Failed to clone file: {:?}", e));
-            FileSyncError::SystemError
-        })?;
-        let file = tokio::fs::File::from_std(std_file);
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(CHUNKS_AHEAD);
+        let recipient = self.recipient.clone();
+        let encryption = tokio::task::spawn_blocking(move || {
+            encrypt_to_writer(
+                BufReader::new(source),
+                BufWriter::with_capacity(CHUNK_SIZE, ChunkSender(tx)),
+                &recipient,
+            )
+        });
 
         // Assume a 5 MB/s transfer rate as the low bound
         let write_timeout = conn.get_write_timeout().max(std::time::Duration::from_secs(
-            total_size / (1024 * 1024 * 5) as u64,
+            plain_size / (1024 * 1024 * 5) as u64,
         ));
         let stream = &mut conn.stream;
 
-        // Limit the reader to the expected total size and copy to the peer with a timeout
         LOGGER.debug(format!(
-            "Starting file transfer, total_size: {} bytes, expected time {}",
-            total_size,
+            "Starting file transfer, plain size: {} bytes, expected time {}",
+            plain_size,
             write_timeout.as_secs_f64() * 1000.0
         ));
-        let sz = tokio::time::timeout(
-            write_timeout,
-            tokio::io::copy(&mut file.take(total_size), stream),
-        )
-        .await
-        .map_err(|e| {
-            LOGGER.warn(format!("File transfer timed out {:?}", e));
-            FileSyncError::Timeout
-        })?
-        .map_err(|e| {
-            LOGGER.warn(format!("Failed writing to peer: {:?}", e));
-            FileSyncError::AbortedByPeer
-        })?;
-        Ok(sz)
+        let transfer = async {
+            let mut sz = 0u64;
+            // The channel closes once the encryption is over, whether it succeeded or not
+            while let Some(chunk) = rx.recv().await {
+                stream.write_all(&chunk).await.map_err(|e| {
+                    LOGGER.warn(format!("Failed writing to peer: {:?}", e));
+                    FileSyncError::AbortedByPeer
+                })?;
+                sz += chunk.len() as u64;
+            }
+            encryption
+                .await
+                .map_err(crate::err::Error::from)
+                .and_then(|res| res)
+                .map_err(|e| {
+                    LOGGER.warn(format!(
+                        "Failed to encrypt {} for transfer: {:?}",
+                        self.source_path.display(),
+                        e
+                    ));
+                    FileSyncError::SystemError
+                })?;
+            stream.shutdown().await.map_err(|e| {
+                LOGGER.warn(format!("Failed closing the transfer: {:?}", e));
+                FileSyncError::AbortedByPeer
+            })?;
+            Ok(sz)
+        };
+        tokio::time::timeout(write_timeout, transfer)
+            .await
+            .map_err(|e| {
+                LOGGER.warn(format!("File transfer timed out {:?}", e));
+                FileSyncError::Timeout
+            })?
     }
 
     pub async fn send(
         &self,
         conn: &mut TcpConn,
     ) -> std::result::Result<FileSendSummary, FileSyncError> {
-        let lumo_file = LumoFile::new(self.source_path.clone()).await.map_err(|e| {
-            LOGGER.warn(format!("LumoFile create error: {:?}", e));
-            FileSyncError::SystemError
-        })?;
-
-        let total_size = lumo_file.size;
-        let checksum = lumo_file.get_checksum().await.map_err(|e| {
-            LOGGER.warn(format!("LumoFile checksum error: {:?}", e));
-            FileSyncError::SystemError
-        })?;
+        // Acquire a read lock on the source to ensure consistency across processes
+        let guard = fs_lock::RwLock::new(&self.source_path)
+            .read()
+            .await
+            .map_err(|e| {
+                LOGGER.warn(format!("Failed to lock file: {:?}", e));
+                FileSyncError::SystemError
+            })?;
+        // Clone the underlying std::fs::File for the encryption while keeping the lock guard alive
+        let (source, plain_size) = guard
+            .try_clone()
+            .and_then(|f| Ok((f, guard.metadata()?.len())))
+            .map_err(|e| {
+                LOGGER.warn(format!("Failed to open file: {:?}", e));
+                FileSyncError::SystemError
+            })?;
 
-        let sync_ack = FileSyncAck::new(self.nonce, Some(checksum), total_size);
+        // The ack goes out before any encryption, the downloader only waits for it so long
+        let sync_ack = FileSyncAck::new(self.nonce, plain_size);
         conn.send_bytes(Bytes::from(sync_ack.to_encryption().map_err(|e| {
             LOGGER.warn(format!("Encrypting ack package failed: {:?}", e));
             FileSyncError::SystemError
@@ -134,24 +178,77 @@ impl FileSendTracker {
         })?;
 
         let start_time = std::time::Instant::now();
-        self.send_file(conn, total_size).await.map_err(|e| {
-            LOGGER.warn(format!("File send error {:?}", e));
-            FileSyncError::AbortedByPeer
-        })?;
+        let sent = self
+            .send_file(conn, source, plain_size)
+            .await
+            .inspect_err(|e| LOGGER.warn(format!("File send error {:?}", e)))?;
+        drop(guard);
 
-        let summary = FileSendSummary::new(self.nonce, total_size, checksum, start_time.elapsed());
-        Ok(summary)
+        Ok(FileSendSummary::new(self.nonce, sent, start_time.elapsed()))
     }
 }
 
 /// Public helper to send an encrypted file over an existing TcpConn.
-/// The source file will be encrypted into a temporary cipher file and then streamed.
-/// The temporary cipher file is removed when the tracker is dropped.
+/// The source file is encrypted for `recipient` while it is streamed.
 pub async fn send_file(
     nonce: u64,
     source_path: PathBuf,
+    recipient: age::x25519::Recipient,
     conn: &mut TcpConn,
 ) -> std::result::Result<FileSendSummary, FileSyncError> {
-    let tracker = FileSendTracker::new(nonce, source_path);
+    let tracker = FileSendTracker::new(nonce, source_path, recipient);
     tracker.send(conn).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, EnvVar};
+    use crate::core::protocol::file_recv::FileRecvTracker;
+    use crate::core::protocol::file_sync::FileSync;
+    use crate::global_var::ENV_VAR;
+    use crate::utilities::temp_dir::tmp_dir;
+    use tokio::net::TcpListener;
+
+    fn ensure_env() {
+        if ENV_VAR.get().is_none() {
+            let mut cfg = Config::new();
+            cfg.identity.machine_name = "test-machine".into();
+            cfg.connection.conn_token = "TEST_TOKEN".into();
+            let wd = std::env::temp_dir().join("lumo_file_send_tests");
+            std::fs::create_dir_all(wd.join(".disc").join("tmp_downloads")).unwrap();
+            cfg.app_config.working_dir = wd.to_string_lossy().to_string();
+            let _ = ENV_VAR.set(EnvVar::from_config(&cfg).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn file_is_encrypted_while_it_is_sent() {
+        ensure_env();
+        std::fs::create_dir_all(ENV_VAR.get().unwrap().get_temp_downloads_dir()).unwrap();
+        let dir = tmp_dir("file_send");
+        let source = dir.join("source");
+        // Spans several chunks of the encryption, and ends within one
+        let plain: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &plain).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let sender = tokio::spawn(async move {
+            let (stream, peer) = listener.accept().await.unwrap();
+            let mut conn = TcpConn::new(stream, peer);
+            let sync = conn.read_bytes(2048).await.unwrap().to_vec();
+            let sync = FileSync::from_encryption(sync.into_boxed_slice()).unwrap();
+            send_file(sync.nonce(), source, sync.recipient().unwrap(), &mut conn)
+                .await
+                .unwrap()
+        });
+
+        let tracker = FileRecvTracker::new(7, None);
+        let received = tracker.recv(TcpConn::connect(addr).await.unwrap()).await;
+        let sent = sender.await.unwrap();
+        let received = received.unwrap();
+        assert_eq!(sent.file_size, received.file_size);
+        assert_eq!(std::fs::read(&received.file_path).unwrap(), plain);
+    }
+}
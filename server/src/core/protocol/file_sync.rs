@@ -1,29 +1,34 @@
 use crate::global_var::ENV_VAR;
-use crate::utilities::crypto::{from_encryption, to_encryption};
+use crate::utilities::clock::within_window;
+use crate::utilities::crypto::{KeyPurpose, associated_data, from_encryption, to_encryption};
 use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 type Nonce = u64;
-type Checksum = u64;
 
 #[derive(Debug)]
 pub enum FileSyncError {
     AbortedByPeer,
     Timeout,
     FileMalformed,
+    /// The received file does not match the checksum announced by the sender
+    ChecksumMismatch,
     SystemError,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileSync {
     nonce: Nonce,
+    /// age X25519 public key generated by the receiver for this transfer only
+    recipient: String,
     timestamp: SystemTime,
 }
 
 impl FileSync {
-    pub fn new(nonce: Nonce) -> Self {
+    pub fn new(nonce: Nonce, recipient: &age::x25519::Recipient) -> Self {
         Self {
             nonce,
+            recipient: recipient.to_string(),
             timestamp: SystemTime::now(),
         }
     }
@@ -32,39 +37,45 @@ impl FileSync {
         self.nonce
     }
 
+    /// Key the file must be encrypted for
+    pub fn recipient(&self) -> crate::err::Result<age::x25519::Recipient> {
+        Ok(self.recipient.parse::<age::x25519::Recipient>()?)
+    }
+
     pub fn to_encryption(&self) -> crate::err::Result<Vec<u8>> {
-        to_encryption(&self, || {
-            let iv = rand::random::<[u8; 16]>();
-            Ok(iv)
-        })
+        to_encryption(
+            KeyPurpose::FileTransfer,
+            &self,
+            &associated_data("FILE_SYNC", ""),
+        )
     }
 
     pub fn from_encryption(ciphertext: Box<[u8]>) -> crate::err::Result<Self> {
-        from_encryption(ciphertext)
+        from_encryption(
+            KeyPurpose::FileTransfer,
+            ciphertext,
+            &associated_data("FILE_SYNC", ""),
+        )
     }
 
-    pub fn is_valid(&self) -> bool {
-        let current_time = SystemTime::now();
-        let diff = current_time
-            .duration_since(self.timestamp)
-            .unwrap_or(std::time::Duration::from_secs(0));
-        diff.as_secs() < ENV_VAR.get().unwrap().get_pull_task_validity_in_sec()
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn is_valid(&self, clock_offset_ms: i64) -> bool {
+        let window = Duration::from_secs(ENV_VAR.get().unwrap().get_pull_task_validity_in_sec());
+        within_window(self.timestamp, clock_offset_ms, window)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileSyncAck {
     nonce: Nonce,
-    maybe_checksum: Option<u64>,
     timestamp: SystemTime,
-    file_size: u64, // in bytes
+    file_size: u64, // of the plain file, in bytes
 }
 
 impl FileSyncAck {
-    pub fn new(nonce: Nonce, maybe_checksum: Option<Checksum>, file_size: u64) -> Self {
+    pub fn new(nonce: Nonce, file_size: u64) -> Self {
         Self {
             nonce,
-            maybe_checksum,
             timestamp: SystemTime::now(),
             file_size,
         }
@@ -75,24 +86,24 @@ impl FileSyncAck {
         self.file_size
     }
 
-    #[inline]
-    pub fn maybe_checksum(&self) -> Option<u64> {
-        self.maybe_checksum
-    }
-
     #[inline]
     pub fn nonce(&self) -> Nonce {
         self.nonce
     }
 
     pub fn to_encryption(&self) -> crate::err::Result<Vec<u8>> {
-        to_encryption(&self, || {
-            let iv = rand::random::<[u8; 16]>();
-            Ok(iv)
-        })
+        to_encryption(
+            KeyPurpose::FileTransfer,
+            &self,
+            &associated_data("FILE_SYNC_ACK", ""),
+        )
     }
 
     pub fn from_encryption(ciphertext: Box<[u8]>) -> crate::err::Result<Self> {
-        from_encryption(ciphertext)
+        from_encryption(
+            KeyPurpose::FileTransfer,
+            ciphertext,
+            &associated_data("FILE_SYNC_ACK", ""),
+        )
     }
 }
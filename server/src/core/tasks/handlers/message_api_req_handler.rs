@@ -3,10 +3,9 @@ use crate::core::tasks::handlers::IGNORE_PEER;
 use crate::core::tasks::{AsyncHandleable, NetworkHandleable};
 use crate::err::Result;
 use crate::global_var::get_msg_sender;
-use crate::interface::handlers::run_handler;
+use crate::interface::handlers::run_authenticated_handler;
 use crate::network::protocol::HandleableNetworkProtocol;
 use api_model::protocol::message::api_request_message::ApiRequestMessage;
-use api_model::protocol::message::api_response_message::ApiResponseMessage;
 use api_model::protocol::protocol::Protocol;
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -16,13 +15,14 @@ use std::str::FromStr;
 #[async_trait]
 impl AsyncHandleable for ApiRequestMessage {
     async fn handle(&mut self) -> Result<()> {
-        let response = run_handler(&self.request).await?;
+        let response = run_authenticated_handler(self).await?;
 
-        let serialized_bytes = Bytes::from(ApiResponseMessage { response }.serialize());
+        let serialized_bytes = Bytes::from(self.response(response).serialize());
 
+        // The client acknowledges responses, a lost one is sent again
         let sender = get_msg_sender().await?;
         sender
-            .send(
+            .send_reliable(
                 SocketAddr::V4(SocketAddrV4::new(
                     Ipv4Addr::from_str(LOCAL_ADDR)?,
                     self.from_port,
@@ -0,0 +1,95 @@
+use crate::core::PEER_TABLE;
+use crate::core::get_known_peers;
+use crate::core::tasks::handlers::IGNORE_SELF;
+use crate::core::tasks::send_hello_to;
+use crate::core::tasks::{AsyncHandleable, NetworkHandleable};
+use crate::err::Result;
+use crate::global_var::{ENV_VAR, LOGGER, get_task_queue_sender};
+use crate::network::protocol::messages::GossipMessage;
+use crate::network::protocol::messages::hello_message::HelloMode;
+use crate::utilities::identity::get_identity;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+
+#[async_trait]
+impl AsyncHandleable for GossipMessage {
+    async fn handle(&mut self) -> Result<()> {
+        LOGGER.debug(format!("GossipMessage: {:?}", self));
+
+        // Only peers heard from directly are listened to, and only if they own an identity key
+        let sender = match PEER_TABLE
+            .check_signature(&self.from_ip, &self.signed_bytes(), self.signature.as_ref())
+            .await
+        {
+            Ok(peer) => peer,
+            Err(e) => {
+                LOGGER.warn(format!("Ignored gossip: {}", e));
+                return Ok(());
+            }
+        };
+
+        let digest = match self.validate_and_parse(sender.clock_offset_ms.unwrap_or(0)) {
+            Ok(digest) => digest,
+            // silently ignore invalid digests
+            Err(_) => return Ok(()),
+        };
+
+        let own_identifier = get_identity()?.fingerprint();
+        let scope_id = ENV_VAR.get().map(|ev| ev.get_if_index()).unwrap_or(0);
+        let mut learned: Vec<SocketAddr> = Vec::new();
+        for entry in digest.get_entries() {
+            let identifier = entry.fingerprint();
+            if identifier == own_identifier || identifier == sender.identifier {
+                continue;
+            }
+            let Some(peer) = entry.to_peer(sender.clock_offset_ms, scope_id) else {
+                continue;
+            };
+            // Entries about to expire anyway are not worth a hello
+            if !peer.peer_valid().await {
+                continue;
+            }
+            // A relayed key is never pinned, but must not contradict the one pinned for the name
+            if let Err(e) = get_known_peers()?
+                .check_relayed(entry.get_peer_name(), &entry.get_public_key())
+                .await
+            {
+                LOGGER.warn(format!(
+                    "Refused gossip about {} from {}: {}",
+                    entry.get_peer_name(),
+                    self.from_ip,
+                    e
+                ));
+                continue;
+            }
+            // Kept as a hint, only a hello the peer signs makes its key trusted
+            let addr = peer.socket_addr(peer.port);
+            if PEER_TABLE.update_peer(peer).await? {
+                learned.push(addr);
+            }
+        }
+
+        // Peers only known through gossip are greeted directly, so that they learn about us
+        // as well and are heard from directly whenever the network allows it
+        if !learned.is_empty() {
+            LOGGER.debug(format!(
+                "Learned {} peers through gossip from {}",
+                learned.len(),
+                self.from_ip
+            ));
+            send_hello_to(
+                &get_task_queue_sender().await?,
+                &learned,
+                HelloMode::empty(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+impl NetworkHandleable for GossipMessage {
+    fn should_ignore_by_sockaddr_peer(&self, peer: &SocketAddr) -> bool {
+        IGNORE_SELF(peer)
+    }
+}
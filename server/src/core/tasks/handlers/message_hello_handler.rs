@@ -1,28 +1,58 @@
 use crate::core::PEER_TABLE;
+use crate::core::get_known_peers;
 use crate::core::tasks::handlers::IGNORE_SELF;
 use crate::core::tasks::{AsyncHandleable, NetworkHandleable};
 use crate::core::topology::Peer;
 use crate::err::Result;
-use crate::global_var::{LOGGER, get_msg_sender};
+use crate::global_var::{ENV_VAR, LOGGER, get_msg_sender};
+use crate::network::peer_socket_addr;
 use crate::network::protocol::messages::HelloMessage;
 use crate::network::protocol::messages::hello_message::HelloMode;
+use crate::utilities::clock::estimate_offset_ms;
 use api_model::protocol::protocol::Protocol;
 use async_trait::async_trait;
 use bytes::Bytes;
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[async_trait]
 impl AsyncHandleable for HelloMessage {
     async fn handle(&mut self) -> Result<()> {
         LOGGER.debug(format!("HelloMessage: {:?}", self));
+        // The age of the hello is checked against the clock offset of the sender once known,
+        // from its own hellos rather than from gossip
+        let known_offset_ms = match self.fingerprint() {
+            Some(fingerprint) => PEER_TABLE
+                .get_peer(&fingerprint)
+                .await
+                .filter(|peer| peer.hops == 0)
+                .and_then(|peer| peer.clock_offset_ms),
+            None => None,
+        };
+        // Anyone on the LAN can send a hello, only trust the ones from nodes knowing the token
+        if let Err(e) = self.verify(known_offset_ms) {
+            LOGGER.warn(format!(
+                "Rejected hello from {} ({}): {}",
+                self.from_ip, self.mac_addr, e
+            ));
+            return Err(format!("Rejected hello from {}: {}", self.from_ip, e).into());
+        }
+        // A verified key still has to be the one pinned for the announced name
+        if let Err(e) = get_known_peers()?
+            .check(&self.from_name, self.public_key.as_ref())
+            .await
+        {
+            LOGGER.warn(format!("Refused hello from {}: {}", self.from_ip, e));
+            return Err(e);
+        }
         update_peer_table(&self).await?;
 
         if self.mode.is_request_reply() {
             // LOGGER.debug("Received a hello message requiring response.");
             let sender = get_msg_sender().await?;
             let resp = HelloMessage::from_env(HelloMode::empty())?;
-            let sock_addr = format!("{}:{}", self.from_ip, self.from_port).parse::<SocketAddr>()?;
+            let sock_addr = peer_socket_addr(IpAddr::from_str(&self.from_ip)?, self.from_port);
             // LOGGER.debug(format!("Response HelloMessage: {:?}", &resp));
             let b = Bytes::from(resp.serialize());
             sender.send(sock_addr, b).await?;
@@ -40,15 +70,30 @@ impl NetworkHandleable for HelloMessage {
 fn generate_peer_from_hello_message(msg: &HelloMessage) -> Result<Peer> {
     let ip_addr = IpAddr::from_str(msg.from_ip.as_str())?;
     let peer_is_leader = msg.mode.is_leader();
-    Ok(Peer::new(
-        msg.mac_addr.clone(),
-        msg.from_name.clone(),
-        ip_addr,
-        peer_is_leader,
-    ))
+    // Verified hellos since version 3 carry the key the peer is known by
+    let identifier = msg.fingerprint().unwrap_or_else(|| msg.mac_addr.clone());
+    // Hellos are timestamped since version 2
+    let clock_offset_ms = (msg.protocol_version >= 2).then(|| {
+        let received_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        estimate_offset_ms(msg.timestamp_ms, received_ms)
+    });
+    Ok(
+        Peer::new(identifier, msg.from_name.clone(), ip_addr, peer_is_leader)
+            .with_subscription(msg.subscription.clone())
+            .with_protocol(msg.protocol_version, msg.capabilities)
+            .with_public_key(msg.public_key.clone())
+            .with_clock_offset(clock_offset_ms)
+            .with_ports(msg.from_port, msg.file_port)
+            // Link-local peers share the link of the interface we announce on
+            .with_scope_id(ENV_VAR.get().map(|ev| ev.get_if_index()).unwrap_or(0)),
+    )
 }
 
 pub async fn update_peer_table(msg: &HelloMessage) -> Result<()> {
     let peer = generate_peer_from_hello_message(msg)?;
-    PEER_TABLE.update_peer(peer).await
+    PEER_TABLE.update_peer(peer).await?;
+    Ok(())
 }
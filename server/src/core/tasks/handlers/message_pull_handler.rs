@@ -1,23 +1,47 @@
+use crate::core::PEER_TABLE;
 use crate::core::tasks::AsyncHandleable;
 use crate::core::tasks::NetworkHandleable;
 use crate::core::tasks::handlers::IGNORE_SELF;
+use crate::core::tasks::handlers::replay_cache::PULL_REQUESTS_SEEN;
 use crate::err::Result;
-use crate::fs::{PullRequestResult, RejectionReason, start_pull_request};
+use crate::fs::{
+    PullRequestResult, RejectionReason, start_archive_pull_request, start_directory_pull_request,
+    start_pull_request,
+};
 use crate::global_var::{ENV_VAR, LOGGER, get_msg_sender};
 use crate::network::protocol;
 use crate::network::protocol::messages::PullMessage;
 use crate::network::protocol::messages::PullResponse;
+use crate::network::protocol::messages::hello_message::Capabilities;
 use crate::network::protocol::messages::pull_message::PullRequest;
-use crate::network::protocol::messages::pull_response_message::PullDecision;
 use crate::network::protocol::messages::pull_response_message::PullResponseMessage;
+use crate::network::protocol::messages::pull_response_message::{ListedFile, PullDecision};
 use api_model::protocol::protocol::Protocol;
 use async_trait::async_trait;
 use bytes::Bytes;
 
+fn to_rejection_reason(reason: RejectionReason) -> protocol::messages::PullRejectionReason {
+    match reason {
+        RejectionReason::FileChecksumMismatch => {
+            protocol::messages::PullRejectionReason::FileOutdated
+        }
+        RejectionReason::PathNotFound => protocol::messages::PullRejectionReason::FileNotFound,
+        RejectionReason::PathNotFile => protocol::messages::PullRejectionReason::FileInvalid,
+        RejectionReason::SystemError => protocol::messages::PullRejectionReason::InternalError,
+    }
+}
+
 impl PullMessage {
     async fn start_pull_request(request: &PullRequest) -> PullDecision {
         // process request, and generate response
-        match start_pull_request(request.get_path(), request.get_checksum().into()).await {
+        let result = if request.is_bulk() {
+            start_archive_pull_request(request.get_path()).await
+        } else if request.is_recursive() {
+            start_directory_pull_request(request.get_path(), request.get_listing_offset()).await
+        } else {
+            start_pull_request(request.get_path(), request.get_checksum().into()).await
+        };
+        match result {
             Ok(result) => {
                 match result {
                     PullRequestResult::Accept(nonce) => {
@@ -25,27 +49,32 @@ impl PullMessage {
                                              request.get_path(), request.get_challenge(), nonce));
                         PullDecision::Accept(request.get_challenge(), nonce)
                     }
+                    PullRequestResult::AcceptArchive(nonce, checksum) => {
+                        LOGGER.trace(format!("[PullRequest] Accepted bulk pull request for '{}', challenge {}, nonce {}, archive checksum {}",
+                                             request.get_path(), request.get_challenge(), nonce, checksum));
+                        PullDecision::Archive(request.get_challenge(), nonce, checksum)
+                    }
+                    PullRequestResult::Listing(files, next_offset) => {
+                        LOGGER.trace(format!(
+                            "[PullRequest] Listed {} files under '{}' from offset {}, challenge {}",
+                            files.len(),
+                            request.get_path(),
+                            request.get_listing_offset(),
+                            request.get_challenge()
+                        ));
+                        let listed_files = files
+                            .into_iter()
+                            .map(|(path, size)| ListedFile {
+                                path: path.to_string_lossy().to_string(),
+                                size,
+                            })
+                            .collect();
+                        PullDecision::Listing(request.get_challenge(), listed_files, next_offset)
+                    }
                     PullRequestResult::Reject(reason) => {
                         LOGGER.trace(format!("[PullRequest] Rejected pull request for file '{}', challenge {}, reason {:?}",
                                              request.get_path(), request.get_challenge(), &reason));
-                        match reason {
-                            RejectionReason::FileChecksumMismatch => PullDecision::Reject(
-                                request.get_challenge(),
-                                protocol::messages::PullRejectionReason::FileOutdated,
-                            ),
-                            RejectionReason::PathNotFound => PullDecision::Reject(
-                                request.get_challenge(),
-                                protocol::messages::PullRejectionReason::FileNotFound,
-                            ),
-                            RejectionReason::PathNotFile => PullDecision::Reject(
-                                request.get_challenge(),
-                                protocol::messages::PullRejectionReason::FileInvalid,
-                            ),
-                            RejectionReason::SystemError => PullDecision::Reject(
-                                request.get_challenge(),
-                                protocol::messages::PullRejectionReason::InternalError,
-                            ),
-                        }
+                        PullDecision::Reject(request.get_challenge(), to_rejection_reason(reason))
                     }
                 }
             }
@@ -65,8 +94,31 @@ impl AsyncHandleable for PullMessage {
     async fn handle(&mut self) -> Result<()> {
         LOGGER.debug(format!("PullMessage: {:?}", self));
 
-        let decision = match self.validate_and_parse() {
-            Ok(request) => PullMessage::start_pull_request(&request).await,
+        let peer = match PEER_TABLE
+            .check_signature(&self.from_ip, &self.signed_bytes(), self.signature.as_ref())
+            .await
+        {
+            Ok(peer) => peer,
+            Err(e) => {
+                LOGGER.warn(format!("Ignored pull request: {}", e));
+                return Ok(());
+            }
+        };
+        let clock_offset_ms = peer.clock_offset_ms.unwrap_or(0);
+
+        let decision = match self.validate_and_parse(clock_offset_ms) {
+            Ok(request) => {
+                // A replayed request would make us stage the file once more
+                if let Err(e) = PULL_REQUESTS_SEEN.check(
+                    &self.from_ip,
+                    request.get_challenge(),
+                    request.valid_until(clock_offset_ms),
+                ) {
+                    LOGGER.warn(format!("Ignored pull request: {}", e));
+                    return Ok(());
+                }
+                PullMessage::start_pull_request(&request).await
+            }
             Err(_) => {
                 PullDecision::Reject(0, protocol::messages::PullRejectionReason::AccessDenied)
             }
@@ -82,11 +134,14 @@ impl AsyncHandleable for PullMessage {
         let reply_message = PullResponseMessage::new(response)?;
         let sender = get_msg_sender().await?;
 
-        let sock_addr = format!("{}:{}", self.from_ip, ENV_VAR.get().unwrap().get_port())
-            .parse::<std::net::SocketAddr>()?;
-        sender
-            .send(sock_addr, Bytes::from(reply_message.serialize()))
-            .await?;
+        let sock_addr = peer.socket_addr(peer.port);
+        let bytes = Bytes::from(reply_message.serialize());
+        // A lost response would leave the download waiting until it times out
+        if peer.supports(Capabilities::RELIABLE_CONTROL) {
+            sender.send_reliable(sock_addr, bytes).await?;
+        } else {
+            sender.send(sock_addr, bytes).await?;
+        }
 
         Ok(())
     }
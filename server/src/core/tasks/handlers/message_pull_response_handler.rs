@@ -1,21 +1,37 @@
-use crate::constants::TCP_FILE_PORT;
+use crate::core::PEER_TABLE;
 use crate::core::protocol::file_recv::{FileRecvSummary, FileRecvTracker};
 use crate::core::protocol::file_sync::FileSyncError;
 use crate::core::tasks::handlers::IGNORE_SELF;
+use crate::core::tasks::handlers::replay_cache::PULL_RESPONSES_SEEN;
+use crate::core::tasks::low_level_tasks::{SendControlMessageTask, SendType};
 use crate::core::tasks::{AsyncHandleable, JobStatus, NetworkHandleable};
+use crate::core::tasks::{JobSummaryStatusCallback, forget_outgoing_pull};
+use crate::core::topology::Peer;
 use crate::fs::file::get_file_checksum;
-use crate::fs::{PendingFileDownloadTask, claim_pending_download};
-use crate::global_var::LOGGER;
+use crate::fs::util::check_received_path;
+use crate::fs::{
+    DirectoryDownloadTracker, FS_INDEX, PendingDirectoryDownloadTask, PendingFileDownloadTask,
+    claim_pending_directory_download, claim_pending_download, install_unpacked_files,
+    start_child_file_download_task, start_directory_page_task, unpack_files,
+};
+use crate::global_var::{ENV_VAR, LOGGER, get_task_queue_sender};
 use crate::network::TcpConn;
+use crate::network::protocol::messages::PullMessage;
+use crate::network::protocol::messages::hello_message::Capabilities;
 use crate::network::protocol::messages::pull_response_message::{
-    PullDecision, PullResponseMessage,
+    ListedFile, PullDecision, PullResponseMessage,
 };
+use crate::types::Expected;
 use crate::utilities::format::size_to_human_readable;
+use crate::utilities::temp_dir::TmpDirGuard;
+use api_model::protocol::protocol::Protocol;
 use async_trait::async_trait;
+use bytes::Bytes;
 use std::fmt::{Debug, Formatter};
-use std::net::{IpAddr, SocketAddr};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 
-type Challenge = u64;
 type Nonce = u64;
 type Checksum = u64;
 
@@ -56,6 +72,11 @@ impl PullResponseMessage {
                     r
                 )));
             }
+            PullDecision::Listing(..) | PullDecision::Archive(..) => {
+                return Err(DownloadFileError::SystemError(
+                    "Received a directory transfer for a file download".to_string(),
+                ));
+            }
         };
 
         let from_checksum = pending_file_download.from_checksum;
@@ -73,6 +94,7 @@ impl PullResponseMessage {
                     DownloadFileError::NetworkError("File download timed out".to_string())
                 }
                 FileSyncError::FileMalformed => DownloadFileError::FileMalformed,
+                FileSyncError::ChecksumMismatch => DownloadFileError::FileChecksumMismatch,
                 FileSyncError::SystemError => DownloadFileError::SystemError(
                     "File download failed due to system error".to_string(),
                 ),
@@ -124,6 +146,24 @@ impl PullResponseMessage {
                 pending_file_download.file_path.display()
             ));
         } else {
+            // The file may come with a directory download, whose subdirectories do not exist yet.
+            // Nothing is created before the target is known to be in the share
+            let target = PathBuf::from(ENV_VAR.get().unwrap().get_working_dir())
+                .join(&pending_file_download.file_path);
+            if !crate::utilities::disk_op::check_path_inbound(&target) {
+                return Err(DownloadFileError::SystemError(format!(
+                    "{} is outside the share",
+                    pending_file_download.file_path.display()
+                )));
+            }
+            if let Some(parent_dir) = target.parent() {
+                tokio::fs::create_dir_all(parent_dir).await.map_err(|e| {
+                    DownloadFileError::SystemError(format!(
+                        "Failed to create parent directory: {:?}",
+                        e
+                    ))
+                })?;
+            }
             crate::utilities::disk_op::fs_rename(
                 &summary.file_path,
                 &pending_file_download.file_path,
@@ -136,11 +176,12 @@ impl PullResponseMessage {
         Ok(summary)
     }
 
-    async fn process_file_download(&self, decision: PullDecision) -> crate::err::Result<()> {
-        let challenge = match decision {
-            PullDecision::Accept(c, _) => c,
-            PullDecision::Reject(c, _) => c,
-        };
+    async fn process_file_download(
+        &self,
+        peer: &Peer,
+        decision: PullDecision,
+    ) -> crate::err::Result<()> {
+        let challenge = decision.get_challenge();
 
         // 1. Claim the pending download task by challenge
         let mut pending = match claim_pending_download(challenge).await {
@@ -169,14 +210,7 @@ impl PullResponseMessage {
         })?;
 
         // 2. Setup TCP connection to sender
-        let ip: IpAddr = self.from_ip.parse().map_err(|e| {
-            LOGGER.warn(format!(
-                "Invalid from_ip '{}' in PullResponseMessage: {:?}",
-                self.from_ip, e
-            ));
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid from_ip")
-        })?;
-        let addr = SocketAddr::new(ip, TCP_FILE_PORT);
+        let addr = peer.socket_addr(peer.file_port);
         let conn = TcpConn::connect(addr).await.map_err(|e| {
             LOGGER.warn(format!("Failed to connect to {}: {:?}", addr, e));
             std::io::Error::new(std::io::ErrorKind::Other, "tcp connect failed")
@@ -202,23 +236,316 @@ impl PullResponseMessage {
                     decrypt_speed
                 );
                 callback(JobStatus::Completed, msg).await?;
+                if let Some(parent) = &pending.parent {
+                    parent.report_success(&pending.file_path).await;
+                }
             }
             Err(e) => {
                 LOGGER.warn(format!(
                     "Failed to download file for challenge {}: {:?}",
                     challenge, e
                 ));
+                if let Some(parent) = &pending.parent {
+                    parent
+                        .report_failure(&pending.file_path, format!("{:?}", e))
+                        .await;
+                }
                 callback(JobStatus::Failed, format!("File download failed: {:?}", e)).await?;
             }
         }
 
         Ok(())
     }
+
+    async fn process_directory_download(
+        &self,
+        peer: &Peer,
+        mut pending: PendingDirectoryDownloadTask,
+        decision: PullDecision,
+    ) -> crate::err::Result<()> {
+        let challenge = decision.get_challenge();
+
+        // Take over the parent job, the directory download reports the aggregated progress from now on
+        let handle = pending.handle.take().ok_or_else(|| {
+            LOGGER.error(format!("No handle found for challenge {}", challenge));
+            std::io::Error::other("No handle found for challenge")
+        })?;
+        let mut callback = handle.take_over().await.map_err(|e| {
+            LOGGER.error(format!(
+                "Failed to take over directory download job for challenge {}: {:?}",
+                challenge, e
+            ));
+            std::io::Error::other("Failed to take over claimable job")
+        })?;
+
+        match decision {
+            PullDecision::Listing(_, files, next_offset) => {
+                self.process_directory_listing(peer, pending, callback, files, next_offset)
+                    .await
+            }
+            PullDecision::Archive(_, nonce, checksum) => {
+                self.process_archive_download(peer, pending, callback, nonce, checksum)
+                    .await
+            }
+            PullDecision::Accept(..) => {
+                callback(
+                    JobStatus::Failed,
+                    String::from("Peer answered a directory pull with a single file transfer"),
+                )
+                .await?;
+                Ok(())
+            }
+            PullDecision::Reject(_, reason) => {
+                callback(
+                    JobStatus::Failed,
+                    format!("Rejected by peer with reason: {}", reason),
+                )
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn process_directory_listing(
+        &self,
+        peer: &Peer,
+        pending: PendingDirectoryDownloadTask,
+        mut callback: Box<JobSummaryStatusCallback>,
+        files: Vec<ListedFile>,
+        next_offset: Option<u64>,
+    ) -> crate::err::Result<()> {
+        if files.is_empty() && next_offset.is_none() {
+            callback(
+                JobStatus::Completed,
+                String::from("No files found in directory"),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        let tracker = Arc::new(DirectoryDownloadTracker::new(
+            pending.dir_path.clone(),
+            0,
+            callback,
+        ));
+        self.pull_listed_files(peer, pending.dir_path, tracker, files, next_offset)
+            .await
+    }
+
+    /// Handle a later page of the listing of a directory download
+    async fn process_directory_page(
+        &self,
+        peer: &Peer,
+        pending: PendingDirectoryDownloadTask,
+        tracker: Arc<DirectoryDownloadTracker>,
+        decision: PullDecision,
+    ) -> crate::err::Result<()> {
+        match decision {
+            PullDecision::Listing(_, files, next_offset) => {
+                self.pull_listed_files(peer, pending.dir_path, tracker, files, next_offset)
+                    .await
+            }
+            PullDecision::Reject(_, reason) => {
+                tracker
+                    .fail_listing(format!("Listing rejected by peer with reason: {}", reason))
+                    .await;
+                Ok(())
+            }
+            PullDecision::Accept(..) | PullDecision::Archive(..) => {
+                tracker
+                    .fail_listing(String::from("Peer answered a listing with a transfer"))
+                    .await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Pull every file of a page of the listing as a child download of the directory download,
+    /// and ask for the next page if any
+    async fn pull_listed_files(
+        &self,
+        peer: &Peer,
+        dir_path: PathBuf,
+        tracker: Arc<DirectoryDownloadTracker>,
+        files: Vec<ListedFile>,
+        next_offset: Option<u64>,
+    ) -> crate::err::Result<()> {
+        let peer_addr = peer.socket_addr(peer.port);
+        let reliable = peer.supports(Capabilities::RELIABLE_CONTROL);
+
+        // Counted before any child can report, so that the tracker does not finish early
+        tracker.add_page(files.len(), next_offset.is_some()).await;
+        let task_queue = get_task_queue_sender().await?;
+        for file in files {
+            let file_path = PathBuf::from(&file.path);
+            if let Err(e) = check_received_path(&dir_path, &file_path) {
+                LOGGER.warn(format!(
+                    "Refused listed file from {}: {}",
+                    peer.peer_name, e
+                ));
+                tracker
+                    .report_failure(&file_path, format!("Refused: {}", e))
+                    .await;
+                continue;
+            }
+            let from_checksum = FS_INDEX
+                .get_latest_checksum(&file_path)
+                .await
+                .unwrap_or(None);
+            let child_challenge = match start_child_file_download_task(
+                &file_path,
+                from_checksum.into(),
+                Expected::Any,
+                tracker.clone(),
+            )
+            .await
+            {
+                Ok(c) => c,
+                Err(e) => {
+                    tracker
+                        .report_failure(&file_path, format!("Failed to start download: {}", e))
+                        .await;
+                    continue;
+                }
+            };
+
+            let pull_message = PullMessage::new(&file.path, None, child_challenge)?.serialize();
+            task_queue
+                .send(Box::new(SendControlMessageTask::new(
+                    SendType::unicast(peer_addr, reliable),
+                    Bytes::from(pull_message),
+                )))
+                .await?;
+        }
+
+        if let Some(offset) = next_offset {
+            let dir = dir_path.to_string_lossy().to_string();
+            let page_challenge = start_directory_page_task(dir_path, tracker).await;
+            let page_message = PullMessage::new_directory(&dir, page_challenge, offset)?;
+            task_queue
+                .send(Box::new(SendControlMessageTask::new(
+                    SendType::unicast(peer_addr, reliable),
+                    Bytes::from(page_message.serialize()),
+                )))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn process_archive_download(
+        &self,
+        peer: &Peer,
+        pending: PendingDirectoryDownloadTask,
+        mut callback: Box<JobSummaryStatusCallback>,
+        nonce: Nonce,
+        checksum: Checksum,
+    ) -> crate::err::Result<()> {
+        // 1. Download the whole archive over a single connection
+        let addr = peer.socket_addr(peer.file_port);
+        let conn = match TcpConn::connect(addr).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                LOGGER.warn(format!("Failed to connect to {}: {:?}", addr, e));
+                callback(
+                    JobStatus::Failed,
+                    format!("Failed to connect to {}: {}", addr, e),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+
+        // The tracker removes the downloaded archive when dropped, keep it until unpacked
+        let archive_tracker = FileRecvTracker::new(nonce, Some(checksum));
+        let summary = match archive_tracker.recv(conn).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                LOGGER.warn(format!(
+                    "Failed to download archive of '{}': {:?}",
+                    pending.dir_path.display(),
+                    e
+                ));
+                callback(
+                    JobStatus::Failed,
+                    format!("Archive download failed: {:?}", e),
+                )
+                .await?;
+                return Ok(());
+            }
+        };
+        LOGGER.info(format!(
+            "Archive of '{}' downloaded, size: {}, download speed: {}/s",
+            pending.dir_path.display(),
+            size_to_human_readable(summary.file_size),
+            size_to_human_readable(
+                (summary.file_size as f64 / summary.download_time.as_secs_f64()) as u64
+            )
+        ));
+
+        // 2. Unpack everything aside first, nothing is moved into place unless the archive is sound
+        let staging_dir: TmpDirGuard =
+            PathBuf::from(ENV_VAR.get().unwrap().get_temp_downloads_dir())
+                .join(format!("unpack-{:x}", nonce))
+                .into();
+        tokio::fs::create_dir_all(&*staging_dir).await?;
+        let files = match unpack_files(&summary.file_path, &staging_dir, &pending.dir_path).await {
+            Ok(files) => files,
+            Err(e) => {
+                LOGGER.warn(format!(
+                    "Failed to unpack archive of '{}': {:?}",
+                    pending.dir_path.display(),
+                    e
+                ));
+                callback(JobStatus::Failed, format!("Archive malformed: {}", e)).await?;
+                return Ok(());
+            }
+        };
+
+        if files.is_empty() {
+            callback(
+                JobStatus::Completed,
+                String::from("No files found in directory"),
+            )
+            .await?;
+            return Ok(());
+        }
+
+        // 3. Move the unpacked files into place, all or nothing
+        match install_unpacked_files(&staging_dir, &files).await {
+            Ok(()) => {
+                callback(
+                    JobStatus::Completed,
+                    format!("{} files transferred", files.len()),
+                )
+                .await?
+            }
+            Err(e) => {
+                LOGGER.warn(format!(
+                    "Failed to install archive of '{}': {:?}",
+                    pending.dir_path.display(),
+                    e
+                ));
+                callback(
+                    JobStatus::Failed,
+                    format!("Failed to move the files into place: {}", e),
+                )
+                .await?
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl AsyncHandleable for PullResponseMessage {
     async fn handle(&mut self) -> crate::err::Result<()> {
+        let peer = PEER_TABLE
+            .check_signature(&self.from_ip, &self.signed_bytes(), self.signature.as_ref())
+            .await
+            .inspect_err(|e| LOGGER.warn(format!("Rejected PullResponseMessage: {}", e)))?;
+        let clock_offset_ms = peer.clock_offset_ms.unwrap_or(0);
         let resp = self.get_response()?;
 
         if resp.get_from_ip() != self.from_ip {
@@ -226,7 +553,7 @@ impl AsyncHandleable for PullResponseMessage {
             return Err("Invalid from_ip in PullResponseMessage".into());
         }
 
-        if !resp.timestamp_valid() {
+        if !resp.timestamp_valid(clock_offset_ms) {
             LOGGER.warn(format!(
                 "PullResponseMessage timestamp is too old, timestamp: {:?}",
                 resp.get_timestamp()
@@ -234,7 +561,30 @@ impl AsyncHandleable for PullResponseMessage {
             return Err("PullResponseMessage timestamp is too old".into());
         }
 
-        let _ = self.process_file_download(*resp.get_decision()).await?;
+        let decision = resp.get_decision().clone();
+        if let Err(e) = PULL_RESPONSES_SEEN.check(
+            &self.from_ip,
+            decision.get_challenge(),
+            resp.valid_until(clock_offset_ms),
+        ) {
+            LOGGER.warn(format!("Rejected PullResponseMessage: {}", e));
+            return Err(e);
+        }
+        // Answered, whatever becomes of the download there is nothing to ask again
+        forget_outgoing_pull(decision.get_challenge()).await;
+        match claim_pending_directory_download(decision.get_challenge()).await {
+            Some(pending) => match pending.tracker.clone() {
+                Some(tracker) => {
+                    self.process_directory_page(&peer, pending, tracker, decision)
+                        .await?
+                }
+                None => {
+                    self.process_directory_download(&peer, pending, decision)
+                        .await?
+                }
+            },
+            None => self.process_file_download(&peer, decision).await?,
+        }
 
         Ok(())
     }
@@ -0,0 +1,125 @@
+use crate::config::PushPolicy;
+use crate::core::PEER_TABLE;
+use crate::core::tasks::AsyncHandleable;
+use crate::core::tasks::NetworkHandleable;
+use crate::core::tasks::handlers::IGNORE_SELF;
+use crate::core::tasks::{get_job_fs_pull_initiate_closure, launch_oneshot_job};
+use crate::core::topology::Peer;
+use crate::err::Result;
+use crate::fs::FS_INDEX;
+use crate::global_var::{ENV_VAR, LOGGER, get_task_queue_sender};
+use crate::network::protocol::messages::PushMessage;
+use crate::network::protocol::messages::push_message::PushOffer;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+impl PushMessage {
+    /// Apply the local push policy to an offer from `peer`, who signed it.
+    /// Returns the offering peer if the offer should be accepted.
+    async fn accept_offer(offer: &PushOffer, peer: Arc<Peer>) -> Option<Arc<Peer>> {
+        let policy = ENV_VAR.get().unwrap().get_push_policy();
+        if policy == PushPolicy::RejectAll {
+            LOGGER.info(format!(
+                "[PushOffer] Rejected push of '{}' from {}: pushes are disabled",
+                offer.get_path(),
+                offer.get_from_ip()
+            ));
+            return None;
+        }
+
+        // Pushes are automatic transfers, only subscribed paths are taken
+        if !ENV_VAR
+            .get()
+            .unwrap()
+            .get_selective_sync()
+            .is_subscribed(offer.get_path())
+        {
+            LOGGER.info(format!(
+                "[PushOffer] Rejected push of '{}' from {}: path is not subscribed",
+                offer.get_path(),
+                offer.get_from_ip()
+            ));
+            return None;
+        }
+
+        match FS_INDEX.get_latest_checksum(offer.get_path()).await {
+            Ok(Some(checksum)) if checksum == offer.get_checksum() => {
+                LOGGER.debug(format!(
+                    "[PushOffer] Skipped push of '{}' from {}: local copy is identical",
+                    offer.get_path(),
+                    offer.get_from_ip()
+                ));
+                None
+            }
+            _ => Some(peer),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncHandleable for PushMessage {
+    async fn handle(&mut self) -> Result<()> {
+        LOGGER.debug(format!("PushMessage: {:?}", self));
+
+        let signer = match PEER_TABLE
+            .check_signature(&self.from_ip, &self.signed_bytes(), self.signature.as_ref())
+            .await
+        {
+            Ok(peer) => peer,
+            Err(e) => {
+                LOGGER.warn(format!("Ignored push offer: {}", e));
+                return Ok(());
+            }
+        };
+
+        let offer = match self.validate_and_parse(signer.clock_offset_ms.unwrap_or(0)) {
+            Ok(offer) => offer,
+            // silently ignore invalid offers
+            Err(_) => return Ok(()),
+        };
+
+        let Some(peer) = PushMessage::accept_offer(&offer, signer).await else {
+            return Ok(());
+        };
+
+        LOGGER.trace(format!(
+            "[PushOffer] Accepted push of '{}' ({} bytes) from {}, offer {:x}",
+            offer.get_path(),
+            offer.get_size(),
+            offer.get_from_ip(),
+            offer.get_offer_id()
+        ));
+
+        let from_checksum = FS_INDEX
+            .get_latest_checksum(offer.get_path())
+            .await
+            .unwrap_or(None);
+        let task_sender = get_task_queue_sender().await?;
+        launch_oneshot_job(
+            "Pushed file pull initiation",
+            &format!(
+                "Initiate pulling pushed file {} from {}",
+                offer.get_path(),
+                offer.get_from_ip()
+            ),
+            get_job_fs_pull_initiate_closure(
+                &peer,
+                offer.get_path(),
+                from_checksum.into(),
+                Some(offer.get_checksum()).into(),
+            )
+            .await?,
+            Some(30),
+            task_sender,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl NetworkHandleable for PushMessage {
+    fn should_ignore_by_sockaddr_peer(&self, peer: &std::net::SocketAddr) -> bool {
+        IGNORE_SELF(peer)
+    }
+}
@@ -1,25 +1,40 @@
 mod message_api_req_handler;
+mod message_gossip_handler;
 mod message_hello_handler;
 mod message_pull_handler;
 mod message_pull_response_handler;
+mod message_push_handler;
+mod replay_cache;
 
 use async_trait::async_trait;
 use std::net::SocketAddr;
 
 use crate::err::Result;
 use crate::global_var::ENV_VAR;
+use crate::network::{get_local_addr, is_sending_port};
 
+/// Ignores what this node sent itself: datagrams from its listening port or one of the ports it
+/// sends from, at one of the addresses of this host. Other nodes on the host are not ignored.
 pub static IGNORE_SELF: fn(&SocketAddr) -> bool = |peer: &SocketAddr| {
-    if peer.ip().is_loopback() {
-        return true;
+    let ev = ENV_VAR.get().unwrap();
+    let own_ip = ev.get_ip_addr();
+    if peer.port() == ev.get_port() || is_sending_port(peer.port()) {
+        // Sockets bound to the unspecified address send from any address of the host
+        if peer.ip() == own_ip || get_local_addr(peer.ip()).is_some() {
+            return true;
+        }
     }
-    if peer.ip() == ENV_VAR.get().unwrap().get_ip_addr() {
+    // Other nodes can only be on loopback when this one is, as when several share a host
+    if peer.ip().is_loopback() && !own_ip.is_loopback() {
         return true;
     }
     false
 };
 
-pub static IGNORE_PEER: fn(&SocketAddr) -> bool = |peer: &SocketAddr| !IGNORE_SELF(peer);
+/// Ignores anything not sent from this host
+pub static IGNORE_PEER: fn(&SocketAddr) -> bool = |peer: &SocketAddr| {
+    !(peer.ip().is_loopback() || peer.ip() == ENV_VAR.get().unwrap().get_ip_addr())
+};
 
 pub trait NetworkHandleable {
     fn should_ignore_by_sockaddr_peer(&self, peer: &SocketAddr) -> bool;
@@ -0,0 +1,135 @@
+//! Replay protection for pull requests and responses
+//!
+//! A pull message is accepted as long as its timestamp is within the validity window, so a
+//! message captured on the network could be sent again within that window. Every challenge seen
+//! from a peer is remembered until the message carrying it would be too old anyway, and a second
+//! message carrying it is refused.
+
+use crate::err::Result;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// Challenges remembered per peer, the oldest ones are forgotten first past this bound
+const MAX_SEEN_PER_PEER: usize = 4096;
+
+/// Challenges of the pull requests received from each peer
+pub(super) static PULL_REQUESTS_SEEN: LazyLock<ReplayCache> =
+    LazyLock::new(|| ReplayCache::new(MAX_SEEN_PER_PEER));
+/// Challenges of the pull responses received from each peer
+pub(super) static PULL_RESPONSES_SEEN: LazyLock<ReplayCache> =
+    LazyLock::new(|| ReplayCache::new(MAX_SEEN_PER_PEER));
+
+#[derive(Default)]
+struct SeenIds {
+    /// Ids along with the time they can be forgotten, in arrival order
+    order: VecDeque<(u64, SystemTime)>,
+    ids: HashSet<u64>,
+}
+
+impl SeenIds {
+    fn forget_expired(&mut self, now: SystemTime) {
+        // Expiry times are not sorted, a message may carry an older timestamp than the one before
+        self.order.retain(|(id, valid_until)| {
+            let keep = *valid_until > now;
+            if !keep {
+                self.ids.remove(id);
+            }
+            keep
+        });
+    }
+}
+
+pub struct ReplayCache {
+    max_per_peer: usize,
+    seen: Mutex<HashMap<String, SeenIds>>,
+}
+
+impl ReplayCache {
+    pub fn new(max_per_peer: usize) -> Self {
+        Self {
+            max_per_peer,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remember `id` from `peer` until `valid_until`, fails if it was already seen
+    pub fn check(&self, peer: &str, id: u64, valid_until: SystemTime) -> Result<()> {
+        self.check_at(peer, id, valid_until, SystemTime::now())
+    }
+
+    fn check_at(
+        &self,
+        peer: &str,
+        id: u64,
+        valid_until: SystemTime,
+        now: SystemTime,
+    ) -> Result<()> {
+        let mut seen = self.seen.lock().map_err(|_| "Replay cache poisoned")?;
+        seen.retain(|_, peer_seen| {
+            peer_seen.forget_expired(now);
+            !peer_seen.ids.is_empty()
+        });
+
+        let peer_seen = seen.entry(peer.to_string()).or_default();
+        if peer_seen.ids.contains(&id) {
+            return Err(format!("Replayed message from {} with challenge {}", peer, id).into());
+        }
+        if peer_seen.order.len() >= self.max_per_peer
+            && let Some((oldest, _)) = peer_seen.order.pop_front()
+        {
+            peer_seen.ids.remove(&oldest);
+        }
+        peer_seen.order.push_back((id, valid_until));
+        peer_seen.ids.insert(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn second_message_with_a_challenge_is_refused() {
+        let cache = ReplayCache::new(16);
+        let valid_until = SystemTime::now() + Duration::from_secs(10);
+
+        assert!(cache.check("10.0.0.2", 7, valid_until).is_ok());
+        assert!(cache.check("10.0.0.2", 7, valid_until).is_err());
+        // Challenges are tracked per peer
+        assert!(cache.check("10.0.0.3", 7, valid_until).is_ok());
+        assert!(cache.check("10.0.0.2", 8, valid_until).is_ok());
+    }
+
+    #[test]
+    fn challenges_are_forgotten_once_their_message_expired() {
+        let cache = ReplayCache::new(16);
+        let now = SystemTime::now();
+        let valid_until = now + Duration::from_secs(10);
+
+        cache.check_at("10.0.0.2", 7, valid_until, now).unwrap();
+        let later = valid_until + Duration::from_secs(1);
+        assert!(
+            cache
+                .check_at("10.0.0.2", 7, later + Duration::from_secs(10), later)
+                .is_ok()
+        );
+        assert_eq!(cache.seen.lock().unwrap()["10.0.0.2"].order.len(), 1);
+    }
+
+    #[test]
+    fn cache_is_bounded_per_peer() {
+        let cache = ReplayCache::new(4);
+        let valid_until = SystemTime::now() + Duration::from_secs(10);
+
+        for id in 0..6 {
+            cache.check("10.0.0.2", id, valid_until).unwrap();
+        }
+        let seen = cache.seen.lock().unwrap();
+        assert_eq!(seen["10.0.0.2"].order.len(), 4);
+        assert!(!seen["10.0.0.2"].ids.contains(&0));
+        assert!(seen["10.0.0.2"].ids.contains(&5));
+    }
+}
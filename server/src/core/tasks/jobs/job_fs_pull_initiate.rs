@@ -1,16 +1,127 @@
 use crate::core::tasks::jobs::JobClosure;
 use crate::core::tasks::low_level_tasks::{SendControlMessageTask, SendType};
+use crate::core::tasks::task_queue::TaskQueueSender;
 use crate::core::topology::Peer;
 use crate::err::Result;
-use crate::fs::start_file_download_task;
-use crate::global_var::get_task_queue_sender;
+use crate::fs::{is_directory_download_pending, is_download_pending, start_file_download_task};
+use crate::global_var::{ENV_VAR, get_task_queue_sender};
 use crate::network::protocol::messages::PullMessage;
+use crate::network::protocol::messages::hello_message::Capabilities;
 use crate::types::Expected;
 use api_model::protocol::protocol::Protocol;
 use bytes::Bytes;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
 
 type Checksum = u64;
+type Challenge = u64;
+
+#[derive(Clone, Copy)]
+enum PullKind {
+    File(Expected<Checksum>),
+    Directory,
+    Archive,
+}
+
+/// A pull request sent to a peer, kept until it is answered so that it can be asked again
+/// when our address changes and the answer would go to the old one
+struct OutgoingPull {
+    target_addr: SocketAddr,
+    /// Whether the peer acknowledges control messages
+    reliable: bool,
+    path: String,
+    kind: PullKind,
+    sent_at: Instant,
+}
+
+impl OutgoingPull {
+    /// Build the request, from our current address
+    fn to_message(&self, challenge: Challenge) -> Result<PullMessage> {
+        match self.kind {
+            PullKind::File(checksum) => PullMessage::new(&self.path, checksum, challenge),
+            PullKind::Directory => PullMessage::new_directory(&self.path, challenge, 0),
+            PullKind::Archive => PullMessage::new_archive(&self.path, challenge),
+        }
+    }
+
+    async fn is_pending(&self, challenge: Challenge) -> bool {
+        match self.kind {
+            PullKind::File(_) => is_download_pending(challenge).await,
+            PullKind::Directory | PullKind::Archive => {
+                is_directory_download_pending(challenge).await
+            }
+        }
+    }
+}
+
+static OUTGOING_PULLS: LazyLock<Mutex<HashMap<Challenge, OutgoingPull>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Time after which a request is not asked again, its download has been given up by then
+fn outgoing_pull_ttl() -> Duration {
+    let validity = ENV_VAR
+        .get()
+        .map(|ev| ev.get_pull_task_validity_in_sec())
+        .unwrap_or(300);
+    Duration::from_secs(validity)
+}
+
+/// Drop the requests sent more than `ttl` ago
+fn expire_outgoing_pulls(outgoing: &mut HashMap<Challenge, OutgoingPull>, ttl: Duration) {
+    outgoing.retain(|_, pull| pull.sent_at.elapsed() < ttl);
+}
+
+/// Forget the request under `challenge`, once it is answered or its download failed
+pub async fn forget_outgoing_pull(challenge: Challenge) {
+    OUTGOING_PULLS.lock().await.remove(&challenge);
+}
+
+async fn send_pull(
+    task_q: &TaskQueueSender,
+    challenge: Challenge,
+    pull: &OutgoingPull,
+) -> Result<()> {
+    let pull_message = pull.to_message(challenge)?.serialize();
+    let send_message_task = SendControlMessageTask::new(
+        SendType::unicast(pull.target_addr, pull.reliable),
+        Bytes::from(pull_message),
+    );
+    task_q.send(Box::new(send_message_task)).await?;
+    Ok(())
+}
+
+/// Send a pull request and remember it until its download is claimed or given up
+async fn send_and_track_pull(challenge: Challenge, pull: OutgoingPull) -> Result<()> {
+    let task_queue = get_task_queue_sender().await?;
+    send_pull(&task_queue, challenge, &pull).await?;
+    let mut outgoing = OUTGOING_PULLS.lock().await;
+    expire_outgoing_pulls(&mut outgoing, outgoing_pull_ttl());
+    outgoing.insert(challenge, pull);
+    Ok(())
+}
+
+/// Ask again, from our current address, for everything still waiting on an answer.
+/// Answered and expired requests are forgotten on the way.
+pub async fn resend_pending_pulls(task_q: &TaskQueueSender) -> Result<usize> {
+    let mut outgoing = OUTGOING_PULLS.lock().await;
+    expire_outgoing_pulls(&mut outgoing, outgoing_pull_ttl());
+    let mut answered = Vec::new();
+    for (challenge, pull) in outgoing.iter() {
+        if pull.is_pending(*challenge).await {
+            send_pull(task_q, *challenge, pull).await?;
+        } else {
+            answered.push(*challenge);
+        }
+    }
+    for challenge in answered {
+        outgoing.remove(&challenge);
+    }
+    Ok(outgoing.len())
+}
 
 pub async fn get_job_fs_pull_initiate_closure(
     peer: &Peer,
@@ -18,18 +129,8 @@ pub async fn get_job_fs_pull_initiate_closure(
     from_checksum: Expected<Checksum>,
     to_checksum: Expected<Checksum>,
 ) -> Result<Box<JobClosure>> {
-    let target_addr: std::net::SocketAddr = format!(
-        "{}:{}",
-        peer.peer_addr.to_string(),
-        crate::constants::UPD_MESSAGE_PORT
-    )
-    .parse()
-    .map_err(|e| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            format!("Invalid peer address: {}, {:?}", e, peer.peer_addr),
-        )
-    })?;
+    let target_addr = peer.socket_addr(peer.port);
+    let reliable = peer.supports(Capabilities::RELIABLE_CONTROL);
     let file_path_buf = PathBuf::from(file_path);
     let closure = move || {
         // This is not efficient in terms of memory usage, but it's fine for now.
@@ -41,25 +142,80 @@ pub async fn get_job_fs_pull_initiate_closure(
                 let file_download_challenge =
                     start_file_download_task(&file_path_buf, from_checksum, to_checksum).await?;
 
-                let pull_message = PullMessage::new(
-                    file_path_buf.to_str().unwrap(),
-                    to_checksum,
+                send_and_track_pull(
                     file_download_challenge,
-                )?
-                .serialize();
-
-                let send_message_task = SendControlMessageTask::new(
-                    SendType::Unicast(target_addr),
-                    Bytes::from(pull_message),
-                );
+                    OutgoingPull {
+                        target_addr,
+                        reliable,
+                        path: file_path_buf.to_string_lossy().to_string(),
+                        kind: PullKind::File(to_checksum),
+                        sent_at: Instant::now(),
+                    },
+                )
+                .await
+            });
+        fut
+    };
 
-                let task_queue = get_task_queue_sender().await?;
-                task_queue.send(Box::new(send_message_task)).await?;
+    Ok(Box::new(closure))
+}
 
-                Ok(())
+/// Ask the peer for the files under `dir_path`, as a listing or as a single archive when `bulk`;
+/// the response is answered with `challenge`.
+pub async fn get_job_fs_pull_directory_initiate_closure(
+    peer: &Peer,
+    dir_path: &str,
+    challenge: u64,
+    bulk: bool,
+) -> Result<Box<JobClosure>> {
+    let target_addr = peer.socket_addr(peer.port);
+    let reliable = peer.supports(Capabilities::RELIABLE_CONTROL);
+    let dir_path = dir_path.to_string();
+    let closure = move || {
+        let dir_path = dir_path.clone();
+        let fut: std::pin::Pin<Box<dyn Future<Output = Result<()>> + Send + 'static>> =
+            Box::pin(async move {
+                let kind = if bulk {
+                    PullKind::Archive
+                } else {
+                    PullKind::Directory
+                };
+                send_and_track_pull(
+                    challenge,
+                    OutgoingPull {
+                        target_addr,
+                        reliable,
+                        path: dir_path,
+                        kind,
+                        sent_at: Instant::now(),
+                    },
+                )
+                .await
             });
         fut
     };
 
     Ok(Box::new(closure))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_requests_are_dropped() {
+        let pull = |sent_at: Instant| OutgoingPull {
+            target_addr: "10.0.0.7:14514".parse().unwrap(),
+            reliable: false,
+            path: String::from("a.txt"),
+            kind: PullKind::Directory,
+            sent_at,
+        };
+        let mut outgoing = HashMap::new();
+        outgoing.insert(1, pull(Instant::now() - Duration::from_secs(600)));
+        outgoing.insert(2, pull(Instant::now()));
+
+        expire_outgoing_pulls(&mut outgoing, Duration::from_secs(300));
+        assert_eq!(outgoing.keys().collect::<Vec<_>>(), [&2]);
+    }
+}
@@ -0,0 +1,41 @@
+use crate::core::tasks::jobs::JobClosure;
+use crate::core::tasks::low_level_tasks::{SendControlMessageTask, SendType};
+use crate::core::topology::Peer;
+use crate::err::Result;
+use crate::global_var::get_task_queue_sender;
+use crate::network::protocol::messages::PushMessage;
+use api_model::protocol::protocol::Protocol;
+use bytes::Bytes;
+
+type Checksum = u64;
+
+pub async fn get_job_fs_push_offer_closure(
+    peer: &Peer,
+    file_path: &str,
+    size: u64,
+    checksum: Checksum,
+) -> Result<Box<JobClosure>> {
+    let target_addr = peer.socket_addr(peer.port);
+    let file_path = file_path.to_string();
+    let closure = move || {
+        let file_path = file_path.clone();
+        let fut: std::pin::Pin<Box<dyn Future<Output = Result<()>> + Send + 'static>> =
+            Box::pin(async move {
+                // The peer pulls the file back through the regular pull flow once it accepts.
+                let push_message = PushMessage::new(&file_path, size, checksum)?.serialize();
+
+                let send_message_task = SendControlMessageTask::new(
+                    SendType::Unicast(target_addr),
+                    Bytes::from(push_message),
+                );
+
+                let task_queue = get_task_queue_sender().await?;
+                task_queue.send(Box::new(send_message_task)).await?;
+
+                Ok(())
+            });
+        fut
+    };
+
+    Ok(Box::new(closure))
+}
@@ -147,7 +147,12 @@ fn generate_callback_closure(
             {
                 let mut job_summary_guard = job_summary_clone.write().await;
                 if job_status == JobStatus::Running {
-                    job_summary_guard.start_job().await?;
+                    if job_summary_guard.status == JobStatus::Running {
+                        // Actors report progress of a running job through the status message
+                        job_summary_guard.update_status_msg(job_status_msg).await;
+                    } else {
+                        job_summary_guard.start_job().await?;
+                    }
                 } else {
                     job_summary_guard
                         .end_job(job_status, job_status_msg)
@@ -160,6 +165,7 @@ fn generate_callback_closure(
 }
 
 pub struct ClaimableJobHandle {
+    job_id: u64,
     take_over_callback_recv: tokio::sync::oneshot::Receiver<Option<Box<JobSummaryStatusCallback>>>,
     take_over_indicator: tokio::sync::oneshot::Sender<()>,
 }
@@ -173,15 +179,22 @@ pub enum ClaimableJobTakeoverError {
 
 impl ClaimableJobHandle {
     pub fn new(
+        job_id: u64,
         recv: tokio::sync::oneshot::Receiver<Option<Box<JobSummaryStatusCallback>>>,
         take_over_indicator: tokio::sync::oneshot::Sender<()>,
     ) -> Self {
         Self {
+            job_id,
             take_over_callback_recv: recv,
             take_over_indicator,
         }
     }
 
+    /// The id of the job summary in the job table
+    pub fn job_id(&self) -> u64 {
+        self.job_id
+    }
+
     pub async fn take_over(
         self,
     ) -> std::result::Result<Box<JobSummaryStatusCallback>, ClaimableJobTakeoverError> {
@@ -229,8 +242,9 @@ where
     );
     let job_idx = JOB_TABLE.insert_job(job_summary).await?;
     let job_detail = JOB_TABLE.get_job(job_idx).await?;
+    let job_id = job_detail.read().await.job_id;
     job.update_callback(generate_callback_closure(job_detail));
-    let job_handle = ClaimableJobHandle::new(take_over_callback_recv, take_over_indicator);
+    let job_handle = ClaimableJobHandle::new(job_id, take_over_callback_recv, take_over_indicator);
     task_queue_sender.send(Box::new(job)).await?;
     Ok(job_handle)
 }
@@ -1,15 +1,17 @@
-use crate::core::PEER_TABLE;
 use crate::core::tasks::jobs::JobClosure;
 use crate::core::tasks::low_level_tasks::{SendControlMessageTask, SendType};
 use crate::core::tasks::task_queue::TaskQueueSender;
+use crate::core::{PEER_TABLE, STATIC_PEERS};
 use crate::err::Result;
-use crate::global_var::ENV_VAR;
+use crate::network::announce_addrs;
 use crate::network::protocol::CUR_LEADER;
 use crate::network::protocol::messages::HelloMessage;
 use crate::network::protocol::messages::hello_message::HelloMode;
+use crate::utilities::identity::get_identity;
 use api_model::protocol::protocol::Protocol;
 use bytes::Bytes;
 use std::future::Future;
+use std::net::SocketAddr;
 
 pub async fn get_job_heartbeat_closure(task_q: &TaskQueueSender) -> Result<Box<JobClosure>> {
     let task_q_sender = task_q.clone();
@@ -30,20 +32,19 @@ pub async fn get_job_heartbeat_closure(task_q: &TaskQueueSender) -> Result<Box<J
                 {
                     let cur_leader_guard = CUR_LEADER.read().await;
                     if let Some(leader) = cur_leader_guard.as_ref() {
-                        if let Some(ev) = ENV_VAR.get() {
-                            if ev.get_mac_addr().eq(leader) {
+                        // The leader is named by its peer identifier, i.e. its key fingerprint
+                        if let Ok(identity) = get_identity() {
+                            if identity.fingerprint().eq(leader) {
                                 hello_mode |= HelloMode::LEADER;
                             }
                         }
                     }
                 }
 
-                let hello_message = HelloMessage::from_env(hello_mode)?;
-                let bytes = Bytes::from(hello_message.serialize());
+                broadcast_hello(&cloned_task_q_sender, hello_mode).await?;
 
-                let task = SendControlMessageTask::new(SendType::Broadcast, bytes.clone());
-
-                cloned_task_q_sender.send(Box::new(task)).await?;
+                let static_peers = STATIC_PEERS.resolve_all().await;
+                send_hello_to(&cloned_task_q_sender, &static_peers, hello_mode).await?;
 
                 Ok(())
             });
@@ -61,14 +62,10 @@ pub async fn get_first_hello_message_closure(task_q: &TaskQueueSender) -> Result
         let fut: std::pin::Pin<Box<dyn Future<Output = Result<()>> + Send + 'static>> =
             Box::pin(async move {
                 // At the beginning of the job, send a broadcast HelloMessage to all nodes in the network. The message requires response.
-                let first_hello_message = HelloMessage::from_env(HelloMode::REQUEST_REPLY)?;
-                let b = Bytes::from(first_hello_message.serialize());
-                cloned_task_q_sender
-                    .send(Box::new(SendControlMessageTask::new(
-                        SendType::Broadcast,
-                        b.clone(),
-                    )))
-                    .await
+                broadcast_hello(&cloned_task_q_sender, HelloMode::REQUEST_REPLY).await?;
+
+                let static_peers = STATIC_PEERS.resolve_all().await;
+                send_hello_to(&cloned_task_q_sender, &static_peers, HelloMode::empty()).await
             });
         fut
     };
@@ -76,6 +73,43 @@ pub async fn get_first_hello_message_closure(task_q: &TaskQueueSender) -> Result
     Ok(Box::new(closure))
 }
 
+/// Broadcast a hello from every address the node announces, each carrying its own address
+pub async fn broadcast_hello(task_q: &TaskQueueSender, mode: HelloMode) -> Result<()> {
+    for from in announce_addrs() {
+        let hello_message = HelloMessage::from_env_at(from.ip, mode)?;
+        task_q
+            .send(Box::new(SendControlMessageTask::new(
+                SendType::Broadcast(from),
+                Bytes::from(hello_message.serialize()),
+            )))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Send a hello directly to each of `addrs`, asking for a reply as they may not have heard of us
+/// otherwise when broadcast does not reach them.
+pub async fn send_hello_to(
+    task_q: &TaskQueueSender,
+    addrs: &[SocketAddr],
+    mode: HelloMode,
+) -> Result<()> {
+    if addrs.is_empty() {
+        return Ok(());
+    }
+    let hello_message = HelloMessage::from_env(mode | HelloMode::REQUEST_REPLY)?;
+    let bytes = Bytes::from(hello_message.serialize());
+    for addr in addrs {
+        task_q
+            .send(Box::new(SendControlMessageTask::new(
+                SendType::Unicast(*addr),
+                bytes.clone(),
+            )))
+            .await?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -83,6 +117,7 @@ mod tests {
     use crate::config::EnvVar;
     use crate::core::tasks::task_queue::{TaskQueue, TaskQueueConfig};
     use crate::global_var::ENV_VAR;
+    use crate::utilities::identity::ensure_test_identity;
 
     // When there are no active peers, the heartbeat closure should still build and run without errors.
     // This also verifies HelloMessage::from_env() path works when ENV_VAR is initialized.
@@ -99,6 +134,7 @@ mod tests {
             let ev = EnvVar::from_config(&cfg)?;
             let _ = ENV_VAR.set(ev);
         }
+        ensure_test_identity();
 
         // Create a task queue; there are no peers so no sends will be enqueued.
         let q = TaskQueue::new(TaskQueueConfig { queue_bound: 8 });
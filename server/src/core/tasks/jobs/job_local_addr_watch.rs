@@ -0,0 +1,70 @@
+use crate::core::tasks::jobs::JobClosure;
+use crate::core::tasks::jobs::job_fs_pull_initiate::resend_pending_pulls;
+use crate::core::tasks::jobs::job_heartbeat::{broadcast_hello, send_hello_to};
+use crate::core::tasks::task_queue::TaskQueueSender;
+use crate::core::{PEER_TABLE, STATIC_PEERS};
+use crate::err::Result;
+use crate::global_var::{ENV_VAR, LOGGER};
+use crate::network::on_local_addr_changed;
+use crate::network::protocol::messages::hello_message::HelloMode;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+
+/// Periodically check that the announced address is still ours, e.g. after moving networks or a
+/// DHCP renewal. On a change, the new address is announced to everyone we know of, and pull
+/// requests still waiting on an answer are asked again so that the answer reaches us.
+pub async fn get_job_local_addr_watch_closure(task_q: &TaskQueueSender) -> Result<Box<JobClosure>> {
+    let task_q_sender = task_q.clone();
+    let closure = move || {
+        let cloned_task_q_sender = task_q_sender.clone();
+        let fut: std::pin::Pin<Box<dyn Future<Output = Result<()>> + Send + 'static>> =
+            Box::pin(async move {
+                let Some(ev) = ENV_VAR.get() else {
+                    return Ok(());
+                };
+                let (old, new) = match ev.refresh_local_addr() {
+                    Ok(Some(change)) => change,
+                    Ok(None) => return Ok(()),
+                    Err(e) => {
+                        // Keep the old address until the host is on a network again
+                        LOGGER.debug(format!("No address to announce: {}", e));
+                        return Ok(());
+                    }
+                };
+                LOGGER.info(format!(
+                    "Local address changed from {} (interface {}) to {} (interface {})",
+                    old.ip, old.if_index, new.ip, new.if_index
+                ));
+                on_local_addr_changed().await?;
+
+                broadcast_hello(&cloned_task_q_sender, HelloMode::REQUEST_REPLY).await?;
+
+                // Peers out of broadcast reach would keep answering the old address otherwise
+                let mut addrs: Vec<SocketAddr> = STATIC_PEERS.resolve_all().await;
+                addrs.extend(
+                    PEER_TABLE
+                        .get_peers()
+                        .await
+                        .iter()
+                        .filter(|p| p.is_active.load(Ordering::Relaxed))
+                        .map(|p| p.socket_addr(p.port)),
+                );
+                addrs.sort();
+                addrs.dedup();
+                send_hello_to(&cloned_task_q_sender, &addrs, HelloMode::empty()).await?;
+
+                let resent = resend_pending_pulls(&cloned_task_q_sender).await?;
+                if resent > 0 {
+                    LOGGER.info(format!(
+                        "Asked again for {} pending pulls from the new address",
+                        resent
+                    ));
+                }
+                Ok(())
+            });
+        fut
+    };
+
+    Ok(Box::new(closure))
+}
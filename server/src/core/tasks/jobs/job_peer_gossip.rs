@@ -0,0 +1,53 @@
+use crate::core::PEER_TABLE;
+use crate::core::tasks::jobs::JobClosure;
+use crate::core::tasks::low_level_tasks::{SendControlMessageTask, SendType};
+use crate::core::tasks::task_queue::TaskQueueSender;
+use crate::err::Result;
+use crate::network::protocol::messages::GossipMessage;
+use crate::network::protocol::messages::hello_message::Capabilities;
+use api_model::protocol::protocol::Protocol;
+use bytes::Bytes;
+use std::future::Future;
+use std::sync::atomic::Ordering;
+
+/// Periodically share the active peers with the active peers that take gossip, so that peers
+/// only some nodes hear from, e.g. reached through a static address or on another subnet,
+/// become known to the whole group.
+pub async fn get_job_peer_gossip_closure(task_q: &TaskQueueSender) -> Result<Box<JobClosure>> {
+    let task_q_sender = task_q.clone();
+    let closure = move || {
+        let cloned_task_q_sender = task_q_sender.clone();
+        let fut: std::pin::Pin<Box<dyn Future<Output = Result<()>> + Send + 'static>> =
+            Box::pin(async move {
+                let active_peers = PEER_TABLE
+                    .get_peers()
+                    .await
+                    .into_iter()
+                    .filter(|p| p.is_active.load(Ordering::Relaxed))
+                    .collect::<Vec<_>>();
+                let recipients = active_peers
+                    .iter()
+                    .filter(|p| p.supports(Capabilities::PEER_GOSSIP))
+                    .collect::<Vec<_>>();
+                // Nothing to learn from a single neighbour about itself
+                if recipients.is_empty() || active_peers.len() < 2 {
+                    return Ok(());
+                }
+
+                let gossip_message = GossipMessage::new(active_peers.iter().map(|p| p.as_ref()))?;
+                let bytes = Bytes::from(gossip_message.serialize());
+                for peer in recipients {
+                    cloned_task_q_sender
+                        .send(Box::new(SendControlMessageTask::new(
+                            SendType::Unicast(peer.socket_addr(peer.port)),
+                            bytes.clone(),
+                        )))
+                        .await?;
+                }
+                Ok(())
+            });
+        fut
+    };
+
+    Ok(Box::new(closure))
+}
@@ -3,16 +3,27 @@ mod job_peer_table_anti_entropy;
 use crate::err::Result;
 pub use job_fs_anti_entropy::{job_fs_inactive_cleanup, job_fs_stale_rescan};
 pub use job_fs_index_dump::get_job_fs_index_dump_closure;
-pub use job_fs_pull_initiate::get_job_fs_pull_initiate_closure;
-pub use job_heartbeat::{get_first_hello_message_closure, get_job_heartbeat_closure};
+pub use job_fs_pull_initiate::{
+    forget_outgoing_pull, get_job_fs_pull_directory_initiate_closure,
+    get_job_fs_pull_initiate_closure,
+};
+pub use job_fs_push_offer::get_job_fs_push_offer_closure;
+pub use job_heartbeat::{
+    get_first_hello_message_closure, get_job_heartbeat_closure, send_hello_to,
+};
+pub use job_local_addr_watch::get_job_local_addr_watch_closure;
+pub use job_peer_gossip::get_job_peer_gossip_closure;
 pub use job_peer_table_anti_entropy::job_peer_table_anti_entropy;
 use std::future::Future;
 use std::pin::Pin;
 mod job_fs_anti_entropy;
 mod job_fs_index_dump;
 mod job_fs_pull_initiate;
+mod job_fs_push_offer;
 pub mod job_genre;
 mod job_heartbeat;
+mod job_local_addr_watch;
+mod job_peer_gossip;
 
 // Re-export claimable job utilities for external modules
 
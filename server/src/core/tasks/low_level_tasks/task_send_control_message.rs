@@ -1,13 +1,28 @@
 use crate::core::tasks::AsyncHandleable;
 use crate::err::Result;
 use crate::global_var::get_msg_sender;
+use crate::network::AnnounceAddr;
 use async_trait::async_trait;
 use bytes::Bytes;
 use std::net::SocketAddr;
 
 pub enum SendType {
-    Broadcast,
+    /// Discovery broadcast from one of the addresses of the node
+    Broadcast(AnnounceAddr),
     Unicast(SocketAddr),
+    /// Unicast the receiver acknowledges, sent again until it does
+    Reliable(SocketAddr),
+}
+
+impl SendType {
+    /// Unicast to `addr`, acknowledged when the receiver supports it
+    pub fn unicast(addr: SocketAddr, reliable: bool) -> Self {
+        if reliable {
+            SendType::Reliable(addr)
+        } else {
+            SendType::Unicast(addr)
+        }
+    }
 }
 
 pub struct SendControlMessageTask {
@@ -30,12 +45,15 @@ impl AsyncHandleable for SendControlMessageTask {
         let bytes = std::mem::take(&mut self.bytes);
 
         match &self.send_type {
-            SendType::Broadcast => {
-                udp_sender.broadcast(bytes).await?;
+            SendType::Broadcast(from) => {
+                udp_sender.broadcast(from, bytes).await?;
             }
             SendType::Unicast(addr) => {
                 udp_sender.send(*addr, bytes).await?;
             }
+            SendType::Reliable(addr) => {
+                udp_sender.send_reliable(*addr, bytes).await?;
+            }
         }
 
         Ok(())
@@ -52,9 +70,9 @@ impl SendControlMessageTask {
     }
 
     #[cfg(test)]
-    fn new_broadcast(bytes: Bytes) -> Self {
+    fn new_broadcast(from: AnnounceAddr, bytes: Bytes) -> Self {
         Self {
-            send_type: SendType::Broadcast,
+            send_type: SendType::Broadcast(from),
             bytes,
         }
     }
@@ -81,7 +99,12 @@ mod tests {
 
     #[test]
     fn drain_bytes_moves_payload_for_broadcast() {
-        let mut task = SendControlMessageTask::new_broadcast(Bytes::from_static(b"bc"));
+        let from = AnnounceAddr {
+            ip: "192.168.1.5".parse().unwrap(),
+            if_index: 2,
+            broadcast: None,
+        };
+        let mut task = SendControlMessageTask::new_broadcast(from, Bytes::from_static(b"bc"));
         let drained = task.drain_bytes_for_test();
         assert_eq!(&drained[..], b"bc");
         assert!(task.bytes.is_empty());
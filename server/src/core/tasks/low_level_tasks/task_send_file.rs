@@ -1,3 +1,4 @@
+use crate::core::PEER_TABLE;
 use crate::core::protocol::file_send;
 use crate::core::protocol::file_sync::FileSync;
 use crate::core::tasks::{AsyncHandleable, JobStatus};
@@ -40,8 +41,19 @@ impl AsyncHandleable for SendFileTask {
                 )
             })?;
 
-        // 2. Validate FileSync by calling is_valid
-        if !sync.is_valid() {
+        // 2. Validate FileSync by calling is_valid, against the clock of the peer if known.
+        // The connection comes from an ephemeral port, so any node on that host may be the peer.
+        let peers = PEER_TABLE
+            .get_peers_by_ip(&self.tcp_conn.peer_addr().ip())
+            .await;
+        let valid = if peers.is_empty() {
+            sync.is_valid(0)
+        } else {
+            peers
+                .iter()
+                .any(|peer| sync.is_valid(peer.clock_offset_ms.unwrap_or(0)))
+        };
+        if !valid {
             LOGGER.warn(format!(
                 "Received invalid/expired FileSync from {} for nonce {:x}",
                 self.tcp_conn.peer_addr(),
@@ -54,6 +66,18 @@ impl AsyncHandleable for SendFileTask {
             .into());
         }
 
+        let recipient = sync.recipient().map_err(|e| {
+            LOGGER.warn(format!(
+                "Received FileSync with an invalid transfer key from {}: {:?}",
+                self.tcp_conn.peer_addr(),
+                e
+            ));
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid transfer key in FileSync",
+            )
+        })?;
+
         // 3 & 4. Check nonce validity and claim the job from file_pull map
         let nonce = sync.nonce();
         let mut pending_pull = match claim_pending_pull(nonce).await {
@@ -86,6 +110,7 @@ impl AsyncHandleable for SendFileTask {
         let res = file_send::send_file(
             nonce,
             pending_pull.temp_file_path.clone(),
+            recipient,
             &mut self.tcp_conn,
         )
         .await;
@@ -2,10 +2,15 @@ mod handlers;
 pub use handlers::AsyncHandleable;
 pub use handlers::NetworkHandleable;
 mod job_summary;
+pub use crate::core::tasks::jobs::forget_outgoing_pull;
+pub use crate::core::tasks::jobs::get_job_fs_pull_directory_initiate_closure;
 pub use crate::core::tasks::jobs::get_job_fs_pull_initiate_closure;
+pub use crate::core::tasks::jobs::get_job_fs_push_offer_closure;
+pub use crate::core::tasks::jobs::send_hello_to;
 use crate::core::tasks::jobs::{
     get_first_hello_message_closure, get_job_fs_index_dump_closure, get_job_heartbeat_closure,
-    job_fs_inactive_cleanup, job_fs_stale_rescan, job_peer_table_anti_entropy,
+    get_job_local_addr_watch_closure, get_job_peer_gossip_closure, job_fs_inactive_cleanup,
+    job_fs_stale_rescan, job_peer_table_anti_entropy,
 };
 pub use crate::core::tasks::low_level_tasks::SendFileTask;
 pub use job_summary::JOB_TABLE;
@@ -17,6 +22,7 @@ mod low_level_tasks;
 pub mod task_queue;
 
 // Re-export public job utilities for external modules
+pub use jobs::JobSummaryStatusCallback;
 pub use jobs::job_genre::claimable_job::{ClaimableJobHandle, launch_claimable_job};
 pub use jobs::job_genre::oneshot_job::launch_oneshot_job;
 pub use jobs::job_genre::periodic_job::launch_periodic_job;
@@ -63,6 +69,24 @@ pub async fn init_jobs(sender: &TaskQueueSender) -> Result<()> {
     )
     .await?;
 
+    let _peer_gossip_job = launch_periodic_job(
+        "Peer table gossip",
+        "Periodically shares active peers with neighbors so that peers out of broadcast reach are learned",
+        get_job_peer_gossip_closure(sender).await?,
+        30,
+        sender.clone(),
+    )
+    .await?;
+
+    let _local_addr_watch_job = launch_periodic_job(
+        "Local address watch",
+        "Periodically checks the announced address is still assigned, and re-announces a new one",
+        get_job_local_addr_watch_closure(sender).await?,
+        5,
+        sender.clone(),
+    )
+    .await?;
+
     let _fs_stable_rescan_job = launch_periodic_job(
         "Stale job rescan",
         "Periodically rescans stale job records from index and updates indices",
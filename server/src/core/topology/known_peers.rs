@@ -0,0 +1,412 @@
+//! Trust-on-first-use pinning of peer identity keys
+//!
+//! The first verified hello of a peer pins its identity key in `.disc/known_peers`, along with
+//! the machine name it was first seen with. Pins are keyed by the key fingerprint, so a pinned
+//! identity is recognized whatever name or address it announces later, its hellos being signed.
+//! A new key announcing the name of a pinned identity is refused until it is explicitly trusted,
+//! so that a node on the same network cannot pass for a known peer by taking its name, and
+//! hellos without any key are refused as soon as one identity is pinned.
+
+use crate::err::Result;
+use crate::global_var::LOGGER;
+use crate::utilities::identity::fingerprint_of;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+/// Refused keys remembered at most, the oldest ones are forgotten first past this bound
+const MAX_REFUSED: usize = 256;
+/// Seconds a refused key stays available to `peer trust`, it is recorded again on its next hello
+const REFUSED_TTL_SECS: u64 = 7 * 24 * 3600;
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownPeer {
+    /// Name the key was first seen with
+    pub peer_name: String,
+    pub public_key: Bytes,
+    /// Seconds since UNIX epoch when the key was pinned, or refused
+    pub pinned_at_secs: u64,
+}
+
+impl KnownPeer {
+    fn new(peer_name: String, public_key: Bytes) -> Self {
+        Self {
+            peer_name,
+            public_key,
+            pinned_at_secs: now_secs(),
+        }
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint_of(&self.public_key)
+    }
+
+    // One pin per line: name, public key and pin time separated by tabs
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}",
+            self.peer_name,
+            hex::encode(&self.public_key),
+            self.pinned_at_secs
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let peer_name = fields.next()?.to_string();
+        let public_key = hex::decode(fields.next()?).ok()?;
+        let pinned_at_secs = fields.next()?.parse().ok()?;
+        if fields.next().is_some() || peer_name.is_empty() {
+            return None;
+        }
+        Some(Self {
+            peer_name,
+            public_key: public_key.into(),
+            pinned_at_secs,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Pins {
+    /// Pinned identities, by key fingerprint
+    pinned: HashMap<String, KnownPeer>,
+    /// Keys refused for announcing the name of a pinned identity, by key fingerprint, so that
+    /// they can be trusted by hand
+    refused: HashMap<String, KnownPeer>,
+}
+
+impl Pins {
+    /// Record the refused key with `fingerprint`, forgetting the refusals older than
+    /// `REFUSED_TTL_SECS` and the oldest one past `MAX_REFUSED`
+    fn remember_refused(&mut self, fingerprint: String, refused: KnownPeer, now_secs: u64) {
+        self.refused
+            .retain(|_, r| now_secs.saturating_sub(r.pinned_at_secs) < REFUSED_TTL_SECS);
+        if !self.refused.contains_key(&fingerprint)
+            && self.refused.len() >= MAX_REFUSED
+            && let Some(oldest) = self
+                .refused
+                .iter()
+                .min_by_key(|(_, r)| r.pinned_at_secs)
+                .map(|(f, _)| f.clone())
+        {
+            self.refused.remove(&oldest);
+        }
+        self.refused.insert(fingerprint, refused);
+    }
+}
+
+pub struct KnownPeers {
+    path: Option<PathBuf>,
+    pins: RwLock<Pins>,
+}
+
+impl KnownPeers {
+    /// Store kept in memory only, for tests
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            pins: RwLock::new(Pins::default()),
+        }
+    }
+
+    /// Load the pins stored at `path`, starting empty if the file does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut pins = Pins::default();
+        if path.exists() {
+            for (line_no, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                match KnownPeer::from_line(line) {
+                    Some(known) => {
+                        pins.pinned.insert(known.fingerprint(), known);
+                    }
+                    None => {
+                        return Err(format!(
+                            "{}:{}: malformed known peer entry",
+                            path.display(),
+                            line_no + 1
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            pins: RwLock::new(pins),
+        })
+    }
+
+    async fn persist(&self, pins: &Pins) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut entries: Vec<&KnownPeer> = pins.pinned.values().collect();
+        entries.sort_by_key(|known| (known.peer_name.clone(), known.fingerprint()));
+        let mut content = String::from("# Peer identity keys pinned on first contact\n");
+        for known in entries {
+            content.push_str(&known.to_line());
+            content.push('\n');
+        }
+        // Write aside then rename, a crash never leaves a truncated file behind
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, content).await?;
+        tokio::fs::rename(&tmp_path, path).await?;
+        Ok(())
+    }
+
+    /// Check the key announced along with `peer_name` against the pins, pinning it on first
+    /// contact. Nodes predating identity keys are only let through while nothing is pinned.
+    pub async fn check(&self, peer_name: &str, public_key: Option<&Bytes>) -> Result<()> {
+        let mut pins = self.pins.write().await;
+        let Some(key) = public_key else {
+            if pins.pinned.is_empty() {
+                return Ok(());
+            }
+            return Err(format!("Peer {} did not announce any identity key", peer_name).into());
+        };
+
+        let fingerprint = fingerprint_of(key);
+        if pins.pinned.contains_key(&fingerprint) {
+            return Ok(());
+        }
+        if let Some(known) = pins.pinned.values().find(|k| k.peer_name == peer_name) {
+            let err = format!(
+                "Identity key of peer {} changed from {} to {}, run `peer trust {}` if this is expected",
+                peer_name,
+                known.fingerprint(),
+                fingerprint,
+                fingerprint
+            );
+            pins.remember_refused(
+                fingerprint,
+                KnownPeer::new(peer_name.to_string(), key.clone()),
+                now_secs(),
+            );
+            return Err(err.into());
+        }
+
+        let known = KnownPeer::new(peer_name.to_string(), key.clone());
+        LOGGER.info(format!(
+            "Pinned identity key {} of new peer {}",
+            fingerprint, peer_name
+        ));
+        pins.pinned.insert(fingerprint, known);
+        self.persist(&pins).await
+    }
+
+    /// Check a key relayed by another peer for `peer_name` against the pins, without pinning it:
+    /// relayed keys are unverified hints until their owner signs a hello with them.
+    /// Refused when another key is pinned for that name.
+    pub async fn check_relayed(&self, peer_name: &str, public_key: &Bytes) -> Result<()> {
+        let pins = self.pins.read().await;
+        let fingerprint = fingerprint_of(public_key);
+        if pins.pinned.contains_key(&fingerprint) {
+            return Ok(());
+        }
+        match pins.pinned.values().find(|k| k.peer_name == peer_name) {
+            Some(known) => Err(format!(
+                "Relayed identity key {} of peer {} is not the pinned one {}",
+                fingerprint,
+                peer_name,
+                known.fingerprint()
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Pin the refused key with `fingerprint`, in place of the key pinned for its name
+    pub async fn trust(&self, fingerprint: &str) -> Result<KnownPeer> {
+        let mut pins = self.pins.write().await;
+        let refused = pins
+            .refused
+            .remove(fingerprint)
+            .ok_or_else(|| format!("No refused key {} to trust", fingerprint))?;
+        pins.pinned.retain(|_, k| k.peer_name != refused.peer_name);
+        let known = KnownPeer::new(refused.peer_name, refused.public_key);
+        pins.pinned.insert(fingerprint.to_string(), known.clone());
+        self.persist(&pins).await?;
+        Ok(known)
+    }
+
+    /// Drop the pin of the key with `fingerprint`, the next key announced with its name is
+    /// pinned again
+    pub async fn forget(&self, fingerprint: &str) -> Result<KnownPeer> {
+        let mut pins = self.pins.write().await;
+        let known = pins
+            .pinned
+            .remove(fingerprint)
+            .ok_or_else(|| format!("Identity key {} is not pinned", fingerprint))?;
+        pins.refused.retain(|_, k| k.peer_name != known.peer_name);
+        self.persist(&pins).await?;
+        Ok(known)
+    }
+
+    /// All pins, along with the last key refused for their name if any
+    pub async fn list(&self) -> Vec<(KnownPeer, Option<Bytes>)> {
+        let pins = self.pins.read().await;
+        let mut entries: Vec<_> = pins
+            .pinned
+            .values()
+            .map(|known| {
+                let refused = pins
+                    .refused
+                    .values()
+                    .filter(|r| r.peer_name == known.peer_name)
+                    .max_by_key(|r| r.pinned_at_secs)
+                    .map(|r| r.public_key.clone());
+                (known.clone(), refused)
+            })
+            .collect();
+        entries.sort_by_key(|(known, _)| (known.peer_name.clone(), known.fingerprint()));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::identity::NodeIdentity;
+    use crate::utilities::temp_dir::tmp_dir;
+
+    #[tokio::test]
+    async fn first_key_is_pinned_and_a_new_one_refused_until_trusted() {
+        let store = KnownPeers::in_memory();
+        let laptop = NodeIdentity::generate().public_key();
+        let impostor = NodeIdentity::generate().public_key();
+
+        assert!(store.check("alice", Some(&laptop)).await.is_ok());
+        assert!(store.check("alice", Some(&laptop)).await.is_ok());
+        assert!(store.check("alice", Some(&impostor)).await.is_err());
+        // Dropping the key does not get around the pin either, whatever the name
+        assert!(store.check("alice", None).await.is_err());
+        assert!(store.check("bob", None).await.is_err());
+
+        let listed = store.list().await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0.public_key, laptop);
+        assert_eq!(listed[0].1, Some(impostor.clone()));
+
+        // Trust goes by the fingerprint of the refused key, not by the name it claims
+        assert!(store.trust("alice").await.is_err());
+        store.trust(&fingerprint_of(&impostor)).await.unwrap();
+        assert!(store.check("alice", Some(&impostor)).await.is_ok());
+        assert!(store.check("alice", Some(&laptop)).await.is_err());
+        assert!(store.trust(&fingerprint_of(&impostor)).await.is_err());
+    }
+
+    #[test]
+    fn refused_keys_are_bounded_and_expire() {
+        let mut pins = Pins::default();
+        let refused_at = |secs: u64| KnownPeer {
+            peer_name: "alice".to_string(),
+            public_key: Bytes::copy_from_slice(&secs.to_be_bytes()),
+            pinned_at_secs: secs,
+        };
+        for secs in 0..MAX_REFUSED as u64 + 1 {
+            pins.remember_refused(secs.to_string(), refused_at(secs), secs);
+        }
+        assert_eq!(pins.refused.len(), MAX_REFUSED);
+        assert!(!pins.refused.contains_key("0"));
+        assert!(pins.refused.contains_key(&MAX_REFUSED.to_string()));
+
+        // Refusals past their time are dropped with the next one
+        let later = MAX_REFUSED as u64 + REFUSED_TTL_SECS;
+        pins.remember_refused("late".to_string(), refused_at(later), later);
+        assert_eq!(pins.refused.len(), 1);
+        assert!(pins.refused.contains_key("late"));
+    }
+
+    #[tokio::test]
+    async fn pinned_identity_is_known_whatever_name_it_announces() {
+        let store = KnownPeers::in_memory();
+        // Nodes predating identity keys pass only while nothing is pinned
+        assert!(store.check("old", None).await.is_ok());
+
+        let laptop = NodeIdentity::generate().public_key();
+        store.check("alice", Some(&laptop)).await.unwrap();
+        assert!(store.check("alice renamed", Some(&laptop)).await.is_ok());
+        let listed = store.list().await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0.peer_name, "alice");
+
+        // Another key taking a fresh name is another identity
+        let desktop = NodeIdentity::generate().public_key();
+        assert!(store.check("bob", Some(&desktop)).await.is_ok());
+        assert_eq!(store.list().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn relayed_keys_are_checked_but_never_pinned() {
+        let store = KnownPeers::in_memory();
+        let laptop = NodeIdentity::generate().public_key();
+        let impostor = NodeIdentity::generate().public_key();
+
+        assert!(store.check_relayed("alice", &impostor).await.is_ok());
+        assert!(store.list().await.is_empty());
+
+        store.check("alice", Some(&laptop)).await.unwrap();
+        assert!(store.check_relayed("alice", &laptop).await.is_ok());
+        assert!(store.check_relayed("alice", &impostor).await.is_err());
+        // Nor is the relayed key recorded as refused, only its owner can get it trusted
+        assert_eq!(store.list().await[0].1, None);
+    }
+
+    #[tokio::test]
+    async fn forgotten_peer_is_pinned_again_on_next_contact() {
+        let store = KnownPeers::in_memory();
+        let old_key = NodeIdentity::generate().public_key();
+        let new_key = NodeIdentity::generate().public_key();
+
+        store.check("alice", Some(&old_key)).await.unwrap();
+        assert!(store.forget("alice").await.is_err());
+        store.forget(&fingerprint_of(&old_key)).await.unwrap();
+        assert!(store.forget(&fingerprint_of(&old_key)).await.is_err());
+        assert!(store.check("alice", Some(&new_key)).await.is_ok());
+        assert_eq!(store.list().await[0].0.public_key, new_key);
+    }
+
+    #[tokio::test]
+    async fn pins_survive_a_reload() {
+        let ws = tmp_dir("known_peers");
+        let path = ws.join("known_peers");
+        let key = NodeIdentity::generate().public_key();
+
+        let store = KnownPeers::load(&path).unwrap();
+        store.check("alice laptop", Some(&key)).await.unwrap();
+
+        let reloaded = KnownPeers::load(&path).unwrap();
+        let listed = reloaded.list().await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0.peer_name, "alice laptop");
+        assert_eq!(listed[0].0.public_key, key);
+        assert!(
+            reloaded
+                .check("alice laptop", Some(&NodeIdentity::generate().public_key()))
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn malformed_store_is_rejected() {
+        let ws = tmp_dir("known_peers");
+        let path = ws.join("known_peers");
+        std::fs::write(&path, "alice\tnot-hex\t0\n").unwrap();
+        assert!(KnownPeers::load(&path).is_err());
+    }
+}
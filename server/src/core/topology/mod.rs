@@ -1,11 +1,34 @@
 use crate::err::Result;
-use std::sync::LazyLock;
+use crate::global_var::ENV_VAR;
+use std::path::Path;
+use std::sync::{LazyLock, OnceLock};
 
+mod known_peers;
 mod peer_table;
+pub mod static_peers;
+pub use known_peers::{KnownPeer, KnownPeers};
 pub use peer_table::{Peer, PeerTable};
+pub use static_peers::StaticPeers;
 
 pub static PEER_TABLE: LazyLock<PeerTable> = LazyLock::new(|| PeerTable::new());
+pub static KNOWN_PEERS: OnceLock<KnownPeers> = OnceLock::new();
+pub static STATIC_PEERS: LazyLock<StaticPeers> = LazyLock::new(|| StaticPeers::new());
 
-pub fn init_topology() -> Result<&'static PeerTable> {
+pub async fn init_topology() -> Result<&'static PeerTable> {
+    let env_var = ENV_VAR.get().ok_or("Environment variables not set")?;
+    let path = env_var.get_known_peers_path();
+    let known_peers = KnownPeers::load(Path::new(&path))?;
+    KNOWN_PEERS
+        .set(known_peers)
+        .map_err(|_| "Known peers already loaded")?;
+    for entry in env_var.get_static_peers() {
+        STATIC_PEERS.add(entry, env_var.get_port()).await;
+    }
     Ok(&PEER_TABLE)
 }
+
+pub fn get_known_peers() -> Result<&'static KnownPeers> {
+    KNOWN_PEERS
+        .get()
+        .ok_or_else(|| "Known peers not loaded".into())
+}
@@ -1,20 +1,34 @@
 use crate::config::APP_CONFIG;
+use crate::config::SelectiveSync;
+use crate::constants::{TCP_FILE_PORT, UPD_MESSAGE_PORT};
 use crate::err::Result;
+use crate::network::protocol::messages::hello_message::Capabilities;
+use crate::network::socket_addr_in_scope;
+use crate::utilities::identity::verify_signature;
+use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::hash::Hash;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 
 pub struct Peer {
+    /// Fingerprint of the identity key, or the MAC address for nodes predating identity keys
     pub identifier: String,
 
     pub peer_name: String,
     pub peer_addr: IpAddr,
+    /// Interface the peer is reached through, only meaningful for IPv6 link-local addresses
+    pub scope_id: u32,
+    /// UDP port the peer listens on for control messages
+    pub port: u16,
+    /// TCP port the peer serves file transfers on
+    pub file_port: u16,
 
     pub is_main: AtomicBool,
     pub is_active: AtomicBool,
@@ -22,6 +36,21 @@ pub struct Peer {
     pub last_seen_ms: AtomicU64,
     /// Minutes east of UTC (UTC = 0). Explicitly stores timezone offset for last_seen.
     pub last_seen_tz_offset_minutes: AtomicI32,
+
+    /// Subtrees the peer advertised it keeps in sync
+    pub subscription: SelectiveSync,
+
+    /// Protocol version the peer advertised, 0 for nodes predating the negotiation
+    pub protocol_version: u32,
+    /// Optional features the peer advertised
+    pub capabilities: Capabilities,
+    /// Identity key the peer advertised, used to check the signature of its messages
+    pub public_key: Option<Bytes>,
+    /// Offset of the peer clock from ours in ms, positive when it is ahead.
+    /// Unknown for nodes whose hellos carry no timestamp.
+    pub clock_offset_ms: Option<i64>,
+    /// Number of gossip relays the peer was learned through, 0 when heard from directly
+    pub hops: u8,
 }
 
 impl Debug for Peer {
@@ -69,36 +98,126 @@ impl Peer {
             identifier,
             peer_name,
             peer_addr,
+            scope_id: 0,
+            port: UPD_MESSAGE_PORT,
+            file_port: TCP_FILE_PORT,
             is_main: AtomicBool::new(is_main),
             is_active: AtomicBool::new(true),
             last_seen_ms: AtomicU64::new(now_ms),
             last_seen_tz_offset_minutes: AtomicI32::new(0),
+            subscription: SelectiveSync::default(),
+            protocol_version: 0,
+            capabilities: Capabilities::empty(),
+            public_key: None,
+            clock_offset_ms: None,
+            hops: 0,
         }
     }
 
-    /// return true if the peer hasn't expired
-    pub async fn peer_valid(&self) -> bool {
-        // 1. Read peer expiration from config (seconds). Use non-blocking try_read; fallback to default 60s
-        let peer_expires_after_in_sec: u64 = APP_CONFIG.get_peer_expires_after_in_sec().await;
+    pub fn with_scope_id(mut self, scope_id: u32) -> Self {
+        self.scope_id = scope_id;
+        self
+    }
 
-        // 2. Get current UTC time in milliseconds since UNIX_EPOCH
-        let now_ms_utc: u64 = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as u64;
+    pub fn with_ports(mut self, port: u16, file_port: u16) -> Self {
+        self.port = port;
+        self.file_port = file_port;
+        self
+    }
+
+    /// Address of the peer socket listening on `port`
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        socket_addr_in_scope(self.peer_addr, port, self.scope_id)
+    }
+
+    pub fn with_subscription(mut self, subscription: SelectiveSync) -> Self {
+        self.subscription = subscription;
+        self
+    }
+
+    pub fn with_protocol(mut self, protocol_version: u32, capabilities: Capabilities) -> Self {
+        self.protocol_version = protocol_version;
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_public_key(mut self, public_key: Option<Bytes>) -> Self {
+        self.public_key = public_key;
+        self
+    }
+
+    pub fn with_clock_offset(mut self, clock_offset_ms: Option<i64>) -> Self {
+        self.clock_offset_ms = clock_offset_ms;
+        self
+    }
+
+    pub fn with_hops(mut self, hops: u8) -> Self {
+        self.hops = hops;
+        self
+    }
 
-        // 3. Convert last_seen to UTC using its timezone offset (minutes east of UTC)
+    /// Set when the peer was last seen, in ms since UNIX epoch on our clock
+    pub fn with_last_seen_ms(self, last_seen_ms: u64) -> Self {
+        self.last_seen_ms.store(last_seen_ms, Ordering::Relaxed);
+        self.last_seen_tz_offset_minutes.store(0, Ordering::Relaxed);
+        self
+    }
+
+    /// return true if the peer advertised the given features and this node supports them too
+    pub fn supports(&self, capabilities: Capabilities) -> bool {
+        Capabilities::local().contains(capabilities) && self.capabilities.contains(capabilities)
+    }
+
+    /// Last seen time in ms since UNIX epoch, UTC
+    pub fn last_seen_utc_ms(&self) -> u64 {
+        // Convert last_seen to UTC using its timezone offset (minutes east of UTC)
         let last_seen_local_ms = self.last_seen_ms.load(Ordering::Relaxed);
         let tz_offset_min = self.last_seen_tz_offset_minutes.load(Ordering::Relaxed);
         let offset_ms: i128 = (tz_offset_min as i128) * 60_000i128; // minutes -> ms
 
         // local time = UTC + offset => UTC = local - offset
-        let last_seen_utc_ms: u64 = if offset_ms >= 0 {
+        if offset_ms >= 0 {
             last_seen_local_ms.saturating_sub(offset_ms as u64)
         } else {
             // negative offset (west of UTC): subtracting a negative => add
             last_seen_local_ms.saturating_add((-offset_ms) as u64)
-        };
+        }
+    }
+
+    /// Whether this entry should replace `existing`, an entry for the same peer.
+    /// Hellos heard directly always win. Entries learned through gossip are mere hints: they
+    /// never replace an entry heard directly from a peer owning an identity key, nor an active
+    /// one, and replace another hint only when they are fresher, or as fresh but fewer hops away.
+    fn supersedes(&self, existing: &Peer) -> bool {
+        if self.hops == 0 {
+            return true;
+        }
+        if existing.hops == 0 && existing.public_key.is_some() {
+            return false;
+        }
+        if !existing.is_active.load(Ordering::Relaxed) {
+            return true;
+        }
+        if existing.hops == 0 {
+            return false;
+        }
+        let (ours, theirs) = (self.last_seen_utc_ms(), existing.last_seen_utc_ms());
+        ours > theirs || (ours == theirs && self.hops < existing.hops)
+    }
+
+    /// return true if the peer hasn't expired
+    pub async fn peer_valid(&self) -> bool {
+        // 1. Read peer expiration from config (seconds). Use non-blocking try_read; fallback to default 60s
+        let peer_expires_after_in_sec: u64 = APP_CONFIG.get_peer_expires_after_in_sec().await;
+
+        // 2. Get current UTC time in milliseconds since UNIX_EPOCH
+        let now_ms_utc: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        // 3. Convert last_seen to UTC
+        let last_seen_utc_ms = self.last_seen_utc_ms();
 
         let expires_ms = peer_expires_after_in_sec.saturating_mul(1000);
         let valid_until_ms = last_seen_utc_ms.saturating_add(expires_ms);
@@ -127,10 +246,17 @@ impl PeerTable {
         Ok(())
     }
 
-    pub async fn update_peer(&self, peer: Peer) -> Result<()> {
+    /// Insert or replace the entry of the peer, following the freshness rules of
+    /// [`Peer::supersedes`]. Returns false if the entry already known was kept instead.
+    pub async fn update_peer(&self, peer: Peer) -> Result<bool> {
         let mut table = self.peers.write().await;
+        if let Some(existing) = table.get(&peer.identifier)
+            && !peer.supersedes(existing)
+        {
+            return Ok(false);
+        }
         table.insert(peer.identifier.clone(), Arc::new(peer));
-        Ok(())
+        Ok(true)
     }
 
     pub async fn get_peer(&self, identifier: &str) -> Option<Arc<Peer>> {
@@ -147,6 +273,66 @@ impl PeerTable {
         }
     }
 
+    /// Find the active peer listening on `addr`, several nodes may share a host
+    pub async fn get_peer_by_addr(&self, addr: &SocketAddr) -> Option<Arc<Peer>> {
+        let table = self.peers.read().await;
+        table
+            .values()
+            .find(|peer| {
+                peer.is_active.load(Ordering::Relaxed)
+                    && peer.peer_addr == addr.ip()
+                    && peer.port == addr.port()
+            })
+            .cloned()
+    }
+
+    /// Active peers at `ip`, whatever port they listen on
+    pub async fn get_peers_by_ip(&self, ip: &IpAddr) -> Vec<Arc<Peer>> {
+        let table = self.peers.read().await;
+        table
+            .values()
+            .filter(|peer| peer.is_active.load(Ordering::Relaxed) && &peer.peer_addr == ip)
+            .cloned()
+            .collect()
+    }
+
+    /// Check a message claimed to be sent from `from_ip` against the identity keys of the peers
+    /// heard from directly at that address, the key tells apart nodes sharing a host. Keys
+    /// relayed through gossip are unverified and never vouch for a message.
+    /// Returns the peer whose key made the signature, and an error if no peer owning an identity
+    /// key is known at that address, or if the signature is missing or forged.
+    pub async fn check_signature(
+        &self,
+        from_ip: &str,
+        signed: &[u8],
+        signature: Option<&Bytes>,
+    ) -> Result<Arc<Peer>> {
+        let addr = IpAddr::from_str(from_ip)?;
+        let keyed: Vec<_> = self
+            .get_peers_by_ip(&addr)
+            .await
+            .into_iter()
+            .filter(|peer| peer.hops == 0 && peer.public_key.is_some())
+            .collect();
+        if keyed.is_empty() {
+            return Err(format!(
+                "Message from unknown peer or peer without identity key {}",
+                from_ip
+            )
+            .into());
+        }
+        let signature =
+            signature.ok_or_else(|| format!("Unsigned message from peer {}", from_ip))?;
+        keyed
+            .into_iter()
+            .find(|peer| {
+                peer.public_key
+                    .as_ref()
+                    .is_some_and(|key| verify_signature(key, signed, signature).is_ok())
+            })
+            .ok_or_else(|| format!("Invalid signature from peer {}", from_ip).into())
+    }
+
     /// Promote the peer to be the main node
     pub async fn promote_peer(&self, identifier: &str) -> Result<()> {
         let table = self.peers.read().await;
@@ -460,4 +646,250 @@ mod tests {
         // Also ensure we can concurrently read while it runs (no deadlock). Do a quick read now.
         let _ = table.peers.read().await; // should not hang
     }
+
+    #[tokio::test]
+    async fn check_signature_requires_the_key_of_the_peer_at_the_address() {
+        use crate::utilities::identity::NodeIdentity;
+        let table = PeerTable::new();
+        let alice = NodeIdentity::generate();
+        table
+            .update_peer(
+                Peer::new(
+                    alice.fingerprint(),
+                    "alice".into(),
+                    "10.0.0.7".parse().unwrap(),
+                    false,
+                )
+                .with_public_key(Some(alice.public_key())),
+            )
+            .await
+            .unwrap();
+
+        let signed = b"PULL\x0010.0.0.7\x00request";
+        let signature = alice.sign(signed);
+        let peer = table
+            .check_signature("10.0.0.7", signed, Some(&signature))
+            .await
+            .unwrap();
+        assert_eq!(peer.identifier, alice.fingerprint());
+
+        let forged = NodeIdentity::generate().sign(signed);
+        assert!(
+            table
+                .check_signature("10.0.0.7", signed, Some(&forged))
+                .await
+                .is_err()
+        );
+        assert!(
+            table
+                .check_signature("10.0.0.7", signed, None)
+                .await
+                .is_err()
+        );
+        // Unknown senders and peers without an identity key cannot be trusted either
+        assert!(
+            table
+                .check_signature("10.0.0.8", signed, Some(&signature))
+                .await
+                .is_err()
+        );
+        table
+            .update_peer(Peer::new(
+                "bob".into(),
+                "bob".into(),
+                "10.0.0.9".parse().unwrap(),
+                false,
+            ))
+            .await
+            .unwrap();
+        assert!(
+            table
+                .check_signature("10.0.0.9", signed, Some(&signature))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn gossiped_entries_only_replace_staler_gossip() {
+        let table = PeerTable::new();
+        let addr: IpAddr = "10.1.0.7".parse().unwrap();
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let gossiped = |hops: u8, last_seen_ms: u64| {
+            Peer::new("aa".into(), "n".into(), addr, false)
+                .with_hops(hops)
+                .with_last_seen_ms(last_seen_ms)
+        };
+
+        assert!(
+            table
+                .update_peer(gossiped(2, now_ms - 10_000))
+                .await
+                .unwrap()
+        );
+        // Staler, or as fresh and further away
+        assert!(
+            !table
+                .update_peer(gossiped(1, now_ms - 20_000))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !table
+                .update_peer(gossiped(3, now_ms - 10_000))
+                .await
+                .unwrap()
+        );
+        // As fresh but closer, then fresher
+        assert!(
+            table
+                .update_peer(gossiped(1, now_ms - 10_000))
+                .await
+                .unwrap()
+        );
+        assert!(
+            table
+                .update_peer(gossiped(2, now_ms - 5_000))
+                .await
+                .unwrap()
+        );
+        assert_eq!(table.get_peer("aa").await.unwrap().hops, 2);
+
+        // Hearing from the peer directly always wins, and gossip no longer replaces it
+        let direct = Peer::new("aa".into(), "n".into(), addr, false);
+        direct
+            .last_seen_ms
+            .store(now_ms - 30_000, Ordering::Relaxed);
+        assert!(table.update_peer(direct).await.unwrap());
+        assert!(!table.update_peer(gossiped(1, now_ms)).await.unwrap());
+        assert_eq!(table.get_peer("aa").await.unwrap().hops, 0);
+
+        // Unless the direct entry went inactive
+        table.disable_peer("aa").await.unwrap();
+        assert!(table.update_peer(gossiped(1, now_ms)).await.unwrap());
+        assert_eq!(table.get_peer("aa").await.unwrap().hops, 1);
+    }
+
+    #[tokio::test]
+    async fn gossiped_keys_never_vouch_for_a_message() {
+        use crate::utilities::identity::NodeIdentity;
+        let table = PeerTable::new();
+        let mallory = NodeIdentity::generate();
+        let hint = Peer::new(
+            mallory.fingerprint(),
+            "alice".into(),
+            "10.0.0.7".parse().unwrap(),
+            false,
+        )
+        .with_public_key(Some(mallory.public_key()))
+        .with_hops(1);
+        table.update_peer(hint).await.unwrap();
+
+        let signed = b"PULL\x0010.0.0.7\x00request";
+        let signature = mallory.sign(signed);
+        assert!(
+            table
+                .check_signature("10.0.0.7", signed, Some(&signature))
+                .await
+                .is_err()
+        );
+
+        // Until the peer proves it owns the key in a hello of its own
+        let direct = Peer::new(
+            mallory.fingerprint(),
+            "alice".into(),
+            "10.0.0.7".parse().unwrap(),
+            false,
+        )
+        .with_public_key(Some(mallory.public_key()));
+        table.update_peer(direct).await.unwrap();
+        assert!(
+            table
+                .check_signature("10.0.0.7", signed, Some(&signature))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn keyed_peers_only_move_with_their_own_hellos() {
+        use crate::utilities::identity::NodeIdentity;
+        let table = PeerTable::new();
+        let key = NodeIdentity::generate().public_key();
+        let at = |addr: &str, hops: u8| {
+            Peer::new("aa".into(), "n".into(), addr.parse().unwrap(), false)
+                .with_public_key(Some(key.clone()))
+                .with_hops(hops)
+        };
+        table.update_peer(at("10.2.0.7", 0)).await.unwrap();
+        table.disable_peer("aa").await.unwrap();
+
+        // Not even through gossip about a peer gone inactive, nor at the same address
+        assert!(!table.update_peer(at("10.2.0.66", 1)).await.unwrap());
+        assert!(!table.update_peer(at("10.2.0.7", 1)).await.unwrap());
+        assert!(table.update_peer(at("10.2.0.8", 0)).await.unwrap());
+        assert_eq!(
+            table.get_peer("aa").await.unwrap().peer_addr,
+            "10.2.0.8".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn get_peer_by_addr_skips_inactive_peers() {
+        let table = PeerTable::new();
+        let addr: SocketAddr = "10.0.0.7:14514".parse().unwrap();
+        table
+            .update_peer(Peer::new("aa".into(), "n".into(), addr.ip(), false))
+            .await
+            .unwrap();
+
+        let found = table.get_peer_by_addr(&addr).await;
+        assert_eq!(found.map(|p| p.identifier.clone()), Some("aa".to_string()));
+
+        table.disable_peer("aa").await.unwrap();
+        assert!(table.get_peer_by_addr(&addr).await.is_none());
+        assert!(
+            table
+                .get_peer_by_addr(&"10.0.0.8:14514".parse().unwrap())
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn nodes_sharing_a_host_are_told_apart() {
+        use crate::utilities::identity::NodeIdentity;
+        let table = PeerTable::new();
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+        let alice = NodeIdentity::generate();
+        let bob = NodeIdentity::generate();
+        for (identity, port) in [(&alice, 14514), (&bob, 24514)] {
+            table
+                .update_peer(
+                    Peer::new(identity.fingerprint(), "n".into(), ip, false)
+                        .with_public_key(Some(identity.public_key()))
+                        .with_ports(port, 11451),
+                )
+                .await
+                .unwrap();
+        }
+
+        let at = |port| SocketAddr::new(ip, port);
+        let found = table.get_peer_by_addr(&at(24514)).await.unwrap();
+        assert_eq!(found.identifier, bob.fingerprint());
+        assert!(table.get_peer_by_addr(&at(34514)).await.is_none());
+        assert_eq!(table.get_peers_by_ip(&ip).await.len(), 2);
+
+        // The signature picks the sender among the nodes at the address
+        let signed = b"PULL\x0010.0.0.7\x00request";
+        let signature = bob.sign(signed);
+        let sender = table
+            .check_signature("10.0.0.7", signed, Some(&signature))
+            .await
+            .unwrap();
+        assert_eq!(sender.port, 24514);
+    }
 }
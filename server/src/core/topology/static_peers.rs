@@ -0,0 +1,136 @@
+//! Peers reached without broadcast
+//!
+//! Broadcast does not cross VLANs, VPNs and many Wi-Fi networks. The peers listed in the config,
+//! or added at runtime through the API and saved to it, are sent hellos directly and asked to reply,
+//! which makes each side known to the other.
+
+use crate::err::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::timeout;
+
+/// Time given to the resolution of an entry
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub struct StaticPeers {
+    /// Entries in `host:port` form, resolved again before every use so that names follow
+    /// address changes
+    entries: RwLock<Vec<String>>,
+}
+
+impl StaticPeers {
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Add `entry`, completed with `default_port` when it has no port.
+    /// Returns the completed entry, or `None` if it was already listed.
+    pub async fn add(&self, entry: &str, default_port: u16) -> Option<String> {
+        let entry = with_default_port(entry, default_port);
+        let mut entries = self.entries.write().await;
+        if entries.contains(&entry) {
+            return None;
+        }
+        entries.push(entry.clone());
+        Some(entry)
+    }
+
+    pub async fn list(&self) -> Vec<String> {
+        self.entries.read().await.clone()
+    }
+
+    /// Addresses of every entry, entries that cannot be resolved right now are skipped.
+    /// Entries are resolved concurrently, so that a slow name server delays the heartbeat by
+    /// at most `RESOLVE_TIMEOUT`.
+    pub async fn resolve_all(&self) -> Vec<SocketAddr> {
+        let lookups: Vec<_> = self
+            .list()
+            .await
+            .into_iter()
+            .map(|entry| {
+                tokio::spawn(timeout(
+                    RESOLVE_TIMEOUT,
+                    async move { resolve(&entry).await },
+                ))
+            })
+            .collect();
+        let mut addrs = Vec::new();
+        for lookup in lookups {
+            if let Ok(Ok(Ok(resolved))) = lookup.await {
+                addrs.extend(resolved);
+            }
+        }
+        addrs
+    }
+}
+
+/// Complete `entry` with `default_port` when it is a bare host name or IP address
+pub fn with_default_port(entry: &str, default_port: u16) -> String {
+    let entry = entry.trim();
+    if entry.parse::<SocketAddr>().is_ok() {
+        return entry.to_string();
+    }
+    // IPv6 addresses contain colons, so they can only be told apart from `host:port` by parsing
+    if let Ok(ip) = entry.parse::<IpAddr>() {
+        return SocketAddr::new(ip, default_port).to_string();
+    }
+    if entry.contains(':') {
+        return entry.to_string();
+    }
+    format!("{}:{}", entry, default_port)
+}
+
+/// Addresses `entry` resolves to, an error if it resolves to none
+pub async fn resolve(entry: &str) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host(entry)
+        .await
+        .map_err(|e| format!("Unable to resolve peer address '{}': {}", entry, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("Peer address '{}' resolves to no address", entry).into());
+    }
+    Ok(addrs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_without_port_get_the_default_one() {
+        assert_eq!(with_default_port("10.1.2.3", 14514), "10.1.2.3:14514");
+        assert_eq!(with_default_port("10.1.2.3:24514", 14514), "10.1.2.3:24514");
+        assert_eq!(with_default_port("fd00::2", 14514), "[fd00::2]:14514");
+        assert_eq!(
+            with_default_port("[fd00::2]:24514", 14514),
+            "[fd00::2]:24514"
+        );
+        assert_eq!(with_default_port("nas.lan", 14514), "nas.lan:14514");
+        assert_eq!(with_default_port(" nas.lan:24514 ", 14514), "nas.lan:24514");
+    }
+
+    #[tokio::test]
+    async fn entries_are_listed_once_and_resolved() {
+        let peers = StaticPeers::new();
+        assert_eq!(
+            peers.add("127.0.0.2", 14514).await.as_deref(),
+            Some("127.0.0.2:14514")
+        );
+        assert!(peers.add("127.0.0.2:14514", 14514).await.is_none());
+        peers.add("127.0.0.3:24514", 14514).await;
+
+        assert_eq!(peers.list().await.len(), 2);
+        assert_eq!(
+            peers.resolve_all().await,
+            vec![
+                "127.0.0.2:14514".parse::<SocketAddr>().unwrap(),
+                "127.0.0.3:24514".parse().unwrap()
+            ]
+        );
+        assert!(resolve("not a host name").await.is_err());
+    }
+}
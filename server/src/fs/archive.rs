@@ -0,0 +1,362 @@
+//! Archive packing for bulk transfers
+//!
+//! A bulk pull ships all the requested files as a single tar archive, so a directory with many
+//! small files costs one pull request and one connection instead of one per file.
+//! The receiver unpacks the whole archive into a staging directory before touching the working
+//! directory, so a broken or malformed archive never leaves a half-written tree behind, and then
+//! moves the files into place all or nothing.
+
+use crate::err::Result;
+use crate::fs::FS_INDEX;
+use crate::fs::fs_lock;
+use crate::fs::util::check_received_path;
+use crate::global_var::{ENV_VAR, LOGGER};
+use notify::EventKind;
+use notify::event::{CreateKind, DataChange, ModifyKind};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Archive entries must stay inside the directory they are unpacked into.
+fn is_safe_entry_path(path: &Path) -> bool {
+    !path.as_os_str().is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Pack the files, given relative to `base`, into a tar archive at `archive_path`.
+/// Files that disappeared since they were indexed are skipped.
+/// Returns the relative paths of the packed files.
+pub(crate) async fn pack_files(
+    base: &Path,
+    files: &[PathBuf],
+    archive_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut builder = tar::Builder::new(BufWriter::new(File::create(archive_path)?));
+    let mut packed = Vec::with_capacity(files.len());
+
+    for rel_path in files {
+        let full_path = base.join(rel_path);
+        if !full_path.is_file() {
+            LOGGER.warn(format!(
+                "Skipping '{}' while packing archive: not a file anymore",
+                full_path.display()
+            ));
+            continue;
+        }
+
+        // Hold the read lock while the file is copied into the archive, as for single file pulls.
+        // The copy itself is blocking, it runs off the runtime and hands the builder back.
+        let guard = fs_lock::RwLock::new(&full_path).read().await?;
+        let mut file = guard.try_clone()?;
+        let entry_path = rel_path.clone();
+        builder = tokio::task::spawn_blocking(move || -> Result<_> {
+            builder.append_file(&entry_path, &mut file)?;
+            Ok(builder)
+        })
+        .await??;
+        drop(guard);
+        packed.push(rel_path.clone());
+    }
+
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        builder.into_inner()?.flush()?;
+        Ok(())
+    })
+    .await??;
+    Ok(packed)
+}
+
+/// Unpack the archive of `dir_path` at `archive_path` into `staging_dir`.
+/// Fails on the first entry that is not a regular file or that would escape `staging_dir`.
+/// Entries the receiver would not take from a listing, outside `dir_path`, in `.disc`, ignored
+/// or unsubscribed, are skipped.
+/// Returns the relative paths of the unpacked files.
+pub(crate) async fn unpack_files(
+    archive_path: &Path,
+    staging_dir: &Path,
+    dir_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let archive_path = archive_path.to_path_buf();
+    let staging_dir = staging_dir.to_path_buf();
+    let dir_path = dir_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        unpack_files_blocking(&archive_path, &staging_dir, &dir_path)
+    })
+    .await?
+}
+
+fn unpack_files_blocking(
+    archive_path: &Path,
+    staging_dir: &Path,
+    dir_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut archive = tar::Archive::new(File::open(archive_path)?);
+    let mut unpacked = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let rel_path = entry.path()?.into_owned();
+        if !entry.header().entry_type().is_file() {
+            return Err(format!(
+                "Archive entry '{}' is not a regular file",
+                rel_path.display()
+            )
+            .into());
+        }
+        if !is_safe_entry_path(&rel_path) {
+            return Err(format!(
+                "Archive entry '{}' escapes the target directory",
+                rel_path.display()
+            )
+            .into());
+        }
+        if let Err(e) = check_received_path(dir_path, &rel_path) {
+            LOGGER.warn(format!("Skipped archive entry: {}", e));
+            continue;
+        }
+        if !entry.unpack_in(staging_dir)? {
+            return Err(format!(
+                "Archive entry '{}' escapes the target directory",
+                rel_path.display()
+            )
+            .into());
+        }
+        unpacked.push(rel_path);
+    }
+
+    Ok(unpacked)
+}
+
+/// A file moved into place by `install_unpacked_files`, and where the copy it replaced is kept
+/// until all the files are in place
+struct InstalledFile {
+    staged: PathBuf,
+    target: PathBuf,
+    replaced: Option<PathBuf>,
+}
+
+/// Move the files installed so far back to the staging dir and put back the copies they
+/// replaced, in reverse order
+async fn roll_back(installed: Vec<InstalledFile>) {
+    for file in installed.into_iter().rev() {
+        let mut result = tokio::fs::rename(&file.target, &file.staged).await;
+        if let (Ok(()), Some(replaced)) = (&result, &file.replaced) {
+            result = tokio::fs::rename(replaced, &file.target).await;
+        }
+        if let Err(e) = result {
+            LOGGER.error(format!(
+                "Failed to roll back '{}' after a failed install: {}",
+                file.target.display(),
+                e
+            ));
+        }
+    }
+}
+
+/// Move the unpacked files from `staging_dir` to the same relative paths under `target_dir`,
+/// all or nothing: the copies they replace are set aside in `staging_dir`, and put back if any
+/// file cannot be moved into place.
+async fn move_into_place(
+    staging_dir: &Path,
+    target_dir: &Path,
+    files: &[PathBuf],
+) -> Result<Vec<InstalledFile>> {
+    let replaced_dir = staging_dir.join(".replaced");
+    let mut installed: Vec<InstalledFile> = Vec::with_capacity(files.len());
+
+    for rel_path in files {
+        let staged = staging_dir.join(rel_path);
+        let target = target_dir.join(rel_path);
+        let result = async {
+            if let Some(parent_dir) = target.parent() {
+                tokio::fs::create_dir_all(parent_dir).await?;
+            }
+            let _write_guard = if target.exists() {
+                Some(fs_lock::RwLock::new(&target).write().await?)
+            } else {
+                None
+            };
+            let replaced = if target.exists() {
+                let replaced = replaced_dir.join(rel_path);
+                if let Some(parent_dir) = replaced.parent() {
+                    tokio::fs::create_dir_all(parent_dir).await?;
+                }
+                tokio::fs::rename(&target, &replaced).await?;
+                Some(replaced)
+            } else {
+                None
+            };
+            if let Err(e) = tokio::fs::rename(&staged, &target).await {
+                if let Some(replaced) = &replaced {
+                    tokio::fs::rename(replaced, &target).await.ok();
+                }
+                return Err(e.into());
+            }
+            Ok::<_, crate::err::Error>(InstalledFile {
+                staged: staged.clone(),
+                target: target.clone(),
+                replaced,
+            })
+        }
+        .await;
+
+        match result {
+            Ok(file) => installed.push(file),
+            Err(e) => {
+                roll_back(installed).await;
+                return Err(
+                    format!("Failed to move '{}' into place: {}", rel_path.display(), e).into(),
+                );
+            }
+        }
+    }
+
+    Ok(installed)
+}
+
+/// Move the files returned by `unpack_files` from `staging_dir` to the same relative paths under
+/// the working directory, replacing any existing copies, and record the changes in the index.
+/// Either all the files are installed, or the working directory is left as it was.
+pub(crate) async fn install_unpacked_files(staging_dir: &Path, files: &[PathBuf]) -> Result<()> {
+    let working_dir = PathBuf::from(ENV_VAR.get().unwrap().get_working_dir());
+    let installed = move_into_place(staging_dir, &working_dir, files).await?;
+
+    for file in installed {
+        let event = if file.replaced.is_some() {
+            EventKind::Modify(ModifyKind::Data(DataChange::Content))
+        } else {
+            EventKind::Create(CreateKind::File)
+        };
+        if let Err(e) = FS_INDEX.on_file_event(&file.target, event).await {
+            // The file watcher may have recorded the change first
+            LOGGER.debug(format!(
+                "Index update for '{}' skipped: {}",
+                file.target.display(),
+                e
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::temp_dir::tmp_dir;
+    use std::fs;
+
+    #[tokio::test]
+    async fn pack_then_unpack_roundtrips_nested_files() {
+        let src = tmp_dir("archive_src");
+        let dst = tmp_dir("archive_dst");
+        fs::create_dir_all(src.join("photos/2024")).unwrap();
+        fs::write(src.join("photos/a.jpg"), b"first").unwrap();
+        fs::write(src.join("photos/2024/b.jpg"), vec![7u8; 10_000]).unwrap();
+
+        let files = vec![
+            PathBuf::from("photos/a.jpg"),
+            PathBuf::from("photos/2024/b.jpg"),
+            PathBuf::from("photos/missing.jpg"),
+        ];
+        let archive = src.join("bundle.tar");
+        let packed = pack_files(&src, &files, &archive).await.unwrap();
+        assert_eq!(packed, files[..2].to_vec());
+
+        let unpacked = unpack_files(&archive, &dst, Path::new("photos"))
+            .await
+            .unwrap();
+        assert_eq!(unpacked, packed);
+        assert_eq!(fs::read(dst.join("photos/a.jpg")).unwrap(), b"first");
+        assert_eq!(
+            fs::read(dst.join("photos/2024/b.jpg")).unwrap(),
+            vec![7u8; 10_000]
+        );
+    }
+
+    #[tokio::test]
+    async fn unpack_rejects_entries_escaping_the_staging_dir() {
+        let ws = tmp_dir("archive_escape");
+        let staging = ws.join("staging");
+        fs::create_dir_all(&staging).unwrap();
+
+        // The tar builder refuses such paths, so write the header name by hand
+        let archive = ws.join("evil.tar");
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        let mut header = tar::Header::new_old();
+        let name = b"../evil.txt";
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.set_size(4);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, &b"boom"[..]).unwrap();
+        builder.finish().unwrap();
+
+        assert!(
+            unpack_files(&archive, &staging, Path::new(""))
+                .await
+                .is_err()
+        );
+        assert!(!ws.join("evil.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn unpack_skips_entries_the_pull_did_not_ask_for() {
+        let ws = tmp_dir("archive_confined");
+        let staging = ws.join("staging");
+        fs::create_dir_all(&staging).unwrap();
+
+        let archive = ws.join("bundle.tar");
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        for name in [
+            "foo/a.txt",
+            "bar/b.txt",
+            ".disc/known_peers",
+            "foo/.DS_Store",
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(4);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, name, &b"data"[..])
+                .unwrap();
+        }
+        builder.finish().unwrap();
+
+        let unpacked = unpack_files(&archive, &staging, Path::new("foo"))
+            .await
+            .unwrap();
+        assert_eq!(unpacked, vec![PathBuf::from("foo/a.txt")]);
+        assert!(!staging.join("bar").exists());
+        assert!(!staging.join(".disc").exists());
+        assert!(!staging.join("foo/.DS_Store").exists());
+    }
+
+    #[tokio::test]
+    async fn failed_install_leaves_the_target_untouched() {
+        let staging = tmp_dir("archive_staging");
+        let target = tmp_dir("archive_target");
+        fs::create_dir_all(staging.join("docs")).unwrap();
+        fs::write(staging.join("docs/a.txt"), b"new a").unwrap();
+        fs::write(staging.join("docs/b.txt"), b"new b").unwrap();
+        fs::create_dir_all(target.join("docs")).unwrap();
+        fs::write(target.join("docs/a.txt"), b"old a").unwrap();
+
+        // The last file vanished from the staging dir, nothing may be installed
+        let files = vec![
+            PathBuf::from("docs/a.txt"),
+            PathBuf::from("docs/b.txt"),
+            PathBuf::from("docs/c.txt"),
+        ];
+        assert!(move_into_place(&staging, &target, &files).await.is_err());
+        assert_eq!(fs::read(target.join("docs/a.txt")).unwrap(), b"old a");
+        assert!(!target.join("docs/b.txt").exists());
+
+        let installed = move_into_place(&staging, &target, &files[..2]).await;
+        assert_eq!(installed.unwrap().len(), 2);
+        assert_eq!(fs::read(target.join("docs/a.txt")).unwrap(), b"new a");
+        assert_eq!(fs::read(target.join("docs/b.txt")).unwrap(), b"new b");
+    }
+}
@@ -56,6 +56,7 @@
 //! - [crate] on_remove(path) -> Result<()> (async)
 //! - [crate] on_modify_content(path) -> Result<()> (async)
 //! - [crate] on_file_event(path, ek: notify::EventKind) -> Result<()> (async)
+//! - [crate] remove_ignored() -> usize (async)
 //! - [crate] index_stale_rescan() -> Result<()> (async)
 //! - [crate] index_inactive_clean() -> Result<()> (async)
 //!
@@ -77,6 +78,7 @@
 use crate::err::Result;
 use crate::fs::LumoFile;
 use crate::fs::fs_op::{fs_read_bytes_deserialized, fs_save_bytes_atomic_internal};
+use crate::fs::ignore_rules::is_ignored;
 use crate::fs::util::{get_relative_path, normalize_path};
 use crate::global_var::ENV_VAR;
 use crate::global_var::LOGGER;
@@ -419,6 +421,21 @@ impl FileIndex {
         guard.map.keys().cloned().collect()
     }
 
+    /// List active files located under the directory `dir`, together with their sizes.
+    pub(crate) async fn list_files_under<P: AsRef<Path>>(&self, dir: P) -> Vec<(PathBuf, u64)> {
+        let prefix = rel_key_from(dir);
+        let guard = self.inner.read().await;
+        guard
+            .active_paths
+            .iter()
+            .filter(|p| p.starts_with(&prefix))
+            .map(|p| {
+                let size = guard.meta.get(p).map(|(size, _)| *size).unwrap_or(0);
+                (p.clone(), size)
+            })
+            .collect()
+    }
+
     /// Find candidate paths that could refer to the same file based on size.
     pub(crate) async fn candidates_by_size(&self, size: u64) -> Vec<PathBuf> {
         let guard = self.inner.read().await;
@@ -883,6 +900,9 @@ impl FileIndex {
 
     pub async fn on_file_event<P: AsRef<Path>>(&self, p: P, ek: EventKind) -> Result<()> {
         LOGGER.debug(format!("on_file_event: {} {:?}", p.as_ref().display(), ek));
+        if is_ignored(&p) {
+            return Err(format!("Ignore event on ignored path {}", p.as_ref().display()).into());
+        }
         match LumoFile::new(p.as_ref().to_path_buf()).await {
             Ok(lf) => {
                 // Case 1: found a file
@@ -921,7 +941,21 @@ impl FileIndex {
 }
 
 impl FileIndex {
+    /// Drop the entries whose paths are ignored, after the ignore rules changed.
+    /// Returns the number of removed entries.
+    pub(crate) async fn remove_ignored(&self) -> usize {
+        let mut removed = 0;
+        for path in self.list_paths().await {
+            if is_ignored(&path) && self.remove(&path).await {
+                removed += 1;
+            }
+        }
+        removed
+    }
+
     pub async fn index_stale_rescan(&self) -> Result<()> {
+        self.remove_ignored().await;
+
         // Snapshot active entries to avoid holding the index lock while doing I/O
         let entries: Vec<(PathBuf, std::sync::Arc<AsyncRwLock<FileEntry>>)> = {
             let guard = self.inner.read().await;
@@ -1073,6 +1107,10 @@ impl FileIndex {
         let index = Self::new();
 
         for entry in serialized.entry_list {
+            // The ignore rules may have changed while the server was down
+            if is_ignored(&entry.path) {
+                continue;
+            }
             let index_entry =
                 FileEntry::new_internal(entry.path.clone(), entry.last_writer.clone());
             index.upsert(index_entry).await;
@@ -1237,6 +1275,35 @@ mod tests {
         assert_eq!(writer.as_deref(), Some("worker-1"));
     }
 
+    #[tokio::test]
+    async fn list_files_under_only_returns_active_files_in_dir() {
+        let tmp = TempDirGuard::new("fs_index_list_files_under_only_returns_active_files_in_dir");
+        let sub = tmp.path().join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        let p1 = sub.join("a.bin");
+        let p2 = sub.join("b.bin");
+        let p3 = tmp.path().join("c.bin");
+        write_bytes(&p1, 1024, 0x01).await;
+        write_bytes(&p2, 2048, 0x02).await;
+        write_bytes(&p3, 512, 0x03).await;
+
+        let index = FileIndex::new();
+        for p in [&p1, &p2, &p3] {
+            let lf = LumoFile::new(p.clone()).await.unwrap();
+            index.upsert(FileEntry::new(lf)).await;
+        }
+        index.deactivate(&p2).await.unwrap();
+
+        let listed = index.list_files_under(&sub).await;
+        assert_eq!(listed, vec![(rel_key_from(&p1), 1024)]);
+
+        let mut all = index.list_files_under(tmp.path()).await;
+        all.sort();
+        let mut expected = vec![(rel_key_from(&p1), 1024), (rel_key_from(&p3), 512)];
+        expected.sort();
+        assert_eq!(all, expected);
+    }
+
     #[tokio::test]
     async fn candidates_by_size_and_remove_updates_indices() {
         let tmp = TempDirGuard::new("fs_index_candidates_by_size_and_remove_updates_indices");
@@ -1,6 +1,7 @@
 use crate::fs::fs_index::FS_INDEX;
+use crate::fs::ignore_rules::{is_ignore_file, is_ignored, reload_ignore_rules};
 use crate::global_var::{ENV_VAR, LOGGER};
-use notify::event::{EventKind, ModifyKind};
+use notify::event::{CreateKind, EventKind, ModifyKind};
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
 use std::path::{Path, PathBuf};
 use std::thread;
@@ -33,11 +34,16 @@ impl FsListener {
 
         // Clone for the closure
         let tx_cloned = tx.clone();
+        // Events may report the root as given or resolved
+        let mut meta_dirs = vec![root.join(".disc")];
+        if let Ok(canonical_root) = root.canonicalize() {
+            meta_dirs.push(canonical_root.join(".disc"));
+        }
         let mut watcher = RecommendedWatcher::new(
             move |res: Result<Event, notify::Error>| {
                 // LOGGER.debug(format!("Captured filesystem event: {:?}", &res));
                 if let Ok(ev) = res {
-                    if let Some(ev) = filter_event(ev) {
+                    if let Some(ev) = filter_event(ev, &meta_dirs) {
                         // Best-effort send it; ignore if receiver dropped
                         let _ = tx_cloned.blocking_send(ev);
                     } else {
@@ -80,6 +86,9 @@ impl FsListener {
             rt.block_on(async {
                 while let Some(ev) = rx.recv().await {
                     LOGGER.debug(format!("Received filesystem event: {:?}", &ev));
+                    if ev.paths.iter().any(is_ignore_file) {
+                        apply_reloaded_ignore_rules().await;
+                    }
                     for p in ev.paths {
                         let path: &Path = p.as_ref();
                         match FS_INDEX.on_file_event(path, ev.kind).await {
@@ -101,55 +110,57 @@ impl FsListener {
     }
 }
 
-fn is_ignored_name(name: &str) -> bool {
-    let lower = name.to_ascii_lowercase();
-    // Common OS metadata files
-    if lower == ".ds_store" || lower == "desktop.ini" || lower == "thumbs.db" {
-        return true;
-    }
-    // Ignore permission testing files
-    if name.starts_with(".perm_check") {
-        return true;
+/// Reload the ignore rules, then drop the newly ignored files from the index,
+/// and index the files that are not ignored anymore.
+async fn apply_reloaded_ignore_rules() {
+    let working_dir = PathBuf::from(ENV_VAR.get().unwrap().get_working_dir());
+    for rel_path in reload_ignore_rules() {
+        let path = working_dir.join(&rel_path);
+        if let Err(e) = FS_INDEX
+            .on_file_event(&path, EventKind::Create(CreateKind::File))
+            .await
+        {
+            LOGGER.warn(format!(
+                "[fs] failed to index re-included file {}: {}",
+                path.display(),
+                e
+            ));
+        }
     }
-    // Ignore .sb- files, which are created by the SuperBlock when it is created.
-    // TODO: add a exclude list
-    if name.contains(".sb-") {
-        return true;
+    let removed = FS_INDEX.remove_ignored().await;
+    if removed > 0 {
+        LOGGER.info(format!(
+            "[fs] removed {} newly ignored files from the index",
+            removed
+        ));
     }
-    false
 }
 
-fn is_ignored_path(path: &Path) -> bool {
-    let meta_dir = PathBuf::from(ENV_VAR.get().unwrap().get_working_dir()).join(".disc");
-    if path.starts_with(&meta_dir) {
-        return true;
-    }
-    false
+/// True for paths under the `.disc` directory of the watched root, given in `meta_dirs`
+fn is_ignored_path(path: &Path, meta_dirs: &[PathBuf]) -> bool {
+    meta_dirs.iter().any(|meta_dir| path.starts_with(meta_dir))
 }
 
-fn filter_event(mut ev: Event) -> Option<Event> {
+fn filter_event(mut ev: Event, meta_dirs: &[PathBuf]) -> Option<Event> {
     // Filter by event kind: only Create, Remove, or Modify(Name)
+    // Any change of an ignore file is wanted, as the rules have to be reloaded
     let is_wanted_kind = match &ev.kind {
         EventKind::Create(_) => true,
         EventKind::Remove(_) => true,
         EventKind::Modify(ModifyKind::Name(_)) => true,
+        EventKind::Modify(_) => ev.paths.iter().any(is_ignore_file),
         _ => false,
     };
     if !is_wanted_kind {
         return None;
     }
 
-    // Filter paths: ignore OS junk and permission probe files; require full perms on target dir
+    // Filter paths: ignore the .lumoignore rules, OS junk and permission probe files;
+    // require full perms on target dir
     ev.paths.retain(|p| {
-        if is_ignored_path(p) {
+        if is_ignored_path(p, meta_dirs) || is_ignored(p) {
             return false;
         }
-        // ignore names like .DS_Store, desktop.ini, and any starting with .perm_check
-        if let Some(name) = p.file_name().and_then(|s| s.to_str()) {
-            if is_ignored_name(name) {
-                return false;
-            }
-        }
         // Check full permissions on the directory (for files: check parent)
         let dir_to_check: &Path = if p.is_dir() {
             p
@@ -0,0 +1,327 @@
+//! Ignore rules for the shared folder
+//!
+//! A path is ignored when its name is one of the built-in OS or probe files, or when it matches
+//! the gitignore-style rules of a `.lumoignore` file at the share root or in any subdirectory.
+//! As with git, the rules of the deepest `.lumoignore` win, negations (`!pattern`) re-include
+//! paths, and the content of an ignored directory cannot be re-included.
+//!
+//! Ignored paths are skipped by the watcher, kept out of the index, and hidden from remote pulls.
+
+use crate::global_var::LOGGER;
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, RwLock};
+
+pub const IGNORE_FILE_NAME: &str = ".lumoignore";
+
+/// Names that are never shared, whatever the rules say.
+fn is_builtin_ignored_name(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    // Common OS metadata files
+    if lower == ".ds_store" || lower == "desktop.ini" || lower == "thumbs.db" {
+        return true;
+    }
+    // Ignore permission testing files
+    if name.starts_with(".perm_check") {
+        return true;
+    }
+    // Ignore .sb- files, which are created by the SuperBlock when it is created.
+    if name.contains(".sb-") {
+        return true;
+    }
+    false
+}
+
+pub struct IgnoreRules {
+    root: PathBuf,
+    canonical_root: Option<PathBuf>,
+    /// Rules of every `.lumoignore`, keyed by its directory relative to root
+    matchers: HashMap<PathBuf, Gitignore>,
+}
+
+impl IgnoreRules {
+    fn empty(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            canonical_root: std::fs::canonicalize(root).ok(),
+            matchers: HashMap::new(),
+        }
+    }
+
+    /// Collect the `.lumoignore` files under `root`, except those in ignored directories.
+    pub fn load(root: &Path) -> Self {
+        let mut rules = Self::empty(root);
+        let mut pending_dirs = vec![PathBuf::new()];
+        while let Some(rel_dir) = pending_dirs.pop() {
+            let dir = root.join(&rel_dir);
+            let ignore_file = dir.join(IGNORE_FILE_NAME);
+            if ignore_file.is_file() {
+                rules.add_ignore_file(&rel_dir, &ignore_file);
+            }
+            // The rules of a directory apply to its children, so they are visited afterward
+            let (sub_dirs, _) = rules.read_dir_entries(&rel_dir);
+            pending_dirs.extend(sub_dirs);
+        }
+        rules
+    }
+
+    fn add_ignore_file(&mut self, rel_dir: &Path, ignore_file: &Path) {
+        let mut builder = GitignoreBuilder::new(self.root.join(rel_dir));
+        if let Some(e) = builder.add(ignore_file) {
+            LOGGER.warn(format!(
+                "Some rules of '{}' are invalid: {}",
+                ignore_file.display(),
+                e
+            ));
+        }
+        match builder.build() {
+            Ok(matcher) => {
+                self.matchers.insert(rel_dir.to_path_buf(), matcher);
+            }
+            Err(e) => LOGGER.warn(format!(
+                "Failed to load ignore rules '{}': {}",
+                ignore_file.display(),
+                e
+            )),
+        }
+    }
+
+    /// Split the entries of a directory into non ignored subdirectories and files, relative to root.
+    /// Symbolic links are not followed.
+    fn read_dir_entries(&self, rel_dir: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        let Ok(entries) = std::fs::read_dir(self.root.join(rel_dir)) else {
+            return (dirs, files);
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let rel_path = rel_dir.join(entry.file_name());
+            if rel_path.starts_with(".disc") {
+                continue;
+            }
+            if file_type.is_dir() && !self.is_ignored_as(&rel_path, true) {
+                dirs.push(rel_path);
+            } else if file_type.is_file() && !self.is_ignored_as(&rel_path, false) {
+                files.push(rel_path);
+            }
+        }
+        (dirs, files)
+    }
+
+    /// List the files under root that are not ignored, relative to root.
+    pub fn list_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        let mut pending_dirs = vec![PathBuf::new()];
+        while let Some(rel_dir) = pending_dirs.pop() {
+            let (sub_dirs, dir_files) = self.read_dir_entries(&rel_dir);
+            pending_dirs.extend(sub_dirs);
+            files.extend(dir_files);
+        }
+        files
+    }
+
+    fn to_relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        if path.is_relative() {
+            return Some(path);
+        }
+        path.strip_prefix(&self.root)
+            .ok()
+            .or_else(|| path.strip_prefix(self.canonical_root.as_ref()?).ok())
+    }
+
+    /// Check a path, either absolute under root or relative to it.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        match self.to_relative(path) {
+            Some(rel_path) => {
+                let is_dir = self.root.join(rel_path).is_dir();
+                self.is_ignored_as(rel_path, is_dir)
+            }
+            // Paths outside of the share are none of our business
+            None => false,
+        }
+    }
+
+    fn is_ignored_as(&self, rel_path: &Path, is_dir: bool) -> bool {
+        if let Some(name) = rel_path.file_name().and_then(|s| s.to_str())
+            && is_builtin_ignored_name(name)
+        {
+            return true;
+        }
+
+        // A path is ignored as soon as one of its parent directories is
+        let mut current = PathBuf::new();
+        let mut components = rel_path.components().peekable();
+        while let Some(component) = components.next() {
+            current.push(component);
+            let current_is_dir = components.peek().is_some() || is_dir;
+            if self.matches(&current, current_is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn matches(&self, rel_path: &Path, is_dir: bool) -> bool {
+        // The rules of the deepest directory take precedence
+        let full_path = self.root.join(rel_path);
+        let mut dir = rel_path.parent();
+        while let Some(rel_dir) = dir {
+            if let Some(matcher) = self.matchers.get(rel_dir) {
+                match matcher.matched(&full_path, is_dir) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+            dir = rel_dir.parent();
+        }
+        false
+    }
+}
+
+// Swapped as a whole, so that a reload walking the tree never blocks the lookups
+static IGNORE_RULES: LazyLock<RwLock<Option<Arc<IgnoreRules>>>> =
+    LazyLock::new(|| RwLock::new(None));
+
+/// Load the rules of the share rooted at `root`.
+pub fn init_ignore_rules<P: AsRef<Path>>(root: P) {
+    let rules = IgnoreRules::load(root.as_ref());
+    LOGGER.info(format!(
+        "Loaded {} ignore rule files under '{}'",
+        rules.matchers.len(),
+        root.as_ref().display()
+    ));
+    *IGNORE_RULES.write().unwrap() = Some(Arc::new(rules));
+}
+
+/// Reload the rules after a `.lumoignore` changed.
+/// Returns the files, relative to root, that were ignored before but are not anymore.
+pub fn reload_ignore_rules() -> Vec<PathBuf> {
+    let Some(old_rules) = IGNORE_RULES.read().unwrap().clone() else {
+        return Vec::new();
+    };
+    let new_rules = IgnoreRules::load(&old_rules.root);
+    let reincluded = new_rules
+        .list_files()
+        .into_iter()
+        .filter(|p| old_rules.is_ignored_as(p, false))
+        .collect();
+    LOGGER.info(format!(
+        "Reloaded {} ignore rule files under '{}'",
+        new_rules.matchers.len(),
+        new_rules.root.display()
+    ));
+    *IGNORE_RULES.write().unwrap() = Some(Arc::new(new_rules));
+    reincluded
+}
+
+/// Check a path, either absolute or relative to the share root, against the ignore rules.
+/// Before the rules are loaded, only the built-in names are ignored.
+pub fn is_ignored<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    match IGNORE_RULES.read().unwrap().as_ref() {
+        Some(rules) => rules.is_ignored(path),
+        None => path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .is_some_and(is_builtin_ignored_name),
+    }
+}
+
+pub fn is_ignore_file<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .file_name()
+        .is_some_and(|name| name == IGNORE_FILE_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::temp_dir::tmp_dir;
+    use std::fs;
+
+    fn touch(root: &Path, rel_path: &str) {
+        let path = root.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"x").unwrap();
+    }
+
+    #[test]
+    fn builtin_names_are_always_ignored() {
+        let share = tmp_dir("ignore_builtin");
+        let rules = IgnoreRules::load(&share);
+        assert!(rules.is_ignored(Path::new("photos/.DS_Store")));
+        assert!(rules.is_ignored(Path::new("Thumbs.db")));
+        assert!(rules.is_ignored(Path::new(".perm_check_123")));
+        assert!(!rules.is_ignored(Path::new("photos/a.jpg")));
+    }
+
+    #[test]
+    fn root_rules_support_negation_and_directory_rules() {
+        let share = tmp_dir("ignore_root");
+        fs::write(
+            share.join(IGNORE_FILE_NAME),
+            "*.log\n!keep.log\nbuild/\n/top.txt\n",
+        )
+        .unwrap();
+        touch(&share, "build/out.bin");
+        touch(&share, "src/build");
+        let rules = IgnoreRules::load(&share);
+
+        assert!(rules.is_ignored(Path::new("debug.log")));
+        assert!(rules.is_ignored(Path::new("src/trace.log")));
+        assert!(!rules.is_ignored(Path::new("src/keep.log")));
+        // Directory rules match the directory and everything below it, but not files
+        assert!(rules.is_ignored(Path::new("build/out.bin")));
+        assert!(!rules.is_ignored(Path::new("src/build")));
+        // Anchored rules only match at the root
+        assert!(rules.is_ignored(&share.join("top.txt")));
+        assert!(!rules.is_ignored(&share.join("src/top.txt")));
+    }
+
+    #[test]
+    fn deeper_rules_take_precedence_and_ignored_dirs_stay_ignored() {
+        let share = tmp_dir("ignore_nested");
+        fs::write(share.join(IGNORE_FILE_NAME), "*.tmp\ncache/\n").unwrap();
+        touch(&share, "cache/a.bin");
+        touch(&share, "cache/.lumoignore");
+        fs::write(share.join("cache").join(IGNORE_FILE_NAME), "!a.bin\n").unwrap();
+        touch(&share, "docs/.lumoignore");
+        fs::write(share.join("docs").join(IGNORE_FILE_NAME), "!draft.tmp\n").unwrap();
+        let rules = IgnoreRules::load(&share);
+
+        assert!(rules.is_ignored(Path::new("notes.tmp")));
+        assert!(!rules.is_ignored(Path::new("docs/draft.tmp")));
+        assert!(rules.is_ignored(Path::new("docs/other.tmp")));
+        // The rules inside an ignored directory are not read, the directory stays ignored
+        assert!(rules.is_ignored(Path::new("cache/a.bin")));
+    }
+
+    #[test]
+    fn list_files_skips_ignored_paths() {
+        let share = tmp_dir("ignore_list");
+        fs::write(share.join(IGNORE_FILE_NAME), "*.log\nbuild/\n").unwrap();
+        touch(&share, "a.txt");
+        touch(&share, "b.log");
+        touch(&share, "build/c.txt");
+        touch(&share, "src/d.txt");
+        touch(&share, ".disc/index");
+        let rules = IgnoreRules::load(&share);
+
+        let mut files = rules.list_files();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from(IGNORE_FILE_NAME),
+                PathBuf::from("a.txt"),
+                PathBuf::from("src/d.txt"),
+            ]
+        );
+    }
+}
@@ -1,19 +1,29 @@
+mod archive;
 pub mod file;
 mod fs_listener;
 pub mod util;
 pub use file::LumoFile;
 mod fs_index;
+mod ignore_rules;
 pub use fs_index::FS_INDEX;
 pub use fs_index::init_fs_index;
+pub use ignore_rules::{init_ignore_rules, is_ignored};
 pub(crate) mod fs_lock;
 pub use fs_lock::RwLock;
 mod fs_op;
 mod task_management;
+pub(crate) use archive::{install_unpacked_files, unpack_files};
 pub use task_management::file_download_tasks::PendingFileDownloadTask;
 pub use task_management::file_request_tasks::{
-    PullRequestResult, RejectionReason, claim_pending_pull, start_pull_request,
+    PullRequestResult, RejectionReason, claim_pending_pull, start_archive_pull_request,
+    start_directory_pull_request, start_pull_request,
+};
+pub use task_management::{
+    DirectoryDownloadTracker, PendingDirectoryDownloadTask, claim_pending_directory_download,
+    claim_pending_download, is_directory_download_pending, is_download_pending,
+    start_child_file_download_task, start_directory_download_task, start_directory_page_task,
+    start_file_download_task,
 };
-pub use task_management::{claim_pending_download, start_file_download_task};
 
 pub use fs_listener::FsListener;
 
@@ -73,6 +83,9 @@ pub async fn init_working_dir<P: AsRef<Path>>(path: P) -> Result<(AsyncLogger, J
 }
 
 pub async fn init_fs<P: AsRef<Path>>(base: P) -> Result<()> {
+    // The ignore rules must be known before any path is watched or indexed
+    init_ignore_rules(&base);
+
     // Start filesystem watcher and spawn a background processor for events
     let (listener, rx) = FsListener::watch(&base).expect("should start watcher");
 
@@ -0,0 +1,354 @@
+//! Recursive directory download framework
+//!
+//! Flow:
+//! 1) The downloader registers a pending directory download under a challenge, and asks the peer
+//!    for the listing of the directory.
+//! 2) The peer answers with a page of the files indexed under the directory.
+//! 3) The downloader claims the pending directory download, and starts one child file download
+//!    per listed file. Every child reports back to the shared tracker. While the listing goes on,
+//!    the next page is asked for under a new challenge, pending along with the same tracker.
+//! 4) Once the listing is over and all children are done, the tracker closes the parent job with
+//!    the aggregated result.
+
+use crate::core::tasks::{
+    ClaimableJobHandle, JobStatus, JobSummaryStatusCallback, forget_outgoing_pull,
+    launch_claimable_job,
+};
+use crate::err::Result;
+use crate::global_var::{ENV_VAR, LOGGER, get_task_queue_sender};
+use rand::Rng;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+
+type Challenge = u64;
+
+struct DirectoryDownloadProgress {
+    /// Files listed so far
+    total: usize,
+    /// Whether more pages of the listing are to come
+    listing_pending: bool,
+    succeeded: usize,
+    failures: Vec<(PathBuf, String)>,
+    callback: Option<Box<JobSummaryStatusCallback>>,
+}
+
+/// Aggregates the results of the child file downloads of a directory download,
+/// and reports them through the job summary of the parent job.
+pub struct DirectoryDownloadTracker {
+    dir_path: PathBuf,
+    progress: Mutex<DirectoryDownloadProgress>,
+}
+
+impl DirectoryDownloadTracker {
+    pub fn new(dir_path: PathBuf, total: usize, callback: Box<JobSummaryStatusCallback>) -> Self {
+        Self {
+            dir_path,
+            progress: Mutex::new(DirectoryDownloadProgress {
+                total,
+                listing_pending: false,
+                succeeded: 0,
+                failures: Vec::new(),
+                callback: Some(callback),
+            }),
+        }
+    }
+
+    /// Count the files of another page of the listing, `more_pages` tells whether it is the last
+    pub async fn add_page(&self, files: usize, more_pages: bool) {
+        let mut progress = self.progress.lock().await;
+        progress.total += files;
+        progress.listing_pending = more_pages;
+        // The children of the previous pages may all be done already
+        self.publish(&mut progress).await;
+    }
+
+    /// Give up on the rest of the listing, the directory download fails once the children listed
+    /// so far are done
+    pub async fn fail_listing(&self, reason: String) {
+        LOGGER.warn(format!(
+            "[{}] listing failed: {}",
+            self.dir_path.display(),
+            reason
+        ));
+        let mut progress = self.progress.lock().await;
+        progress.total += 1;
+        progress.listing_pending = false;
+        progress.failures.push((self.dir_path.clone(), reason));
+        self.publish(&mut progress).await;
+    }
+
+    pub async fn report_success(&self, path: &Path) {
+        LOGGER.trace(format!(
+            "[{}] child download of '{}' succeeded",
+            self.dir_path.display(),
+            path.display()
+        ));
+        self.report(None).await;
+    }
+
+    pub async fn report_failure(&self, path: &Path, reason: String) {
+        LOGGER.trace(format!(
+            "[{}] child download of '{}' failed: {}",
+            self.dir_path.display(),
+            path.display(),
+            reason
+        ));
+        self.report(Some((path.to_path_buf(), reason))).await;
+    }
+
+    async fn report(&self, failure: Option<(PathBuf, String)>) {
+        let mut progress = self.progress.lock().await;
+        match failure {
+            Some(failure) => progress.failures.push(failure),
+            None => progress.succeeded += 1,
+        }
+        self.publish(&mut progress).await;
+    }
+
+    async fn publish(&self, progress: &mut DirectoryDownloadProgress) {
+        let done = progress.succeeded + progress.failures.len();
+        let (status, msg) = if done < progress.total || progress.listing_pending {
+            (
+                JobStatus::Running,
+                format!(
+                    "{}/{} files transferred, {} failed",
+                    progress.succeeded,
+                    progress.total,
+                    progress.failures.len()
+                ),
+            )
+        } else if progress.failures.is_empty() {
+            (
+                JobStatus::Completed,
+                format!("{} files transferred", progress.total),
+            )
+        } else {
+            let mut msg = format!(
+                "{}/{} files transferred, {} failed:",
+                progress.succeeded,
+                progress.total,
+                progress.failures.len()
+            );
+            for (path, reason) in &progress.failures {
+                msg.push_str(&format!("\n{}: {}", path.display(), reason));
+            }
+            (JobStatus::Failed, msg)
+        };
+
+        let result = match progress.callback.as_mut() {
+            Some(callback) => callback(status, msg).await,
+            None => return,
+        };
+        if let Err(e) = result {
+            LOGGER.warn(format!(
+                "Failed to update the job of directory download '{}': {:?}",
+                self.dir_path.display(),
+                e
+            ));
+        }
+        if status != JobStatus::Running {
+            // The parent job is terminated, late reports are ignored
+            progress.callback = None;
+        }
+    }
+}
+
+pub struct PendingDirectoryDownloadTask {
+    pub dir_path: PathBuf,
+    /// Parent job, waiting for the first page of the listing
+    pub handle: Option<ClaimableJobHandle>,
+    /// Tracker of the download, for the next pages of the listing
+    pub tracker: Option<Arc<DirectoryDownloadTracker>>,
+}
+
+static PENDING_DIRECTORY_DOWNLOADS: LazyLock<
+    RwLock<HashMap<Challenge, PendingDirectoryDownloadTask>>,
+> = LazyLock::new(|| RwLock::new(HashMap::new()));
+
+async fn cancel_pending(challenge: Challenge) {
+    LOGGER.debug(format!(
+        "Removing directory download task for challenge {} from pending downloads map, result is ignored",
+        challenge
+    ));
+    forget_outgoing_pull(challenge).await;
+    let _ = PENDING_DIRECTORY_DOWNLOADS.write().await.remove(&challenge);
+}
+
+pub async fn claim_pending_directory_download(
+    challenge: Challenge,
+) -> Option<PendingDirectoryDownloadTask> {
+    PENDING_DIRECTORY_DOWNLOADS.write().await.remove(&challenge)
+}
+
+/// Whether the directory download under `challenge` still waits for the peer to answer
+pub async fn is_directory_download_pending(challenge: Challenge) -> bool {
+    PENDING_DIRECTORY_DOWNLOADS
+        .read()
+        .await
+        .contains_key(&challenge)
+}
+
+/// Register a pending directory download.
+/// Returns the challenge to send along with the listing request, and the id of the parent job.
+pub async fn start_directory_download_task<P: AsRef<Path>>(path: P) -> Result<(Challenge, u64)> {
+    // 0 is reserved for bad requests, the server does not respond to challenges with 0.
+    let challenge = rand::rng().random_range(1..u64::MAX);
+
+    let q_sender = get_task_queue_sender().await?;
+    let job_name = format!(
+        "download dir: \"{}\"",
+        path.as_ref().to_path_buf().display()
+    );
+    let summary = format!(
+        "Directory download for \"{}\", challenge: {:016x}",
+        path.as_ref().to_path_buf().display(),
+        challenge
+    );
+    let cleanup = move || async move {
+        cancel_pending(challenge).await;
+        Ok(())
+    };
+    let handle = launch_claimable_job(&job_name, &summary, cleanup, 300, q_sender).await?;
+    let job_id = handle.job_id();
+
+    let pending = PendingDirectoryDownloadTask {
+        dir_path: path.as_ref().to_path_buf(),
+        handle: Some(handle),
+        tracker: None,
+    };
+    PENDING_DIRECTORY_DOWNLOADS
+        .write()
+        .await
+        .insert(challenge, pending);
+
+    LOGGER.info(format!(
+        "Pending directory download for {}, with challenge {:016x}",
+        path.as_ref().to_path_buf().display(),
+        challenge
+    ));
+
+    Ok((challenge, job_id))
+}
+
+/// Register the wait for the next page of the listing of a directory download.
+/// Returns the challenge to send along with the request for the page. The listing fails if the
+/// page does not come within the pull validity.
+pub async fn start_directory_page_task(
+    dir_path: PathBuf,
+    tracker: Arc<DirectoryDownloadTracker>,
+) -> Challenge {
+    let challenge = rand::rng().random_range(1..u64::MAX);
+    PENDING_DIRECTORY_DOWNLOADS.write().await.insert(
+        challenge,
+        PendingDirectoryDownloadTask {
+            dir_path,
+            handle: None,
+            tracker: Some(tracker),
+        },
+    );
+
+    let validity = ENV_VAR
+        .get()
+        .map(|ev| ev.get_pull_task_validity_in_sec())
+        .unwrap_or(300);
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(validity)).await;
+        if let Some(pending) = claim_pending_directory_download(challenge).await
+            && let Some(tracker) = pending.tracker
+        {
+            tracker
+                .fail_listing(String::from("the next page of the listing never came"))
+                .await;
+        }
+    });
+
+    challenge
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    type Records = Arc<std::sync::Mutex<Vec<(JobStatus, String)>>>;
+
+    fn recording_callback() -> (Box<JobSummaryStatusCallback>, Records) {
+        let records = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let records_clone = records.clone();
+        let callback: Box<JobSummaryStatusCallback> = Box::new(move |status, msg| {
+            records_clone.lock().unwrap().push((status, msg));
+            Box::pin(async { Ok(()) })
+        });
+        (callback, records)
+    }
+
+    #[tokio::test]
+    async fn tracker_reports_progress_then_completes() {
+        let (callback, records) = recording_callback();
+        let tracker = DirectoryDownloadTracker::new(PathBuf::from("dir"), 2, callback);
+
+        tracker.report_success(Path::new("dir/a")).await;
+        tracker.report_success(Path::new("dir/b")).await;
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, JobStatus::Running);
+        assert_eq!(records[0].1, "1/2 files transferred, 0 failed");
+        assert_eq!(records[1].0, JobStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn tracker_fails_with_per_file_reasons_and_ignores_late_reports() {
+        let (callback, records) = recording_callback();
+        let tracker = DirectoryDownloadTracker::new(PathBuf::from("dir"), 2, callback);
+
+        tracker
+            .report_failure(Path::new("dir/a"), "timed out".to_string())
+            .await;
+        tracker.report_success(Path::new("dir/b")).await;
+        tracker.report_success(Path::new("dir/c")).await;
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].0, JobStatus::Failed);
+        assert!(records[1].1.starts_with("1/2 files transferred, 1 failed:"));
+        assert!(records[1].1.contains("dir/a: timed out"));
+    }
+
+    #[tokio::test]
+    async fn tracker_waits_for_the_last_page_of_the_listing() {
+        let (callback, records) = recording_callback();
+        let tracker = DirectoryDownloadTracker::new(PathBuf::from("dir"), 1, callback);
+        tracker.add_page(0, true).await;
+
+        // The first page is done, but the listing goes on
+        tracker.report_success(Path::new("dir/a")).await;
+        tracker.add_page(1, false).await;
+        tracker.report_success(Path::new("dir/b")).await;
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records[..3].iter().all(|(s, _)| *s == JobStatus::Running));
+        assert_eq!(
+            records[3],
+            (JobStatus::Completed, "2 files transferred".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn tracker_fails_when_the_listing_breaks_off() {
+        let (callback, records) = recording_callback();
+        let tracker = DirectoryDownloadTracker::new(PathBuf::from("dir"), 1, callback);
+        tracker.add_page(0, true).await;
+        tracker.report_success(Path::new("dir/a")).await;
+        tracker.fail_listing("no answer".to_string()).await;
+
+        let records = records.lock().unwrap();
+        let (status, msg) = records.last().unwrap();
+        assert_eq!(*status, JobStatus::Failed);
+        assert!(msg.contains("dir: no answer"), "{msg}");
+    }
+}
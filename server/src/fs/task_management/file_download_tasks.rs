@@ -1,11 +1,12 @@
-use crate::core::tasks::{ClaimableJobHandle, launch_claimable_job};
+use crate::core::tasks::{ClaimableJobHandle, forget_outgoing_pull, launch_claimable_job};
 use crate::err::Result;
+use crate::fs::task_management::directory_download_tasks::DirectoryDownloadTracker;
 use crate::global_var::{LOGGER, get_task_queue_sender};
 use crate::types::Expected;
 use rand::Rng;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 use tokio::sync::RwLock;
 
 type Checksum = u64;
@@ -21,6 +22,9 @@ pub struct PendingFileDownloadTask {
 
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub handle: Option<ClaimableJobHandle>,
+
+    /// Set when the download is a child of a directory download
+    pub parent: Option<Arc<DirectoryDownloadTracker>>,
 }
 
 impl PendingFileDownloadTask {
@@ -38,8 +42,14 @@ impl PendingFileDownloadTask {
             to_checksum,
             created_at: chrono::Utc::now(),
             handle: Some(handle),
+            parent: None,
         }
     }
+
+    pub fn with_parent(mut self, parent: Arc<DirectoryDownloadTracker>) -> Self {
+        self.parent = Some(parent);
+        self
+    }
 }
 
 static PENDING_DOWNLOADS: LazyLock<RwLock<HashMap<Challenge, PendingFileDownloadTask>>> =
@@ -50,7 +60,21 @@ async fn cancel_pending(challenge: Challenge) {
         "Removing download task for challenge {} from pending downloads map, result is ignored",
         challenge
     ));
-    let _ = PENDING_DOWNLOADS.write().await.remove(&challenge);
+    forget_outgoing_pull(challenge).await;
+    let removed = PENDING_DOWNLOADS.write().await.remove(&challenge);
+    if let Some(PendingFileDownloadTask {
+        file_path,
+        parent: Some(parent),
+        ..
+    }) = removed
+    {
+        parent
+            .report_failure(
+                &file_path,
+                String::from("Download was never started by peer"),
+            )
+            .await;
+    }
 }
 
 async fn claim_by_nonce(challenge: Challenge) -> Option<PendingFileDownloadTask> {
@@ -61,6 +85,11 @@ pub async fn claim_pending_download(challenge: Challenge) -> Option<PendingFileD
     claim_by_nonce(challenge).await
 }
 
+/// Whether the download under `challenge` still waits for the peer to answer
+pub async fn is_download_pending(challenge: Challenge) -> bool {
+    PENDING_DOWNLOADS.read().await.contains_key(&challenge)
+}
+
 async fn insert_download_task(task: PendingFileDownloadTask) {
     PENDING_DOWNLOADS.write().await.insert(task.challenge, task);
 }
@@ -69,6 +98,25 @@ pub async fn start_file_download_task<P: AsRef<Path>>(
     path: P,
     from_checksum: Expected<Checksum>,
     to_checksum: Expected<Checksum>,
+) -> Result<Challenge> {
+    start_download_task(path, from_checksum, to_checksum, None).await
+}
+
+/// Start a file download reporting its result to the directory download it belongs to.
+pub async fn start_child_file_download_task<P: AsRef<Path>>(
+    path: P,
+    from_checksum: Expected<Checksum>,
+    to_checksum: Expected<Checksum>,
+    parent: Arc<DirectoryDownloadTracker>,
+) -> Result<Challenge> {
+    start_download_task(path, from_checksum, to_checksum, Some(parent)).await
+}
+
+async fn start_download_task<P: AsRef<Path>>(
+    path: P,
+    from_checksum: Expected<Checksum>,
+    to_checksum: Expected<Checksum>,
+    parent: Option<Arc<DirectoryDownloadTracker>>,
 ) -> Result<Challenge> {
     // Make sure the challenge is not 0, 0 is reserved for bad requests, and the server will not respond to challenges with 0.
     let challenge = rand::rng().random_range(1..u64::MAX);
@@ -91,13 +139,16 @@ pub async fn start_file_download_task<P: AsRef<Path>>(
     let download_job_handle =
         launch_claimable_job(&job_name, &summary, cleanup, 300, q_sender).await?;
 
-    let pending_download_task = PendingFileDownloadTask::new(
+    let mut pending_download_task = PendingFileDownloadTask::new(
         challenge,
         path.as_ref().to_path_buf(),
         from_checksum,
         to_checksum,
         download_job_handle,
     );
+    if let Some(parent) = parent {
+        pending_download_task = pending_download_task.with_parent(parent);
+    }
     insert_download_task(pending_download_task).await;
 
     LOGGER.info(format!(
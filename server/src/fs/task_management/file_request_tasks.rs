@@ -5,14 +5,24 @@
 //! 2) Server validates path and (optionally) verifies checksum.
 //! 3) Server moves the file into .disc/tmp_downloads, launches a claimable job, and generates a random nonce.
 //! 4) Server saves the claimable job handle together with the nonce in a global map so the downloader can "claim" it.
+//!
+//! A bulk (archive) pull follows the same flow, except that in step 3 all the files under the
+//! requested directory are packed into a single archive, which is then sent as one file.
+//!
+//! The copies are kept in clear until the downloader connects: they are only encrypted once the
+//! downloader sent the ephemeral key of the transfer (see `core::protocol::file_send`). Until then
+//! they are only readable by the owner of the server.
 
 use crate::core::tasks::{ClaimableJobHandle, launch_claimable_job};
 use crate::err::Result;
+use crate::fs::FS_INDEX;
+use crate::fs::LumoFile;
+use crate::fs::archive::pack_files;
 use crate::fs::fs_lock;
-use crate::fs::util::normalize_path;
+use crate::fs::is_ignored;
+use crate::fs::util::{get_relative_path, normalize_path};
 use crate::global_var::{ENV_VAR, LOGGER, get_task_queue_sender};
 use crate::types::Expected;
-use crate::utilities::crypto::f_to_encryption;
 use crate::utilities::temp_dir::TmpDirGuard;
 use rand::random;
 use std::collections::HashMap;
@@ -76,7 +86,7 @@ impl PendingPull {
         // Prepare temp destination in .disc/tmp_downloads
         let base_download_dir = PathBuf::from(ENV_VAR.get().unwrap().get_temp_downloads_dir());
         let tmp_dir = base_download_dir.join(format!("send-{:x}", nonce));
-        tokio::fs::create_dir_all(&tmp_dir).await.map_err(|e| {
+        create_private_dir(&tmp_dir).await.map_err(|e| {
             LOGGER.error(format!(
                 "Failed to create temp dir {}: {:?}",
                 tmp_dir.display(),
@@ -128,20 +138,30 @@ impl PendingPull {
                         RejectionReason::SystemError
                     })?;
 
-            let passphrase = format!("{}", nonce);
-            f_to_encryption(&original_full_path, &tmp_dest, &passphrase)
+            // Still under the read lock, so the copy matches the checksum
+            tokio::fs::copy(&original_full_path, &tmp_dest)
                 .await
                 .map_err(|e| {
                     LOGGER.error(format!(
-                        "Failed to encrypt file {}: {:?}",
+                        "Failed to copy file {}: {:?}",
                         original_full_path.display(),
                         e
                     ));
                     RejectionReason::SystemError
                 })?;
+            drop(read_guard);
+            // The copy takes the mode of the source
+            restrict_to_owner(&tmp_dest).await.map_err(|e| {
+                LOGGER.error(format!(
+                    "Failed to restrict access to {}: {:?}",
+                    tmp_dest.display(),
+                    e
+                ));
+                RejectionReason::SystemError
+            })?;
 
             LOGGER.debug(format!(
-                "Copied and encrypted file '{}' to temp location '{}', file checksum {}",
+                "Copied file '{}' to temp location '{}', file checksum {}",
                 original_full_path.display(),
                 tmp_dest.display(),
                 checksum
@@ -175,6 +195,112 @@ impl PendingPull {
             handle: Some(handle),
         })
     }
+
+    /// Pack `files` (relative to working_dir) into a single encrypted archive for a bulk pull.
+    /// The checksum of a PendingPull created this way is the checksum of the plain archive.
+    pub async fn new_archive(
+        nonce: Nonce,
+        dir_path: PathBuf,
+        files: &[PathBuf],
+    ) -> std::result::Result<Self, RejectionReason> {
+        let base_download_dir = PathBuf::from(ENV_VAR.get().unwrap().get_temp_downloads_dir());
+        let tmp_dir = base_download_dir.join(format!("send-{:x}", nonce));
+        create_private_dir(&tmp_dir).await.map_err(|e| {
+            LOGGER.error(format!(
+                "Failed to create temp dir {}: {:?}",
+                tmp_dir.display(),
+                e
+            ));
+            RejectionReason::SystemError
+        })?;
+        let archive_path = tmp_dir.join(format!("archive-{:x}.tar", nonce));
+        let tmp_dir_guard: TmpDirGuard = tmp_dir.into();
+
+        let working_dir = PathBuf::from(ENV_VAR.get().unwrap().get_working_dir());
+        let packed = pack_files(&working_dir, files, &archive_path)
+            .await
+            .map_err(|e| {
+                LOGGER.error(format!(
+                    "Failed to pack archive for {}: {:?}",
+                    dir_path.display(),
+                    e
+                ));
+                RejectionReason::SystemError
+            })?;
+        restrict_to_owner(&archive_path).await.map_err(|e| {
+            LOGGER.error(format!(
+                "Failed to restrict access to {}: {:?}",
+                archive_path.display(),
+                e
+            ));
+            RejectionReason::SystemError
+        })?;
+
+        let checksum = async {
+            LumoFile::new(archive_path.clone())
+                .await?
+                .get_checksum()
+                .await
+        }
+        .await
+        .map_err(|e| {
+            LOGGER.error(format!(
+                "Failed to get checksum for archive {}: {:?}",
+                archive_path.display(),
+                e
+            ));
+            RejectionReason::SystemError
+        })?;
+
+        LOGGER.debug(format!(
+            "Packed {} files under '{}' into archive '{}', archive checksum {}",
+            packed.len(),
+            dir_path.display(),
+            archive_path.display(),
+            checksum
+        ));
+
+        let handle = Self::create_claimable_job(nonce, &dir_path)
+            .await
+            .map_err(|e| {
+                LOGGER.error(format!(
+                    "Failed to create claimable job for {}: {:?}",
+                    dir_path.display(),
+                    e
+                ));
+                RejectionReason::SystemError
+            })?;
+
+        Ok(Self {
+            nonce,
+            original_path: dir_path,
+            temp_file_path: archive_path,
+            temp_path: tmp_dir_guard,
+            checksum,
+            created_at: chrono::Utc::now(),
+            handle: Some(handle),
+        })
+    }
+}
+
+/// Create the staging dir of a pull, owner only
+async fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    let mut builder = tokio::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(dir).await
+}
+
+async fn restrict_to_owner(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
 }
 
 impl Drop for PendingPull {
@@ -216,7 +342,12 @@ impl Display for RejectionReason {
 
 pub enum PullRequestResult {
     Accept(Nonce),
+    /// Answer to a bulk request: the nonce of the archive, and the checksum of the plain archive
+    AcceptArchive(Nonce, Checksum),
     Reject(RejectionReason),
+    /// Relative paths and sizes of a page of the files to pull for a recursive request, and the
+    /// offset of the next page if the listing goes on
+    Listing(Vec<(PathBuf, u64)>, Option<u64>),
 }
 
 /// Core implementation for processing a file pull request.
@@ -228,7 +359,8 @@ pub async fn start_pull_request(
     // Resolve and validate source path
     let src = normalize_path(path_str)?;
 
-    if !src.exists() {
+    // Ignored paths are not shared, as if they did not exist
+    if !src.exists() || is_ignored(&src) {
         return Ok(PullRequestResult::Reject(RejectionReason::PathNotFound));
     }
 
@@ -249,6 +381,95 @@ pub async fn start_pull_request(
     }
 }
 
+/// A page of a listing has to fit in a single control message, leave room for encryption and
+/// framing.
+const MAX_LISTING_PAGE_BYTES: usize = 48 * 1024;
+
+/// Enumerate the indexed files under `src`; a plain file lists itself.
+async fn list_requested_files(src: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    let meta = tokio::fs::metadata(src).await?;
+    if meta.is_dir() {
+        Ok(FS_INDEX.list_files_under(src).await)
+    } else {
+        let rel_path = get_relative_path(&src)?;
+        Ok(vec![(rel_path, meta.len())])
+    }
+}
+
+/// Cut the page of `files` starting at `offset` that fits in `max_bytes`, and the offset of the
+/// next page. A page holds at least one file, whatever its path length.
+fn listing_page(
+    mut files: Vec<(PathBuf, u64)>,
+    offset: u64,
+    max_bytes: usize,
+) -> (Vec<(PathBuf, u64)>, Option<u64>) {
+    // Pages are cut from the same order on every request
+    files.sort();
+    let start = usize::try_from(offset)
+        .unwrap_or(usize::MAX)
+        .min(files.len());
+    let mut page_bytes = 0;
+    let mut end = start;
+    for (path, _) in &files[start..] {
+        // Rough estimate of the encoded size: the path plus the length prefix and the size
+        page_bytes += path.as_os_str().len() + 16;
+        if page_bytes > max_bytes && end > start {
+            break;
+        }
+        end += 1;
+    }
+    let next_offset = (end < files.len()).then_some(end as u64);
+    (files.drain(start..end).collect(), next_offset)
+}
+
+/// Core implementation for processing a recursive pull request.
+/// Enumerates the indexed files under the requested directory from `offset`, as many as fit in
+/// a message; a plain file lists itself.
+pub async fn start_directory_pull_request(
+    path_str: &str,
+    offset: u64,
+) -> Result<PullRequestResult> {
+    let src = normalize_path(path_str)?;
+
+    // Ignored paths are not shared, as if they did not exist
+    if !src.exists() || is_ignored(&src) {
+        return Ok(PullRequestResult::Reject(RejectionReason::PathNotFound));
+    }
+
+    let files = list_requested_files(&src).await?;
+    let (page, next_offset) = listing_page(files, offset, MAX_LISTING_PAGE_BYTES);
+    Ok(PullRequestResult::Listing(page, next_offset))
+}
+
+/// Core implementation for processing a bulk pull request.
+/// Packs the indexed files under the requested directory into a single archive, which is then
+/// claimed and sent like a single file.
+pub async fn start_archive_pull_request(path_str: &str) -> Result<PullRequestResult> {
+    let src = normalize_path(path_str)?;
+
+    // Ignored paths are not shared, as if they did not exist
+    if !src.exists() || is_ignored(&src) {
+        return Ok(PullRequestResult::Reject(RejectionReason::PathNotFound));
+    }
+
+    let files: Vec<PathBuf> = list_requested_files(&src)
+        .await?
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+
+    let nonce = random::<u64>();
+
+    match PendingPull::new_archive(nonce, src, &files).await {
+        Ok(pending) => {
+            let checksum = pending.checksum;
+            PENDING_PULLS.write().await.insert(nonce, pending);
+            Ok(PullRequestResult::AcceptArchive(nonce, checksum))
+        }
+        Err(e) => Ok(PullRequestResult::Reject(e)),
+    }
+}
+
 /// Claim a pending transfer by its nonce. Returns the ClaimableJobHandle if present,
 /// and removes the entry from the registry. The caller can then call `take_over()` on the handle.
 async fn claim_by_nonce(nonce: Nonce) -> Option<PendingPull> {
@@ -269,3 +490,36 @@ async fn cancel_pending(nonce: Nonce) {
     ));
     let _ = PENDING_PULLS.write().await.remove(&nonce);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listings_are_cut_in_pages_that_cover_every_file_once() {
+        let files: Vec<(PathBuf, u64)> = (0..10)
+            .rev()
+            .map(|i| (PathBuf::from(format!("dir/file-{}", i)), i))
+            .collect();
+        // Every entry is estimated at 10 + 16 bytes, so 3 fit in a page
+        let mut offset = 0;
+        let mut listed = Vec::new();
+        loop {
+            let (page, next) = listing_page(files.clone(), offset, 80);
+            assert!(!page.is_empty() && page.len() <= 3);
+            listed.extend(page);
+            match next {
+                Some(next) => offset = next,
+                None => break,
+            }
+        }
+        let mut expected = files.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        // A path longer than a page still gets listed, alone
+        let (page, next) = listing_page(files.clone(), 0, 1);
+        assert_eq!((page.len(), next), (1, Some(1)));
+        assert_eq!(listing_page(files, 10, 80), (vec![], None));
+    }
+}
@@ -1,3 +1,11 @@
+pub mod directory_download_tasks;
+pub use directory_download_tasks::{
+    DirectoryDownloadTracker, PendingDirectoryDownloadTask, claim_pending_directory_download,
+    is_directory_download_pending, start_directory_download_task, start_directory_page_task,
+};
 pub mod file_download_tasks;
-pub use file_download_tasks::{claim_pending_download, start_file_download_task};
+pub use file_download_tasks::{
+    claim_pending_download, is_download_pending, start_child_file_download_task,
+    start_file_download_task,
+};
 pub mod file_request_tasks;
@@ -15,11 +15,13 @@
 //! The checks are best-effort and based on attempting real operations. They should work on Linux,
 //! macOS, and Windows.
 
+use crate::config::SelectiveSync;
 use crate::err::Result;
+use crate::fs::is_ignored;
 use crate::global_var::ENV_VAR;
 use std::fs;
 use std::io;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Result of probing directory permissions for the current process.
@@ -229,6 +231,43 @@ pub fn get_relative_path<P: AsRef<Path>>(path: &P) -> Result<PathBuf> {
     }
 }
 
+/// Check a path sent by a peer for the download of `dir_path`, before anything is written to it.
+/// It has to be a plain relative path under `dir_path`, outside `.disc`, and neither ignored nor
+/// unsubscribed, as a peer is free to list whatever it likes.
+pub fn check_received_path(dir_path: &Path, path: &Path) -> Result<()> {
+    let subscription = ENV_VAR.get().map(|ev| ev.get_selective_sync());
+    check_received_path_with(dir_path, path, subscription)?;
+    if is_ignored(path) {
+        return Err(format!("'{}' is ignored", path.display()).into());
+    }
+    Ok(())
+}
+
+fn check_received_path_with(
+    dir_path: &Path,
+    path: &Path,
+    subscription: Option<&SelectiveSync>,
+) -> Result<()> {
+    let is_plain = |p: &Path| p.components().all(|c| matches!(c, Component::Normal(_)));
+    if path.as_os_str().is_empty() || !is_plain(path) {
+        return Err(format!("'{}' is not a plain relative path", path.display()).into());
+    }
+    let dir_path: PathBuf = dir_path
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect();
+    if !is_plain(&dir_path) || !path.starts_with(&dir_path) {
+        return Err(format!("'{}' is not under '{}'", path.display(), dir_path.display()).into());
+    }
+    if path.starts_with(".disc") {
+        return Err(format!("'{}' is node metadata", path.display()).into());
+    }
+    if subscription.is_some_and(|s| !s.is_subscribed(path)) {
+        return Err(format!("'{}' is not subscribed", path.display()).into());
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -412,6 +451,31 @@ mod tests {
         assert_eq!(r3, base, "subseconds should be dropped and odd floored");
     }
 
+    #[test]
+    fn received_paths_stay_plain_under_the_requested_dir() {
+        let check = |dir: &str, path: &str, sub: Option<&SelectiveSync>| {
+            check_received_path_with(Path::new(dir), Path::new(path), sub).is_ok()
+        };
+        assert!(check("foo", "foo/a.txt", None));
+        assert!(check(".", "foo/a.txt", None));
+        assert!(check("", "foo/a.txt", None));
+
+        assert!(!check("foo", "bar/a.txt", None));
+        assert!(!check("foo", "foo/../bar/a.txt", None));
+        assert!(!check("foo", "/foo/a.txt", None));
+        assert!(!check("foo", "./foo/a.txt", None));
+        assert!(!check("foo", "", None));
+        assert!(!check(".", ".disc/known_peers", None));
+        assert!(!check(".disc", ".disc/api_secret", None));
+
+        let sub = SelectiveSync {
+            include: Vec::new(),
+            exclude: vec![String::from("foo/private")],
+        };
+        assert!(check("foo", "foo/a.txt", Some(&sub)));
+        assert!(!check("foo", "foo/private/a.txt", Some(&sub)));
+    }
+
     #[test]
     fn round_to_fat32_pre_epoch_clamps_to_epoch() {
         let before = UNIX_EPOCH - Duration::from_secs(1);
@@ -0,0 +1,113 @@
+use crate::err::Result;
+use crate::global_var::LOGGER;
+use crate::interface::handlers::run_authenticated_handler;
+use api_model::protocol::framing::{MessageAssembler, encode};
+use api_model::protocol::message::api_request_message::ApiRequestMessage;
+use api_model::protocol::message::api_response_message::{
+    ApiResponseKind, ApiResponseMessage, UNKNOWN_REQUEST_ID,
+};
+use api_model::protocol::protocol::Protocol;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Longest request accepted over the socket, requests are small
+const MAX_REQUEST_LEN: usize = 1024 * 1024;
+
+/// Read one framed message, returns `None` when the client closed the connection in between
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut assembler = MessageAssembler::new(MAX_REQUEST_LEN);
+    let mut first = true;
+    loop {
+        let len = match reader.read_u32().await {
+            Err(e) if first && e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            other => other?,
+        };
+        first = false;
+        if !assembler.expect_part(len)? {
+            return Ok(Some(assembler.into_message()));
+        }
+        let mut part = vec![0u8; len as usize];
+        reader.read_exact(&mut part).await?;
+        assembler.push_part(&part);
+    }
+}
+
+/// Answer the requests of a local API connection in turn, until the client closes it.
+/// Responses are framed, so they are not bound by the size of a datagram.
+pub async fn serve_api_connection<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> Result<()> {
+    while let Some(bytes) = read_message(&mut stream).await? {
+        let response = match ApiRequestMessage::deserialize(&bytes) {
+            Ok(request) => request.response(
+                run_authenticated_handler(&request)
+                    .await
+                    .unwrap_or_else(|e| ApiResponseKind::Error(e.to_string())),
+            ),
+            Err(e) => {
+                LOGGER.warn(format!("Malformed API request: {}", e));
+                ApiResponseMessage {
+                    request_id: UNKNOWN_REQUEST_ID,
+                    response: ApiResponseKind::Error(format!("Malformed request: {}", e)),
+                }
+            }
+        };
+        stream.write_all(&encode(&response.serialize())).await?;
+        stream.flush().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::api_secret::ensure_test_api_secret;
+    use api_model::protocol::framing::read_message as read_message_sync;
+
+    #[tokio::test]
+    async fn requests_are_answered_in_turn_on_the_same_connection() -> Result<()> {
+        let secret = ensure_test_api_secret();
+        let (client, server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(serve_api_connection(server));
+
+        let (mut reader, mut writer) = tokio::io::split(client);
+        let request = ApiRequestMessage::new(
+            7,
+            String::new(),
+            0,
+            api_model::protocol::message::api_request_message::ApiRequestKind::Info,
+        );
+        let authenticated = request.clone().authenticated(secret.as_bytes())?;
+        for request in [request, authenticated] {
+            writer.write_all(&encode(&request.serialize())).await?;
+            let mut len = [0u8; 4];
+            let mut framed = Vec::new();
+            // Read the response back part by part until the end of message
+            loop {
+                reader.read_exact(&mut len).await?;
+                framed.extend_from_slice(&len);
+                let n = u32::from_be_bytes(len) as usize;
+                if n == 0 {
+                    break;
+                }
+                let mut part = vec![0u8; n];
+                reader.read_exact(&mut part).await?;
+                framed.extend_from_slice(&part);
+            }
+            let bytes = read_message_sync(&mut &framed[..], usize::MAX)?.unwrap();
+            let response = ApiResponseMessage::deserialize(&bytes)?;
+            assert_eq!(response.request_id, request.request_id);
+            // Info has no handler, which only shows once the request is authenticated
+            if request.auth_tag.is_none() {
+                assert!(matches!(
+                    response.response,
+                    ApiResponseKind::Unauthenticated(_)
+                ));
+            } else {
+                assert!(matches!(response.response, ApiResponseKind::Error(_)));
+            }
+        }
+
+        drop(writer);
+        drop(reader);
+        server.await??;
+        Ok(())
+    }
+}
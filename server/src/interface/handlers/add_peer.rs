@@ -0,0 +1,44 @@
+use crate::core::STATIC_PEERS;
+use crate::core::static_peers::{resolve, with_default_port};
+use crate::core::tasks::send_hello_to;
+use crate::err::Result;
+use crate::global_var::{ENV_VAR, LOGGER, get_task_queue_sender};
+use crate::network::protocol::messages::hello_message::HelloMode;
+use api_model::protocol::models::peer::add_peer::{AddPeerRequest, AddPeerResponse};
+use cli_handler::cli_handler;
+
+#[cli_handler(AddPeer)]
+pub async fn add_peer(request: &AddPeerRequest) -> Result<AddPeerResponse> {
+    let env_var = ENV_VAR.get().ok_or("Environment variables not set")?;
+    let default_port = env_var.get_port();
+    let addr = with_default_port(&request.addr, default_port);
+    // Refuse what cannot be reached now rather than retrying it forever
+    let resolved = resolve(&addr).await?;
+    let added = STATIC_PEERS.add(&addr, default_port).await.is_some();
+    let mut save_error = None;
+    if added {
+        LOGGER.info(format!("Added static peer {} ({:?})", addr, resolved));
+        if let Err(e) = env_var.save_static_peer(&addr) {
+            LOGGER.warn(format!(
+                "Static peer {} is only kept until the server stops, unable to save it: {}",
+                addr, e
+            ));
+            save_error = Some(e.to_string());
+        }
+    }
+
+    // Introduce ourselves right away instead of at the next heartbeat
+    send_hello_to(
+        &get_task_queue_sender().await?,
+        &resolved,
+        HelloMode::empty(),
+    )
+    .await?;
+
+    Ok(AddPeerResponse {
+        addr,
+        resolved: resolved.iter().map(|a| a.to_string()).collect(),
+        added,
+        save_error,
+    })
+}
@@ -29,6 +29,12 @@ pub async fn list_peers(_request: &ListPeersRequest) -> Result<ListPeersResponse
                 p.last_seen_ms.load(Ordering::Relaxed),
                 p.last_seen_tz_offset_minutes.load(Ordering::Relaxed),
             ),
+            subscribed: p.subscription.include.clone(),
+            unsubscribed: p.subscription.exclude.clone(),
+            protocol_version: p.protocol_version,
+            capabilities: p.capabilities.names(),
+            clock_offset_ms: p.clock_offset_ms,
+            hops: p.hops,
         })
         .collect();
 
@@ -24,7 +24,10 @@ pub async fn local_pull_file(request: &LocalPullFileRequest) -> Result<LocalPull
                 LocalPullFileResult::Reject(PullFileError::InternalError)
             }
         },
-        Err(e) => LocalPullFileResult::Reject(PullFileError::InternalError),
+        // Listings and archives only answer recursive and bulk pulls
+        Ok(PullRequestResult::Listing(..) | PullRequestResult::AcceptArchive(..)) | Err(_) => {
+            LocalPullFileResult::Reject(PullFileError::InternalError)
+        }
     };
 
     Ok(LocalPullFileResponse { result })
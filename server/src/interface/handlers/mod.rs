@@ -1,16 +1,41 @@
+use crate::global_var::LOGGER;
+use crate::interface::handlers::add_peer::add_peer;
 use crate::interface::handlers::list_local_files::list_local_files;
 use crate::interface::handlers::list_peers::list_peers;
 use crate::interface::handlers::list_tasks::list_tasks;
 use crate::interface::handlers::local_pull_file::local_pull_file;
+use crate::interface::handlers::peer_keys::{forget_peer, list_peer_keys, trust_peer};
 use crate::interface::handlers::pull_file::pull_file;
-use api_model::protocol::message::api_request_message::ApiRequestKind;
+use crate::interface::handlers::push_file::push_file;
+use crate::interface::handlers::rotate_token::rotate_token;
+use crate::utilities::api_secret::check_api_request;
+use api_model::protocol::message::api_request_message::{ApiRequestKind, ApiRequestMessage};
 use api_model::protocol::message::api_response_message::ApiResponseKind;
 
+pub mod add_peer;
 mod list_local_files;
 pub mod list_peers;
 pub mod list_tasks;
 pub mod local_pull_file;
+pub mod peer_keys;
 pub mod pull_file;
+pub mod push_file;
+pub mod rotate_token;
+
+/// Answer the request if it is tagged with the API secret, any local user could send it otherwise.
+/// Requests are answered once, and only shortly after they were made
+pub async fn run_authenticated_handler(
+    message: &ApiRequestMessage,
+) -> crate::err::Result<ApiResponseKind> {
+    if let Err(e) = check_api_request(message) {
+        LOGGER.warn(format!("Rejected API request: {}", e));
+        return Ok(ApiResponseKind::Unauthenticated(format!(
+            "{}, the client has to run as the user owning the server to read its API secret",
+            e
+        )));
+    }
+    run_handler(&message.request).await
+}
 
 pub async fn run_handler(api_request_kind: &ApiRequestKind) -> crate::err::Result<ApiResponseKind> {
     let response = match api_request_kind {
@@ -19,6 +44,12 @@ pub async fn run_handler(api_request_kind: &ApiRequestKind) -> crate::err::Resul
         ApiRequestKind::ListTasks(req) => list_tasks(req).await,
         ApiRequestKind::PullFile(req) => pull_file(req).await,
         ApiRequestKind::ListLocalFiles(req) => list_local_files(req).await,
+        ApiRequestKind::PushFile(req) => push_file(req).await,
+        ApiRequestKind::ListPeerKeys(req) => list_peer_keys(req).await,
+        ApiRequestKind::TrustPeer(req) => trust_peer(req).await,
+        ApiRequestKind::ForgetPeer(req) => forget_peer(req).await,
+        ApiRequestKind::RotateToken(req) => rotate_token(req).await,
+        ApiRequestKind::AddPeer(req) => add_peer(req).await,
         _ => return Err(format!("Handler for {:?} not found", api_request_kind).into()),
     };
     Ok(response)
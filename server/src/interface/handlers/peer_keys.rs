@@ -0,0 +1,58 @@
+use crate::core::KnownPeer;
+use crate::core::get_known_peers;
+use crate::err::Result;
+use crate::global_var::LOGGER;
+use crate::utilities::identity::fingerprint_of;
+use api_model::protocol::models::peer::peer_keys::{
+    ForgetPeerRequest, ForgetPeerResponse, ListPeerKeysRequest, ListPeerKeysResponse, PeerKey,
+    TrustPeerRequest, TrustPeerResponse,
+};
+use bytes::Bytes;
+use cli_handler::cli_handler;
+use std::time::{Duration, UNIX_EPOCH};
+
+fn to_peer_key(known: &KnownPeer, refused: Option<&Bytes>) -> PeerKey {
+    PeerKey {
+        peer_name: known.peer_name.clone(),
+        fingerprint: known.fingerprint(),
+        pinned_at: UNIX_EPOCH + Duration::from_secs(known.pinned_at_secs),
+        refused_fingerprint: refused.map(|key| fingerprint_of(key)),
+    }
+}
+
+#[cli_handler(ListPeerKeys)]
+pub async fn list_peer_keys(_request: &ListPeerKeysRequest) -> Result<ListPeerKeysResponse> {
+    let keys = get_known_peers()?
+        .list()
+        .await
+        .iter()
+        .map(|(known, refused)| to_peer_key(known, refused.as_ref()))
+        .collect();
+    Ok(ListPeerKeysResponse { keys })
+}
+
+#[cli_handler(TrustPeer)]
+pub async fn trust_peer(request: &TrustPeerRequest) -> Result<TrustPeerResponse> {
+    let known = get_known_peers()?.trust(&request.fingerprint).await?;
+    LOGGER.info(format!(
+        "Trusted identity key {} for peer {}",
+        known.fingerprint(),
+        known.peer_name
+    ));
+    Ok(TrustPeerResponse {
+        key: to_peer_key(&known, None),
+    })
+}
+
+#[cli_handler(ForgetPeer)]
+pub async fn forget_peer(request: &ForgetPeerRequest) -> Result<ForgetPeerResponse> {
+    let known = get_known_peers()?.forget(&request.fingerprint).await?;
+    LOGGER.info(format!(
+        "Forgot identity key {} of peer {}",
+        known.fingerprint(),
+        known.peer_name
+    ));
+    Ok(ForgetPeerResponse {
+        key: to_peer_key(&known, None),
+    })
+}
@@ -1,8 +1,13 @@
 use crate::core::PEER_TABLE;
-use crate::core::tasks::{get_job_fs_pull_initiate_closure, launch_oneshot_job};
+use crate::core::Peer;
+use crate::core::tasks::{
+    get_job_fs_pull_directory_initiate_closure, get_job_fs_pull_initiate_closure,
+    launch_oneshot_job,
+};
 use crate::err::Result;
-use crate::fs::FS_INDEX;
+use crate::fs::{FS_INDEX, start_directory_download_task};
 use crate::global_var::{LOGGER, get_task_queue_sender};
+use crate::network::protocol::messages::hello_message::Capabilities;
 use api_model::protocol::models::file::pull_file::{PullFileRequest, PullFileResponse};
 use cli_handler::cli_handler;
 
@@ -22,6 +27,25 @@ pub async fn pull_file(request: &PullFileRequest) -> Result<PullFileResponse> {
         .await
         .ok_or_else(|| format!("Peer {} not found", request.peer_identifier))?;
 
+    if request.recursive {
+        if !peer.supports(Capabilities::RECURSIVE_PULL) {
+            return Err(format!(
+                "Peer {} does not support directory pulls",
+                request.peer_identifier
+            )
+            .into());
+        }
+        // Older peers only answer with the listing, every file is then pulled on its own
+        let bulk = request.bulk && peer.supports(Capabilities::BULK_ARCHIVE);
+        if request.bulk && !bulk {
+            LOGGER.info(format!(
+                "Peer {} does not support bulk transfers, pulling files one by one",
+                request.peer_identifier
+            ));
+        }
+        return pull_directory(&peer, &file_path, bulk).await;
+    }
+
     LOGGER.trace(format!("In the middle: {}", "QAQ"));
 
     // 3. initiate an oneshot job get_fs_pull_initiate (to be implemented)
@@ -43,5 +67,30 @@ pub async fn pull_file(request: &PullFileRequest) -> Result<PullFileResponse> {
 
     LOGGER.trace(format!("Pull file job initiated with ID: {}", job));
 
-    Ok(PullFileResponse)
+    Ok(PullFileResponse { job_id: None })
+}
+
+async fn pull_directory(peer: &Peer, dir_path: &str, bulk: bool) -> Result<PullFileResponse> {
+    // The parent job aggregates all the child downloads once the peer answers with the listing,
+    // or all the unpacked files once the peer sends the archive in bulk mode
+    let (challenge, job_id) = start_directory_download_task(dir_path).await?;
+
+    let task_sender = get_task_queue_sender().await?;
+    let job = launch_oneshot_job(
+        "Pull directory initiation",
+        &format!(
+            "Initiate pulling directory {} from {}",
+            dir_path, &peer.peer_name
+        ),
+        get_job_fs_pull_directory_initiate_closure(peer, dir_path, challenge, bulk).await?,
+        Some(30),
+        task_sender,
+    )
+    .await?;
+
+    LOGGER.trace(format!("Pull directory job initiated with ID: {}", job));
+
+    Ok(PullFileResponse {
+        job_id: Some(job_id),
+    })
 }
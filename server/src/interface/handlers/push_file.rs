@@ -0,0 +1,61 @@
+use crate::core::PEER_TABLE;
+use crate::core::tasks::{get_job_fs_push_offer_closure, launch_oneshot_job};
+use crate::err::Result;
+use crate::fs::FS_INDEX;
+use crate::global_var::{LOGGER, get_task_queue_sender};
+use crate::network::protocol::messages::hello_message::Capabilities;
+use api_model::protocol::models::file::push_file::{PushFileRequest, PushFileResponse};
+use cli_handler::cli_handler;
+
+#[cli_handler(PushFile)]
+pub async fn push_file(request: &PushFileRequest) -> Result<PushFileResponse> {
+    LOGGER.trace(format!("Received push file request: {:?}", request).as_str());
+
+    // 1. The offered file must be indexed, so that the peer is able to pull it back
+    let (rel_path, size) = FS_INDEX
+        .with_entry(&request.path, |e| {
+            (e.lumo_file().rel_path(), e.lumo_file().size)
+        })
+        .await
+        .ok_or_else(|| format!("File {} not found in index", request.path))?;
+    let checksum = FS_INDEX
+        .get_latest_checksum(&rel_path)
+        .await?
+        .ok_or_else(|| format!("File {} not found in index", request.path))?;
+    let rel_path = rel_path.to_string_lossy().to_string();
+
+    // 2. find the peer
+    let peer = PEER_TABLE
+        .get_peer(&request.peer_identifier)
+        .await
+        .ok_or_else(|| format!("Peer {} not found", request.peer_identifier))?;
+    if !peer.supports(Capabilities::PUSH_OFFER) {
+        return Err(format!(
+            "Peer {} does not accept push offers",
+            request.peer_identifier
+        )
+        .into());
+    }
+    if !peer.subscription.is_subscribed(&rel_path) {
+        return Err(format!(
+            "Peer {} is not subscribed to {}, it would reject the offer",
+            request.peer_identifier, rel_path
+        )
+        .into());
+    }
+
+    // 3. offer the file, the peer decides whether to pull it according to its push policy
+    let task_sender = get_task_queue_sender().await?;
+    let job = launch_oneshot_job(
+        "Push file offer",
+        &format!("Offer file {} to {}", &rel_path, &peer.peer_name),
+        get_job_fs_push_offer_closure(&peer, &rel_path, size, checksum).await?,
+        Some(30),
+        task_sender,
+    )
+    .await?;
+
+    LOGGER.trace(format!("Push file job initiated with ID: {}", job));
+
+    Ok(PushFileResponse)
+}
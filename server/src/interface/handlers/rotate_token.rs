@@ -0,0 +1,40 @@
+use crate::err::Result;
+use crate::global_var::LOGGER;
+use crate::utilities::crypto::rotate_conn_token;
+use api_model::protocol::models::group::rotate_token::{RotateTokenRequest, RotateTokenResponse};
+use cli_handler::cli_handler;
+use std::time::Duration;
+
+/// Longest time the previous token is accepted for, enough to rotate every node of a group
+const MAX_OVERLAP: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Same rule as the token asked for when the config is created
+fn is_valid_token(token: &str) -> bool {
+    (1..=64).contains(&token.len()) && token.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[cli_handler(RotateToken)]
+pub async fn rotate_token(request: &RotateTokenRequest) -> Result<RotateTokenResponse> {
+    if !is_valid_token(&request.new_token) {
+        return Err("The connection token must be 1-64 alphanumeric characters".into());
+    }
+    let overlap = Duration::from_secs(request.overlap_secs);
+    if overlap > MAX_OVERLAP {
+        return Err(format!(
+            "The overlap must be at most {}s, the previous token would stay valid too long",
+            MAX_OVERLAP.as_secs()
+        )
+        .into());
+    }
+    let new_token = request.new_token.clone();
+    // Key derivation is slow on purpose, keep it off the runtime threads
+    let previous_accepted_until =
+        tokio::task::spawn_blocking(move || rotate_conn_token(&new_token, overlap)).await??;
+    LOGGER.info(format!(
+        "Rotated the connection token, the previous one is accepted for {}s more",
+        request.overlap_secs
+    ));
+    Ok(RotateTokenResponse {
+        previous_accepted_until,
+    })
+}
@@ -1 +1,3 @@
+#[cfg(unix)]
+pub mod api_socket;
 pub mod handlers;
@@ -6,6 +6,8 @@ use crate::err::Result;
 use crate::fs::{init_fs, init_working_dir};
 use crate::global_var::{ENV_VAR, GLOBAL_VAR, GlobalVar, LOGGER, LOGGER_CELL};
 use crate::network::{init_network, terminate_network};
+use crate::utilities::api_secret::init_api_secret;
+use crate::utilities::identity::{get_identity, init_identity};
 use core::tasks::{init_task_queue, shutdown_core};
 use tokio::sync::Mutex;
 use tokio::{select, signal};
@@ -34,7 +36,7 @@ fn print_version_and_exit() -> ! {
     std::process::exit(0)
 }
 
-async fn init(config: &Config) -> Result<()> {
+async fn init(config: &Config, config_path: String) -> Result<()> {
     // Start server initialization
     // 1. Read config
     //   1.0. Test config validation
@@ -52,7 +54,9 @@ async fn init(config: &Config) -> Result<()> {
 
     // panic on failures
 
-    let env_var = EnvVar::from_config(config).expect("Failed to set environment variables");
+    let env_var = EnvVar::from_config(config)
+        .expect("Failed to set environment variables")
+        .with_config_path(config_path);
 
     let (logger, logger_handle) = init_working_dir(env_var.get_working_dir())
         .await
@@ -64,6 +68,21 @@ async fn init(config: &Config) -> Result<()> {
     LOGGER_CELL.set(logger).expect("Logger already set");
     // LOGGER enabled starting from this point
 
+    let env_var = ENV_VAR.get().unwrap();
+    if let Err(e) = init_identity(
+        &env_var.get_private_key_location(),
+        &env_var.get_public_key_location(),
+        env_var.get_working_dir(),
+    ) {
+        LOGGER.error(format!("Failed to load node identity: {}", e));
+        return Err(format!("Failed to load node identity: {}", e).into());
+    }
+    LOGGER.info(format!("Node identity {}", get_identity()?.fingerprint()));
+    if let Err(e) = init_api_secret(&env_var.get_api_secret_path()) {
+        LOGGER.error(format!("Failed to load API secret: {}", e));
+        return Err(format!("Failed to load API secret: {}", e).into());
+    }
+
     init_fs(ENV_VAR.get().unwrap().get_working_dir()).await?;
 
     // Starts core initialization
@@ -77,7 +96,10 @@ async fn init(config: &Config) -> Result<()> {
             panic!("Failed to initialize task queue");
         }
     };
-    let _ = init_topology();
+    if let Err(e) = init_topology().await {
+        LOGGER.error(format!("Failed to initialize topology: {}", e));
+        panic!("Failed to initialize topology");
+    }
     // Ends core initialization
 
     let network_setup = match init_network(&task_queue).await {
@@ -160,9 +182,12 @@ async fn main() {
         .or(Some(""));
 
     match get_or_create_config(cfg_path_opt) {
-        Ok(config) => {
+        Ok((config, config_path)) => {
             dbg!(&config);
-            init(&config).await.unwrap();
+            if let Err(e) = init(&config, config_path).await {
+                eprintln!("Failed to start the server: {}", e);
+                std::process::exit(1);
+            }
             dbg!(ENV_VAR.get().unwrap());
         }
         Err(e) => {
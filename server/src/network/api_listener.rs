@@ -0,0 +1,123 @@
+use crate::err::Result;
+use crate::global_var::LOGGER;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// ApiListener accepts local API connections on a Unix socket.
+/// Only the owner of the socket file may connect, as it is created in a 0700 directory.
+pub struct ApiListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+/// Handle to a running API listener task, allowing graceful shutdown.
+#[derive(Debug)]
+pub struct ApiListenerHandle {
+    handle: JoinHandle<()>,
+    shutdown_tx: oneshot::Sender<()>,
+    path: PathBuf,
+}
+
+impl ApiListenerHandle {
+    /// Signal shutdown, await the listener task to exit and remove the socket file.
+    pub async fn shutdown(self) -> Result<()> {
+        let _ = self.shutdown_tx.send(());
+        let _ = self.handle.await;
+        let _ = std::fs::remove_file(&self.path);
+        Ok(())
+    }
+}
+
+impl ApiListener {
+    /// Bind to the socket at `path`, replacing the one left behind by a previous run
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        LOGGER.info(format!("Binding API listener to {}", path.display()));
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            if !metadata.file_type().is_socket() {
+                return Err(format!("{} exists and is not a socket", path.display()).into());
+            }
+            std::fs::remove_file(&path)?;
+        }
+        // The socket is reachable by anyone between bind and chmod, unless its directory is not
+        if let Some(parent) = path.parent() {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)?;
+            std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700))?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(Self { listener, path })
+    }
+
+    /// Start an infinite accept loop in a background task.
+    /// The provided handler will be invoked for each accepted connection.
+    /// The task runs until a shutdown signal is received.
+    pub fn into_task(
+        self,
+        mut on_conn: impl FnMut(UnixStream) + Send + 'static,
+    ) -> ApiListenerHandle {
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        let path = self.path.clone();
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    biased;
+                    _ = &mut shutdown_rx => {
+                        LOGGER.info("API listener received shutdown signal, exiting...");
+                        break;
+                    }
+                    res = self.listener.accept() => {
+                        match res {
+                            Ok((stream, _)) => on_conn(stream),
+                            Err(e) => {
+                                LOGGER.debug(format!("Failed to accept API connection: {:?}", e));
+                                continue;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        ApiListenerHandle {
+            handle,
+            shutdown_tx,
+            path,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn socket_is_owner_only_and_replaces_a_stale_one() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("api-listener-{}", std::process::id()));
+        let path = dir.join(".disc").join("api.sock");
+
+        // A socket file left behind by a crashed run
+        drop(ApiListener::bind(&path)?);
+        let listener = ApiListener::bind(&path)?;
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let mode = std::fs::metadata(dir.join(".disc"))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let handle = listener.into_task(|_| {});
+        UnixStream::connect(&path).await?;
+        handle.shutdown().await?;
+        assert!(!path.exists());
+
+        // Never remove what is not a socket
+        std::fs::write(&path, b"data")?;
+        assert!(ApiListener::bind(&path).is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
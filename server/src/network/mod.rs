@@ -1,7 +1,10 @@
+#[cfg(unix)]
+mod api_listener;
 pub mod protocol;
+mod reliable;
 mod udp_listener;
 mod udp_sender;
-pub use udp_sender::NetworkSender;
+pub use udp_sender::{NetworkSender, is_sending_port};
 mod tcp_listener;
 mod tcp_sender;
 pub use tcp_sender::TcpConn;
@@ -10,9 +13,39 @@ mod util;
 use crate::core::tasks::SendFileTask;
 use crate::core::tasks::task_queue::TaskQueue;
 use crate::err::Result;
-use crate::global_var::LOGGER;
+use crate::global_var::{ENV_VAR, GLOBAL_VAR, LOGGER};
 use crate::network::protocol::parse_message;
-pub use util::get_private_ipv4_with_mac;
+use std::net::{IpAddr, SocketAddr};
+pub use util::{
+    AnnounceAddr, LocalAddr, get_interface_addr, get_local_addr, get_private_ip_with_mac,
+    socket_addr_in_scope,
+};
+
+/// Socket address of a peer at `ip:port`, link-local addresses are scoped to our interface
+pub fn peer_socket_addr(ip: IpAddr, port: u16) -> SocketAddr {
+    let scope_id = ENV_VAR.get().map(|ev| ev.get_if_index()).unwrap_or(0);
+    socket_addr_in_scope(ip, port, scope_id)
+}
+
+/// Addresses the node announces itself from, none before the environment is set
+pub fn announce_addrs() -> Vec<AnnounceAddr> {
+    match ENV_VAR.get() {
+        Some(ev) => util::get_announce_addrs(&ev.get_local_addr(), ev.get_bind_addr().is_some()),
+        None => Vec::new(),
+    }
+}
+
+/// Follow a change of the node address: discovery groups are joined on the interfaces the node
+/// is now on. Sockets bound to the old address are replaced by the sender on their next use.
+pub async fn on_local_addr_changed() -> Result<()> {
+    let Some(gv) = GLOBAL_VAR.get() else {
+        return Ok(());
+    };
+    if let Some(setup) = gv.network_setup.lock().await.as_ref() {
+        setup.listener_handle.join_discovery_groups();
+    }
+    Ok(())
+}
 
 #[derive(Debug)]
 pub struct NetworkSetup {
@@ -21,6 +54,9 @@ pub struct NetworkSetup {
     // pub listener: listener::UdpListener,
     pub listener_handle: udp_listener::ListenerHandle,
     pub tcp_listener_handle: tcp_listener::ListenerHandle,
+    /// Local API over a Unix socket, the API is served over UDP only when it is missing
+    #[cfg(unix)]
+    pub api_listener_handle: Option<api_listener::ApiListenerHandle>,
 }
 
 /// Initiate network connections and other setup tasks.
@@ -32,20 +68,32 @@ pub async fn init_network(task_queue: &TaskQueue) -> Result<NetworkSetup> {
     let udp_listener = udp_listener::UdpListener::bind().await?;
 
     let task_queue_sender = task_queue.sender();
-    let udp_join_handle = udp_listener.into_task(move |bytes, peer| match parse_message(&bytes) {
-        Ok(msg) => {
-            if msg.should_ignore_by_sockaddr_peer(&peer) {
-                return;
+    let ack_sender = udp_sender.sender();
+    let udp_join_handle = udp_listener.into_task(move |bytes, peer| {
+        let Some(bytes) = reliable::receive(bytes, peer, &ack_sender) else {
+            return;
+        };
+        match parse_message(&bytes) {
+            Ok(None) => {
+                LOGGER.debug(format!(
+                    "Ignoring message of unknown type from {:?}: {:?}",
+                    peer, bytes
+                ));
             }
-            if let Err(e) = task_queue_sender.try_send(msg) {
-                LOGGER.error(format!("Unable to send message to task queue: {:?}", e));
+            Ok(Some(msg)) => {
+                if msg.should_ignore_by_sockaddr_peer(&peer) {
+                    return;
+                }
+                if let Err(e) = task_queue_sender.try_send(msg) {
+                    LOGGER.error(format!("Unable to send message to task queue: {:?}", e));
+                }
+            }
+            Err(e) => {
+                LOGGER.error(format!(
+                    "Unable to translate bytes into messages bytes: {:?}, peer: {:?}, error: {:?}",
+                    bytes, peer, e
+                ));
             }
-        }
-        Err(e) => {
-            LOGGER.error(format!(
-                "Unable to translate bytes into messages bytes: {:?}, peer: {:?}, error: {:?}",
-                bytes, peer, e
-            ));
         }
     });
     let tcp_task_queue_sender = task_queue.sender();
@@ -60,12 +108,39 @@ pub async fn init_network(task_queue: &TaskQueue) -> Result<NetworkSetup> {
         sender: udp_sender,
         listener_handle: udp_join_handle,
         tcp_listener_handle: tcp_join_handle,
+        #[cfg(unix)]
+        api_listener_handle: init_api_listener(),
     })
 }
 
+#[cfg(unix)]
+fn init_api_listener() -> Option<api_listener::ApiListenerHandle> {
+    let path = ENV_VAR.get()?.get_api_socket_path();
+    match api_listener::ApiListener::bind(&path) {
+        Ok(listener) => Some(listener.into_task(|stream| {
+            tokio::spawn(async move {
+                if let Err(e) = crate::interface::api_socket::serve_api_connection(stream).await {
+                    LOGGER.debug(format!("API connection closed: {}", e));
+                }
+            });
+        })),
+        Err(e) => {
+            LOGGER.warn(format!(
+                "Unable to serve the local API on {}, only UDP is available: {}",
+                path, e
+            ));
+            None
+        }
+    }
+}
+
 pub async fn terminate_network(setup: NetworkSetup) -> Result<()> {
     let _ = setup.sender.shutdown().await;
     let _ = setup.listener_handle.shutdown().await?;
     let _ = setup.tcp_listener_handle.shutdown().await?;
+    #[cfg(unix)]
+    if let Some(api_listener_handle) = setup.api_listener_handle {
+        api_listener_handle.shutdown().await?;
+    }
     Ok(())
 }
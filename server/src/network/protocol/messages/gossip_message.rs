@@ -0,0 +1,432 @@
+use crate::config::SelectiveSync;
+use crate::core::Peer;
+use crate::err::Result;
+use crate::global_var::{ENV_VAR, LOGGER};
+use crate::network::protocol::HandleableNetworkProtocol;
+use crate::network::protocol::messages::hello_message::{Capabilities, MAX_HELLO_AGE};
+use crate::utilities::clock::within_window;
+use crate::utilities::crypto::{KeyPurpose, associated_data, from_encryption, to_encryption};
+use crate::utilities::identity::{fingerprint_of, get_identity, message_signing_bytes};
+use api_model::protocol::protocol::Protocol;
+use api_model::protocol::token::Token;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Entries are dropped once they have been relayed this many times
+pub const MAX_GOSSIP_HOPS: u8 = 3;
+
+/// At most this many entries are gossiped at once, the most recently seen ones, so that the
+/// message fits in a datagram
+pub const MAX_GOSSIP_ENTRIES: usize = 128;
+
+/// What a node knows about one of its active peers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GossipEntry {
+    peer_name: String,
+    peer_addr: IpAddr,
+    port: u16,
+    file_port: u16,
+    public_key: Vec<u8>,
+
+    protocol_version: u32,
+    capabilities: u32,
+    subscription: SelectiveSync,
+
+    /// Last time the sender heard of the peer, in ms since UNIX epoch on the sender clock
+    last_seen_ms: u64,
+    /// Offset of the peer clock from the sender clock
+    clock_offset_ms: Option<i64>,
+    /// Relays between the peer and the sender, 0 when the sender heard from it directly
+    hops: u8,
+}
+
+impl GossipEntry {
+    /// Entry of `peer`, `None` for peers without identity keys as they could not be told apart
+    /// from impostors by the receiver
+    pub fn from_peer(peer: &Peer) -> Option<Self> {
+        Some(Self {
+            peer_name: peer.peer_name.clone(),
+            peer_addr: peer.peer_addr,
+            port: peer.port,
+            file_port: peer.file_port,
+            public_key: peer.public_key.as_ref()?.to_vec(),
+            protocol_version: peer.protocol_version,
+            capabilities: peer.capabilities.bits(),
+            subscription: peer.subscription.clone(),
+            last_seen_ms: peer.last_seen_utc_ms(),
+            clock_offset_ms: peer.clock_offset_ms,
+            hops: peer.hops,
+        })
+    }
+
+    pub fn get_peer_name(&self) -> &str {
+        &self.peer_name
+    }
+
+    pub fn get_public_key(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.public_key)
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint_of(&self.public_key)
+    }
+
+    /// Peer described by the entry, as learned from a sender whose clock is `sender_offset_ms`
+    /// away from ours. `None` if the entry has been relayed too many times already.
+    pub fn to_peer(&self, sender_offset_ms: Option<i64>, scope_id: u32) -> Option<Peer> {
+        let hops = self.hops.checked_add(1).filter(|h| *h <= MAX_GOSSIP_HOPS)?;
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let sender_offset_ms = sender_offset_ms.unwrap_or(0);
+        // Never seen later than now, whatever the clocks say
+        let last_seen_ms =
+            (self.last_seen_ms as i128 - sender_offset_ms as i128).clamp(0, now_ms as i128) as u64;
+        // The peer is as far from our clock as it is from the sender's plus the sender's own
+        let clock_offset_ms = self
+            .clock_offset_ms
+            .map(|offset| offset.saturating_add(sender_offset_ms));
+        Some(
+            Peer::new(
+                self.fingerprint(),
+                self.peer_name.clone(),
+                self.peer_addr,
+                false,
+            )
+            .with_scope_id(scope_id)
+            .with_ports(self.port, self.file_port)
+            .with_subscription(self.subscription.clone())
+            .with_protocol(
+                self.protocol_version,
+                Capabilities::from_bits_truncate(self.capabilities),
+            )
+            .with_public_key(Some(self.get_public_key()))
+            .with_clock_offset(clock_offset_ms)
+            .with_hops(hops)
+            .with_last_seen_ms(last_seen_ms),
+        )
+    }
+}
+
+/// Active peers known to a node, sent to its neighbours so that they learn about the peers
+/// they cannot hear from directly, e.g. on another subnet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerDigest {
+    from_ip: String,
+    entries: Vec<GossipEntry>,
+    time_stamp: SystemTime,
+}
+
+impl PeerDigest {
+    pub fn new(from_ip: String, entries: Vec<GossipEntry>) -> Self {
+        Self {
+            from_ip,
+            entries,
+            time_stamp: SystemTime::now(),
+        }
+    }
+
+    /// Entries of the active `peers` that can still be relayed, most recently seen first
+    pub fn from_peers<'a>(from_ip: String, peers: impl IntoIterator<Item = &'a Peer>) -> Self {
+        let mut entries: Vec<GossipEntry> = peers
+            .into_iter()
+            .filter(|p| p.is_active.load(Ordering::Relaxed) && p.hops < MAX_GOSSIP_HOPS)
+            .filter_map(GossipEntry::from_peer)
+            .collect();
+        entries.sort_by(|a, b| b.last_seen_ms.cmp(&a.last_seen_ms));
+        entries.truncate(MAX_GOSSIP_ENTRIES);
+        Self::new(from_ip, entries)
+    }
+
+    pub fn get_from_ip(&self) -> &str {
+        &self.from_ip
+    }
+
+    pub fn get_entries(&self) -> &[GossipEntry] {
+        &self.entries
+    }
+
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn digest_time_valid(&self, clock_offset_ms: i64) -> bool {
+        within_window(self.time_stamp, clock_offset_ms, MAX_HELLO_AGE)
+    }
+
+    pub fn to_encryption(&self) -> Result<Vec<u8>> {
+        to_encryption(
+            KeyPurpose::Control,
+            self,
+            &associated_data("GOSSIP", &self.from_ip),
+        )
+    }
+
+    /// Decrypt a digest claimed to be sent by `from_ip`
+    pub fn from_encryption(ciphertext: Box<[u8]>, from_ip: &str) -> Result<Self> {
+        from_encryption(
+            KeyPurpose::Control,
+            ciphertext,
+            &associated_data("GOSSIP", from_ip),
+        )
+    }
+}
+
+pub struct GossipMessage {
+    pub from_ip: String,
+    pub digest: Bytes,
+    // Made with the identity key of the sender
+    pub signature: Option<Bytes>,
+}
+
+impl Debug for GossipMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "GossipMessage {{ from_ip: {}, digest: <encrypted> }}",
+            self.from_ip
+        )?;
+        match PeerDigest::from_encryption(
+            self.digest.clone().to_vec().into_boxed_slice(),
+            &self.from_ip,
+        ) {
+            Ok(digest) => write!(f, "PeerDigest {{ entries: {} }}", digest.entries.len()),
+            Err(_) => write!(f, "PeerDigest {{ <decryption failed> }}"),
+        }
+    }
+}
+
+impl GossipMessage {
+    /// Bytes covered by the signature
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        message_signing_bytes("GOSSIP", &self.from_ip, &self.digest)
+    }
+
+    fn signed(mut self) -> Result<Self> {
+        self.signature = Some(get_identity()?.sign(&self.signed_bytes()));
+        Ok(self)
+    }
+
+    /// Gossip the active peers among `peers`
+    pub fn new<'a>(peers: impl IntoIterator<Item = &'a Peer>) -> Result<Self> {
+        if let Some(ev) = ENV_VAR.get() {
+            let from_ip = ev.get_ip_addr();
+
+            let encrypted_digest =
+                PeerDigest::from_peers(from_ip.to_string(), peers).to_encryption()?;
+
+            return Self {
+                from_ip: from_ip.to_string(),
+                digest: encrypted_digest.into(),
+                signature: None,
+            }
+            .signed();
+        }
+
+        Err("Failed to generate gossip message because env_var not found.".into())
+    }
+
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn validate_and_parse(&self, clock_offset_ms: i64) -> Result<PeerDigest> {
+        let from_ip_out = &self.from_ip;
+
+        let normalized_data = self.digest.to_vec().into_boxed_slice();
+
+        match PeerDigest::from_encryption(normalized_data, from_ip_out) {
+            Ok(digest) => {
+                if !digest.digest_time_valid(clock_offset_ms) {
+                    LOGGER.warn(format!("Peer digest from {} is too old", &from_ip_out));
+                    return Err("Digest is too old".into());
+                }
+                if from_ip_out != digest.get_from_ip() {
+                    LOGGER.warn(format!(
+                        "Peer digest from {} is not from the same IP as the sender",
+                        &from_ip_out
+                    ));
+                    return Err("Digest is not from the same IP".into());
+                }
+                Ok(digest)
+            }
+            Err(e) => {
+                LOGGER.warn(format!("Failed to deserialize peer digest: {}", e));
+                Err("Digest decryption failed".into())
+            }
+        }
+    }
+}
+
+impl HandleableNetworkProtocol for GossipMessage {}
+
+impl Protocol for GossipMessage {
+    fn serialize(&self) -> Vec<u8> {
+        // Format: +GOSSIP, +from_ip, $<digest-bytes>, $<signature>
+        let tokens = vec![
+            Token::Simple(String::from("GOSSIP")),
+            Token::Simple(self.from_ip.clone()),
+            Token::Data(self.digest.clone()),
+            Token::Data(self.signature.clone().unwrap_or_default()),
+        ];
+        let mut out = Vec::new();
+        for t in tokens {
+            out.extend_from_slice(&t.to_bytes());
+        }
+        out
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let tokens = Token::parse_all_compat(bytes)?;
+        Self::from_tokens(&tokens)
+    }
+
+    fn from_tokens(tokens: &[Token]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        use std::io;
+        if tokens.len() != 4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected 4 tokens for GossipMessage, got {}", tokens.len()),
+            )
+            .into());
+        }
+        match &tokens[0] {
+            Token::Simple(s) if s == "GOSSIP" => {}
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected leading Simple(\"GOSSIP\"), got {:?}", other),
+                )
+                .into());
+            }
+        }
+        let from_ip = match &tokens[1] {
+            Token::Simple(s) => s.clone(),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Simple for from_ip, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        let digest = match &tokens[2] {
+            Token::Data(b) => b.clone(),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Data for digest, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        let signature = match &tokens[3] {
+            Token::Data(b) => (!b.is_empty()).then(|| b.clone()),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Data for signature, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        Ok(GossipMessage {
+            from_ip,
+            digest,
+            signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::identity::NodeIdentity;
+
+    fn peer(identity: &NodeIdentity, hops: u8) -> Peer {
+        Peer::new(
+            identity.fingerprint(),
+            "alice".into(),
+            "10.1.0.7".parse().unwrap(),
+            false,
+        )
+        .with_public_key(Some(identity.public_key()))
+        .with_clock_offset(Some(1_000))
+        .with_hops(hops)
+    }
+
+    #[test]
+    fn entries_are_relayed_a_limited_number_of_times() {
+        let identity = NodeIdentity::generate();
+        let entry = GossipEntry::from_peer(&peer(&identity, 0)).unwrap();
+        let learned = entry.to_peer(Some(-500), 2).unwrap();
+        assert_eq!(learned.identifier, identity.fingerprint());
+        assert_eq!(learned.hops, 1);
+        assert_eq!(learned.scope_id, 2);
+        assert_eq!(learned.clock_offset_ms, Some(500));
+
+        let far = GossipEntry::from_peer(&peer(&identity, MAX_GOSSIP_HOPS)).unwrap();
+        assert!(far.to_peer(None, 0).is_none());
+
+        // Peers that cannot be relayed any further are not gossiped at all
+        let digest = PeerDigest::from_peers(
+            "10.0.0.1".into(),
+            &[
+                peer(&identity, MAX_GOSSIP_HOPS),
+                peer(&NodeIdentity::generate(), MAX_GOSSIP_HOPS - 1),
+                Peer::new(
+                    "legacy".into(),
+                    "bob".into(),
+                    "10.1.0.8".parse().unwrap(),
+                    false,
+                ),
+            ],
+        );
+        assert_eq!(digest.get_entries().len(), 1);
+    }
+
+    #[test]
+    fn last_seen_is_moved_to_our_clock_and_never_in_the_future() {
+        let identity = NodeIdentity::generate();
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let seen = peer(&identity, 0).with_last_seen_ms(now_ms - 20_000);
+        let entry = GossipEntry::from_peer(&seen).unwrap();
+
+        // The sender clock is 5s ahead of ours
+        let learned = entry.to_peer(Some(5_000), 0).unwrap();
+        assert_eq!(learned.last_seen_utc_ms(), now_ms - 25_000);
+
+        // Seen "later" than now by a clock far ahead
+        let learned = entry.to_peer(Some(-60_000), 0).unwrap();
+        assert!(learned.last_seen_utc_ms() <= now_ms + 1_000);
+    }
+
+    #[test]
+    fn roundtrip_serialize_deserialize() -> Result<()> {
+        let msg = GossipMessage {
+            from_ip: "10.0.0.1".into(),
+            digest: Bytes::from_static(b"digest"),
+            signature: Some(Bytes::from_static(b"signature")),
+        };
+        let back = GossipMessage::deserialize(&msg.serialize())?;
+        assert_eq!(back.from_ip, msg.from_ip);
+        assert_eq!(back.digest, msg.digest);
+        assert_eq!(back.signature, msg.signature);
+
+        let unsigned = GossipMessage {
+            signature: None,
+            ..back
+        };
+        let back = GossipMessage::deserialize(&unsigned.serialize())?;
+        assert!(back.signature.is_none());
+        Ok(())
+    }
+}
@@ -1,10 +1,18 @@
+use crate::config::SelectiveSync;
+use crate::constants::TCP_FILE_PORT;
 use crate::err::Result;
 use crate::global_var::ENV_VAR;
 use crate::network::protocol::HandleableNetworkProtocol;
+use crate::utilities::clock::within_window;
+use crate::utilities::crypto::{compute_tag, verify_tag};
+use crate::utilities::identity::{fingerprint_of, get_identity, verify_signature};
 use api_model::protocol::protocol::Protocol;
 use api_model::protocol::token::Token;
 use bitflags::bitflags;
+use bytes::Bytes;
 use std::fmt::{Debug, Display, Formatter};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 bitflags! {
     #[derive(Default)]
@@ -27,6 +35,66 @@ impl Display for HelloMode {
     }
 }
 
+/// Version of the node to node protocol spoken by this build.
+/// Nodes older than the version negotiation do not send it and are treated as version 0.
+/// Version 2 added the timestamp and the authentication tag to hello messages.
+/// Version 3 added the node public key and the signature made with it.
+/// Version 4 added the TCP port file transfers are served on.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Hellos stamped further than this from the local clock, corrected by the estimated offset of
+/// the sender clock, are considered stale
+pub const MAX_HELLO_AGE: Duration = Duration::from_secs(60);
+
+/// How far the clock of a node we have no offset for yet may be from ours. The offset is
+/// estimated from its first hello, later hellos are held to [`MAX_HELLO_AGE`].
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+bitflags! {
+    /// Optional features a node advertises in its hello messages.
+    /// A feature is only used with a peer that advertised it; bits are never reused.
+    #[derive(Default)]
+    pub struct Capabilities: u32 {
+        const LENGTH_PREFIXED_DATA = 1 << 0;
+        const RECURSIVE_PULL = 1 << 1;
+        const BULK_ARCHIVE = 1 << 2;
+        const PUSH_OFFER = 1 << 3;
+        const SELECTIVE_SYNC = 1 << 4;
+        const PEER_GOSSIP = 1 << 5;
+        const RELIABLE_CONTROL = 1 << 6;
+    }
+}
+
+impl Capabilities {
+    /// Features supported by this build
+    pub fn local() -> Self {
+        Capabilities::all()
+    }
+
+    pub fn names(self) -> Vec<String> {
+        [
+            (Capabilities::LENGTH_PREFIXED_DATA, "length-prefixed-data"),
+            (Capabilities::RECURSIVE_PULL, "recursive-pull"),
+            (Capabilities::BULK_ARCHIVE, "bulk-archive"),
+            (Capabilities::PUSH_OFFER, "push-offer"),
+            (Capabilities::SELECTIVE_SYNC, "selective-sync"),
+            (Capabilities::PEER_GOSSIP, "peer-gossip"),
+            (Capabilities::RELIABLE_CONTROL, "reliable-control"),
+        ]
+        .into_iter()
+        .filter(|(cap, _)| self.contains(*cap))
+        .map(|(_, name)| name.to_string())
+        .collect()
+    }
+}
+
 impl HelloMode {
     pub fn is_request_reply(self) -> bool {
         self.contains(HelloMode::REQUEST_REPLY)
@@ -36,6 +104,19 @@ impl HelloMode {
     }
 }
 
+/// Fields of a hello message following the mode, see [`HelloMessage::parse_trailer`]
+#[derive(Default)]
+struct HelloTrailer {
+    protocol_version: u32,
+    capabilities: Capabilities,
+    timestamp_ms: u64,
+    public_key: Option<Bytes>,
+    file_port: u16,
+    subscription: SelectiveSync,
+    auth_tag: Option<Bytes>,
+    signature: Option<Bytes>,
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct HelloMessage {
     pub from_ip: String,
@@ -46,14 +127,41 @@ pub struct HelloMessage {
     // 0b01: request reply
     // 0b10: I am leader
     pub mode: HelloMode,
+    // 0 when the sender predates the version negotiation
+    pub protocol_version: u32,
+    pub capabilities: Capabilities,
+    // Milliseconds since UNIX epoch when the hello was created, 0 before version 2
+    pub timestamp_ms: u64,
+    // Ed25519 identity key of the sender, None before version 3
+    pub public_key: Option<Bytes>,
+    // TCP port the sender serves file transfers on, TCP_FILE_PORT before version 4
+    pub file_port: u16,
+    // Subtrees the sender keeps in sync, only sent when it is not the whole share
+    pub subscription: SelectiveSync,
+    // HMAC of all the other fields, keyed by the connection token
+    pub auth_tag: Option<Bytes>,
+    // Signature of all the other fields but the tag, made with the identity key
+    pub signature: Option<Bytes>,
 }
 
 impl Debug for HelloMessage {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "HelloMessage {{ from_ip: {}, from_port: {}, from_name: {}, mac_addr: {}, mode: {} }}",
-            self.from_ip, self.from_port, self.from_name, self.mac_addr, self.mode
+            "HelloMessage {{ from_ip: {}, from_port: {}, from_name: {}, mac_addr: {}, mode: {}, protocol_version: {}, capabilities: [{}], timestamp_ms: {}, fingerprint: {}, file_port: {}, subscription: {:?}, authenticated: {}, signed: {} }}",
+            self.from_ip,
+            self.from_port,
+            self.from_name,
+            self.mac_addr,
+            self.mode,
+            self.protocol_version,
+            self.capabilities.names().join("|"),
+            self.timestamp_ms,
+            self.fingerprint().as_deref().unwrap_or("-"),
+            self.file_port,
+            self.subscription,
+            self.auth_tag.is_some(),
+            self.signature.is_some()
         )
     }
 }
@@ -72,22 +180,270 @@ impl HelloMessage {
             from_name,
             mac_addr,
             mode,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::local(),
+            timestamp_ms: now_ms(),
+            public_key: None,
+            file_port: TCP_FILE_PORT,
+            subscription: SelectiveSync::default(),
+            auth_tag: None,
+            signature: None,
         }
     }
 
+    /// Fingerprint of the advertised identity key, which identifies the sender
+    pub fn fingerprint(&self) -> Option<String> {
+        self.public_key.as_deref().map(fingerprint_of)
+    }
+
+    /// Tokens covered by the authentication tag, i.e. the whole message but the tag
+    fn signed_tokens(&self) -> Vec<Token> {
+        let mut tokens = vec![
+            Token::Simple(String::from("HELLO")),
+            Token::Simple(self.from_ip.clone()),
+            Token::Integer(self.from_port as u64),
+            Token::Simple(self.from_name.clone()),
+            Token::Simple(self.mac_addr.clone()),
+            Token::Integer(self.mode.bits() as u64),
+        ];
+        if self.protocol_version > 0 {
+            tokens.push(Token::Integer(self.protocol_version as u64));
+            tokens.push(Token::Integer(self.capabilities.bits() as u64));
+        }
+        if self.protocol_version >= 2 {
+            tokens.push(Token::Integer(self.timestamp_ms));
+        }
+        if self.protocol_version >= 3 {
+            tokens.push(Token::Data(self.public_key.clone().unwrap_or_default()));
+        }
+        if self.protocol_version >= 4 {
+            tokens.push(Token::Integer(self.file_port as u64));
+        }
+        // Nodes syncing the whole share do not send their subscription
+        if !self.subscription.is_everything() {
+            tokens.push(Token::Data(Self::encode_subscription(&self.subscription)));
+        }
+        tokens
+    }
+
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for t in self.signed_tokens() {
+            out.extend_from_slice(&t.to_bytes());
+        }
+        out
+    }
+
+    /// Attach the node public key, the authentication tag and the signature,
+    /// must be the last change made to the message
+    pub fn sign(mut self) -> Result<Self> {
+        if self.protocol_version >= 3 {
+            let identity = get_identity()?;
+            self.public_key = Some(identity.public_key());
+            let signed = self.signed_bytes();
+            self.auth_tag = Some(compute_tag(&signed)?);
+            self.signature = Some(identity.sign(&signed));
+        } else {
+            self.auth_tag = Some(compute_tag(&self.signed_bytes())?);
+        }
+        Ok(self)
+    }
+
+    /// Check the hello was sent recently by a node knowing the connection token, and that it
+    /// owns the advertised identity key. Hellos from nodes predating identity keys (version 3)
+    /// are refused, as nothing would prove who sent them.
+    /// `clock_offset_ms` is the estimated offset of the sender clock, if it is known already.
+    pub fn verify(&self, clock_offset_ms: Option<i64>) -> Result<()> {
+        let tag = self
+            .auth_tag
+            .as_ref()
+            .ok_or("hello message is not authenticated")?;
+        let signed = self.signed_bytes();
+        verify_tag(&signed, tag)?;
+        let public_key = self
+            .public_key
+            .as_ref()
+            .ok_or("hello message does not advertise a public key")?;
+        let signature = self
+            .signature
+            .as_ref()
+            .ok_or("hello message is not signed")?;
+        verify_signature(public_key, &signed, signature)?;
+        let sent_at = UNIX_EPOCH + Duration::from_millis(self.timestamp_ms);
+        let fresh = match clock_offset_ms {
+            Some(offset_ms) => within_window(sent_at, offset_ms, MAX_HELLO_AGE),
+            None => within_window(sent_at, 0, MAX_CLOCK_SKEW),
+        };
+        if !fresh {
+            let age_ms = now_ms().abs_diff(self.timestamp_ms);
+            return Err(format!("hello message is stale, sent {} ms away from now", age_ms).into());
+        }
+        Ok(())
+    }
+
+    pub fn with_subscription(mut self, subscription: SelectiveSync) -> Self {
+        self.subscription = subscription;
+        self
+    }
+
+    pub fn with_file_port(mut self, file_port: u16) -> Self {
+        self.file_port = file_port;
+        self
+    }
+
+    fn encode_subscription(subscription: &SelectiveSync) -> Bytes {
+        bincode::serde::encode_to_vec(subscription, bincode::config::standard())
+            .expect("selective sync settings are always encodable")
+            .into()
+    }
+
+    fn decode_subscription(bytes: &[u8]) -> Result<SelectiveSync> {
+        let (subscription, _) = bincode::serde::decode_from_slice::<SelectiveSync, _>(
+            bytes,
+            bincode::config::standard(),
+        )?;
+        Ok(subscription)
+    }
+
+    /// Parse the tokens following the mode:
+    /// - version 0: `[subscription]`
+    /// - version 1: `version, capabilities[, subscription]`
+    /// - version 2: `version, capabilities, timestamp[, subscription], tag`
+    /// - version 3: `version, capabilities, timestamp, public key[, subscription], tag, signature`
+    /// - version 4 and later: `version, capabilities, timestamp, public key, file port[, subscription], tag, signature`
+    ///
+    /// Capability bits unknown to this build are kept so that the tag still matches.
+    fn parse_trailer(tokens: &[Token]) -> Result<HelloTrailer> {
+        use std::io;
+        let mut trailer = HelloTrailer {
+            file_port: TCP_FILE_PORT,
+            ..Default::default()
+        };
+        let rest = match tokens {
+            [Token::Integer(version), Token::Integer(caps), rest @ ..] => {
+                trailer.protocol_version = u32::try_from(*version).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("protocol version out of range: {}", version),
+                    )
+                })?;
+                let bits = u32::try_from(*caps).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("capabilities out of range: {}", caps),
+                    )
+                })?;
+                trailer.capabilities = Capabilities { bits };
+                rest
+            }
+            [Token::Integer(_)] | [Token::Integer(_), _, ..] => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected Integer tokens for both protocol version and capabilities",
+                )
+                .into());
+            }
+            rest => rest,
+        };
+        let rest = if trailer.protocol_version >= 3 {
+            match rest {
+                [
+                    Token::Integer(timestamp_ms),
+                    Token::Data(public_key),
+                    rest @ ..,
+                    Token::Data(tag),
+                    Token::Data(signature),
+                ] => {
+                    trailer.timestamp_ms = *timestamp_ms;
+                    trailer.public_key = (!public_key.is_empty()).then(|| public_key.clone());
+                    trailer.auth_tag = (!tag.is_empty()).then(|| tag.clone());
+                    trailer.signature = (!signature.is_empty()).then(|| signature.clone());
+                    rest
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "expected timestamp, public key, authentication tag and signature tokens",
+                    )
+                    .into());
+                }
+            }
+        } else if trailer.protocol_version >= 2 {
+            match rest {
+                [Token::Integer(timestamp_ms), rest @ .., Token::Data(tag)] => {
+                    trailer.timestamp_ms = *timestamp_ms;
+                    trailer.auth_tag = (!tag.is_empty()).then(|| tag.clone());
+                    rest
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "expected timestamp and authentication tag tokens",
+                    )
+                    .into());
+                }
+            }
+        } else {
+            rest
+        };
+        let rest = if trailer.protocol_version >= 4 {
+            match rest {
+                [Token::Integer(port), rest @ ..] => {
+                    trailer.file_port = u16::try_from(*port).map_err(|_| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("file port out of range: {}", port),
+                        )
+                    })?;
+                    rest
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "expected Integer token for the file port",
+                    )
+                    .into());
+                }
+            }
+        } else {
+            rest
+        };
+        trailer.subscription = match rest {
+            [] => SelectiveSync::default(),
+            [Token::Data(b)] => Self::decode_subscription(b)?,
+            [other] => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Data for subscription, got {:?}", other),
+                )
+                .into());
+            }
+            [_, extra, ..] => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected extra token: {:?}", extra),
+                )
+                .into());
+            }
+        };
+        Ok(trailer)
+    }
+
     pub fn from_env(mode: HelloMode) -> Result<Self> {
+        let from_ip = ENV_VAR.get().ok_or("Fail to fetch env var")?.get_ip_addr();
+        HelloMessage::from_env_at(from_ip, mode)
+    }
+
+    /// Hello announcing `from_ip`, one of the addresses of this node
+    pub fn from_env_at(from_ip: IpAddr, mode: HelloMode) -> Result<Self> {
         if let Some(ev) = ENV_VAR.get() {
-            let from_ip = ev.get_ip_addr();
             let from_port = ev.get_port();
             let from_name = ev.get_machine_name();
             let mac_addr = ev.get_mac_addr();
-            return Ok(HelloMessage::new(
-                from_ip.to_string(),
-                from_port,
-                from_name,
-                mac_addr,
-                mode,
-            ));
+            return HelloMessage::new(from_ip.to_string(), from_port, from_name, mac_addr, mode)
+                .with_subscription(ev.get_selective_sync().clone())
+                .with_file_port(ev.get_file_sync_port())
+                .sign();
         }
         Err("Fail to fetch env var".into())
     }
@@ -96,14 +452,14 @@ impl HelloMessage {
 impl HandleableNetworkProtocol for HelloMessage {}
 impl Protocol for HelloMessage {
     fn serialize(&self) -> Vec<u8> {
-        let tokens = vec![
-            Token::Simple(String::from("HELLO")),
-            Token::Simple(self.from_ip.clone()),
-            Token::Integer(self.from_port as u64),
-            Token::Simple(self.from_name.clone()),
-            Token::Simple(self.mac_addr.clone()),
-            Token::Integer(self.mode.bits() as u64),
-        ];
+        let mut tokens = self.signed_tokens();
+        // An unsigned hello carries an empty tag, which its receiver rejects
+        if self.protocol_version >= 2 {
+            tokens.push(Token::Data(self.auth_tag.clone().unwrap_or_default()));
+        }
+        if self.protocol_version >= 3 {
+            tokens.push(Token::Data(self.signature.clone().unwrap_or_default()));
+        }
         // Concatenate token wire-format bytes
         let mut out = Vec::new();
         for t in tokens {
@@ -117,7 +473,7 @@ impl Protocol for HelloMessage {
         Self: Sized,
     {
         use std::io;
-        let tokens = Token::parse_all(bytes)?;
+        let tokens = Token::parse_all_compat(bytes)?;
         // Take ownership of tokens to avoid unnecessary clones
         let mut it = tokens.into_iter();
 
@@ -249,6 +605,10 @@ impl Protocol for HelloMessage {
             }
         };
 
+        // The trailing tokens depend on the version of the sender
+        let rest: Vec<Token> = it.by_ref().take(8).collect();
+        let trailer = Self::parse_trailer(&rest)?;
+
         // Ensure there are no extra tokens
         if let Some(extra) = it.next() {
             return Err(io::Error::new(
@@ -264,6 +624,14 @@ impl Protocol for HelloMessage {
             from_name,
             mac_addr,
             mode,
+            protocol_version: trailer.protocol_version,
+            capabilities: trailer.capabilities,
+            timestamp_ms: trailer.timestamp_ms,
+            public_key: trailer.public_key,
+            file_port: trailer.file_port,
+            subscription: trailer.subscription,
+            auth_tag: trailer.auth_tag,
+            signature: trailer.signature,
         })
     }
 
@@ -272,10 +640,13 @@ impl Protocol for HelloMessage {
         Self: Sized,
     {
         use std::io;
-        if tokens.len() != 6 {
+        if !(6..=14).contains(&tokens.len()) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("expected 6 tokens for HelloMessage, got {}", tokens.len()),
+                format!(
+                    "expected 6 to 14 tokens for HelloMessage, got {}",
+                    tokens.len()
+                ),
             )
             .into());
         }
@@ -367,12 +738,21 @@ impl Protocol for HelloMessage {
                 .into());
             }
         };
+        let trailer = Self::parse_trailer(&tokens[6..])?;
         Ok(HelloMessage {
             from_ip,
             from_port,
             from_name,
             mac_addr,
             mode,
+            protocol_version: trailer.protocol_version,
+            capabilities: trailer.capabilities,
+            timestamp_ms: trailer.timestamp_ms,
+            public_key: trailer.public_key,
+            file_port: trailer.file_port,
+            subscription: trailer.subscription,
+            auth_tag: trailer.auth_tag,
+            signature: trailer.signature,
         })
     }
 }
@@ -396,7 +776,7 @@ mod tests {
         let m = msg();
         let bytes = m.serialize();
         let tokens = Token::parse_all(&bytes)?;
-        assert_eq!(tokens.len(), 6);
+        assert_eq!(tokens.len(), 13);
         match &tokens[0] {
             Token::Simple(s) => assert_eq!(s, "HELLO"),
             other => panic!("expected HELLO header, got {:?}", other),
@@ -421,6 +801,29 @@ mod tests {
             Token::Integer(v) => assert_eq!(*v, HelloMode::REQUEST_REPLY.bits() as u64),
             other => panic!("expected mode Integer, got {:?}", other),
         }
+        match &tokens[6] {
+            Token::Integer(v) => assert_eq!(*v, PROTOCOL_VERSION as u64),
+            other => panic!("expected version Integer, got {:?}", other),
+        }
+        match &tokens[7] {
+            Token::Integer(v) => assert_eq!(*v, Capabilities::local().bits() as u64),
+            other => panic!("expected capabilities Integer, got {:?}", other),
+        }
+        match &tokens[8] {
+            Token::Integer(v) => assert_eq!(*v, m.timestamp_ms),
+            other => panic!("expected timestamp Integer, got {:?}", other),
+        }
+        match &tokens[10] {
+            Token::Integer(v) => assert_eq!(*v, TCP_FILE_PORT as u64),
+            other => panic!("expected file port Integer, got {:?}", other),
+        }
+        // Not signed, so the public key, the tag and the signature are left empty
+        for (i, field) in [(9, "public key"), (11, "tag"), (12, "signature")] {
+            match &tokens[i] {
+                Token::Data(b) => assert!(b.is_empty()),
+                other => panic!("expected {} Data, got {:?}", field, other),
+            }
+        }
         Ok(())
     }
 
@@ -470,6 +873,176 @@ mod tests {
         assert!(HelloMessage::from_tokens(&tokens).is_err());
     }
 
+    #[test]
+    fn roundtrip_keeps_subscription() -> crate::err::Result<()> {
+        let m = msg().with_subscription(SelectiveSync {
+            include: vec!["artifacts".into()],
+            exclude: vec!["artifacts/tmp".into()],
+        });
+        let bytes = m.serialize();
+        assert_eq!(Token::parse_all(&bytes)?.len(), 14);
+        let back = HelloMessage::deserialize(&bytes)?;
+        assert_eq!(m, back);
+        assert_eq!(HelloMessage::from_tokens(&Token::parse_all(&bytes)?)?, m);
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_hello_has_no_version_nor_capabilities() -> crate::err::Result<()> {
+        let mut tokens = vec![
+            Token::Simple("HELLO".into()),
+            Token::Simple("192.168.1.10".into()),
+            Token::Integer(8080),
+            Token::Simple("alice".into()),
+            Token::Simple("aa:bb:cc:dd:ee:ff".into()),
+            Token::Integer(1),
+        ];
+        let legacy = HelloMessage::from_tokens(&tokens)?;
+        assert_eq!(legacy.protocol_version, 0);
+        assert!(legacy.capabilities.is_empty());
+
+        // Older nodes sent the subscription right after the mode
+        let subscription = SelectiveSync {
+            include: vec!["docs".into()],
+            exclude: vec![],
+        };
+        tokens.push(Token::Data(HelloMessage::encode_subscription(
+            &subscription,
+        )));
+        let legacy = HelloMessage::from_tokens(&tokens)?;
+        assert_eq!(legacy.protocol_version, 0);
+        assert_eq!(legacy.subscription, subscription);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_capabilities_are_ignored() -> crate::err::Result<()> {
+        let mut m = msg();
+        m.capabilities = Capabilities::PUSH_OFFER;
+        let mut tokens = Token::parse_all(&m.serialize())?;
+        tokens[7] = Token::Integer((Capabilities::PUSH_OFFER.bits() | 1 << 31) as u64);
+        let back = HelloMessage::from_tokens(&tokens)?;
+        assert!(back.capabilities.contains(Capabilities::PUSH_OFFER));
+        assert_eq!(back.capabilities.names(), vec!["push-offer".to_string()]);
+        // Kept as received so that the authentication tag still matches
+        assert_eq!(back.serialize(), {
+            let mut out = Vec::new();
+            for t in &tokens {
+                out.extend_from_slice(&t.to_bytes());
+            }
+            out
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn version_1_hello_has_no_timestamp_nor_tag() -> crate::err::Result<()> {
+        let tokens = vec![
+            Token::Simple("HELLO".into()),
+            Token::Simple("192.168.1.10".into()),
+            Token::Integer(8080),
+            Token::Simple("alice".into()),
+            Token::Simple("aa:bb:cc:dd:ee:ff".into()),
+            Token::Integer(1),
+            Token::Integer(1),
+            Token::Integer(Capabilities::PUSH_OFFER.bits() as u64),
+        ];
+        let old = HelloMessage::from_tokens(&tokens)?;
+        assert_eq!(old.protocol_version, 1);
+        assert_eq!(old.timestamp_ms, 0);
+        assert_eq!(old.file_port, TCP_FILE_PORT);
+        assert!(old.auth_tag.is_none());
+        assert!(old.verify(None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn signed_hello_verifies_and_rejects_tampering() -> crate::err::Result<()> {
+        use crate::config::{Config, EnvVar};
+        use crate::utilities::identity::NodeIdentity;
+        if ENV_VAR.get().is_none() {
+            let mut cfg = Config::new();
+            cfg.identity.machine_name = "test-machine".into();
+            cfg.connection.conn_token = "TOKEN".into();
+            cfg.app_config.working_dir = "~/".into();
+            let _ = ENV_VAR.set(EnvVar::from_config(&cfg)?);
+        }
+        let identity = crate::utilities::identity::ensure_test_identity();
+
+        let signed = msg().sign()?;
+        let back = HelloMessage::deserialize(&signed.serialize())?;
+        assert!(back.verify(Some(0)).is_ok());
+        assert_eq!(back.fingerprint(), Some(identity.fingerprint()));
+
+        // Knowing the token is not enough to pass for another node
+        let mut impostor = back.clone();
+        impostor.public_key = Some(NodeIdentity::generate().public_key());
+        impostor.auth_tag = Some(compute_tag(&impostor.signed_bytes())?);
+        assert!(impostor.verify(Some(0)).is_err());
+
+        // Nor to pass for a node without identity key
+        let mut keyless = back.clone();
+        keyless.public_key = None;
+        keyless.signature = None;
+        keyless.auth_tag = Some(compute_tag(&keyless.signed_bytes())?);
+        assert!(keyless.verify(Some(0)).is_err());
+
+        // Claiming leadership invalidates the tag
+        let mut forged = back.clone();
+        forged.mode |= HelloMode::LEADER;
+        assert!(forged.verify(Some(0)).is_err());
+
+        // So does an old timestamp, and refreshing the timestamp requires signing again
+        let mut stale = msg();
+        stale.timestamp_ms -= MAX_HELLO_AGE.as_millis() as u64 + 1_000;
+        let stale = stale.sign()?;
+        assert!(stale.verify(Some(0)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn hello_age_is_checked_against_the_sender_clock() -> crate::err::Result<()> {
+        use crate::config::{Config, EnvVar};
+        if ENV_VAR.get().is_none() {
+            let mut cfg = Config::new();
+            cfg.identity.machine_name = "test-machine".into();
+            cfg.connection.conn_token = "TOKEN".into();
+            cfg.app_config.working_dir = "~/".into();
+            let _ = ENV_VAR.set(EnvVar::from_config(&cfg)?);
+        }
+        crate::utilities::identity::ensure_test_identity();
+
+        // Sent by a node whose clock is ten minutes behind ours
+        let mut skewed = msg();
+        skewed.timestamp_ms -= 10 * 60 * 1_000;
+        let skewed = skewed.sign()?;
+        // First contact, its offset is not known yet
+        assert!(skewed.verify(None).is_ok());
+        // Once the offset is known, only recent hellos pass
+        assert!(skewed.verify(Some(-10 * 60 * 1_000)).is_ok());
+        assert!(skewed.verify(Some(0)).is_err());
+
+        let mut far_off = msg();
+        far_off.timestamp_ms -= MAX_CLOCK_SKEW.as_millis() as u64 + 1_000;
+        assert!(far_off.sign()?.verify(None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_keeps_file_port() -> crate::err::Result<()> {
+        let m = msg().with_file_port(21451);
+        let back = HelloMessage::deserialize(&m.serialize())?;
+        assert_eq!(back.file_port, 21451);
+
+        // Version 3 nodes serve files on the reserved port
+        let mut v3 = msg().with_file_port(21451);
+        v3.protocol_version = 3;
+        let back = HelloMessage::deserialize(&v3.serialize())?;
+        assert_eq!(back.protocol_version, 3);
+        assert_eq!(back.file_port, TCP_FILE_PORT);
+        Ok(())
+    }
+
     #[test]
     fn deserialize_rejects_extra_tokens() -> crate::err::Result<()> {
         // Create valid hello bytes
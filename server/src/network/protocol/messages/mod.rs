@@ -1,8 +1,12 @@
+pub mod gossip_message;
 pub mod hello_message;
 pub mod pull_message;
 pub mod pull_response_message;
+pub mod push_message;
 
+pub use gossip_message::GossipMessage;
 pub use hello_message::HelloMessage;
 pub use pull_message::PullMessage;
 pub use pull_response_message::PullRejectionReason;
 pub use pull_response_message::PullResponse;
+pub use push_message::PushMessage;
@@ -1,17 +1,16 @@
 use crate::err::Result;
 use crate::global_var::{ENV_VAR, LOGGER};
 use crate::network::protocol::HandleableNetworkProtocol;
-use crate::utilities::crypto::from_encryption;
-use crate::utilities::crypto::to_encryption;
+use crate::utilities::clock::{to_local_time, within_window};
+use crate::utilities::crypto::{KeyPurpose, associated_data, from_encryption, to_encryption};
+use crate::utilities::identity::{get_identity, message_signing_bytes};
 use api_model::protocol::protocol::Protocol;
 use api_model::protocol::token::Token;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use std::fmt::Debug;
 use std::time::{Duration, SystemTime};
 
-type Challenge = u64;
 type Checksum = u64;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +19,12 @@ pub struct PullRequest {
 
     path: String,
     checksum: Option<Checksum>,
+    /// Ask for a listing of all files under `path` instead of the file itself
+    recursive: bool,
+    /// Ask for all files under `path` as a single archive instead of a listing
+    bulk: bool,
+    /// Position in the listing of a recursive request to list from, listings come in pages
+    listing_offset: u64,
 
     challenge: u64,
     time_stamp: SystemTime,
@@ -34,11 +39,29 @@ impl PullRequest {
             from_ip,
             path,
             checksum: checksum.into(),
+            recursive: false,
+            bulk: false,
+            listing_offset: 0,
             challenge,
             time_stamp: SystemTime::now(),
         }
     }
 
+    pub fn with_recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    pub fn with_bulk(mut self, bulk: bool) -> Self {
+        self.bulk = bulk;
+        self
+    }
+
+    pub fn with_listing_offset(mut self, listing_offset: u64) -> Self {
+        self.listing_offset = listing_offset;
+        self
+    }
+
     pub fn get_from_ip(&self) -> &str {
         &self.from_ip
     }
@@ -55,35 +78,53 @@ impl PullRequest {
         self.checksum
     }
 
-    pub fn request_time_valid(&self) -> bool {
-        let now = SystemTime::now();
-        let diff = now
-            .duration_since(self.time_stamp)
-            .unwrap_or(Duration::from_secs(0));
-        diff.as_secs() < ENV_VAR.get().unwrap().get_pull_task_validity_in_sec()
+    pub fn is_recursive(&self) -> bool {
+        self.recursive
+    }
+
+    pub fn is_bulk(&self) -> bool {
+        self.bulk
+    }
+
+    pub fn get_listing_offset(&self) -> u64 {
+        self.listing_offset
     }
 
-    fn generate_iv_from_challenge(challenge: Challenge) -> Result<[u8; 16]> {
-        let mut hasher = Sha256::new();
-        hasher.update(challenge.to_be_bytes());
-        hasher.update(b"pull_iv");
-        let digest = hasher.finalize();
-        let iv: [u8; 16] = digest[..16].try_into().map_err(|_| "IV too short")?;
-        Ok(iv)
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn request_time_valid(&self, clock_offset_ms: i64) -> bool {
+        let window = Duration::from_secs(ENV_VAR.get().unwrap().get_pull_task_validity_in_sec());
+        within_window(self.time_stamp, clock_offset_ms, window)
+    }
+
+    /// Time on our clock after which the request is too old to be accepted
+    pub fn valid_until(&self, clock_offset_ms: i64) -> SystemTime {
+        to_local_time(self.time_stamp, clock_offset_ms)
+            + Duration::from_secs(ENV_VAR.get().unwrap().get_pull_task_validity_in_sec())
     }
 
     pub fn to_encryption(&self) -> Result<Vec<u8>> {
-        to_encryption(self, || Self::generate_iv_from_challenge(self.challenge))
+        to_encryption(
+            KeyPurpose::Control,
+            self,
+            &associated_data("PULL", &self.from_ip),
+        )
     }
 
-    pub fn from_encryption(ciphertext: Box<[u8]>) -> Result<Self> {
-        from_encryption(ciphertext)
+    /// Decrypt a request claimed to be sent by `from_ip`
+    pub fn from_encryption(ciphertext: Box<[u8]>, from_ip: &str) -> Result<Self> {
+        from_encryption(
+            KeyPurpose::Control,
+            ciphertext,
+            &associated_data("PULL", from_ip),
+        )
     }
 }
 
 pub struct PullMessage {
     pub from_ip: String,
     pub request: Bytes,
+    // Made with the identity key of the sender, absent from nodes predating identity keys
+    pub signature: Option<Bytes>,
 }
 
 impl Debug for PullMessage {
@@ -93,11 +134,14 @@ impl Debug for PullMessage {
             "PullMessage {{ from_ip: {}, request: <encrypted> }}",
             self.from_ip
         )?;
-        match PullRequest::from_encryption(self.request.clone().to_vec().into_boxed_slice()) {
+        match PullRequest::from_encryption(
+            self.request.clone().to_vec().into_boxed_slice(),
+            &self.from_ip,
+        ) {
             Ok(request) => write!(
                 f,
-                "PullRequest {{ path: {}, checksum: {:?}, challenge: {} }}",
-                request.path, request.checksum, request.challenge
+                "PullRequest {{ path: {}, checksum: {:?}, recursive: {}, bulk: {}, challenge: {} }}",
+                request.path, request.checksum, request.recursive, request.bulk, request.challenge
             ),
             Err(_) => write!(f, "PullRequest {{ <decryption failed> }}"),
         }
@@ -105,6 +149,16 @@ impl Debug for PullMessage {
 }
 
 impl PullMessage {
+    /// Bytes covered by the signature
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        message_signing_bytes("PULL", &self.from_ip, &self.request)
+    }
+
+    fn signed(mut self) -> Result<Self> {
+        self.signature = Some(get_identity()?.sign(&self.signed_bytes()));
+        Ok(self)
+    }
+
     pub fn new<T>(path: &str, checksum: T, challenge: u64) -> Result<Self>
     where
         T: Into<Option<Checksum>>,
@@ -116,25 +170,73 @@ impl PullMessage {
                 PullRequest::new(from_ip.to_string(), path.to_string(), checksum, challenge)
                     .to_encryption()?;
 
-            return Ok(Self {
+            return Self {
                 from_ip: from_ip.to_string(),
                 request: encrypted_request.into(),
-            });
+                signature: None,
+            }
+            .signed();
         }
 
         Err("Failed to generate pull message because env_var not found.".into())
     }
 
-    pub fn validate_and_parse(&self) -> Result<PullRequest> {
+    /// Ask the peer for the page of the list of files under the directory `path` starting at
+    /// `listing_offset`.
+    pub fn new_directory(path: &str, challenge: u64, listing_offset: u64) -> Result<Self> {
+        if let Some(ev) = ENV_VAR.get() {
+            let from_ip = ev.get_ip_addr();
+
+            let encrypted_request =
+                PullRequest::new(from_ip.to_string(), path.to_string(), None, challenge)
+                    .with_recursive(true)
+                    .with_listing_offset(listing_offset)
+                    .to_encryption()?;
+
+            return Self {
+                from_ip: from_ip.to_string(),
+                request: encrypted_request.into(),
+                signature: None,
+            }
+            .signed();
+        }
+
+        Err("Failed to generate pull message because env_var not found.".into())
+    }
+
+    /// Ask the peer for all files under the directory `path`, packed into a single archive.
+    pub fn new_archive(path: &str, challenge: u64) -> Result<Self> {
+        if let Some(ev) = ENV_VAR.get() {
+            let from_ip = ev.get_ip_addr();
+
+            let encrypted_request =
+                PullRequest::new(from_ip.to_string(), path.to_string(), None, challenge)
+                    .with_recursive(true)
+                    .with_bulk(true)
+                    .to_encryption()?;
+
+            return Self {
+                from_ip: from_ip.to_string(),
+                request: encrypted_request.into(),
+                signature: None,
+            }
+            .signed();
+        }
+
+        Err("Failed to generate pull message because env_var not found.".into())
+    }
+
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn validate_and_parse(&self, clock_offset_ms: i64) -> Result<PullRequest> {
         let from_ip_out = &self.from_ip;
 
         let normalized_data = self.request.to_vec().into_boxed_slice();
 
-        match PullRequest::from_encryption(normalized_data) {
+        match PullRequest::from_encryption(normalized_data, from_ip_out) {
             Ok(pull_request) => {
-                if !pull_request.request_time_valid() {
+                if !pull_request.request_time_valid(clock_offset_ms) {
                     let time_diff = SystemTime::now()
-                        .duration_since(pull_request.time_stamp)
+                        .duration_since(to_local_time(pull_request.time_stamp, clock_offset_ms))
                         .unwrap_or(Duration::from_secs(0))
                         .as_secs();
                     LOGGER.warn(format!(
@@ -164,12 +266,15 @@ impl HandleableNetworkProtocol for PullMessage {}
 
 impl Protocol for PullMessage {
     fn serialize(&self) -> Vec<u8> {
-        // Format: +PULL, +from_ip, $<request-bytes>
-        let tokens = vec![
+        // Format: +PULL, +from_ip, $<request-bytes>[, $<signature>]
+        let mut tokens = vec![
             Token::Simple(String::from("PULL")),
             Token::Simple(self.from_ip.clone()),
             Token::Data(self.request.clone()),
         ];
+        if let Some(signature) = &self.signature {
+            tokens.push(Token::Data(signature.clone()));
+        }
         let mut out = Vec::new();
         for t in tokens {
             out.extend_from_slice(&t.to_bytes());
@@ -181,7 +286,7 @@ impl Protocol for PullMessage {
     where
         Self: Sized,
     {
-        let tokens = Token::parse_all(bytes)?;
+        let tokens = Token::parse_all_compat(bytes)?;
         Self::from_tokens(&tokens)
     }
 
@@ -190,10 +295,13 @@ impl Protocol for PullMessage {
         Self: Sized,
     {
         use std::io;
-        if tokens.len() != 3 {
+        if !(3..=4).contains(&tokens.len()) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!("expected 3 tokens for PullMessage, got {}", tokens.len()),
+                format!(
+                    "expected 3 or 4 tokens for PullMessage, got {}",
+                    tokens.len()
+                ),
             )
             .into());
         }
@@ -227,6 +335,21 @@ impl Protocol for PullMessage {
                 .into());
             }
         };
-        Ok(PullMessage { from_ip, request })
+        let signature = match tokens.get(3) {
+            None => None,
+            Some(Token::Data(b)) => Some(b.clone()),
+            Some(other) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Data for signature, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        Ok(PullMessage {
+            from_ip,
+            request,
+            signature,
+        })
     }
 }
@@ -1,7 +1,9 @@
 use crate::err::Result;
 use crate::global_var::ENV_VAR;
 use crate::network::protocol::HandleableNetworkProtocol;
-use crate::utilities::crypto::{from_encryption, to_encryption};
+use crate::utilities::clock::{to_local_time, within_window};
+use crate::utilities::crypto::{KeyPurpose, associated_data, from_encryption, to_encryption};
+use crate::utilities::identity::{get_identity, message_signing_bytes};
 use api_model::protocol::protocol::Protocol;
 use api_model::protocol::token::Token;
 use bytes::Bytes;
@@ -11,6 +13,7 @@ use std::time::{Duration, SystemTime};
 
 type Nonce = u64;
 type Challenge = u64;
+type Checksum = u64;
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum PullRejectionReason {
@@ -39,10 +42,34 @@ impl Display for PullRejectionReason {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+/// A file found under a directory requested by a recursive pull
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListedFile {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PullDecision {
     Accept(Challenge, Nonce),
     Reject(Challenge, PullRejectionReason),
+    /// Answer to a recursive pull: a page of the files to pull one by one, and the offset to
+    /// ask the next page from, if any
+    Listing(Challenge, Vec<ListedFile>, Option<u64>),
+    /// Answer to a bulk pull: the nonce to fetch the archive with, and the checksum of the
+    /// plain archive
+    Archive(Challenge, Nonce, Checksum),
+}
+
+impl PullDecision {
+    pub fn get_challenge(&self) -> Challenge {
+        match self {
+            PullDecision::Accept(c, _) => *c,
+            PullDecision::Reject(c, _) => *c,
+            PullDecision::Listing(c, ..) => *c,
+            PullDecision::Archive(c, ..) => *c,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -74,45 +101,72 @@ impl PullResponse {
     }
 
     pub fn to_encryption(&self) -> Result<Vec<u8>> {
-        to_encryption(self, || {
-            let iv: [u8; 16] = rand::random();
-            Ok(iv)
-        })
+        to_encryption(
+            KeyPurpose::Control,
+            self,
+            &associated_data("PULL_RESPONSE", &self.from_ip),
+        )
     }
 
-    pub fn from_encryption(ciphertext: Box<[u8]>) -> Result<Self> {
-        from_encryption(ciphertext)
+    /// Decrypt a response claimed to be sent by `from_ip`
+    pub fn from_encryption(ciphertext: Box<[u8]>, from_ip: &str) -> Result<Self> {
+        from_encryption(
+            KeyPurpose::Control,
+            ciphertext,
+            &associated_data("PULL_RESPONSE", from_ip),
+        )
     }
 
-    pub fn timestamp_valid(&self) -> bool {
-        let now = SystemTime::now();
-        let diff = now
-            .duration_since(self.timestamp)
-            .unwrap_or(Duration::from_secs(0));
-        diff.as_secs() < ENV_VAR.get().unwrap().get_pull_task_validity_in_sec()
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn timestamp_valid(&self, clock_offset_ms: i64) -> bool {
+        let window = Duration::from_secs(ENV_VAR.get().unwrap().get_pull_task_validity_in_sec());
+        within_window(self.timestamp, clock_offset_ms, window)
+    }
+
+    /// Time on our clock after which the response is too old to be accepted
+    pub fn valid_until(&self, clock_offset_ms: i64) -> SystemTime {
+        to_local_time(self.timestamp, clock_offset_ms)
+            + Duration::from_secs(ENV_VAR.get().unwrap().get_pull_task_validity_in_sec())
     }
 }
 
 pub struct PullResponseMessage {
     pub from_ip: String,
     pub response: Bytes,
+    // Made with the identity key of the sender, absent from nodes predating identity keys
+    pub signature: Option<Bytes>,
 }
 
 impl PullResponseMessage {
+    /// Bytes covered by the signature
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        message_signing_bytes("PULL_RESPONSE", &self.from_ip, &self.response)
+    }
+
+    fn signed(mut self) -> Result<Self> {
+        self.signature = Some(get_identity()?.sign(&self.signed_bytes()));
+        Ok(self)
+    }
+
     pub fn new(response: PullResponse) -> Result<Self> {
         if let Some(ev) = crate::global_var::ENV_VAR.get() {
             let from_ip = ev.get_ip_addr();
             let encrypted_response = Bytes::from(response.to_encryption()?);
-            return Ok(Self {
+            return Self {
                 from_ip: from_ip.to_string(),
                 response: encrypted_response,
-            });
+                signature: None,
+            }
+            .signed();
         }
         Err("Failed to generate pull response because env_var not found.".into())
     }
 
     pub fn get_response(&self) -> Result<PullResponse> {
-        PullResponse::from_encryption(self.response.clone().to_vec().into_boxed_slice())
+        PullResponse::from_encryption(
+            self.response.clone().to_vec().into_boxed_slice(),
+            &self.from_ip,
+        )
     }
 }
 
@@ -121,12 +175,15 @@ impl HandleableNetworkProtocol for PullResponseMessage {}
 
 impl Protocol for PullResponseMessage {
     fn serialize(&self) -> Vec<u8> {
-        // Format: +PULL_RESPONSE, +from_ip, $<response-bytes>
-        let tokens = vec![
+        // Format: +PULL_RESPONSE, +from_ip, $<response-bytes>[, $<signature>]
+        let mut tokens = vec![
             Token::Simple(String::from("PULL_RESPONSE")),
             Token::Simple(self.from_ip.clone()),
             Token::Data(self.response.clone()),
         ];
+        if let Some(signature) = &self.signature {
+            tokens.push(Token::Data(signature.clone()));
+        }
         let mut out = Vec::new();
         for t in tokens {
             out.extend_from_slice(&t.to_bytes());
@@ -138,7 +195,7 @@ impl Protocol for PullResponseMessage {
     where
         Self: Sized,
     {
-        let tokens = Token::parse_all(bytes)?;
+        let tokens = Token::parse_all_compat(bytes)?;
         Self::from_tokens(&tokens)
     }
 
@@ -147,11 +204,11 @@ impl Protocol for PullResponseMessage {
         Self: Sized,
     {
         use std::io;
-        if tokens.len() != 3 {
+        if !(3..=4).contains(&tokens.len()) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!(
-                    "expected 3 tokens for PullResponseMessage, got {}",
+                    "expected 3 or 4 tokens for PullResponseMessage, got {}",
                     tokens.len()
                 ),
             )
@@ -190,6 +247,21 @@ impl Protocol for PullResponseMessage {
                 .into());
             }
         };
-        Ok(PullResponseMessage { from_ip, response })
+        let signature = match tokens.get(3) {
+            None => None,
+            Some(Token::Data(b)) => Some(b.clone()),
+            Some(other) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Data for signature, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        Ok(PullResponseMessage {
+            from_ip,
+            response,
+            signature,
+        })
     }
 }
@@ -0,0 +1,264 @@
+use crate::err::Result;
+use crate::global_var::{ENV_VAR, LOGGER};
+use crate::network::protocol::HandleableNetworkProtocol;
+use crate::utilities::clock::{to_local_time, within_window};
+use crate::utilities::crypto::{KeyPurpose, associated_data, from_encryption, to_encryption};
+use crate::utilities::identity::{get_identity, message_signing_bytes};
+use api_model::protocol::protocol::Protocol;
+use api_model::protocol::token::Token;
+use bytes::Bytes;
+use rand::random;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::time::{Duration, SystemTime};
+
+type OfferId = u64;
+type Checksum = u64;
+
+/// A file offered by a peer. The receiver decides whether to accept it, and if so
+/// pulls the file back from the offering peer through the regular pull flow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushOffer {
+    from_ip: String,
+
+    path: String,
+    size: u64,
+    checksum: Checksum,
+
+    offer_id: OfferId,
+    time_stamp: SystemTime,
+}
+
+impl PushOffer {
+    pub fn new(from_ip: String, path: String, size: u64, checksum: Checksum) -> Self {
+        Self {
+            from_ip,
+            path,
+            size,
+            checksum,
+            offer_id: random::<OfferId>(),
+            time_stamp: SystemTime::now(),
+        }
+    }
+
+    pub fn get_from_ip(&self) -> &str {
+        &self.from_ip
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    pub fn get_offer_id(&self) -> OfferId {
+        self.offer_id
+    }
+
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn offer_time_valid(&self, clock_offset_ms: i64) -> bool {
+        let window = Duration::from_secs(ENV_VAR.get().unwrap().get_pull_task_validity_in_sec());
+        within_window(self.time_stamp, clock_offset_ms, window)
+    }
+
+    pub fn to_encryption(&self) -> Result<Vec<u8>> {
+        to_encryption(
+            KeyPurpose::Control,
+            self,
+            &associated_data("PUSH", &self.from_ip),
+        )
+    }
+
+    /// Decrypt an offer claimed to be sent by `from_ip`
+    pub fn from_encryption(ciphertext: Box<[u8]>, from_ip: &str) -> Result<Self> {
+        from_encryption(
+            KeyPurpose::Control,
+            ciphertext,
+            &associated_data("PUSH", from_ip),
+        )
+    }
+}
+
+pub struct PushMessage {
+    pub from_ip: String,
+    pub offer: Bytes,
+    // Made with the identity key of the sender, absent from nodes predating identity keys
+    pub signature: Option<Bytes>,
+}
+
+impl Debug for PushMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("PushMessage");
+        debug.field("from_ip", &self.from_ip);
+        match PushOffer::from_encryption(self.offer.to_vec().into_boxed_slice(), &self.from_ip) {
+            Ok(offer) => debug.field("offer", &offer),
+            Err(_) => debug.field("offer", &format_args!("<decryption failed>")),
+        };
+        debug.field("signed", &self.signature.is_some()).finish()
+    }
+}
+
+impl PushMessage {
+    /// Bytes covered by the signature
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        message_signing_bytes("PUSH", &self.from_ip, &self.offer)
+    }
+
+    fn signed(mut self) -> Result<Self> {
+        self.signature = Some(get_identity()?.sign(&self.signed_bytes()));
+        Ok(self)
+    }
+
+    pub fn new(path: &str, size: u64, checksum: Checksum) -> Result<Self> {
+        if let Some(ev) = ENV_VAR.get() {
+            let from_ip = ev.get_ip_addr();
+
+            let encrypted_offer =
+                PushOffer::new(from_ip.to_string(), path.to_string(), size, checksum)
+                    .to_encryption()?;
+
+            return Self {
+                from_ip: from_ip.to_string(),
+                offer: encrypted_offer.into(),
+                signature: None,
+            }
+            .signed();
+        }
+
+        Err("Failed to generate push message because env_var not found.".into())
+    }
+
+    /// `clock_offset_ms` is the offset of the sender clock from ours
+    pub fn validate_and_parse(&self, clock_offset_ms: i64) -> Result<PushOffer> {
+        let from_ip_out = &self.from_ip;
+
+        let normalized_data = self.offer.to_vec().into_boxed_slice();
+
+        match PushOffer::from_encryption(normalized_data, from_ip_out) {
+            Ok(offer) => {
+                if !offer.offer_time_valid(clock_offset_ms) {
+                    let sent_at = to_local_time(offer.time_stamp, clock_offset_ms);
+                    let time_diff = SystemTime::now()
+                        .duration_since(sent_at)
+                        .unwrap_or(Duration::from_secs(0))
+                        .as_secs();
+                    LOGGER.warn(format!(
+                        "Push offer from {} is out of its validity window. The offer was generated {} seconds ago",
+                        &from_ip_out, time_diff
+                    ));
+                    return Err("Offer is too old or from the future".into());
+                }
+                if from_ip_out != offer.get_from_ip() {
+                    LOGGER.warn(format!(
+                        "Push offer from {} is not from the same IP as the sender",
+                        &from_ip_out
+                    ));
+                    return Err("Offer is not from the same IP".into());
+                }
+                Ok(offer)
+            }
+            Err(e) => {
+                LOGGER.warn(format!("Failed to deserialize push offer: {}", e));
+                Err("Offer decryption failed".into())
+            }
+        }
+    }
+}
+
+impl HandleableNetworkProtocol for PushMessage {}
+
+impl Protocol for PushMessage {
+    fn serialize(&self) -> Vec<u8> {
+        // Format: +PUSH, +from_ip, $<offer-bytes>[, $<signature>]
+        let mut tokens = vec![
+            Token::Simple(String::from("PUSH")),
+            Token::Simple(self.from_ip.clone()),
+            Token::Data(self.offer.clone()),
+        ];
+        if let Some(signature) = &self.signature {
+            tokens.push(Token::Data(signature.clone()));
+        }
+        let mut out = Vec::new();
+        for t in tokens {
+            out.extend_from_slice(&t.to_bytes());
+        }
+        out
+    }
+
+    fn deserialize(bytes: &[u8]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let tokens = Token::parse_all_compat(bytes)?;
+        Self::from_tokens(&tokens)
+    }
+
+    fn from_tokens(tokens: &[Token]) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        use std::io;
+        if !(3..=4).contains(&tokens.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expected 3 or 4 tokens for PushMessage, got {}",
+                    tokens.len()
+                ),
+            )
+            .into());
+        }
+        match &tokens[0] {
+            Token::Simple(s) if s == "PUSH" => {}
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected leading Simple(\"PUSH\"), got {:?}", other),
+                )
+                .into());
+            }
+        }
+        let from_ip = match &tokens[1] {
+            Token::Simple(s) => s.clone(),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Simple for from_ip, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        let offer = match &tokens[2] {
+            Token::Data(b) => b.clone(),
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Data for offer, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        let signature = match tokens.get(3) {
+            None => None,
+            Some(Token::Data(b)) => Some(b.clone()),
+            Some(other) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("expected Data for signature, got {:?}", other),
+                )
+                .into());
+            }
+        };
+        Ok(PushMessage {
+            from_ip,
+            offer,
+            signature,
+        })
+    }
+}
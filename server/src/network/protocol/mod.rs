@@ -8,7 +8,9 @@ use bytes::Bytes;
 
 mod consensus;
 pub mod messages;
+use crate::network::protocol::messages::GossipMessage;
 use crate::network::protocol::messages::PullMessage;
+use crate::network::protocol::messages::PushMessage;
 use crate::network::protocol::messages::pull_response_message::PullResponseMessage;
 pub use consensus::CUR_LEADER;
 
@@ -17,17 +19,21 @@ pub trait HandleableNetworkProtocol:
 {
 }
 
-pub fn parse_message(bytes: &Bytes) -> Result<Box<dyn HandleableNetworkProtocol>> {
-    let tokens = Token::parse_all(bytes)?;
+/// Parse the bytes into a message.
+/// Returns `None` for message types this node does not know, e.g. sent by a newer peer.
+pub fn parse_message(bytes: &Bytes) -> Result<Option<Box<dyn HandleableNetworkProtocol>>> {
+    let tokens = Token::parse_all_compat(bytes)?;
 
     match tokens.get(0) {
         Some(head) => match head {
             Token::Simple(str) => match str.as_str() {
-                "HELLO" => Ok(Box::new(HelloMessage::from_tokens(&tokens)?)),
-                "API_REQUEST" => Ok(Box::new(ApiRequestMessage::from_tokens(&tokens)?)),
-                "PULL" => Ok(Box::new(PullMessage::from_tokens(&tokens)?)),
-                "PULL_RESPONSE" => Ok(Box::new(PullResponseMessage::from_tokens(&tokens)?)),
-                _ => unimplemented!(),
+                "HELLO" => Ok(Some(Box::new(HelloMessage::from_tokens(&tokens)?))),
+                "API_REQUEST" => Ok(Some(Box::new(ApiRequestMessage::from_tokens(&tokens)?))),
+                "PULL" => Ok(Some(Box::new(PullMessage::from_tokens(&tokens)?))),
+                "PULL_RESPONSE" => Ok(Some(Box::new(PullResponseMessage::from_tokens(&tokens)?))),
+                "PUSH" => Ok(Some(Box::new(PushMessage::from_tokens(&tokens)?))),
+                "GOSSIP" => Ok(Some(Box::new(GossipMessage::from_tokens(&tokens)?))),
+                _ => Ok(None),
             },
             _ => Err(String::from("Unable to parse message because tokens are malformed.").into()),
         },
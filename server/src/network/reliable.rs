@@ -0,0 +1,219 @@
+//! Acknowledged delivery of control messages over UDP.
+//!
+//! A message sent reliably is wrapped in a `ReliableMessage` under a random id, and sent again
+//! with an exponential backoff until the receiver answers with an `AckMessage` carrying the same
+//! id, or the attempts run out. The receiver acknowledges every copy it gets, as the previous
+//! acknowledgement may be the datagram that was lost, and handles only the first one.
+
+use crate::global_var::LOGGER;
+use crate::network::udp_sender::NetworkSender;
+use api_model::protocol::message::reliable_message::{AckMessage, ReliableMessage};
+use api_model::protocol::protocol::Protocol;
+use api_model::protocol::token::Token;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// How long the ids of received messages are remembered, longer than any retransmission lasts
+const DEDUP_WINDOW: Duration = Duration::from_secs(120);
+/// Bound on the number of remembered ids, the oldest ones are forgotten first
+const DEDUP_CAPACITY: usize = 4096;
+
+/// How a reliable message is retransmitted until it is acknowledged
+#[derive(Clone, Debug)]
+pub struct RetransmitPolicy {
+    /// Number of times the message is sent at most, the first one included
+    pub max_attempts: u32,
+    /// Wait for an acknowledgement after the first attempt, doubled after every other one
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetransmitPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 6,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(4),
+        }
+    }
+}
+
+impl RetransmitPolicy {
+    /// Time to wait for an acknowledgement after `attempt` (0 for the first one)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+}
+
+struct PendingAck {
+    to: IpAddr,
+    acked: oneshot::Sender<()>,
+}
+
+static PENDING_ACKS: LazyLock<Mutex<HashMap<u64, PendingAck>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Wait for the acknowledgement of `msg_id`, which has to come from `to`
+pub(super) fn expect_ack(msg_id: u64, to: IpAddr) -> oneshot::Receiver<()> {
+    let (acked, rx) = oneshot::channel();
+    PENDING_ACKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(msg_id, PendingAck { to, acked });
+    rx
+}
+
+/// Stop waiting for the acknowledgement of `msg_id`
+pub(super) fn forget_ack(msg_id: u64) {
+    PENDING_ACKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(&msg_id);
+}
+
+fn acknowledge(from: SocketAddr, msg_id: u64) {
+    let mut pending = PENDING_ACKS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Only the receiver can stop the retransmission of what was sent to it
+    if pending.get(&msg_id).is_some_and(|p| p.to == from.ip())
+        && let Some(p) = pending.remove(&msg_id)
+    {
+        let _ = p.acked.send(());
+    }
+}
+
+/// Ids of the reliable messages received lately, per sender
+struct RecentIds {
+    seen: HashMap<(IpAddr, u64), Instant>,
+    order: VecDeque<(IpAddr, u64)>,
+}
+
+impl RecentIds {
+    fn new() -> Self {
+        Self {
+            seen: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remember `key`, returns false when it was seen within the window already
+    fn first_seen(&mut self, key: (IpAddr, u64), now: Instant) -> bool {
+        while let Some(oldest) = self.order.front() {
+            let expired = self
+                .seen
+                .get(oldest)
+                .is_none_or(|at| now.duration_since(*at) > DEDUP_WINDOW);
+            if !expired && self.order.len() < DEDUP_CAPACITY {
+                break;
+            }
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        if self.seen.contains_key(&key) {
+            return false;
+        }
+        self.seen.insert(key, now);
+        self.order.push_back(key);
+        true
+    }
+}
+
+static RECENT_IDS: LazyLock<Mutex<RecentIds>> = LazyLock::new(|| Mutex::new(RecentIds::new()));
+
+/// Take care of the reliability envelope of a datagram received from `peer`.
+/// Acknowledgements are consumed, reliable messages are acknowledged through `sender` and
+/// unwrapped the first time they are seen. Returns the message to handle, if any.
+pub fn receive(bytes: Bytes, peer: SocketAddr, sender: &NetworkSender) -> Option<Bytes> {
+    let head = match Token::parse_one(&bytes) {
+        Ok((Token::Simple(head), _)) => head,
+        _ => return Some(bytes),
+    };
+    match head.as_str() {
+        "ACK" => {
+            match AckMessage::deserialize(&bytes) {
+                Ok(ack) => acknowledge(peer, ack.msg_id),
+                Err(e) => LOGGER.debug(format!("Malformed ACK from {}: {}", peer, e)),
+            }
+            None
+        }
+        "RELIABLE" => {
+            let message = match ReliableMessage::deserialize(&bytes) {
+                Ok(message) => message,
+                Err(e) => {
+                    LOGGER.debug(format!("Malformed reliable message from {}: {}", peer, e));
+                    return None;
+                }
+            };
+            let mut ack_addr = peer;
+            ack_addr.set_port(message.reply_port);
+            if let Err(e) = sender.try_send(ack_addr, Bytes::from(message.ack().serialize())) {
+                LOGGER.warn(format!(
+                    "Unable to acknowledge message {} from {}: {}",
+                    message.msg_id, peer, e
+                ));
+            }
+            let first = RECENT_IDS
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .first_seen((peer.ip(), message.msg_id), Instant::now());
+            first.then_some(message.payload)
+        }
+        _ => Some(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetransmitPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        let waits = (0..5).map(|a| policy.backoff(a)).collect::<Vec<_>>();
+        assert_eq!(
+            waits,
+            [100, 200, 400, 500, 500]
+                .map(Duration::from_millis)
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn repeated_ids_are_dropped_per_sender_until_forgotten() {
+        let mut recent = RecentIds::new();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(recent.first_seen((a, 7), now));
+        assert!(!recent.first_seen((a, 7), now));
+        assert!(recent.first_seen((b, 7), now));
+
+        let later = now + DEDUP_WINDOW + Duration::from_secs(1);
+        assert!(recent.first_seen((a, 7), later));
+    }
+
+    #[test]
+    fn acknowledgements_only_count_from_the_receiver() {
+        let to: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut acked = expect_ack(0xfeed, to);
+
+        acknowledge("10.0.0.9:14514".parse().unwrap(), 0xfeed);
+        assert!(acked.try_recv().is_err());
+
+        acknowledge(SocketAddr::new(to, 14514), 0xfeed);
+        assert!(acked.try_recv().is_ok());
+    }
+}
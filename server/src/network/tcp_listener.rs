@@ -1,7 +1,9 @@
 use crate::constants::TCP_FILE_PORT;
 use crate::err::Result;
-use crate::global_var::LOGGER;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use crate::global_var::{ENV_VAR, LOGGER};
+use crate::network::util::{socket_addr_in_scope, unmap_socket_addr};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use tokio::net::{TcpListener as TokioTcpListener, TcpStream as TokioTcpStream};
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
@@ -33,11 +35,45 @@ impl ListenerHandle {
 }
 
 impl TcpListener {
-    /// Bind to 0.0.0.0:TCP_FILE_PORT
+    /// Bind to the configured address and file port, or to [::] for both IPv4 and IPv6 when no
+    /// address is configured, or to 0.0.0.0 on hosts without IPv6
     pub async fn bind() -> Result<Self> {
-        let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), TCP_FILE_PORT);
+        let (bind_addr, port, if_index) = ENV_VAR
+            .get()
+            .map(|ev| {
+                (
+                    ev.get_bind_addr(),
+                    ev.get_file_sync_port(),
+                    ev.get_if_index(),
+                )
+            })
+            .unwrap_or((None, TCP_FILE_PORT, 0));
+        if let Some(ip) = bind_addr {
+            return Self::bind_on(socket_addr_in_scope(ip, port, if_index)).await;
+        }
+        match Self::bind_dual_stack(port) {
+            Ok(listener) => Ok(listener),
+            Err(e) => {
+                LOGGER.warn(format!(
+                    "Unable to bind a dual-stack TCP listener, falling back to IPv4: {:?}",
+                    e
+                ));
+                Self::bind_on(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)).await
+            }
+        }
+    }
+
+    /// Bind to [::]:port accepting IPv4 connections as well
+    pub fn bind_dual_stack(port: u16) -> Result<Self> {
+        let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
         LOGGER.info(format!("Binding TCP listener to {}", addr));
-        let listener = TokioTcpListener::bind(addr).await?;
+        let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_only_v6(false)?;
+        socket.set_reuse_address(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        let listener = TokioTcpListener::from_std(socket.into())?;
         Ok(Self { listener })
     }
 
@@ -72,6 +108,7 @@ impl TcpListener {
                     res = self.listener.accept() => {
                         match res {
                             Ok((stream, peer)) => {
+                                let peer = unmap_socket_addr(peer);
                                 LOGGER.debug(format!("Accepted TCP connection from {:?}", peer));
                                 on_conn(stream, peer);
                             }
@@ -98,6 +135,39 @@ mod tests {
     use tokio::net::TcpStream as ClientTcpStream;
     use tokio::sync::oneshot;
 
+    #[tokio::test]
+    async fn dual_stack_listener_accepts_ipv4_and_ipv6() -> Result<()> {
+        let Ok(listener) = TcpListener::bind_dual_stack(0) else {
+            // Host without IPv6
+            return Ok(());
+        };
+        let port = listener.local_addr()?.port();
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<SocketAddr>(2);
+        let handle = listener.into_task(move |_stream, peer| {
+            let _ = tx.try_send(peer);
+        });
+
+        let _v4 = ClientTcpStream::connect((Ipv4Addr::LOCALHOST, port)).await?;
+        let peer = tokio::time::timeout(std::time::Duration::from_secs(2), rx.recv())
+            .await
+            .expect("IPv4 connection accepted")
+            .unwrap();
+        // Reported as a plain IPv4 address rather than ::ffff:127.0.0.1
+        assert_eq!(peer.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        if let Ok(_v6) = ClientTcpStream::connect((Ipv6Addr::LOCALHOST, port)).await {
+            let peer = tokio::time::timeout(std::time::Duration::from_secs(2), rx.recv())
+                .await
+                .expect("IPv6 connection accepted")
+                .unwrap();
+            assert_eq!(peer.ip(), IpAddr::V6(Ipv6Addr::LOCALHOST));
+        }
+
+        handle.shutdown().await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn tcp_listener_accepts_one_connection_and_reads_payload() -> Result<()> {
         // Bind listener on a local ephemeral port (localhost:0)
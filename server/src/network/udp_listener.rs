@@ -1,8 +1,12 @@
-use crate::constants::UPD_MESSAGE_PORT;
+use crate::constants::{DISCOVERY_MULTICAST_V4, DISCOVERY_MULTICAST_V6, UPD_MESSAGE_PORT};
 use crate::err::Result;
-use crate::global_var::LOGGER;
+use crate::global_var::{ENV_VAR, LOGGER};
+use crate::network::announce_addrs;
+use crate::network::util::{AnnounceAddr, socket_addr_in_scope, unmap_socket_addr};
 use bytes::Bytes;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
@@ -16,11 +20,17 @@ pub struct UdpListener {
 /// Handle to a running UDP listener task, allowing graceful shutdown.
 #[derive(Debug)]
 pub struct ListenerHandle {
+    socket: Arc<UdpSocket>,
     handle: JoinHandle<()>,
     shutdown_tx: oneshot::Sender<()>,
 }
 
 impl ListenerHandle {
+    /// Join the discovery groups on the interfaces the node is on now, see [`join_groups`]
+    pub fn join_discovery_groups(&self) {
+        join_groups(&self.socket);
+    }
+
     /// Signal shutdown and await the listener task to exit.
     pub async fn shutdown(self) -> Result<()> {
         // Ignore if already closed
@@ -31,13 +41,53 @@ impl ListenerHandle {
 }
 
 impl UdpListener {
-    /// bind to any
+    /// bind to the configured address and port, or to any address on both IPv4 and IPv6 when
+    /// none is configured and the host has IPv6
     pub async fn bind() -> Result<Self> {
-        let (ip, port) = (IpAddr::V4(Ipv4Addr::UNSPECIFIED), UPD_MESSAGE_PORT);
-        let addr = SocketAddr::new(ip, port);
+        let (bind_addr, port, if_index) = ENV_VAR
+            .get()
+            .map(|ev| (ev.get_bind_addr(), ev.get_port(), ev.get_if_index()))
+            .unwrap_or((None, UPD_MESSAGE_PORT, 0));
+        if let Some(ip) = bind_addr {
+            return Self::bind_on(socket_addr_in_scope(ip, port, if_index)).await;
+        }
+        match Self::bind_dual_stack(port) {
+            Ok(listener) => Ok(listener),
+            Err(e) => {
+                LOGGER.warn(format!(
+                    "Unable to bind a dual-stack UDP listener, falling back to IPv4: {:?}",
+                    e
+                ));
+                Self::bind_on(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)).await
+            }
+        }
+    }
+
+    /// Bind to a specific socket address
+    pub async fn bind_on(addr: SocketAddr) -> Result<Self> {
         LOGGER.info(format!("Binding UDP listener to {}", addr));
         let socket = UdpSocket::bind(addr).await?;
         socket.set_broadcast(true)?;
+        // Multicast only reaches sockets bound to the unspecified address
+        if addr.ip() == IpAddr::V4(Ipv4Addr::UNSPECIFIED) {
+            join_groups(&socket);
+        }
+        Ok(Self { socket })
+    }
+
+    /// Bind to [::]:port receiving IPv4 datagrams as well, and join the discovery groups
+    pub fn bind_dual_stack(port: u16) -> Result<Self> {
+        let addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+        LOGGER.info(format!("Binding UDP listener to {}", addr));
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+
+        // Hellos are multicast to both discovery groups, IPv4 ones also arrive as broadcasts
+        let socket = UdpSocket::from_std(socket.into())?;
+        join_groups(&socket);
         Ok(Self { socket })
     }
 
@@ -54,6 +104,8 @@ impl UdpListener {
         mut on_packet: impl FnMut(Bytes, SocketAddr) + Send + 'static,
     ) -> ListenerHandle {
         let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        let socket = Arc::new(self.socket);
+        let task_socket = socket.clone();
         let handle = tokio::spawn(async move {
             let mut buf = vec![0u8; 64 * 1024]; // max UDP payload size safe buffer
             loop {
@@ -64,9 +116,10 @@ impl UdpListener {
                         LOGGER.info("Upd listener received shutdown signal, exiting...");
                         break;
                     }
-                    res = self.socket.recv_from(&mut buf) => {
+                    res = task_socket.recv_from(&mut buf) => {
                         match res {
                             Ok((n, peer)) => {
+                                let peer = unmap_socket_addr(peer);
                                 let data = Bytes::copy_from_slice(&buf[..n]);
                                 // LOGGER.debug(format!("Received UDP packet from {:?} with length {}", peer, n));
                                 on_packet(data, peer);
@@ -83,12 +136,52 @@ impl UdpListener {
             }
         });
         ListenerHandle {
+            socket,
             handle,
             shutdown_tx,
         }
     }
 }
 
+/// Join the discovery group of each family the socket receives on the interface of every
+/// address the node announces from, as multicast only arrives on the interfaces it was joined on.
+/// Before the environment is set, the groups are joined on the interface the system picks.
+/// Groups already joined on an interface are kept.
+fn join_groups(socket: &UdpSocket) {
+    let v4_only = socket.local_addr().is_ok_and(|addr| addr.is_ipv4());
+    let mut addrs = announce_addrs();
+    if addrs.is_empty() {
+        addrs = vec![
+            AnnounceAddr {
+                ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                if_index: 0,
+                broadcast: None,
+            },
+            AnnounceAddr {
+                ip: IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                if_index: 0,
+                broadcast: None,
+            },
+        ];
+    }
+    for addr in addrs {
+        let res = match addr.ip {
+            IpAddr::V4(v4) => socket.join_multicast_v4(DISCOVERY_MULTICAST_V4, v4),
+            IpAddr::V6(_) if v4_only => continue,
+            IpAddr::V6(_) => socket.join_multicast_v6(&DISCOVERY_MULTICAST_V6, addr.if_index),
+        };
+        match res {
+            Ok(()) => {}
+            // Already a member there, as when two addresses share an interface
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {}
+            Err(e) => LOGGER.warn(format!(
+                "Unable to join discovery group on {} (interface {}): {:?}",
+                addr.ip, addr.if_index, e
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,6 +210,7 @@ mod tests {
             queue_bound: 16,
             connect_timeout: Duration::from_secs(2),
             write_timeout: Duration::from_secs(2),
+            ..SenderConfig::default()
         });
         sender_server
             .sender()
@@ -1,9 +1,20 @@
-use crate::constants::UPD_MESSAGE_PORT;
+use crate::constants::{DISCOVERY_MULTICAST_V4, DISCOVERY_MULTICAST_V6, UPD_MESSAGE_PORT};
+use crate::core::PEER_TABLE;
 use crate::err::Result;
-use crate::global_var::ENV_VAR;
+use crate::global_var::{ENV_VAR, LOGGER};
+use crate::network::protocol::messages::hello_message::Capabilities;
+use crate::network::reliable::{RetransmitPolicy, expect_ack, forget_ack};
+use crate::network::util::{AnnounceAddr, socket_addr_in_scope};
+use api_model::protocol::message::reliable_message::ReliableMessage;
+use api_model::protocol::protocol::Protocol;
+use api_model::protocol::token::{DataFraming, Token};
 use bytes::Bytes;
+use rand::Rng;
+use socket2::SockRef;
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::ops::Deref;
+use std::sync::{LazyLock, Mutex};
 use std::time::Duration;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
@@ -20,11 +31,13 @@ use tokio::time::timeout;
 pub struct NetworkSenderCore {
     tx: mpsc::Sender<SendReq>,
     worker: JoinHandle<()>,
+    retransmit: RetransmitPolicy,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct NetworkSender {
     tx: mpsc::Sender<SendReq>,
+    retransmit: RetransmitPolicy,
 }
 
 #[derive(Clone, Debug)]
@@ -36,6 +49,8 @@ pub struct SenderConfig {
     pub connect_timeout: Duration,
     /// Write timeout for sending bytes on a UDP socket.
     pub write_timeout: Duration,
+    /// Retransmission of the messages sent with `send_reliable`.
+    pub retransmit: RetransmitPolicy,
 }
 
 impl Default for SenderConfig {
@@ -44,12 +59,18 @@ impl Default for SenderConfig {
             queue_bound: 1024,
             connect_timeout: Duration::from_secs(3),
             write_timeout: Duration::from_secs(3),
+            retransmit: RetransmitPolicy::default(),
         }
     }
 }
 
 enum SendReq {
-    Data { addr: SocketAddr, bytes: Bytes },
+    Data {
+        /// Address to send from, the one of the node when `None`
+        from: Option<IpAddr>,
+        addr: SocketAddr,
+        bytes: Bytes,
+    },
     Shutdown,
 }
 
@@ -66,13 +87,19 @@ impl NetworkSenderCore {
         } else {
             mpsc::channel(config.queue_bound)
         };
+        let retransmit = config.retransmit.clone();
         let worker = tokio::spawn(run_worker(rx, config));
-        Self { tx, worker }
+        Self {
+            tx,
+            worker,
+            retransmit,
+        }
     }
 
     pub fn sender(&self) -> NetworkSender {
         NetworkSender {
             tx: self.tx.clone(),
+            retransmit: self.retransmit.clone(),
         }
     }
 
@@ -89,7 +116,12 @@ impl NetworkSenderCore {
 impl NetworkSender {
     /// Enqueue a send operation and await its result.
     pub async fn send(&self, addr: SocketAddr, bytes: Bytes) -> Result<()> {
-        let req = SendReq::Data { addr, bytes };
+        let bytes = frame_for_peer(addr, bytes).await;
+        self.enqueue(None, addr, bytes).await
+    }
+
+    async fn enqueue(&self, from: Option<IpAddr>, addr: SocketAddr, bytes: Bytes) -> Result<()> {
+        let req = SendReq::Data { from, addr, bytes };
         // If the channel is closed, report an error.
         if let Err(_e) = self.tx.send(req).await {
             return Err(std::io::Error::new(
@@ -101,18 +133,77 @@ impl NetworkSender {
         Ok(())
     }
 
-    /// Broadcast the same bytes to multiple addresses by enqueuing one sending per address.
+    /// Enqueue a send operation without waiting, failing when the queue is full.
+    /// For callers that cannot await, e.g. the listener acknowledging messages.
+    pub fn try_send(&self, addr: SocketAddr, bytes: Bytes) -> Result<()> {
+        self.tx
+            .try_send(SendReq::Data {
+                from: None,
+                addr,
+                bytes,
+            })
+            .map_err(|e| format!("Unable to enqueue message to {}: {}", addr, e).into())
+    }
+
+    /// Send the bytes so that the receiver acknowledges them, sending them again in the
+    /// background until it does or the attempts run out.
+    /// Returns once the first attempt is enqueued, the receiver has to support acknowledgements.
+    pub async fn send_reliable(&self, addr: SocketAddr, bytes: Bytes) -> Result<()> {
+        let msg_id = rand::rng().random::<u64>();
+        let reply_port = ENV_VAR
+            .get()
+            .map(|ev| ev.get_port())
+            .unwrap_or(UPD_MESSAGE_PORT);
+        let envelope = Bytes::from(ReliableMessage::new(msg_id, reply_port, bytes).serialize());
+
+        let mut acked = expect_ack(msg_id, addr.ip());
+        if let Err(e) = self.send(addr, envelope.clone()).await {
+            forget_ack(msg_id);
+            return Err(e);
+        }
+
+        let sender = self.clone();
+        tokio::spawn(async move {
+            let policy = &sender.retransmit;
+            for attempt in 0..policy.max_attempts {
+                if timeout(policy.backoff(attempt), &mut acked).await.is_ok() {
+                    return;
+                }
+                if attempt + 1 == policy.max_attempts {
+                    break;
+                }
+                LOGGER.debug(format!(
+                    "Message {:016x} to {} not acknowledged, sending it again",
+                    msg_id, addr
+                ));
+                if sender.send(addr, envelope.clone()).await.is_err() {
+                    break;
+                }
+            }
+            forget_ack(msg_id);
+            LOGGER.warn(format!(
+                "Message {:016x} to {} was never acknowledged, giving up",
+                msg_id, addr
+            ));
+        });
+        Ok(())
+    }
+
+    /// Broadcast the bytes from `from` by enqueuing one sending per discovery destination.
     /// This awaits each enqueue to apply backpressure. Stops and returns an error on the first failure.
-    pub async fn broadcast(&self, bytes: Bytes) -> Result<()> {
-        // Send the payload to 255.255.255.255:<port>. If ENV_VAR is not initialized (e.g., in tests),
-        // fall back to the conventional default port used by this project (14514).
+    pub async fn broadcast(&self, from: &AnnounceAddr, bytes: Bytes) -> Result<()> {
+        // Send the payload to the IPv4 discovery multicast group and to the directed broadcast
+        // address of the network of `from`, or to the IPv6 discovery group on its interface as
+        // IPv6 has no broadcast. Unknown peers get the length-prefixed framing anyway.
+        // If ENV_VAR is not initialized (e.g., in tests), fall back to the conventional default
+        // port used by this project (14514).
         let port = ENV_VAR
             .get()
             .map(|ev| ev.get_port())
             .unwrap_or(UPD_MESSAGE_PORT);
-        let broadcast_ip = IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255));
-        let addr = SocketAddr::new(broadcast_ip, port);
-        self.send(addr, bytes.clone()).await?;
+        for addr in discovery_addrs(from, port) {
+            self.enqueue(Some(from.ip), addr, bytes.clone()).await?;
+        }
         Ok(())
     }
 
@@ -128,61 +219,168 @@ impl NetworkSender {
     }
 }
 
-async fn bind_and_connect(addr: SocketAddr) -> std::io::Result<UdpSocket> {
-    // Determine local bind IP:
-    // - If destination is loopback, bind to the corresponding loopback IP to ensure local routing.
-    // - Else, prefer the IP from EnvVar (if available and same family); otherwise fall back to unspecified for that family.
-    let local_ip: IpAddr = if addr.ip().is_loopback() {
-        if addr.is_ipv4() {
-            IpAddr::V4(Ipv4Addr::LOCALHOST)
-        } else {
-            IpAddr::V6(Ipv6Addr::LOCALHOST)
+/// Frame the Data tokens of a message the legacy way for a peer known not to support the
+/// length-prefixed framing. Unknown peers and broadcasts get the length-prefixed framing, which
+/// every node accepts since it was introduced.
+async fn frame_for_peer(addr: SocketAddr, bytes: Bytes) -> Bytes {
+    let legacy = PEER_TABLE
+        .get_peer_by_addr(&addr)
+        .await
+        .is_some_and(|peer| !peer.supports(Capabilities::LENGTH_PREFIXED_DATA));
+    if !legacy {
+        return bytes;
+    }
+    match Token::reframe(&bytes, DataFraming::Legacy) {
+        Ok(reframed) => Bytes::from(reframed),
+        Err(e) => {
+            LOGGER.warn(format!(
+                "Unable to frame the message to {} the legacy way, sending it as is: {}",
+                addr, e
+            ));
+            bytes
+        }
+    }
+}
+
+/// Destinations of the hellos announcing `from`: the discovery group of its family on its
+/// interface, and for IPv4 the broadcast address of its network, or limited broadcast when it
+/// has none.
+fn discovery_addrs(from: &AnnounceAddr, port: u16) -> Vec<SocketAddr> {
+    match from.ip {
+        IpAddr::V4(_) => vec![
+            SocketAddr::new(IpAddr::V4(DISCOVERY_MULTICAST_V4), port),
+            SocketAddr::new(
+                IpAddr::V4(from.broadcast.unwrap_or(Ipv4Addr::BROADCAST)),
+                port,
+            ),
+        ],
+        IpAddr::V6(_) => vec![SocketAddr::V6(SocketAddrV6::new(
+            DISCOVERY_MULTICAST_V6,
+            port,
+            0,
+            from.if_index,
+        ))],
+    }
+}
+
+/// Local ports of the sockets this node sends from, with the number of sockets on each.
+/// The sockets use ephemeral ports, datagrams from these ports on this host are our own.
+static SENDING_PORTS: LazyLock<Mutex<HashMap<u16, usize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Whether this node sends from local `port`
+pub fn is_sending_port(port: u16) -> bool {
+    SENDING_PORTS.lock().unwrap().contains_key(&port)
+}
+
+/// A socket this node sends from, its port is registered as long as it is open
+#[derive(Debug)]
+struct SendingSocket {
+    socket: UdpSocket,
+    port: u16,
+}
+
+impl SendingSocket {
+    fn new(socket: UdpSocket) -> std::io::Result<Self> {
+        let port = socket.local_addr()?.port();
+        *SENDING_PORTS.lock().unwrap().entry(port).or_insert(0) += 1;
+        Ok(Self { socket, port })
+    }
+}
+
+impl Deref for SendingSocket {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+impl Drop for SendingSocket {
+    fn drop(&mut self) {
+        let mut ports = SENDING_PORTS.lock().unwrap();
+        if let Some(count) = ports.get_mut(&self.port) {
+            *count -= 1;
+            if *count == 0 {
+                ports.remove(&self.port);
+            }
         }
-    } else if let Some(ev) = ENV_VAR.get() {
-        let ip = ev.get_ip_addr();
-        match (ip, addr) {
-            (IpAddr::V4(ipv4), SocketAddr::V4(_)) => IpAddr::V4(ipv4),
-            (IpAddr::V6(ipv6), SocketAddr::V6(_)) => IpAddr::V6(ipv6),
-            // Family mismatch: use unspecified for the destination family
-            (_, SocketAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-            (_, SocketAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    }
+}
+
+async fn bind_and_connect(
+    from: Option<IpAddr>,
+    addr: SocketAddr,
+) -> std::io::Result<SendingSocket> {
+    // Determine local bind IP:
+    // - If an address to send from is given, bind to it.
+    // - If destination is loopback, bind to the loopback IP from EnvVar when the node is bound to one, so that
+    //   nodes sharing the host can tell each other apart, otherwise to the corresponding loopback IP.
+    // - Else, prefer the IP from EnvVar (if available, same family and not loopback); otherwise fall back to
+    //   unspecified for that family.
+    let env_ip = ENV_VAR
+        .get()
+        .map(|ev| ev.get_ip_addr())
+        .filter(|ip| ip.is_ipv4() == addr.is_ipv4() && ip.is_loopback() == addr.ip().is_loopback());
+    let local_ip: IpAddr = match from.or(env_ip) {
+        Some(ip) => ip,
+        None if addr.ip().is_loopback() => {
+            if addr.is_ipv4() {
+                IpAddr::V4(Ipv4Addr::LOCALHOST)
+            } else {
+                IpAddr::V6(Ipv6Addr::LOCALHOST)
+            }
         }
-    } else {
-        if addr.is_ipv4() {
-            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
-        } else {
-            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        None => {
+            if addr.is_ipv4() {
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+            } else {
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+            }
         }
     };
 
-    let local = SocketAddr::new(local_ip, 0);
+    // Link-local addresses can only be bound along with their interface
+    let scope_id = ENV_VAR.get().map(|ev| ev.get_if_index()).unwrap_or(0);
+    let local = socket_addr_in_scope(local_ip, 0, scope_id);
     let s = UdpSocket::bind(local).await?;
-    // Enable broadcast if destination is the limited broadcast address
+    // Directed broadcast addresses cannot be told apart from unicast ones by the address alone,
+    // so enable broadcast on every IPv4 socket
     if let SocketAddr::V4(v4) = addr {
-        if v4.ip().octets() == [255, 255, 255, 255] {
-            s.set_broadcast(true)?;
+        s.set_broadcast(true)?;
+        // Multicast leaves through the interface of the node address rather than the default route
+        if let IpAddr::V4(local_v4) = local_ip
+            && v4.ip().is_multicast()
+            && !local_v4.is_unspecified()
+        {
+            SockRef::from(&s).set_multicast_if_v4(&local_v4)?;
         }
     }
     s.connect(addr).await?;
-    Ok(s)
+    SendingSocket::new(s)
 }
 
 async fn run_worker(mut rx: mpsc::Receiver<SendReq>, cfg: SenderConfig) {
     // Cache connected UDP sockets per addr for reuse.
-    let mut conns: HashMap<SocketAddr, UdpSocket> = HashMap::new();
+    let mut conns: HashMap<(Option<IpAddr>, SocketAddr), SendingSocket> = HashMap::new();
 
     while let Some(req) = rx.recv().await {
         match req {
-            SendReq::Data { addr, bytes } => {
+            SendReq::Data { from, addr, bytes } => {
                 let _res = async {
                     // Get or create a connected UDP socket
-                    let sock = match conns.remove(&addr) {
+                    // Sockets bound to an address the host no longer has cannot send anymore,
+                    // the address of the others is given anew on every send
+                    let sock = match conns
+                        .remove(&(from, addr))
+                        .filter(|s| from.is_some() || !bound_to_stale_addr(s))
+                    {
                         Some(s) => s,
                         None => {
                             let s = if cfg.connect_timeout.is_zero() {
-                                bind_and_connect(addr).await?
+                                bind_and_connect(from, addr).await?
                             } else {
-                                timeout(cfg.connect_timeout, bind_and_connect(addr))
+                                timeout(cfg.connect_timeout, bind_and_connect(from, addr))
                                     .await
                                     .map_err(|_| {
                                         std::io::Error::new(
@@ -199,20 +397,20 @@ async fn run_worker(mut rx: mpsc::Receiver<SendReq>, cfg: SenderConfig) {
                     match send_with_timeout(sock, &bytes, cfg.write_timeout).await {
                         Ok(s) => {
                             // put back for reuse
-                            conns.insert(addr, s);
+                            conns.insert((from, addr), s);
                             Ok(())
                         }
                         Err(_e) => {
                             // attempt single re-bind/connect
                             let s = if cfg.connect_timeout.is_zero() {
-                                bind_and_connect(addr).await?
+                                bind_and_connect(from, addr).await?
                             } else {
-                                timeout(cfg.connect_timeout, bind_and_connect(addr))
+                                timeout(cfg.connect_timeout, bind_and_connect(from, addr))
                                     .await
                                     .map_err(|_| format!("reconnect timeout to {}", addr))??
                             };
                             let s = send_with_timeout(s, &bytes, cfg.write_timeout).await?;
-                            conns.insert(addr, s);
+                            conns.insert((from, addr), s);
                             Ok(())
                         }
                     }
@@ -229,7 +427,25 @@ async fn run_worker(mut rx: mpsc::Receiver<SendReq>, cfg: SenderConfig) {
     // Clean up: let UdpSockets drop here.
 }
 
-async fn send_with_timeout(sock: UdpSocket, bytes: &Bytes, to: Duration) -> Result<UdpSocket> {
+/// Whether `sock` was bound to the node address before it changed
+fn bound_to_stale_addr(sock: &SendingSocket) -> bool {
+    let Some(ev) = ENV_VAR.get() else {
+        return false;
+    };
+    match sock.local_addr() {
+        Ok(local) => {
+            let ip = local.ip();
+            !ip.is_unspecified() && !ip.is_loopback() && ip != ev.get_ip_addr()
+        }
+        Err(_) => true,
+    }
+}
+
+async fn send_with_timeout(
+    sock: SendingSocket,
+    bytes: &Bytes,
+    to: Duration,
+) -> Result<SendingSocket> {
     timeout(to, async {
         let _ = sock.send(bytes).await?;
         Ok::<_, crate::err::Error>(())
@@ -246,9 +462,9 @@ async fn send_once(
     write_timeout: Duration,
 ) -> Result<()> {
     let sock = if connect_timeout.is_zero() {
-        bind_and_connect(addr).await?
+        bind_and_connect(None, addr).await?
     } else {
-        timeout(connect_timeout, bind_and_connect(addr))
+        timeout(connect_timeout, bind_and_connect(None, addr))
             .await
             .map_err(|_| {
                 std::io::Error::new(
@@ -321,6 +537,18 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn sending_ports_are_registered_while_the_socket_is_open() -> Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let sock = bind_and_connect(None, server.local_addr()?).await?;
+        let port = sock.local_addr()?.port();
+        assert!(is_sending_port(port));
+
+        drop(sock);
+        assert!(!is_sending_port(port));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_broadcast_multiple_receivers() -> Result<()> {
         // Prepare two fake IPv4 SocketAddrs with different ports to exercise the API.
@@ -333,8 +561,54 @@ mod tests {
 
         let sender = NetworkSenderCore::new_queue_worker(SenderConfig::default()).sender();
         let payload = Bytes::from_static(b"bcast");
-        // This should send to 255.255.255.255 without error.
-        sender.broadcast(payload.clone()).await?;
+        // This should send to the discovery group and the local broadcast addresses without error.
+        let from = AnnounceAddr {
+            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            if_index: 0,
+            broadcast: None,
+        };
+        sender.broadcast(&from, payload.clone()).await?;
         Ok(())
     }
+
+    #[test]
+    fn ipv4_addresses_announce_on_the_multicast_group_and_their_network() {
+        let from = AnnounceAddr {
+            ip: IpAddr::V4(Ipv4Addr::new(172, 17, 0, 1)),
+            if_index: 3,
+            broadcast: Some(Ipv4Addr::new(172, 17, 255, 255)),
+        };
+        assert_eq!(
+            discovery_addrs(&from, 14514),
+            [
+                SocketAddr::new(IpAddr::V4(DISCOVERY_MULTICAST_V4), 14514),
+                "172.17.255.255:14514".parse().unwrap(),
+            ]
+        );
+
+        // Networks without a broadcast address fall back to limited broadcast
+        let from = AnnounceAddr {
+            broadcast: None,
+            ..from
+        };
+        assert_eq!(
+            discovery_addrs(&from, 14514)[1],
+            "255.255.255.255:14514".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn ipv6_addresses_announce_on_the_multicast_group_of_their_interface() {
+        let from = AnnounceAddr {
+            ip: "fe80::1".parse().unwrap(),
+            if_index: 2,
+            broadcast: None,
+        };
+        let addrs = discovery_addrs(&from, 14514);
+        let [SocketAddr::V6(v6)] = addrs[..] else {
+            panic!("expected a single IPv6 destination");
+        };
+        assert_eq!(*v6.ip(), DISCOVERY_MULTICAST_V6);
+        assert_eq!(v6.scope_id(), 2);
+    }
 }
@@ -3,11 +3,97 @@
 //! Provides a cross-platform helper to discover a private (RFC1918) IPv4 address
 //! of the current machine. If multiple interfaces exist, any one private IPv4 may
 //! be returned. If no private IPv4 address can be inferred, `None` is returned.
+//! Hosts without one fall back to an IPv6 unique local or link-local address.
 //!
 //! This implementation avoids external dependencies and should work on Linux,
 //! macOS, and Windows.
 
-use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+
+/// Address this node is reachable at on the local network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalAddr {
+    pub ip: IpAddr,
+    pub mac: [u8; 6],
+    /// Index of the interface holding `ip`, scopes link-local addresses and multicast
+    pub if_index: u32,
+}
+
+/// Return the address this node should announce: a private IPv4 when there is one, otherwise
+/// an IPv6 unique local address, otherwise an IPv6 link-local address.
+pub fn get_private_ip_with_mac() -> Option<LocalAddr> {
+    if let Some((v4, mac)) = get_private_ipv4_with_mac() {
+        let if_index = pnet_datalink::interfaces()
+            .iter()
+            .find(|iface| iface.ips.iter().any(|ipnet| ipnet.ip() == IpAddr::V4(v4)))
+            .map(|iface| iface.index)
+            .unwrap_or(0);
+        return Some(LocalAddr {
+            ip: IpAddr::V4(v4),
+            mac,
+            if_index,
+        });
+    }
+    get_private_ipv6_with_mac()
+}
+
+/// Return an IPv6 unique local address, or a link-local one if there is none, of an interface
+/// that has a MAC address.
+pub fn get_private_ipv6_with_mac() -> Option<LocalAddr> {
+    let ifaces = pnet_datalink::interfaces();
+    let candidates = || {
+        ifaces
+            .iter()
+            .filter(|iface| iface.is_up())
+            .filter_map(|iface| {
+                let mac = iface.mac?.octets();
+                Some(iface.ips.iter().filter_map(move |ipnet| match ipnet.ip() {
+                    IpAddr::V6(v6) => Some(LocalAddr {
+                        ip: IpAddr::V6(v6),
+                        mac,
+                        if_index: iface.index,
+                    }),
+                    IpAddr::V4(_) => None,
+                }))
+            })
+    };
+    let find = |kind: AddrKind| {
+        candidates()
+            .flatten()
+            .find(|addr| classify_ip(&addr.ip) == kind)
+    };
+    find(AddrKind::UniqueLocalV6).or_else(|| find(AddrKind::LinkLocalV6))
+}
+
+/// Return the address of the interface named `name`, preferring a private IPv4, then an IPv6
+/// unique local, then an IPv6 link-local address, then any other one such as a loopback address.
+pub fn get_interface_addr(name: &str) -> Option<LocalAddr> {
+    let iface = pnet_datalink::interfaces()
+        .into_iter()
+        .find(|iface| iface.name == name)?;
+    let ip = iface
+        .ips
+        .iter()
+        .map(|ipnet| ipnet.ip())
+        .min_by_key(classify_ip)?;
+    Some(LocalAddr {
+        ip,
+        mac: iface.mac.map(|mac| mac.octets()).unwrap_or_default(),
+        if_index: iface.index,
+    })
+}
+
+/// Return the interface holding `ip`, with an all-zero MAC for interfaces without one
+pub fn get_local_addr(ip: IpAddr) -> Option<LocalAddr> {
+    pnet_datalink::interfaces()
+        .into_iter()
+        .find(|iface| iface.ips.iter().any(|ipnet| ipnet.ip() == ip))
+        .map(|iface| LocalAddr {
+            ip,
+            mac: iface.mac.map(|mac| mac.octets()).unwrap_or_default(),
+            if_index: iface.index,
+        })
+}
 
 /// Return a private (RFC1918) IPv4 address and its MAC address if both can be inferred.
 ///
@@ -134,6 +220,133 @@ pub fn get_private_ipv4() -> Option<Ipv4Addr> {
     None
 }
 
+/// An address hellos are announced from. Each carries its own address and leaves through its own
+/// interface, so that the peers of every network the host is on learn an address they can reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnnounceAddr {
+    pub ip: IpAddr,
+    pub if_index: u32,
+    /// Directed broadcast address of the IPv4 network of `ip`, if it has one
+    pub broadcast: Option<Ipv4Addr>,
+}
+
+/// Addresses of an interface along with the prefix length of their network
+#[derive(Debug, Clone)]
+pub struct InterfaceAddrs {
+    pub if_index: u32,
+    pub ips: Vec<(IpAddr, u8)>,
+}
+
+/// Return the addresses to announce the node at `node` from, see [`announce_addrs`].
+/// Only the interfaces that are up are considered, loopback excluded.
+///
+/// Limited broadcast to 255.255.255.255 and multicast only leave through one interface, so hosts
+/// with several interfaces have to address each network on its own.
+pub fn get_announce_addrs(node: &LocalAddr, bound: bool) -> Vec<AnnounceAddr> {
+    let ifaces: Vec<InterfaceAddrs> = pnet_datalink::interfaces()
+        .into_iter()
+        .filter(|iface| iface.is_up() && !iface.is_loopback())
+        .map(|iface| InterfaceAddrs {
+            if_index: iface.index,
+            ips: iface
+                .ips
+                .iter()
+                .map(|ipnet| (ipnet.ip(), ipnet.prefix()))
+                .collect(),
+        })
+        .collect();
+    announce_addrs(node, bound, &ifaces)
+}
+
+/// The node address first then, unless the node is `bound` to it, the most private address of
+/// each family on every interface of `ifaces`. Link-local IPv6 addresses are only announced on
+/// the interface of the node, the one peers scope them to.
+pub fn announce_addrs(
+    node: &LocalAddr,
+    bound: bool,
+    ifaces: &[InterfaceAddrs],
+) -> Vec<AnnounceAddr> {
+    let broadcast_of = |ip: IpAddr| match ip {
+        IpAddr::V4(v4) => ifaces
+            .iter()
+            .flat_map(|iface| iface.ips.iter())
+            .find(|(addr, _)| *addr == ip)
+            .and_then(|(_, prefix)| directed_broadcast(v4, *prefix)),
+        IpAddr::V6(_) => None,
+    };
+    let mut addrs = vec![AnnounceAddr {
+        ip: node.ip,
+        if_index: node.if_index,
+        broadcast: broadcast_of(node.ip),
+    }];
+    if bound {
+        return addrs;
+    }
+    for iface in ifaces {
+        for v4 in [true, false] {
+            let announced =
+                |addr: &AnnounceAddr| addr.if_index == iface.if_index && addr.ip.is_ipv4() == v4;
+            if addrs.iter().any(announced) {
+                continue;
+            }
+            let best = iface
+                .ips
+                .iter()
+                .map(|(ip, _)| *ip)
+                .filter(|ip| ip.is_ipv4() == v4)
+                .filter(|ip| match classify_ip(ip) {
+                    AddrKind::PrivateV4 | AddrKind::UniqueLocalV6 => true,
+                    AddrKind::LinkLocalV6 => iface.if_index == node.if_index,
+                    AddrKind::Other => false,
+                })
+                .min_by_key(classify_ip);
+            if let Some(ip) = best {
+                addrs.push(AnnounceAddr {
+                    ip,
+                    if_index: iface.if_index,
+                    broadcast: broadcast_of(ip),
+                });
+            }
+        }
+    }
+    addrs
+}
+
+/// Broadcast address of the network `ip/prefix`, `None` for /31 and /32 networks which have none
+pub fn directed_broadcast(ip: Ipv4Addr, prefix: u8) -> Option<Ipv4Addr> {
+    if prefix >= 31 {
+        return None;
+    }
+    let host_mask = u32::MAX >> prefix;
+    Some(Ipv4Addr::from(u32::from(ip) | host_mask))
+}
+
+/// Kinds of addresses a node may announce, from the most to the least preferred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AddrKind {
+    /// RFC1918 IPv4, also when mapped to IPv6
+    PrivateV4,
+    /// IPv6 unique local (fc00::/7)
+    UniqueLocalV6,
+    /// IPv6 unicast link-local (fe80::/10)
+    LinkLocalV6,
+    /// Anything else, such as loopback, global or multicast addresses
+    Other,
+}
+
+pub fn classify_ip(ip: &IpAddr) -> AddrKind {
+    match ip {
+        IpAddr::V4(v4) if is_private_ipv4(v4) => AddrKind::PrivateV4,
+        IpAddr::V4(_) => AddrKind::Other,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => classify_ip(&IpAddr::V4(v4)),
+            None if is_unique_local_ipv6(v6) => AddrKind::UniqueLocalV6,
+            None if is_link_local_ipv6(v6) => AddrKind::LinkLocalV6,
+            None => AddrKind::Other,
+        },
+    }
+}
+
 /// Check if an IPv4 address is within the RFC1918 private ranges.
 /// - 10.0.0.0/8
 /// - 172.16.0.0/12
@@ -149,6 +362,41 @@ pub fn is_private_ipv4(ip: &Ipv4Addr) -> bool {
     }
 }
 
+/// Check if an IPv6 address is a unique local address (fc00::/7)
+#[inline]
+pub fn is_unique_local_ipv6(ip: &Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xfe00) == 0xfc00
+}
+
+/// Check if an IPv6 address is a unicast link-local address (fe80::/10)
+#[inline]
+pub fn is_link_local_ipv6(ip: &Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xffc0) == 0xfe80
+}
+
+/// Socket address of `ip:port`, scoped to the interface `scope_id` when `ip` is IPv6
+/// link-local, as those addresses are only meaningful on one link.
+pub fn socket_addr_in_scope(ip: IpAddr, port: u16, scope_id: u32) -> SocketAddr {
+    match ip {
+        IpAddr::V6(v6) if is_link_local_ipv6(&v6) => {
+            SocketAddr::V6(SocketAddrV6::new(v6, port, 0, scope_id))
+        }
+        _ => SocketAddr::new(ip, port),
+    }
+}
+
+/// Turn the IPv4-mapped addresses reported by dual-stack sockets back into IPv4 ones, so that
+/// peers are known by the same address whichever socket they reached.
+pub fn unmap_socket_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(IpAddr::V4(v4), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -175,6 +423,171 @@ mod tests {
         assert!(!is_private_ipv4(&Ipv4Addr::new(8, 8, 8, 8))); // public
     }
 
+    #[test]
+    fn classify_private_ipv6_ranges() {
+        assert!(is_unique_local_ipv6(&"fd12:3456:789a::1".parse().unwrap()));
+        assert!(is_unique_local_ipv6(&"fc00::1".parse().unwrap()));
+        assert!(!is_unique_local_ipv6(&"fe80::1".parse().unwrap()));
+
+        assert!(is_link_local_ipv6(
+            &"fe80::1c2a:3bff:fe4d:5e6f".parse().unwrap()
+        ));
+        assert!(is_link_local_ipv6(&"febf::1".parse().unwrap()));
+        assert!(!is_link_local_ipv6(&"fec0::1".parse().unwrap()));
+
+        let kind = |ip: &str| classify_ip(&ip.parse().unwrap());
+        assert_eq!(kind("fd00::2"), AddrKind::UniqueLocalV6);
+        assert_eq!(kind("fe80::1"), AddrKind::LinkLocalV6);
+        assert_eq!(kind("::ffff:192.168.1.10"), AddrKind::PrivateV4);
+        assert_eq!(kind("::ffff:8.8.8.8"), AddrKind::Other);
+        assert_eq!(kind("::1"), AddrKind::Other); // loopback
+        assert_eq!(kind("2001:db8::1"), AddrKind::Other); // global
+        assert_eq!(kind("ff02::1"), AddrKind::Other); // multicast
+        // Interfaces announce their most private address
+        assert!(AddrKind::PrivateV4 < AddrKind::UniqueLocalV6);
+        assert!(AddrKind::UniqueLocalV6 < AddrKind::LinkLocalV6);
+    }
+
+    #[test]
+    fn directed_broadcast_of_networks() {
+        assert_eq!(
+            directed_broadcast(Ipv4Addr::new(192, 168, 1, 10), 24),
+            Some(Ipv4Addr::new(192, 168, 1, 255))
+        );
+        assert_eq!(
+            directed_broadcast(Ipv4Addr::new(10, 1, 2, 3), 8),
+            Some(Ipv4Addr::new(10, 255, 255, 255))
+        );
+        assert_eq!(
+            directed_broadcast(Ipv4Addr::new(172, 17, 0, 1), 16),
+            Some(Ipv4Addr::new(172, 17, 255, 255))
+        );
+        assert_eq!(
+            directed_broadcast(Ipv4Addr::new(10, 0, 0, 1), 0),
+            Some(Ipv4Addr::BROADCAST)
+        );
+        assert_eq!(directed_broadcast(Ipv4Addr::new(10, 0, 0, 1), 31), None);
+        assert_eq!(directed_broadcast(Ipv4Addr::new(10, 0, 0, 1), 32), None);
+    }
+
+    #[test]
+    fn every_interface_is_announced_on_with_its_own_address() {
+        let node = LocalAddr {
+            ip: "192.168.1.5".parse().unwrap(),
+            mac: [0; 6],
+            if_index: 2,
+        };
+        let ifaces = [
+            InterfaceAddrs {
+                if_index: 2,
+                ips: vec![
+                    ("192.168.1.5".parse().unwrap(), 24),
+                    ("fe80::1".parse().unwrap(), 64),
+                ],
+            },
+            InterfaceAddrs {
+                if_index: 3,
+                ips: vec![
+                    ("203.0.113.7".parse().unwrap(), 24),
+                    ("172.17.0.1".parse().unwrap(), 16),
+                    ("fe80::2".parse().unwrap(), 64),
+                ],
+            },
+            InterfaceAddrs {
+                if_index: 4,
+                ips: vec![("fd00::4".parse().unwrap(), 64)],
+            },
+        ];
+        let addrs = announce_addrs(&node, false, &ifaces);
+        assert_eq!(
+            addrs,
+            [
+                AnnounceAddr {
+                    ip: node.ip,
+                    if_index: 2,
+                    broadcast: Some(Ipv4Addr::new(192, 168, 1, 255)),
+                },
+                AnnounceAddr {
+                    ip: "fe80::1".parse().unwrap(),
+                    if_index: 2,
+                    broadcast: None,
+                },
+                AnnounceAddr {
+                    ip: "172.17.0.1".parse().unwrap(),
+                    if_index: 3,
+                    broadcast: Some(Ipv4Addr::new(172, 17, 255, 255)),
+                },
+                AnnounceAddr {
+                    ip: "fd00::4".parse().unwrap(),
+                    if_index: 4,
+                    broadcast: None,
+                },
+            ]
+        );
+
+        // A node bound to its address only has its listener there
+        assert_eq!(announce_addrs(&node, true, &ifaces), addrs[..1]);
+    }
+
+    #[test]
+    fn only_link_local_addresses_are_scoped() {
+        let link_local: IpAddr = "fe80::1".parse().unwrap();
+        let SocketAddr::V6(scoped) = socket_addr_in_scope(link_local, 14514, 3) else {
+            panic!("expected an IPv6 socket address");
+        };
+        assert_eq!(scoped.scope_id(), 3);
+        assert_eq!(scoped.port(), 14514);
+
+        let SocketAddr::V6(ula) = socket_addr_in_scope("fd00::2".parse().unwrap(), 14514, 3) else {
+            panic!("expected an IPv6 socket address");
+        };
+        assert_eq!(ula.scope_id(), 0);
+        assert_eq!(
+            socket_addr_in_scope(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 14514, 3),
+            "10.0.0.1:14514".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn mapped_addresses_are_unmapped() {
+        let mapped: SocketAddr = "[::ffff:192.168.1.10]:14514".parse().unwrap();
+        assert_eq!(
+            unmap_socket_addr(mapped),
+            "192.168.1.10:14514".parse().unwrap()
+        );
+        let v6: SocketAddr = "[fd00::2]:14514".parse().unwrap();
+        assert_eq!(unmap_socket_addr(v6), v6);
+    }
+
+    #[test]
+    fn loopback_addresses_are_found_on_their_interface() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let Some(addr) = get_local_addr(localhost) else {
+            // No loopback interface listed in this environment
+            return;
+        };
+        assert_eq!(addr.ip, localhost);
+        assert_ne!(addr.if_index, 0);
+
+        let iface = pnet_datalink::interfaces()
+            .into_iter()
+            .find(|iface| iface.index == addr.if_index)
+            .unwrap();
+        assert!(get_interface_addr(&iface.name).is_some());
+        assert!(get_interface_addr("no-such-interface").is_none());
+    }
+
+    #[test]
+    fn get_private_ip_with_mac_optional_and_valid() {
+        if let Some(addr) = get_private_ip_with_mac() {
+            assert!(
+                classify_ip(&addr.ip) != AddrKind::Other,
+                "Returned IP must be private: {}",
+                addr.ip
+            );
+        }
+    }
+
     #[test]
     fn get_private_ipv4_is_optional_and_valid() {
         // This environment-agnostic test only verifies that if an address is returned,
@@ -0,0 +1,193 @@
+//! Local API secret
+//!
+//! The local API only answers requests tagged with a secret stored in `.disc/api_secret`, which
+//! is created on first run and readable by its owner only. Being able to read it is what tells
+//! the user running the node apart from other users of the same machine, who can reach the
+//! loopback address as well.
+//!
+//! Tagged requests carry their timestamp, a request is only answered within the replay window
+//! after it was made, and only once.
+
+use crate::err::Result;
+use crate::utilities::clock::within_window;
+use api_model::protocol::api_secret::{format_api_secret, parse_api_secret};
+use api_model::protocol::message::api_request_message::ApiRequestMessage;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+pub use api_model::protocol::api_secret::API_SECRET_LEN;
+
+/// How long after it was made a request is answered, in the past or in the future as the client
+/// shares the clock of the server
+const API_REQUEST_WINDOW: Duration = Duration::from_secs(30);
+
+static API_SECRET: OnceLock<ApiSecret> = OnceLock::new();
+
+/// Tags of the requests answered within the replay window, with when they were
+static ANSWERED_TAGS: LazyLock<Mutex<HashMap<Bytes, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub struct ApiSecret {
+    secret: [u8; API_SECRET_LEN],
+}
+
+impl ApiSecret {
+    pub fn generate() -> Self {
+        Self {
+            secret: rand::random::<[u8; API_SECRET_LEN]>(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Load the secret from `path`, or generate and store a new one if it does not exist.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let secret = Self::generate();
+        secret.store(path)?;
+        Ok(secret)
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        // Whoever loosened the permissions, the secret is only worth something to its owner
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+        let content = fs::read_to_string(path)?;
+        let secret = parse_api_secret(&content).ok_or_else(|| {
+            format!(
+                "{} is not an API secret, remove it to generate a new one",
+                path.display()
+            )
+        })?;
+        Ok(Self { secret })
+    }
+
+    fn store(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = format_api_secret(&self.secret);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        std::io::Write::write_all(&mut options.open(path)?, content.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Load or create the API secret, must be called once before the API is served
+pub fn init_api_secret(path: &str) -> Result<()> {
+    let secret = ApiSecret::load_or_generate(Path::new(path))?;
+    API_SECRET
+        .set(secret)
+        .map_err(|_| "API secret already initialized")?;
+    Ok(())
+}
+
+pub fn get_api_secret() -> Result<&'static ApiSecret> {
+    API_SECRET
+        .get()
+        .ok_or_else(|| "API secret not initialized".into())
+}
+
+/// Check the request is tagged with the API secret, was made within the replay window and was
+/// not answered already
+pub fn check_api_request(message: &ApiRequestMessage) -> Result<()> {
+    message.verify(get_api_secret()?.as_bytes())?;
+    if !within_window(message.sent_at(), 0, API_REQUEST_WINDOW) {
+        return Err("request is outside the replay window, check the clock of the client".into());
+    }
+    let tag = message.auth_tag.clone().unwrap_or_default();
+    let now = Instant::now();
+    let mut answered = ANSWERED_TAGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // A request stamped up to a window ahead stays valid for two, its tag is refused by the
+    // window once forgotten
+    answered.retain(|_, at| now.duration_since(*at) < API_REQUEST_WINDOW * 2);
+    if answered.insert(tag, now).is_some() {
+        return Err("request was replayed".into());
+    }
+    Ok(())
+}
+
+/// Use a throwaway secret in tests that serve API requests
+#[cfg(test)]
+pub(crate) fn ensure_test_api_secret() -> &'static ApiSecret {
+    API_SECRET.get_or_init(ApiSecret::generate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::temp_dir::tmp_dir;
+
+    #[test]
+    fn secret_is_generated_once_for_its_owner_only() {
+        let dir = tmp_dir("api_secret");
+        let path = dir.join(".disc").join("api_secret");
+
+        let generated = ApiSecret::load_or_generate(&path).unwrap();
+        let loaded = ApiSecret::load_or_generate(&path).unwrap();
+        assert_eq!(generated.as_bytes(), loaded.as_bytes());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+
+            // Loosened permissions are tightened again
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+            ApiSecret::load_or_generate(&path).unwrap();
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn requests_are_answered_once_within_the_replay_window() {
+        use api_model::protocol::message::api_request_message::ApiRequestKind;
+
+        let secret = ensure_test_api_secret();
+        let request = |timestamp_ms: u64| {
+            let mut request =
+                ApiRequestMessage::new(rand::random(), String::new(), 0, ApiRequestKind::Info);
+            request.timestamp_ms = timestamp_ms;
+            request.authenticated(secret.as_bytes()).unwrap()
+        };
+        let now_ms = ApiRequestMessage::new(0, String::new(), 0, ApiRequestKind::Info).timestamp_ms;
+
+        let fresh = request(now_ms);
+        assert!(check_api_request(&fresh).is_ok());
+        assert!(check_api_request(&fresh).is_err());
+
+        let window_ms = API_REQUEST_WINDOW.as_millis() as u64;
+        assert!(check_api_request(&request(now_ms - 2 * window_ms)).is_err());
+        assert!(check_api_request(&request(now_ms + 2 * window_ms)).is_err());
+        assert!(check_api_request(&request(now_ms - window_ms / 2)).is_ok());
+    }
+
+    #[test]
+    fn garbage_is_not_taken_for_a_secret() {
+        let dir = tmp_dir("api_secret");
+        let path = dir.join("api_secret");
+        fs::write(&path, "not a secret").unwrap();
+        assert!(ApiSecret::load_or_generate(&path).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+//! Clock offsets between nodes
+//!
+//! Messages are stamped with the clock of their sender. The offset of each peer clock is
+//! estimated from the timestamp of its hellos and taken into account when checking how old a
+//! message is, so that nodes whose clocks disagree can still exchange files.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Offset in ms of the clock that showed `sent_ms` when we received it at `received_ms`,
+/// positive when that clock is ahead of ours.
+/// The transit time is neglected, it stays far below the validity windows on a LAN.
+pub fn estimate_offset_ms(sent_ms: u64, received_ms: u64) -> i64 {
+    (sent_ms as i128 - received_ms as i128).clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+/// Local time at which a clock `offset_ms` away from ours showed `remote`
+pub fn to_local_time(remote: SystemTime, offset_ms: i64) -> SystemTime {
+    let shift = Duration::from_millis(offset_ms.unsigned_abs());
+    if offset_ms >= 0 {
+        remote.checked_sub(shift).unwrap_or(UNIX_EPOCH)
+    } else {
+        remote + shift
+    }
+}
+
+/// True if `sent_at`, read on a clock `offset_ms` away from ours, is less than `window` away
+/// from now, in the past or in the future.
+pub fn within_window(sent_at: SystemTime, offset_ms: i64, window: Duration) -> bool {
+    let sent_at = to_local_time(sent_at, offset_ms);
+    let diff = SystemTime::now()
+        .duration_since(sent_at)
+        .unwrap_or_else(|e| e.duration());
+    diff < window
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_is_positive_when_the_peer_clock_is_ahead() {
+        assert_eq!(estimate_offset_ms(15_000, 0), 15_000);
+        assert_eq!(estimate_offset_ms(1_000, 16_000), -15_000);
+    }
+
+    #[test]
+    fn skewed_timestamps_are_valid_once_corrected() {
+        let window = Duration::from_secs(10);
+        let ahead = SystemTime::now() + Duration::from_secs(15);
+        let behind = SystemTime::now() - Duration::from_secs(15);
+
+        assert!(!within_window(ahead, 0, window));
+        assert!(!within_window(behind, 0, window));
+        assert!(within_window(ahead, 15_000, window));
+        assert!(within_window(behind, -15_000, window));
+        // A message sent too long ago is still too old on the peer clock
+        let old = ahead - Duration::from_secs(20);
+        assert!(!within_window(old, 15_000, window));
+    }
+}
@@ -1,114 +1,275 @@
 use crate::err::Result;
 use crate::fs::fs_lock;
-use crate::global_var::{ENV_VAR, LOGGER};
-use crate::utilities::crypto;
-use aes::Aes256;
+use crate::global_var::ENV_VAR;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
 use bincode::config;
 use bytes::Bytes;
-use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, block_padding::Pkcs7};
-use cbc::{Decryptor, Encryptor};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use sha2::{Digest, Sha256};
+use sha2::Sha256;
 use std::fs::{self, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::Path;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// AES-256-GCM nonce size in bytes
+pub const NONCE_LEN: usize = 12;
+
+/// Salt shared by every node of a group, the connection token alone tells groups apart
+const GROUP_SALT: &[u8] = b"lumo-group-key-v1";
+
+/// What a subkey of the group key is used for, a subkey never serves two purposes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPurpose {
+    /// Encryption of control messages
+    Control,
+    /// Authentication tags of hello messages
+    ControlTag,
+    /// Encryption of the file transfer handshake
+    FileTransfer,
+}
 
-type Aes256Cbc = Encryptor<Aes256>;
-type Aes256CbcDec = Decryptor<Aes256>;
+impl KeyPurpose {
+    const ALL: [KeyPurpose; 3] = [
+        KeyPurpose::Control,
+        KeyPurpose::ControlTag,
+        KeyPurpose::FileTransfer,
+    ];
+
+    fn info(self) -> &'static [u8] {
+        match self {
+            KeyPurpose::Control => b"lumo control",
+            KeyPurpose::ControlTag => b"lumo control tag",
+            KeyPurpose::FileTransfer => b"lumo file transfer",
+        }
+    }
+}
 
-static KEY: LazyLock<[u8; 32]> = LazyLock::new(|| get_key());
+/// Subkeys derived from one connection token
+#[derive(Clone, PartialEq, Eq)]
+struct GroupKeys {
+    subkeys: [[u8; 32]; KeyPurpose::ALL.len()],
+}
 
-fn get_key() -> [u8; 32] {
-    let seed = ENV_VAR.get().unwrap().get_conn_token();
-    let mut hasher = Sha256::new();
-    hasher.update(seed.as_bytes());
-    hasher.finalize().into()
+impl GroupKeys {
+    /// Stretch the token with Argon2id, then expand one subkey per purpose with HKDF-SHA256.
+    fn derive(conn_token: &str) -> Result<Self> {
+        let params = Params::new(19 * 1024, 2, 1, Some(32))
+            .map_err(|e| format!("Invalid key derivation parameters: {}", e))?;
+        let mut group_key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(conn_token.as_bytes(), GROUP_SALT, &mut group_key)
+            .map_err(|e| format!("Failed to derive the group key: {}", e))?;
+
+        let hkdf = Hkdf::<Sha256>::new(Some(GROUP_SALT), &group_key);
+        let mut subkeys = [[0u8; 32]; KeyPurpose::ALL.len()];
+        for purpose in KeyPurpose::ALL {
+            hkdf.expand(purpose.info(), &mut subkeys[purpose as usize])
+                .map_err(|e| format!("Failed to derive the {:?} key: {}", purpose, e))?;
+        }
+        Ok(Self { subkeys })
+    }
+
+    fn get(&self, purpose: KeyPurpose) -> &[u8; 32] {
+        &self.subkeys[purpose as usize]
+    }
 }
 
-#[inline]
-pub fn encrypt(data: Bytes, iv: &[u8]) -> Result<Bytes> {
-    if iv.len() != 16 {
-        return Err("IV must be 16 bytes for AES-256-CBC".into());
+/// Keys of the current token, and of the previous one while a rotation overlap lasts
+#[derive(Clone)]
+struct GroupKeyring {
+    current: GroupKeys,
+    previous: Option<(GroupKeys, SystemTime)>,
+}
+
+impl GroupKeyring {
+    fn new(keys: GroupKeys) -> Self {
+        Self {
+            current: keys,
+            previous: None,
+        }
+    }
+
+    /// Switch to `keys`, still accepting the replaced ones for `overlap`.
+    /// Returns the time the replaced keys stop being accepted.
+    fn rotate(&mut self, keys: GroupKeys, overlap: Duration) -> Result<SystemTime> {
+        if keys == self.current {
+            return Err("The new connection token is the current one".into());
+        }
+        let until = SystemTime::now()
+            .checked_add(overlap)
+            .ok_or("The overlap is too long")?;
+        let replaced = std::mem::replace(&mut self.current, keys);
+        self.previous = (!overlap.is_zero()).then_some((replaced, until));
+        Ok(until)
     }
-    let key: &[u8; 32] = &*KEY;
 
-    // Prepare buffer with extra capacity for PKCS7 padding (max one block)
-    let mut buf = data.to_vec();
-    let msg_len = buf.len();
-    let block_size = 16; // AES block size in bytes
-    buf.resize(msg_len + block_size, 0u8);
+    /// Keys incoming messages may be protected with, the current ones first
+    fn accepted(&self) -> impl Iterator<Item = &GroupKeys> {
+        let previous = self
+            .previous
+            .as_ref()
+            .filter(|(_, until)| SystemTime::now() < *until)
+            .map(|(keys, _)| keys);
+        std::iter::once(&self.current).chain(previous)
+    }
+}
+
+/// Serializes token rotations, which write the config file outside the keyring lock
+static ROTATION_LOCK: Mutex<()> = Mutex::new(());
 
-    let cipher = Aes256Cbc::new_from_slices(key, iv)?;
+static KEYRING: LazyLock<RwLock<GroupKeyring>> = LazyLock::new(|| {
+    let keys = GroupKeys::derive(&ENV_VAR.get().unwrap().get_conn_token())
+        .expect("Failed to derive the group keys");
+    RwLock::new(GroupKeyring::new(keys))
+});
+
+fn with_keyring<T>(f: impl FnOnce(&GroupKeyring) -> Result<T>) -> Result<T> {
+    let keyring = KEYRING.read().map_err(|_| "Group keyring poisoned")?;
+    f(&keyring)
+}
 
+/// Replace the connection token of the group. Messages protected with the previous token are
+/// still accepted for `overlap`, giving the other nodes time to rotate as well.
+/// The new token is saved to the config file, so that a restart keeps it.
+/// Returns the time the previous token stops being accepted.
+pub fn rotate_conn_token(new_token: &str, overlap: Duration) -> Result<SystemTime> {
+    // Derive before taking the lock, Argon2 is slow on purpose
+    let keys = GroupKeys::derive(new_token)?;
+    // One rotation at a time, the keyring and the config file change together
+    let _rotating = ROTATION_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let (replaced, until) = {
+        let mut keyring = KEYRING.write().map_err(|_| "Group keyring poisoned")?;
+        let replaced = keyring.clone();
+        let until = keyring.rotate(keys, overlap)?;
+        (replaced, until)
+    };
+    // Messages keep being checked while the file is written
+    if let Err(e) = ENV_VAR.get().unwrap().update_conn_token(new_token) {
+        // Keep using the token the config file holds
+        *KEYRING.write().map_err(|_| "Group keyring poisoned")? = replaced;
+        return Err(format!("Failed to save the new connection token: {}", e).into());
+    }
+    Ok(until)
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hmac(key: &[u8; 32], data: &[u8]) -> Result<HmacSha256> {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key)?;
+    mac.update(data);
+    Ok(mac)
+}
+
+/// HMAC-SHA256 tag of `data`, proving the sender knows the connection token.
+pub fn compute_tag(data: &[u8]) -> Result<Bytes> {
+    with_keyring(|keyring| {
+        let mac = hmac(keyring.current.get(KeyPurpose::ControlTag), data)?;
+        Ok(Bytes::copy_from_slice(&mac.finalize().into_bytes()))
+    })
+}
+
+/// Check the tag of `data` in constant time.
+pub fn verify_tag(data: &[u8], tag: &[u8]) -> Result<()> {
+    with_keyring(|keyring| {
+        for keys in keyring.accepted() {
+            if hmac(keys.get(KeyPurpose::ControlTag), data)?
+                .verify_slice(tag)
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+        Err(std::io::Error::other("Authentication tag mismatch").into())
+    })
+}
+
+/// Associated data binding a ciphertext to the type of the message carrying it and its sender,
+/// so that a ciphertext cannot be replayed inside another message or on behalf of another node.
+pub fn associated_data(kind: &str, sender: &str) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(kind.len() + 1 + sender.len());
+    aad.extend_from_slice(kind.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(sender.as_bytes());
+    aad
+}
+
+#[inline]
+pub fn encrypt(purpose: KeyPurpose, data: Bytes, nonce: &[u8], aad: &[u8]) -> Result<Bytes> {
+    let nonce: [u8; NONCE_LEN] = nonce
+        .try_into()
+        .map_err(|_| "Nonce must be 12 bytes for AES-256-GCM")?;
+    let cipher =
+        with_keyring(|keyring| Ok(Aes256Gcm::new_from_slice(keyring.current.get(purpose))?))?;
     let out = cipher
-        .encrypt_padded_mut::<Pkcs7>(&mut buf, msg_len)
-        .map_err(|e| -> crate::err::Error {
-            Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Padding failed due to {}", e),
-            ))
-        })?;
-
-    Ok(Bytes::copy_from_slice(out))
+        .encrypt(&Nonce::from(nonce), Payload { msg: &data, aad })
+        .map_err(|e| std::io::Error::other(format!("Encryption failed due to {}", e)))?;
+
+    Ok(Bytes::from(out))
 }
 
+/// Decrypt and authenticate the ciphertext.
+/// Fails if the ciphertext, the nonce or the associated data were tampered with.
 #[inline]
-pub fn decrypt(cipher: Bytes, iv: &[u8]) -> Result<Bytes> {
-    if iv.len() != 16 {
-        return Err("IV must be 16 bytes for AES-256-CBC".into());
-    }
-    let key: &[u8; 32] = &*KEY;
-
-    // Decrypt in-place and remove PKCS7 padding
-    let mut buf = cipher.to_vec();
-    let dec = Aes256CbcDec::new_from_slices(key, iv)?;
-    let out = dec
-        .decrypt_padded_mut::<Pkcs7>(&mut buf)
-        .map_err(|e| -> crate::err::Error {
-            Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Unpadding failed due to {}", e),
-            ))
-        })?;
-
-    Ok(Bytes::copy_from_slice(out))
+pub fn decrypt(purpose: KeyPurpose, cipher: Bytes, nonce: &[u8], aad: &[u8]) -> Result<Bytes> {
+    let nonce: [u8; NONCE_LEN] = nonce
+        .try_into()
+        .map_err(|_| "Nonce must be 12 bytes for AES-256-GCM")?;
+    let ciphers = with_keyring(|keyring| {
+        keyring
+            .accepted()
+            .map(|keys| Ok(Aes256Gcm::new_from_slice(keys.get(purpose))?))
+            .collect::<Result<Vec<_>>>()
+    })?;
+    let mut last_err = None;
+    for dec in ciphers {
+        match dec.decrypt(&Nonce::from(nonce), Payload { msg: &cipher, aad }) {
+            Ok(out) => return Ok(Bytes::from(out)),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    let reason = last_err.map(|e| e.to_string()).unwrap_or_default();
+    Err(std::io::Error::other(format!("Authentication failed due to {}", reason)).into())
 }
 
-pub fn to_encryption<T, F>(data: &T, generate_iv: F) -> Result<Vec<u8>>
+/// Serialize and encrypt `data` under a fresh random nonce, prepended in clear to the output.
+pub fn to_encryption<T>(purpose: KeyPurpose, data: &T, aad: &[u8]) -> Result<Vec<u8>>
 where
     T: Serialize,
-    F: Fn() -> Result<[u8; 16]>,
 {
     // Serialize self using bincode v2 serde API
     let cfg = config::standard();
     let raw_bytes = bincode::serde::encode_to_vec(data, cfg)?;
 
-    let iv = generate_iv()?;
+    let nonce: [u8; NONCE_LEN] = rand::random();
 
-    // Encrypt the serialized payload using AES-256-CBC (PKCS7)
-    let encrypted = encrypt(Bytes::from(raw_bytes), &iv)?;
+    // Encrypt the serialized payload using AES-256-GCM
+    let encrypted = encrypt(purpose, Bytes::from(raw_bytes), &nonce, aad)?;
 
-    // Prepend IV in clear so the receiver can decrypt
-    let mut out: Vec<u8> = Vec::with_capacity(16 + encrypted.len());
-    out.extend_from_slice(&iv);
+    // Prepend the nonce in clear so the receiver can decrypt
+    let mut out: Vec<u8> = Vec::with_capacity(NONCE_LEN + encrypted.len());
+    out.extend_from_slice(&nonce);
     out.extend_from_slice(&encrypted);
     Ok(out)
 }
 
-pub fn from_encryption<T>(ciphertext: Box<[u8]>) -> Result<T>
+pub fn from_encryption<T>(purpose: KeyPurpose, ciphertext: Box<[u8]>, aad: &[u8]) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    if ciphertext.len() < 16 {
-        return Err("Incorrect cipher: IV too short".into());
+    if ciphertext.len() < NONCE_LEN {
+        return Err("Incorrect cipher: nonce too short".into());
     }
-    let iv: [u8; 16] = ciphertext[..16]
-        .try_into()
-        .map_err(|_| "Incorrect cipher: IV too short")?;
+    let (nonce, encrypted) = ciphertext.split_at(NONCE_LEN);
 
-    let decrypted = crypto::decrypt(Bytes::from(ciphertext[16..].to_vec()), &iv)?;
+    let decrypted = decrypt(purpose, Bytes::copy_from_slice(encrypted), nonce, aad)?;
 
     let raw_bytes = decrypted.to_vec();
     let (message, _) = bincode::serde::decode_from_slice(&raw_bytes, config::standard())?;
@@ -116,25 +277,16 @@ where
     Ok(message)
 }
 
-fn identity_from_password(password: &str, salt: &str) -> Result<age::x25519::Identity> {
-    // Derive a 32-byte secret from password+salt via SHA-256
-    let mut hasher = Sha256::new();
-    hasher.update(password.as_bytes());
-    hasher.update(salt.as_bytes());
-    let hash = hasher.finalize();
-
-    // Encode as proper age bech32 secret key string with HRP "age-secret-key-"
-    let sk: [u8; 32] = hash[..32].try_into().unwrap();
-    let sk_b32 = bech32::ToBase32::to_base32(&sk);
-    let sk_bech32 = bech32::encode("age-secret-key-", sk_b32, bech32::Variant::Bech32)?;
-
-    Ok(sk_bech32.parse::<age::x25519::Identity>()?)
-}
-
+/// Encrypt the file for `recipient`, the ephemeral X25519 key the receiver generated for this
+/// transfer. age wraps the file key with another ephemeral X25519 key of its own, so once both
+/// sides dropped their secrets a captured transfer cannot be decrypted, even with the token.
+/// Transfers stream the encryption with [`encrypt_to_writer`], the whole file is only encrypted
+/// at once in tests.
+#[cfg(test)]
 pub async fn f_to_encryption<P: AsRef<Path>>(
     from_path: P,
     to_path: P,
-    passphrase: &str,
+    recipient: &age::x25519::Recipient,
 ) -> Result<()> {
     let from = from_path.as_ref();
     let to = to_path.as_ref();
@@ -158,21 +310,15 @@ pub async fn f_to_encryption<P: AsRef<Path>>(
         return Err("to_path must not exist".into());
     }
 
-    let identity = identity_from_password(ENV_VAR.get().unwrap().get_conn_token(), passphrase)?;
-    let recipient = identity.to_public();
-    let encryptor =
-        age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))?;
-
     // 2) Open files
     let infile = &*fs_lock::RwLock::new(from).read().await?;
-    let mut reader = BufReader::new(infile);
+    let reader = std::io::BufReader::new(infile);
     let outfile = OpenOptions::new().write(true).create_new(true).open(to)?;
-    let mut writer = encryptor.wrap_output(BufWriter::new(outfile))?;
 
-    LOGGER.trace(format!("Encrypting {} to {}", from.display(), to.display()).as_str());
-    std::io::copy(&mut reader, &mut writer)?;
-    writer.finish()?;
-    LOGGER.trace(
+    crate::global_var::LOGGER
+        .trace(format!("Encrypting {} to {}", from.display(), to.display()).as_str());
+    encrypt_to_writer(reader, BufWriter::new(outfile), recipient)?;
+    crate::global_var::LOGGER.trace(
         format!(
             "Completed encrypting {} to {}",
             from.display(),
@@ -183,10 +329,36 @@ pub async fn f_to_encryption<P: AsRef<Path>>(
     Ok(())
 }
 
+/// Encrypt everything `reader` yields for `recipient` into `writer`, for the ephemeral key the
+/// receiver generated for the transfer.
+/// Blocking, the output is written as it is produced so it can be streamed to the peer.
+pub fn encrypt_to_writer<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    recipient: &age::x25519::Recipient,
+) -> Result<()> {
+    let encryptor =
+        age::Encryptor::with_recipients(std::iter::once(recipient as &dyn age::Recipient))?;
+    let mut writer = encryptor.wrap_output(writer)?;
+    std::io::copy(&mut reader, &mut writer)?;
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+/// Upper bound of the size of `plain_size` bytes once encrypted for a single recipient.
+/// age adds a header of a few hundred bytes, a nonce, and a tag to every 64 KiB chunk.
+pub fn max_encrypted_size(plain_size: u64) -> u64 {
+    const HEADER_BOUND: u64 = 1024;
+    const CHUNK_SIZE: u64 = 64 * 1024;
+    const TAG_SIZE: u64 = 16;
+    HEADER_BOUND + 16 + plain_size + (plain_size / CHUNK_SIZE + 1) * TAG_SIZE
+}
+
+/// Decrypt a file encrypted by [`encrypt_to_writer`] with the ephemeral key of the transfer.
 pub async fn f_from_encryption<P: AsRef<Path>>(
     from_path: P,
     to_path: P,
-    passphrase: &str,
+    identity: &age::x25519::Identity,
 ) -> Result<()> {
     let from = from_path.as_ref();
     let to = to_path.as_ref();
@@ -215,9 +387,8 @@ pub async fn f_from_encryption<P: AsRef<Path>>(
     let outfile = OpenOptions::new().write(true).create_new(true).open(to)?;
     let mut writer = BufWriter::new(outfile);
 
-    let identity = identity_from_password(ENV_VAR.get().unwrap().get_conn_token(), passphrase)?;
     let decryptor = age::Decryptor::new(infile)?;
-    let mut reader = decryptor.decrypt(std::iter::once(&identity as &dyn age::Identity))?;
+    let mut reader = decryptor.decrypt(std::iter::once(identity as &dyn age::Identity))?;
 
     std::io::copy(&mut reader, &mut writer)?;
     Ok(())
@@ -285,19 +456,133 @@ mod tests {
         let data = Bytes::from("hello world");
         let data_copy = data.clone();
 
-        let iv = [0x8; 16];
+        let nonce = [0x8; NONCE_LEN];
+        let aad = associated_data("PULL", "192.168.1.10");
 
-        let encrypted = encrypt(data, &iv).unwrap();
+        let encrypted = encrypt(KeyPurpose::Control, data, &nonce, &aad).unwrap();
 
-        let decrypted = decrypt(encrypted, &iv).unwrap();
+        let decrypted = decrypt(KeyPurpose::Control, encrypted, &nonce, &aad).unwrap();
 
         assert_eq!(data_copy, decrypted);
     }
 
+    #[test]
+    fn test_decrypt_rejects_tampering() {
+        ensure_env();
+
+        let aad = associated_data("PULL", "192.168.1.10");
+        let sealed =
+            to_encryption(KeyPurpose::Control, &String::from("hello world"), &aad).unwrap();
+
+        // Flipping any bit of the ciphertext breaks the authentication tag
+        let mut flipped = sealed.clone();
+        flipped[NONCE_LEN] ^= 0x01;
+        assert!(
+            from_encryption::<String>(KeyPurpose::Control, flipped.into_boxed_slice(), &aad)
+                .is_err()
+        );
+
+        // So does claiming another message type or another sender
+        let other_kind = associated_data("PUSH", "192.168.1.10");
+        let other_sender = associated_data("PULL", "192.168.1.11");
+        assert!(
+            from_encryption::<String>(
+                KeyPurpose::Control,
+                sealed.clone().into_boxed_slice(),
+                &other_kind
+            )
+            .is_err()
+        );
+        assert!(
+            from_encryption::<String>(
+                KeyPurpose::Control,
+                sealed.clone().into_boxed_slice(),
+                &other_sender
+            )
+            .is_err()
+        );
+
+        let back: String =
+            from_encryption(KeyPurpose::Control, sealed.into_boxed_slice(), &aad).unwrap();
+        assert_eq!(back, "hello world");
+    }
+
+    #[test]
+    fn test_tag_roundtrip_and_tampering() {
+        ensure_env();
+
+        let tag = compute_tag(b"HELLO alice").unwrap();
+        assert!(verify_tag(b"HELLO alice", &tag).is_ok());
+        assert!(verify_tag(b"HELLO mallory", &tag).is_err());
+        assert!(verify_tag(b"HELLO alice", &tag[..16]).is_err());
+    }
+
+    #[test]
+    fn test_subkeys_are_separate_per_purpose_and_token() {
+        let keys = GroupKeys::derive("TOKEN_A").unwrap();
+        assert!(keys == GroupKeys::derive("TOKEN_A").unwrap());
+        assert!(keys != GroupKeys::derive("TOKEN_B").unwrap());
+
+        for (i, a) in KeyPurpose::ALL.iter().enumerate() {
+            for b in &KeyPurpose::ALL[i + 1..] {
+                assert_ne!(
+                    keys.get(*a),
+                    keys.get(*b),
+                    "{:?} and {:?} share a key",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rotation_accepts_previous_keys_during_overlap_only() {
+        let old_keys = GroupKeys::derive("OLD_TOKEN").unwrap();
+        let new_keys = GroupKeys::derive("NEW_TOKEN").unwrap();
+        let mut keyring = GroupKeyring::new(old_keys.clone());
+
+        assert!(
+            keyring
+                .rotate(old_keys.clone(), Duration::from_secs(60))
+                .is_err()
+        );
+        keyring
+            .rotate(new_keys.clone(), Duration::from_secs(60))
+            .unwrap();
+        assert!(keyring.current == new_keys);
+        let accepted: Vec<_> = keyring.accepted().collect();
+        assert_eq!(accepted.len(), 2);
+        assert!(*accepted[1] == old_keys);
+
+        // Once the overlap is over only the new keys are accepted
+        keyring.previous = Some((old_keys.clone(), SystemTime::now() - Duration::from_secs(1)));
+        assert_eq!(keyring.accepted().count(), 1);
+
+        // Rotating without overlap drops the previous keys right away
+        keyring.rotate(old_keys.clone(), Duration::ZERO).unwrap();
+        assert!(keyring.previous.is_none());
+
+        // An overlap past the end of time is refused rather than overflowing
+        assert!(keyring.rotate(new_keys, Duration::MAX).is_err());
+        assert!(keyring.current == old_keys);
+    }
+
+    #[test]
+    fn test_to_encryption_uses_fresh_nonces() {
+        ensure_env();
+
+        let aad = associated_data("PULL", "192.168.1.10");
+        let a = to_encryption(KeyPurpose::Control, &42u64, &aad).unwrap();
+        let b = to_encryption(KeyPurpose::Control, &42u64, &aad).unwrap();
+        assert_ne!(a[..NONCE_LEN], b[..NONCE_LEN]);
+        assert_ne!(a, b);
+    }
+
     #[tokio::test]
     async fn test_file_encrypt_decrypt_roundtrip_various_sizes() {
         ensure_env();
-        let sizes = [0usize, 1, 15, 16, 17, 100_000];
+        let sizes = [0usize, 1, 15, 16, 17, 65_536, 100_000];
 
         for &sz in &sizes {
             // Per-iteration isolated workspace so each size cleans up independently
@@ -312,14 +597,17 @@ mod tests {
                 plain.push((i % 251) as u8);
             }
             fs::write(&from, &plain).unwrap();
-            let passphrase = "QAQ";
+            let identity = age::x25519::Identity::generate();
 
             // encrypt to file
-            f_to_encryption(&from, &enc, &passphrase).await.unwrap();
+            f_to_encryption(&from, &enc, &identity.to_public())
+                .await
+                .unwrap();
             assert!(enc.exists());
+            assert!(fs::metadata(&enc).unwrap().len() <= max_encrypted_size(sz as u64));
 
             // decrypt back
-            f_from_encryption(&enc, &dec, &passphrase).await.unwrap();
+            f_from_encryption(&enc, &dec, &identity).await.unwrap();
             let round = fs::read(&dec).unwrap();
             assert_eq!(round, plain);
 
@@ -336,9 +624,9 @@ mod tests {
         fs::write(&from, b"abc").unwrap();
         fs::write(&enc, b"already there").unwrap();
 
-        let passphrase = "QAQ";
+        let recipient = age::x25519::Identity::generate().to_public();
 
-        let err = f_to_encryption(&from, &enc, &passphrase).await;
+        let err = f_to_encryption(&from, &enc, &recipient).await;
         if let Ok(()) = err {
             panic!("should error");
         }
@@ -354,8 +642,8 @@ mod tests {
         let from = in_dir(&ws, "missing");
         let enc = in_dir(&ws, "out");
         // from does not exist
-        let passphrase = "QAQ";
-        let err = f_to_encryption(&from, &enc, &passphrase).await;
+        let recipient = age::x25519::Identity::generate().to_public();
+        let err = f_to_encryption(&from, &enc, &recipient).await;
         if let Ok(()) = err {
             panic!("should error");
         }
@@ -363,26 +651,31 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn test_decrypt_with_wrong_passphrase_fails() {
+    async fn test_decrypt_with_another_transfer_key_fails() {
         ensure_env();
         // prepare temporary workspace and files
-        let ws = tmp_dir("wrong_pw");
-        let from = in_dir(&ws, "plain_wrong_pw");
-        let enc = in_dir(&ws, "enc_wrong_pw");
-        let dec = in_dir(&ws, "dec_wrong_pw");
+        let ws = tmp_dir("wrong_key");
+        let from = in_dir(&ws, "plain_wrong_key");
+        let enc = in_dir(&ws, "enc_wrong_key");
+        let dec = in_dir(&ws, "dec_wrong_key");
 
         // write some plaintext
         fs::write(&from, b"The quick brown fox jumps over the lazy dog").unwrap();
 
-        // encrypt with passphrase A
-        let passphrase_ok = "correct horse battery staple";
-        f_to_encryption(&from, &enc, passphrase_ok).await.unwrap();
+        // encrypt for the key of transfer A
+        let transfer_a = age::x25519::Identity::generate();
+        f_to_encryption(&from, &enc, &transfer_a.to_public())
+            .await
+            .unwrap();
         assert!(enc.exists());
 
-        // try decrypt with passphrase B (wrong)
-        let passphrase_bad = "wrong passphrase";
-        let res = f_from_encryption(&enc, &dec, passphrase_bad).await;
-        assert!(res.is_err(), "decryption should fail with wrong passphrase");
+        // try decrypt with the key of transfer B
+        let transfer_b = age::x25519::Identity::generate();
+        let res = f_from_encryption(&enc, &dec, &transfer_b).await;
+        assert!(
+            res.is_err(),
+            "decryption should fail with the key of another transfer"
+        );
         // No manual cleanup: ws guard removes the directory
     }
 }
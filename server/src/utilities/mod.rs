@@ -1,6 +1,9 @@
+pub mod api_secret;
+pub mod clock;
 pub mod crypto;
 pub mod disk_op;
 pub(crate) mod format;
+pub mod identity;
 pub mod logger;
 pub mod temp_dir;
 
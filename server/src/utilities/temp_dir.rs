@@ -38,3 +38,23 @@ impl Deref for TmpDirGuard {
         &self.0
     }
 }
+
+/// Create a fresh directory under the system temp dir for a test, removed with the guard
+#[cfg(test)]
+pub(crate) fn tmp_dir(prefix: &str) -> TmpDirGuard {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    // Tests run in parallel, the clock alone may hand out the same name twice
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!(
+        "lumo_{}_{}_{}",
+        prefix,
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    TmpDirGuard::from(dir)
+}